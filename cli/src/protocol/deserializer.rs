@@ -21,6 +21,7 @@
 
 //! This module provides methods to deserialize an incoming response packet
 
+use libtdb::cluster::Redirect;
 use libtdb::terrapipe::RespCodes;
 use libtdb::util::terminal;
 use std::fmt;
@@ -50,6 +51,17 @@ pub enum DataType {
     UnsignedInt(Option<Result<u64, std::num::ParseIntError>>),
 }
 
+impl DataGroup {
+    /// Returns the redirect in this datagroup, if the server sent one instead of a
+    /// response
+    pub fn redirect(&self) -> Option<Redirect> {
+        match self.0.as_slice() {
+            [DataType::RespCode(Some(rc))] => Redirect::parse(rc),
+            _ => None,
+        }
+    }
+}
+
 impl fmt::Display for DataGroup {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         for element in self.0.iter() {
@@ -74,8 +86,15 @@ impl fmt::Display for DataGroup {
                                 RespCodes::OtherError(_) => {
                                     terminal::write_error("(Other Error) ")?
                                 }
+                                RespCodes::Redirect(_) => terminal::write_error("(Redirect) ")?,
+                                RespCodes::ReadOnly => terminal::write_error("(Read-only Error) ")?,
                             }
                         }
+                    } else if let Some(redirect) = Redirect::parse(rc) {
+                        terminal::write_info(format!(
+                            "(Slot {} is served by {}) ",
+                            redirect.slot, redirect.addr
+                        ))?;
                     } else {
                         terminal::write_error(format!("(\"{}\") ", rc))?;
                     }
@@ -21,8 +21,9 @@
 
 mod deserializer;
 use bytes::{Buf, BytesMut};
-use deserializer::ClientResult;
+use deserializer::{ClientResult, DataGroup};
 use lazy_static::lazy_static;
+use libtdb::cluster::Router;
 use libtdb::terrapipe;
 use libtdb::TResult;
 use libtdb::BUF_CAP;
@@ -30,6 +31,9 @@ use regex::Regex;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 
+/// The most redirects that we follow for a single query
+const MAX_REDIRECTS: usize = 4;
+
 lazy_static! {
     static ref RE: Regex = Regex::new("[^\\s\"']+|\"[^\"]*\"|'[^']*'").unwrap();
 }
@@ -38,6 +42,10 @@ lazy_static! {
 pub struct Connection {
     stream: TcpStream,
     buffer: BytesMut,
+    /// The `host:port` of the node that `stream` is connected to
+    addr: String,
+    /// Picks the node of a cluster that serves the keys in a query
+    router: Router,
 }
 
 impl Connection {
@@ -48,6 +56,8 @@ impl Connection {
         Ok(Connection {
             stream,
             buffer: BytesMut::with_capacity(BUF_CAP),
+            addr: host.to_owned(),
+            router: Router::new(host),
         })
     }
     /// This function will write a query to the stream and read the response from the
@@ -59,13 +69,68 @@ impl Connection {
     /// by this function (usually, "Invalid Response" is written to the terminal).
     /// - If the packet is incomplete, it will wait to read the entire response from the stream
     /// - If the packet is corrupted, it will output "Invalid Response"
+    ///
+    /// If the server is a node in a cluster and the key is served by another node, we
+    /// connect to that node and run the query there instead. The nodes that we're
+    /// redirected to are remembered, so later queries for their slots go straight there
     pub async fn run_query(&mut self, query: String) {
-        let query = terrapipe::proc_query(query);
-        match self.stream.write_all(&query).await {
+        let args: Vec<&str> = query.split_whitespace().collect();
+        let node = self.router.node_for(&args).to_owned();
+        if node != self.addr && !self.switch_to(&node).await {
+            return;
+        }
+        let query = terrapipe::proc_args(&args);
+        let mut redirects = 0;
+        loop {
+            let groups = match self.send_query(&query).await {
+                Some(groups) => groups,
+                None => return,
+            };
+            let redirect = match groups.as_slice() {
+                [group] if redirects < MAX_REDIRECTS => group.redirect(),
+                _ => None,
+            };
+            let redirect = match redirect {
+                Some(redirect) => redirect,
+                None => {
+                    for group in groups {
+                        println!("{}", group);
+                    }
+                    return;
+                }
+            };
+            redirects += 1;
+            self.router.redirected(&redirect);
+            if !self.switch_to(&redirect.addr).await {
+                return;
+            }
+        }
+    }
+    /// Connect to the node at `addr` instead, returning `false` if we couldn't (which is
+    /// written to the terminal)
+    async fn switch_to(&mut self, addr: &str) -> bool {
+        match TcpStream::connect(addr).await {
+            Ok(stream) => {
+                println!("Redirected to {}", addr);
+                self.stream = stream;
+                self.buffer.clear();
+                self.addr = addr.to_owned();
+                true
+            }
+            Err(e) => {
+                eprintln!("ERROR: Couldn't connect to {}: {}", addr, e);
+                false
+            }
+        }
+    }
+    /// Write the `query` packet to the stream and read the response, returning `None` if
+    /// anything went wrong (which is written to the terminal)
+    async fn send_query(&mut self, query: &[u8]) -> Option<Vec<DataGroup>> {
+        match self.stream.write_all(query).await {
             Ok(_) => (),
             Err(_) => {
                 eprintln!("ERROR: Couldn't write data to socket");
-                return;
+                return None;
             }
         };
         loop {
@@ -73,32 +138,26 @@ impl Connection {
                 Ok(_) => (),
                 Err(e) => {
                     eprintln!("ERROR: {}", e);
-                    return;
+                    return None;
                 }
             }
             match self.try_response().await {
                 ClientResult::Empty(f) => {
                     self.buffer.advance(f);
                     eprintln!("ERROR: The remote end reset the connection");
-                    return;
+                    return None;
                 }
                 ClientResult::Incomplete => {
                     continue;
                 }
                 ClientResult::Response(r, f) => {
                     self.buffer.advance(f);
-                    if r.len() == 0 {
-                        return;
-                    }
-                    for group in r {
-                        println!("{}", group);
-                    }
-                    return;
+                    return Some(r);
                 }
                 ClientResult::InvalidResponse(_) => {
                     self.buffer.clear();
                     eprintln!("{}", ClientResult::InvalidResponse(0));
-                    return;
+                    return None;
                 }
             }
         }
@@ -0,0 +1,310 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # Hash slots
+//!
+//! In cluster mode, the keys are split into [`SLOTS`] hash slots and every slot is
+//! served by one of the nodes. The slot of a key is the CRC16 (XMODEM) of the key,
+//! modulo the number of slots. If the key has a non-empty _hash tag_, that is, a part
+//! between the first `{` and the next `}`, only the hash tag is hashed, so that related
+//! keys like `{user1}.name` and `{user1}.mail` end up in the same slot.
+//!
+//! A node that gets a query for a key in a slot that it doesn't serve replies with a
+//! redirect (response code `7`), which carries the slot and the address of the node
+//! that serves it:
+//! ```text
+//! !<len>\n7 <slot> <host:port>\n
+//! ```
+//! Clients can use a [`Router`] to send queries straight to the node that serves their
+//! keys: it keeps a [`SlotMap`] that is filled in whenever they are redirected
+
+use std::ops::RangeInclusive;
+
+/// The number of hash slots
+pub const SLOTS: u16 = 16384;
+/// The actions whose arguments are keys
+pub const KEY_ACTIONS: [&str; 6] = ["GET", "DEL", "EXISTS", "MGET", "SDEL", "KEYLEN"];
+/// The actions whose arguments are pairs of keys and values
+pub const PAIR_ACTIONS: [&str; 7] = [
+    "SET", "UPDATE", "MSET", "MUPDATE", "SSET", "SUPDATE", "USET",
+];
+
+/// Returns the CRC16 (XMODEM) checksum of `data`
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Returns the part of `key` that is hashed, which is its hash tag if it has one
+pub fn hash_tag(key: &str) -> &str {
+    if let Some(open) = key.find('{') {
+        if let Some(len) = key[open + 1..].find('}') {
+            if len != 0 {
+                return &key[open + 1..open + 1 + len];
+            }
+        }
+    }
+    key
+}
+
+/// Returns the hash slot of `key`
+pub fn key_slot(key: &str) -> u16 {
+    crc16(hash_tag(key).as_bytes()) % SLOTS
+}
+
+/// Returns the keys in a query with `args`, which start with the action
+///
+/// Only the actions in [`KEY_ACTIONS`] and [`PAIR_ACTIONS`] have keys
+pub fn query_keys<T: AsRef<str>>(args: &[T]) -> impl Iterator<Item = &str> {
+    let action = args.first().map(|action| action.as_ref().to_uppercase());
+    let (step, keys) = match action.as_deref() {
+        Some(action) if KEY_ACTIONS.contains(&action) => (1, args.len()),
+        Some(action) if PAIR_ACTIONS.contains(&action) => (2, args.len()),
+        _ => (1, 0),
+    };
+    args.iter()
+        .skip(1)
+        .step_by(step)
+        .take(keys)
+        .map(|key| key.as_ref())
+}
+
+/// Parse a slot (like `42`) or a range of slots (like `0-8191`)
+pub fn parse_slots(slots: &str) -> Option<RangeInclusive<u16>> {
+    let (start, end) = match slots.find('-') {
+        Some(dash) => (&slots[..dash], &slots[dash + 1..]),
+        None => (slots, slots),
+    };
+    match (start.parse::<u16>(), end.parse::<u16>()) {
+        (Ok(start), Ok(end)) if start <= end && end < SLOTS => Some(start..=end),
+        _ => None,
+    }
+}
+
+/// A redirect to the node that serves a slot
+#[derive(Debug, PartialEq, Clone)]
+pub struct Redirect {
+    /// The slot of the key in the query
+    pub slot: u16,
+    /// The `host:port` of the node that serves the slot
+    pub addr: String,
+}
+
+impl Redirect {
+    /// Parse the body of a redirect response code, that is, `7 <slot> <host:port>`
+    pub fn parse(rcode: &str) -> Option<Self> {
+        let mut parts = rcode.split(' ');
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some("7"), Some(slot), Some(addr), None) => match slot.parse::<u16>() {
+                Ok(slot) if slot < SLOTS && !addr.is_empty() => Some(Redirect {
+                    slot,
+                    addr: addr.to_owned(),
+                }),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// The node that serves each slot
+#[derive(Debug, PartialEq, Clone)]
+pub struct SlotMap {
+    /// The `host:port` of every node that serves a slot
+    nodes: Vec<String>,
+    /// The position of the node in `nodes` that serves each slot
+    owners: Vec<Option<usize>>,
+}
+
+impl SlotMap {
+    /// Create a new `SlotMap` in which no slot is served by any node
+    pub fn new() -> Self {
+        SlotMap {
+            nodes: Vec::new(),
+            owners: vec![None; SLOTS as usize],
+        }
+    }
+    /// Returns the `host:port` of the node that serves `slot`
+    pub fn owner(&self, slot: u16) -> Option<&str> {
+        self.owners
+            .get(slot as usize)
+            .copied()
+            .flatten()
+            .map(|node| self.nodes[node].as_str())
+    }
+    /// Returns the `host:port` of the node that serves `key`
+    pub fn route(&self, key: &str) -> Option<&str> {
+        self.owner(key_slot(key))
+    }
+    /// Make the node at `addr` serve the `slots`
+    pub fn assign(&mut self, slots: RangeInclusive<u16>, addr: &str) {
+        let node = match self.nodes.iter().position(|node| node == addr) {
+            Some(node) => node,
+            None => {
+                self.nodes.push(addr.to_owned());
+                self.nodes.len() - 1
+            }
+        };
+        for slot in slots {
+            if let Some(owner) = self.owners.get_mut(slot as usize) {
+                *owner = Some(node);
+            }
+        }
+    }
+    /// Update the map after a redirect
+    pub fn redirected(&mut self, redirect: &Redirect) {
+        self.assign(redirect.slot..=redirect.slot, &redirect.addr);
+    }
+    /// Returns the ranges of slots that are served by a node, in order, with the
+    /// `host:port` of the node
+    pub fn ranges(&self) -> Vec<(RangeInclusive<u16>, &str)> {
+        let mut ranges: Vec<(RangeInclusive<u16>, usize)> = Vec::new();
+        for (slot, owner) in self.owners.iter().enumerate() {
+            let (slot, owner) = match owner {
+                Some(owner) => (slot as u16, *owner),
+                None => continue,
+            };
+            match ranges.last_mut() {
+                Some((range, node)) if *node == owner && *range.end() + 1 == slot => {
+                    *range = *range.start()..=slot
+                }
+                _ => ranges.push((slot..=slot, owner)),
+            }
+        }
+        ranges
+            .into_iter()
+            .map(|(range, node)| (range, self.nodes[node].as_str()))
+            .collect()
+    }
+}
+
+impl Default for SlotMap {
+    fn default() -> Self {
+        SlotMap::new()
+    }
+}
+
+/// Routes the queries of a client to the nodes of a cluster
+///
+/// Queries go to the node that serves the slot of their keys if the router knows it, and
+/// to the node that the client first connected to otherwise. The router learns where the
+/// slots are from the redirects that the client gets
+#[derive(Debug, Clone)]
+pub struct Router {
+    /// The `host:port` of the node that the client first connected to
+    seed: String,
+    /// The nodes that we've been redirected to
+    map: SlotMap,
+}
+
+impl Router {
+    /// Create a new `Router` that sends every query to the node at `seed` until it is
+    /// redirected
+    pub fn new(seed: &str) -> Self {
+        Router {
+            seed: seed.to_owned(),
+            map: SlotMap::new(),
+        }
+    }
+    /// Returns the `host:port` of the node that a query with `args` (which start with the
+    /// action) should be sent to
+    pub fn node_for<T: AsRef<str>>(&self, args: &[T]) -> &str {
+        query_keys(args)
+            .next()
+            .and_then(|key| self.map.route(key))
+            .unwrap_or(&self.seed)
+    }
+    /// Update the slot map after a redirect, so that the queries for the slot go to the
+    /// node that serves it from now on
+    pub fn redirected(&mut self, redirect: &Redirect) {
+        self.map.redirected(redirect);
+    }
+}
+
+#[test]
+fn test_key_slot() {
+    assert_eq!(crc16(b"123456789"), 0x31c3);
+    assert_eq!(key_slot("foo"), 12182);
+    assert_eq!(hash_tag("{user1}.name"), "user1");
+    assert_eq!(key_slot("{user1}.name"), key_slot("{user1}.mail"));
+    // Empty and unterminated tags don't count
+    assert_eq!(hash_tag("{}.name"), "{}.name");
+    assert_eq!(hash_tag("{user1.name"), "{user1.name");
+    assert_eq!(parse_slots("42"), Some(42..=42));
+    assert_eq!(parse_slots("0-8191"), Some(0..=8191));
+    assert_eq!(parse_slots("8191-0"), None);
+    assert_eq!(parse_slots("0-16384"), None);
+}
+
+#[test]
+fn test_slot_map() {
+    let mut map = SlotMap::new();
+    assert_eq!(map.route("foo"), None);
+    map.assign(0..=8191, "127.0.0.1:2003");
+    map.assign(8192..=16383, "127.0.0.1:2004");
+    assert_eq!(map.owner(0), Some("127.0.0.1:2003"));
+    assert_eq!(map.route("foo"), Some("127.0.0.1:2004"));
+    let redirect = Redirect::parse("7 12182 127.0.0.1:2005").unwrap();
+    assert_eq!(redirect.slot, 12182);
+    map.redirected(&redirect);
+    assert_eq!(map.route("foo"), Some("127.0.0.1:2005"));
+    assert_eq!(
+        map.ranges(),
+        vec![
+            (0..=8191, "127.0.0.1:2003"),
+            (8192..=12181, "127.0.0.1:2004"),
+            (12182..=12182, "127.0.0.1:2005"),
+            (12183..=16383, "127.0.0.1:2004"),
+        ]
+    );
+    assert_eq!(Redirect::parse("6 12182 127.0.0.1:2005"), None);
+    assert_eq!(Redirect::parse("7 16384 127.0.0.1:2005"), None);
+}
+
+#[test]
+fn test_router() {
+    let mut router = Router::new("127.0.0.1:2003");
+    let set = ["set", "foo", "bar"];
+    assert_eq!(query_keys(&set).collect::<Vec<_>>(), vec!["foo"]);
+    assert_eq!(
+        query_keys(&["MGET", "a", "b"]).collect::<Vec<_>>(),
+        vec!["a", "b"]
+    );
+    assert_eq!(query_keys(&["DBSIZE"]).count(), 0);
+    assert_eq!(query_keys(&["HEYA", "foo"]).count(), 0);
+    assert_eq!(router.node_for(&set), "127.0.0.1:2003");
+    let redirect = Redirect::parse("7 12182 127.0.0.1:2004").unwrap();
+    router.redirected(&redirect);
+    // `foo` is in slot 12182, so it goes straight to the node that serves it now
+    assert_eq!(router.node_for(&["GET", "foo"]), "127.0.0.1:2004");
+    assert_eq!(router.node_for(&["GET", "bar"]), "127.0.0.1:2003");
+    assert_eq!(router.node_for(&["DBSIZE"]), "127.0.0.1:2003");
+}
@@ -23,6 +23,7 @@
 //!
 //! This contains modules which are shared by both the `cli` and the `server` modules
 
+pub mod cluster;
 pub mod terrapipe;
 pub mod util;
 use std::error::Error;
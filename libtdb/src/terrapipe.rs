@@ -44,6 +44,13 @@ pub enum RespCodes {
     /// `6`: Some other error - the wrapped `String` will be returned in the response body.
     /// Just a note, this gets quite messy, especially when we're using it for deconding responses
     OtherError(Option<String>),
+    /// `7`: Redirect - the key is in a hash slot that is served by another node. The
+    /// wrapped `String` is the body of the response code, which has the slot and the
+    /// address of the node (see [`Redirect`](crate::cluster::Redirect))
+    Redirect(Option<String>),
+    /// `8`: Read-only Error - the server is in read-only mode and refuses actions that
+    /// change the data
+    ReadOnly,
 }
 
 impl From<RespCodes> for u8 {
@@ -57,6 +64,8 @@ impl From<RespCodes> for u8 {
             PacketError => 4,
             ServerError => 5,
             OtherError(_) => 6,
+            Redirect(_) => 7,
+            ReadOnly => 8,
         }
     }
 }
@@ -72,6 +81,8 @@ impl From<RespCodes> for char {
             PacketError => '4',
             ServerError => '5',
             OtherError(_) => '6',
+            Redirect(_) => '7',
+            ReadOnly => '8',
         }
     }
 }
@@ -88,6 +99,8 @@ impl RespCodes {
                 4 => PacketError,
                 5 => ServerError,
                 6 => OtherError(extra),
+                7 => Redirect(extra),
+                8 => ReadOnly,
                 _ => return None,
             },
             Err(_) => return None,
@@ -104,6 +117,8 @@ impl RespCodes {
             4 => PacketError,
             5 => ServerError,
             6 => OtherError(extra),
+            7 => Redirect(extra),
+            8 => ReadOnly,
             _ => return None,
         };
         Some(res)
@@ -113,7 +128,7 @@ impl RespCodes {
             Some(r) => r,
             None => return None,
         };
-        if result > 6 {
+        if result > 8 {
             return None;
         }
         return RespCodes::from_u8(result, None);
@@ -127,9 +142,17 @@ where
 {
     // TODO(@ohsayan): Enable "" to be escaped
     // let args: Vec<&str> = RE.find_iter(&querystr).map(|val| val.as_str()).collect();
-    let querystr = querystr.as_ref().to_owned();
-    let args: Vec<&str> = querystr.split_whitespace().collect();
-    let mut bytes = Vec::with_capacity(querystr.len());
+    let args: Vec<&str> = querystr.as_ref().split_whitespace().collect();
+    proc_args(&args)
+}
+
+/// Prepare a query packet from a list of values, which (unlike with [`proc_query`]) may
+/// contain whitespace
+pub fn proc_args<T>(args: &[T]) -> Vec<u8>
+where
+    T: AsRef<str>,
+{
+    let mut bytes = Vec::with_capacity(args.iter().map(|arg| arg.as_ref().len() + 4).sum());
     bytes.extend(b"#2\n*1\n#");
     let arg_len_bytes = args.len().to_string().into_bytes();
     let arg_len_bytes_len = (arg_len_bytes.len() + 1).to_string().into_bytes();
@@ -137,7 +160,8 @@ where
     bytes.extend(b"\n&");
     bytes.extend(arg_len_bytes);
     bytes.push(b'\n');
-    args.into_iter().for_each(|arg| {
+    args.iter().for_each(|arg| {
+        let arg = arg.as_ref();
         bytes.push(b'#');
         let len_bytes = arg.len().to_string().into_bytes();
         bytes.extend(len_bytes);
@@ -157,4 +181,10 @@ fn test_queryproc() {
             .to_owned(),
         proc_query(query)
     );
+    assert_eq!(
+        "#2\n*1\n#2\n&3\n#3\nSET\n#1\nx\n#5\nhey y\n"
+            .as_bytes()
+            .to_owned(),
+        proc_args(&["SET", "x", "hey y"])
+    );
 }
@@ -0,0 +1,240 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # The `AUTH` action
+//!
+//! A listener with a `password` only serves clients that sent it with `AUTH <password>`.
+//! Until then, every other query gets an "Authentication required" error. This also goes
+//! for replicas, the other members of a Raft group and the other nodes of a cluster, so
+//! they send the password in the `password` setting of their section (see [`send_auth`])
+//! right after connecting
+
+use crate::protocol::{responses, Connection, Query};
+use libtdb::terrapipe;
+use libtdb::TResult;
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::{self, Duration};
+
+/// Returns `true` if `query` is an `AUTH` query
+///
+/// Whether a client is authenticated is up to its connection, so the connection handler
+/// has to check for `AUTH` before handing the query off to the query engine
+pub fn is_auth(query: &Query) -> bool {
+    match query {
+        Query::Simple(act) => act
+            .get_first()
+            .map(|first| first.eq_ignore_ascii_case("AUTH"))
+            .unwrap_or(false),
+        Query::Pipelined(_) => false,
+    }
+}
+
+/// Run an `AUTH` query on a connection to a listener with the given `password`, if it
+/// has one
+///
+/// This returns `true` if the client sent the right password
+pub async fn auth(con: &mut Connection, act: Query, password: Option<&str>) -> TResult<bool> {
+    let (response, authenticated) = match (act, password) {
+        (Query::Simple(act), Some(password)) if act.howmany() == 1 => {
+            // `howmany` is 1, so there is exactly one argument
+            let given = act.into_iter().next().unwrap_or_default();
+            if matches(given.as_bytes(), password.as_bytes()) {
+                (responses::fresp::R_OKAY.to_owned(), true)
+            } else {
+                log::warn!(
+                    "Client {} sent the wrong password",
+                    con.get_peer()
+                        .map(|peer| peer.to_string())
+                        .unwrap_or_else(|_| "unknown".to_owned())
+                );
+                (responses::fresp::R_WRONG_PASSWORD.to_owned(), false)
+            }
+        }
+        (Query::Simple(act), None) if act.howmany() == 1 => (
+            responses::other_error("This listener doesn't have a password"),
+            false,
+        ),
+        _ => (responses::fresp::R_ACTION_ERR.to_owned(), false),
+    };
+    con.write_response(response).await?;
+    con.flush_stream().await?;
+    Ok(authenticated)
+}
+
+/// Send `AUTH <password>` on a connection that this server opened to another server,
+/// waiting at most `timeout` for the reply
+///
+/// This fails if the other server refuses the password, which includes the case where
+/// its listener doesn't have one
+pub async fn send_auth<S>(stream: &mut S, password: &str, timeout: Duration) -> Result<(), String>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let query = terrapipe::proc_args(&["AUTH", password]);
+    let result = time::timeout(timeout, async {
+        stream.write_all(&query).await?;
+        let mut reply = vec![0u8; responses::fresp::R_OKAY.len()];
+        stream.read_exact(&mut reply).await?;
+        Ok::<_, io::Error>(reply)
+    })
+    .await;
+    match result {
+        Ok(Ok(reply)) if reply == *responses::fresp::R_OKAY => Ok(()),
+        Ok(Ok(_)) => Err("The server refused the password".to_owned()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err("The server stopped responding".to_owned()),
+    }
+}
+
+/// Compare `given` to `password` in a time that only depends on their lengths, so that
+/// the password can't be guessed one byte at a time from how long the comparison takes
+fn matches(given: &[u8], password: &[u8]) -> bool {
+    given.len() == password.len()
+        && given
+            .iter()
+            .zip(password)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[test]
+fn test_matches() {
+    assert!(matches(b"hunter2", b"hunter2"));
+    assert!(!matches(b"hunter3", b"hunter2"));
+    assert!(!matches(b"hunter", b"hunter2"));
+    assert!(!matches(b"", b"hunter2"));
+}
+
+#[cfg(test)]
+/// Run `query` on `stream`, returning the response
+async fn run_query(stream: &mut tokio::net::TcpStream, query: &str) -> Vec<u8> {
+    use libtdb::terrapipe;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    stream
+        .write_all(&terrapipe::proc_query(query))
+        .await
+        .unwrap();
+    let mut response = vec![0u8; 1024];
+    let len = stream.read(&mut response).await.unwrap();
+    response.truncate(len);
+    response
+}
+
+#[tokio::test]
+async fn test_auth_per_listener() {
+    use crate::dbnet::{self, Binding};
+    use crate::CoreDB;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::oneshot;
+    let open = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let locked = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let open_addr = open.local_addr().unwrap();
+    let locked_addr = locked.local_addr().unwrap();
+    let bindings = vec![
+        Binding::plain(open),
+        Binding::with_password(locked, "hunter2"),
+    ];
+    let (stop, stopped) = oneshot::channel::<()>();
+    tokio::spawn(dbnet::test_run_bindings(
+        bindings,
+        CoreDB::new_empty(0),
+        stopped,
+    ));
+    let mut locked = TcpStream::connect(locked_addr).await.unwrap();
+    assert_eq!(
+        run_query(&mut locked, "SET x 100").await,
+        *responses::fresp::R_AUTH_REQUIRED
+    );
+    assert_eq!(
+        run_query(&mut locked, "AUTH hunter3").await,
+        *responses::fresp::R_WRONG_PASSWORD
+    );
+    assert_eq!(
+        run_query(&mut locked, "SET x 100").await,
+        *responses::fresp::R_AUTH_REQUIRED
+    );
+    assert_eq!(
+        run_query(&mut locked, "AUTH hunter2").await,
+        *responses::fresp::R_OKAY
+    );
+    assert_eq!(
+        run_query(&mut locked, "SET x 100").await,
+        *responses::fresp::R_OKAY
+    );
+    // Both listeners serve the same data, and the other one doesn't need a password
+    let mut open = TcpStream::connect(open_addr).await.unwrap();
+    assert_eq!(
+        run_query(&mut open, "UPDATE x 200").await,
+        *responses::fresp::R_OKAY
+    );
+    assert_eq!(
+        run_query(&mut open, "AUTH hunter2").await,
+        responses::other_error("This listener doesn't have a password")
+    );
+    // Other servers send the password in the same way
+    let timeout = Duration::from_secs(5);
+    let mut link = TcpStream::connect(locked_addr).await.unwrap();
+    assert!(send_auth(&mut link, "hunter3", timeout).await.is_err());
+    let mut link = TcpStream::connect(locked_addr).await.unwrap();
+    send_auth(&mut link, "hunter2", timeout).await.unwrap();
+    assert_eq!(
+        run_query(&mut link, "GET x").await,
+        b"#2
+*1
+#2
+&1
++3
+200
+".to_vec()
+    );
+    let mut link = TcpStream::connect(open_addr).await.unwrap();
+    assert!(send_auth(&mut link, "hunter2", timeout).await.is_err());
+    let _ = stop.send(());
+}
+
+#[tokio::test]
+async fn test_config_hides_password() {
+    use crate::dbnet::{self, Binding};
+    use crate::CoreDB;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::oneshot;
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let db = CoreDB::new_empty(0);
+    db.shared.config.write().listeners[0].password = Some("hunter2".to_owned());
+    let (stop, stopped) = oneshot::channel::<()>();
+    tokio::spawn(dbnet::test_run_bindings(
+        vec![Binding::with_password(listener, "hunter2")],
+        db,
+        stopped,
+    ));
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    assert_eq!(
+        run_query(&mut stream, "AUTH hunter2").await,
+        *responses::fresp::R_OKAY
+    );
+    assert_eq!(
+        run_query(&mut stream, "CONFIG GET server.password").await,
+        b"#2\n*1\n#2\n&1\n+3\n***\n".to_vec()
+    );
+    let _ = stop.send(());
+}
@@ -0,0 +1,287 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # Client management
+//!
+//! Every connection accepted by `dbnet::Listener` is registered in the `ClientRegistry`
+//! until its `CHandler` is dropped. The registry is inspected and managed with the
+//! `CLIENT` action:
+//! - `CLIENT LIST` returns one line for each connected client
+//! - `CLIENT KILL ID <id>`, `CLIENT KILL ADDR <ip:port>` and `CLIENT KILL NAME <name>`
+//!   disconnect the matching clients and return how many were killed
+//! - `CLIENT SETNAME <name>` names the current connection
+//!
+//! Killing a client fires its `Terminator`, so the handler shuts down exactly like it
+//! would when the server is shutting down
+
+use crate::coredb::CoreDB;
+use crate::protocol::{responses, ActionGroup, Connection};
+use crate::resp::{BytesWrapper, GroupBegin};
+use bytes::Bytes;
+use libtdb::TResult;
+use parking_lot::{Mutex, RwLock};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::broadcast;
+
+/// Information about a connected client
+#[derive(Debug)]
+pub struct ClientInfo {
+    /// A unique, increasing ID for the client
+    id: u64,
+    /// The address of the remote end (if we could find out what it was)
+    addr: Option<SocketAddr>,
+    /// The name set by `CLIENT SETNAME`
+    name: RwLock<Option<String>>,
+    /// When the client connected
+    connected_at: Instant,
+    /// When the client last ran an action, and what action it was
+    last_command: Mutex<(Instant, Option<String>)>,
+    /// Bytes read from the client
+    bytes_in: AtomicU64,
+    /// Bytes written to the client
+    bytes_out: AtomicU64,
+    /// Fires the client's `Terminator`
+    kill_switch: broadcast::Sender<()>,
+}
+
+impl ClientInfo {
+    /// Returns the ID of the client
+    pub const fn id(&self) -> u64 {
+        self.id
+    }
+    /// Note that the client has just run `action`
+    pub fn touch(&self, action: &str) {
+        *self.last_command.lock() = (Instant::now(), Some(action.to_owned()));
+    }
+    pub fn add_bytes_in(&self, n: usize) {
+        self.bytes_in.fetch_add(n as u64, Ordering::Relaxed);
+    }
+    pub fn add_bytes_out(&self, n: usize) {
+        self.bytes_out.fetch_add(n as u64, Ordering::Relaxed);
+    }
+    /// Disconnect the client
+    fn kill(&self) {
+        // The handler might already be on its way out, in which case there's nobody to tell
+        let _ = self.kill_switch.send(());
+    }
+    /// Render the client as a single line of text
+    fn render(&self, now: Instant) -> String {
+        let (last_active, ref last_command) = *self.last_command.lock();
+        format!(
+            "id={} addr={} name={} age={} idle={} cmd={} bytes_in={} bytes_out={}",
+            self.id,
+            self.addr
+                .map(|a| a.to_string())
+                .unwrap_or_else(|| "unknown".to_owned()),
+            self.name.read().as_deref().unwrap_or(""),
+            now.duration_since(self.connected_at).as_secs(),
+            now.duration_since(last_active).as_secs(),
+            last_command.as_deref().unwrap_or("NULL"),
+            self.bytes_in.load(Ordering::Relaxed),
+            self.bytes_out.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// The registry of connected clients
+#[derive(Debug)]
+pub struct ClientRegistry {
+    /// The ID to be given to the next client
+    next_id: AtomicU64,
+    /// The connected clients, by ID
+    clients: RwLock<HashMap<u64, Arc<ClientInfo>>>,
+}
+
+impl ClientRegistry {
+    /// Create a new, empty registry
+    pub fn new() -> Self {
+        ClientRegistry {
+            next_id: AtomicU64::new(1),
+            clients: RwLock::new(HashMap::new()),
+        }
+    }
+    /// Register a newly accepted client, unless `maxclients` clients are already connected
+    ///
+    /// This returns the client's entry along with the receiving end of its kill switch,
+    /// which should be handed to the client's `Terminator`
+    pub fn register(
+        &self,
+        addr: Option<SocketAddr>,
+        maxclients: usize,
+    ) -> Option<(Arc<ClientInfo>, broadcast::Receiver<()>)> {
+        let mut clients = self.clients.write();
+        if clients.len() >= maxclients {
+            return None;
+        }
+        let (kill_switch, killed) = broadcast::channel(1);
+        let now = Instant::now();
+        let client = Arc::new(ClientInfo {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            addr,
+            name: RwLock::new(None),
+            connected_at: now,
+            last_command: Mutex::new((now, None)),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            kill_switch,
+        });
+        clients.insert(client.id, client.clone());
+        Some((client, killed))
+    }
+    /// Remove a disconnected client from the registry
+    pub fn deregister(&self, id: u64) {
+        self.clients.write().remove(&id);
+    }
+    /// Kill all the clients for which `filter` returns `true`, returning the number of
+    /// clients that were killed
+    fn kill_where(&self, filter: impl Fn(&ClientInfo) -> bool) -> usize {
+        self.clients
+            .read()
+            .values()
+            .filter(|client| filter(client))
+            .map(|client| client.kill())
+            .count()
+    }
+    /// Returns every client rendered as a line of text, oldest first
+    fn list(&self) -> Vec<String> {
+        let now = Instant::now();
+        let clients = self.clients.read();
+        let mut ids: Vec<&u64> = clients.keys().collect();
+        ids.sort();
+        ids.into_iter().map(|id| clients[id].render(now)).collect()
+    }
+}
+
+/// Run a `CLIENT` query
+pub async fn client(handle: &CoreDB, con: &mut Connection, act: ActionGroup) -> TResult<()> {
+    let howmany = act.howmany();
+    let mut args = act.into_iter();
+    let subaction = args.next().map(|arg| arg.to_uppercase());
+    let registry = &handle.shared.clients;
+    match (subaction.as_deref(), howmany) {
+        (Some("LIST"), 1) => {
+            let clients = registry.list();
+            con.write_response(GroupBegin(clients.len())).await?;
+            for client in clients {
+                con.write_response(BytesWrapper(Bytes::from(client)))
+                    .await?;
+            }
+            Ok(())
+        }
+        (Some("KILL"), 3) => {
+            let filter = args.next().map(|arg| arg.to_uppercase());
+            // We've already checked the number of arguments
+            let value = args.next().unwrap();
+            let killed = match filter.as_deref() {
+                Some("ID") => match value.parse::<u64>() {
+                    Ok(id) => registry.kill_where(|client| client.id == id),
+                    Err(_) => {
+                        return con
+                            .write_response(responses::fresp::R_ACTION_ERR.to_owned())
+                            .await
+                    }
+                },
+                Some("ADDR") => match value.parse::<SocketAddr>() {
+                    Ok(addr) => registry.kill_where(|client| client.addr == Some(addr)),
+                    Err(_) => {
+                        return con
+                            .write_response(responses::fresp::R_ACTION_ERR.to_owned())
+                            .await
+                    }
+                },
+                Some("NAME") => registry
+                    .kill_where(|client| client.name.read().as_deref() == Some(value.as_str())),
+                _ => {
+                    return con
+                        .write_response(responses::fresp::R_ACTION_ERR.to_owned())
+                        .await
+                }
+            };
+            con.write_response(GroupBegin(1)).await?;
+            con.write_response(killed).await
+        }
+        (Some("SETNAME"), 2) => {
+            let name = args.next().unwrap();
+            // Names show up in `CLIENT LIST`, so they can't break up the line
+            if name.is_empty() || name.chars().any(char::is_whitespace) {
+                return con
+                    .write_response(responses::fresp::R_ACTION_ERR.to_owned())
+                    .await;
+            }
+            *con.client().name.write() = Some(name);
+            con.write_response(responses::fresp::R_OKAY.to_owned())
+                .await
+        }
+        _ => {
+            con.write_response(responses::fresp::R_ACTION_ERR.to_owned())
+                .await
+        }
+    }
+}
+
+#[test]
+fn test_registry_register_and_deregister() {
+    let registry = ClientRegistry::new();
+    let (first, _k1) = registry.register(None, 2).unwrap();
+    let (second, _k2) = registry
+        .register(Some("127.0.0.1:4000".parse().unwrap()), 2)
+        .unwrap();
+    // There's no room for a third client
+    assert!(registry.register(None, 2).is_none());
+    assert_eq!(first.id(), 1);
+    assert_eq!(second.id(), 2);
+    assert_eq!(registry.clients.read().len(), 2);
+    second.touch("GET");
+    second.add_bytes_in(10);
+    second.add_bytes_out(20);
+    let list = registry.list();
+    assert!(list[0].starts_with("id=1 addr=unknown name= age=0 idle=0 cmd=NULL "));
+    assert!(list[1].starts_with("id=2 addr=127.0.0.1:4000 name= "));
+    assert!(list[1].ends_with("cmd=GET bytes_in=10 bytes_out=20"));
+    registry.deregister(first.id());
+    assert_eq!(registry.clients.read().len(), 1);
+}
+
+#[test]
+fn test_registry_kill() {
+    let registry = ClientRegistry::new();
+    let (first, mut k1) = registry
+        .register(Some("127.0.0.1:4000".parse().unwrap()), 2)
+        .unwrap();
+    let (second, mut k2) = registry
+        .register(Some("127.0.0.1:4001".parse().unwrap()), 2)
+        .unwrap();
+    *second.name.write() = Some("worker".to_owned());
+    assert_eq!(registry.kill_where(|c| c.name.read().is_none()), 1);
+    assert!(k1.try_recv().is_ok());
+    assert!(k2.try_recv().is_err());
+    assert_eq!(registry.kill_where(|c| c.id == 42), 0);
+    let addr = "127.0.0.1:4001".parse().unwrap();
+    assert_eq!(registry.kill_where(|c| c.addr == Some(addr)), 1);
+    assert!(k2.try_recv().is_ok());
+    // Killing doesn't deregister the client, the handler does that when it quits
+    assert_eq!(registry.clients.read().len(), 2);
+    assert_eq!(first.id(), 1);
+}
@@ -0,0 +1,148 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # The `CONFIG` action
+//!
+//! `CONFIG` inspects and changes the configuration of the running server. Settings are
+//! named `section.key`, after the section and the key in the configuration file:
+//! - `CONFIG GET` returns every setting as a `name=value` line, sorted by name
+//! - `CONFIG GET <name>` returns the value of a setting
+//! - `CONFIG SET <name> <value> [<name> <value> ...]` changes settings while the server is
+//!   running. Only the settings in [`HOT_SETTINGS`](crate::config::HOT_SETTINGS) can be changed, and either all the
+//!   changes are made or none of them are
+//! - `CONFIG REWRITE` writes the settings that were changed while the server was running
+//!   to the configuration file that the server was started with. Settings from the
+//!   command line or the environment aren't written, and comments in the file are lost
+
+use crate::config::ParsedConfig;
+use crate::coredb::{CoreDB, Shared};
+use crate::protocol::{responses, ActionGroup, Connection};
+use crate::resp::{BytesWrapper, GroupBegin};
+use bytes::Bytes;
+use libtdb::TResult;
+
+/// Run a `CONFIG` query
+pub async fn config(handle: &CoreDB, con: &mut Connection, act: ActionGroup) -> TResult<()> {
+    let howmany = act.howmany();
+    let mut args = act.into_iter();
+    let subaction = args.next().map(|arg| arg.to_uppercase());
+    let shared = &handle.shared;
+    match (subaction.as_deref(), howmany) {
+        (Some("GET"), 1) => {
+            // The error can't be held across an `await`
+            let settings = effective(shared).settings().map_err(|e| e.to_string());
+            let settings = match settings {
+                Ok(settings) => settings,
+                Err(e) => {
+                    log::error!("Failed to render the configuration with error: '{}'", e);
+                    return con
+                        .write_response(responses::fresp::R_SERVER_ERR.to_owned())
+                        .await;
+                }
+            };
+            con.write_response(GroupBegin(settings.len())).await?;
+            for (name, value) in settings {
+                con.write_response(BytesWrapper(Bytes::from(format!("{}={}", name, value))))
+                    .await?;
+            }
+            Ok(())
+        }
+        (Some("GET"), 2) => {
+            // `howmany` says that there's another argument
+            let name = args.next().unwrap_or_default();
+            let value = effective(shared).settings().ok().and_then(|settings| {
+                settings
+                    .into_iter()
+                    .find(|(setting, _)| *setting == name)
+                    .map(|(_, value)| value)
+            });
+            match value {
+                Some(value) => {
+                    con.write_response(GroupBegin(1)).await?;
+                    con.write_response(BytesWrapper(Bytes::from(value))).await
+                }
+                None => con.write_response(responses::fresp::R_NIL.to_owned()).await,
+            }
+        }
+        (Some("SET"), n) if n > 1 && n % 2 == 1 => {
+            let mut changes = Vec::with_capacity(n / 2);
+            while let (Some(name), Some(value)) = (args.next(), args.next()) {
+                changes.push((name, value));
+            }
+            match shared.set_settings(&changes).await {
+                Ok(()) => {
+                    if let Ok(peer) = con.get_peer() {
+                        for (name, value) in &changes {
+                            log::info!("Setting '{}' set to '{}' by client {}", name, value, peer);
+                        }
+                    }
+                    con.write_response(responses::fresp::R_OKAY.to_owned())
+                        .await
+                }
+                Err(e) => con.write_response(responses::other_error(&e)).await,
+            }
+        }
+        (Some("REWRITE"), 1) => {
+            let cfg_file = match &shared.config_file {
+                Some(cfg_file) => cfg_file,
+                None => {
+                    return con
+                        .write_response(responses::other_error(
+                            "The server wasn't started with a configuration file",
+                        ))
+                        .await
+                }
+            };
+            let written = cfg_file
+                .rewrite(&effective(shared))
+                .map_err(|e| e.to_string());
+            match written {
+                Ok(()) => {
+                    log::info!("Wrote the configuration to '{}'", cfg_file);
+                    con.write_response(responses::fresp::R_OKAY.to_owned())
+                        .await
+                }
+                Err(e) => {
+                    log::error!(
+                        "Failed to write the configuration to '{}' with error: '{}'",
+                        cfg_file,
+                        e
+                    );
+                    con.write_response(responses::fresp::R_SERVER_ERR.to_owned())
+                        .await
+                }
+            }
+        }
+        _ => {
+            con.write_response(responses::fresp::R_ACTION_ERR.to_owned())
+                .await
+        }
+    }
+}
+
+/// Returns the effective configuration
+///
+/// The primary can be changed with `REPLICAOF`, so it is taken from the replication state
+fn effective(shared: &Shared) -> ParsedConfig {
+    let mut cfg = shared.config.read().clone();
+    cfg.replication.primary = shared.replication.primary();
+    cfg
+}
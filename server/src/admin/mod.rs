@@ -0,0 +1,34 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # Administrative actions
+//! Unlike the actions in the K/V engine, these actions don't touch the data stored in
+//! the database; instead they help operators inspect and manage the server itself
+
+pub mod auth;
+pub mod clients;
+pub mod config;
+pub mod monitor;
+pub mod readonly;
+pub mod save;
+pub mod shutdown;
+pub mod slowlog;
+pub mod snapshot;
@@ -0,0 +1,217 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # Live command stream
+//!
+//! Running `MONITOR` turns a connection into a feed of every query that the server
+//! receives from that point on. Each query is sent as a single line:
+//! ```text
+//! <unix time>.<micros> [<keyspace> <client>] "ACTION" "arg1" "arg2" ...
+//! ```
+//! Queries are published by the connection handlers on a broadcast channel, but only
+//! while at least one monitor is attached, so the feed costs nothing otherwise. A monitor
+//! that can't keep up is told how many queries it missed instead of slowing down the
+//! rest of the server
+
+use crate::coredb::CoreDB;
+use crate::dbnet::Terminator;
+use crate::protocol::{responses, ActionGroup, Connection, Query, QueryResult};
+use crate::resp::{BytesWrapper, GroupBegin};
+use bytes::Bytes;
+use chrono::prelude::*;
+use libtdb::TResult;
+use std::net::SocketAddr;
+use tokio::sync::broadcast::{self, RecvError};
+
+/// The number of queries that a monitor can fall behind by before it starts missing them
+const MONITOR_BACKLOG: usize = 1024;
+/// The keyspace that queries run against
+///
+/// There is only one keyspace right now, but monitors report it so that the feed
+/// won't have to change when there are more
+const DEFAULT_KEYSPACE: &str = "default";
+
+/// The publishing end of the live command stream
+#[derive(Debug)]
+pub struct Monitor {
+    tx: broadcast::Sender<Bytes>,
+}
+
+impl Monitor {
+    /// Create a new `Monitor` with no attached monitors
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(MONITOR_BACKLOG);
+        Monitor { tx }
+    }
+    /// Returns `true` if at least one connection is monitoring
+    pub fn is_attached(&self) -> bool {
+        self.tx.receiver_count() != 0
+    }
+    /// Publish the query received from `client` to all the attached monitors
+    pub fn publish(&self, query: &Query, client: Option<SocketAddr>) {
+        if !self.is_attached() {
+            return;
+        }
+        match query {
+            Query::Simple(act) => self.publish_group(act, client),
+            Query::Pipelined(acts) => acts.iter().for_each(|act| self.publish_group(act, client)),
+        }
+    }
+    fn publish_group(&self, act: &ActionGroup, client: Option<SocketAddr>) {
+        let line = render(Utc::now(), client, act);
+        // The last monitor might have gone away in the meantime, which is fine
+        let _ = self.tx.send(Bytes::from(line));
+    }
+    /// Attach a new monitor
+    fn subscribe(&self) -> broadcast::Receiver<Bytes> {
+        self.tx.subscribe()
+    }
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Monitor::new()
+    }
+}
+
+/// Render a query as a line in the feed
+fn render(time: DateTime<Utc>, client: Option<SocketAddr>, act: &ActionGroup) -> String {
+    let mut line = format!(
+        "{}.{:06} [{} {}]",
+        time.timestamp(),
+        time.timestamp_subsec_micros(),
+        DEFAULT_KEYSPACE,
+        client
+            .map(|c| c.to_string())
+            .unwrap_or_else(|| "unknown".to_owned())
+    );
+    act.get_ref().iter().for_each(|arg| {
+        line.push(' ');
+        // The `Debug` impl quotes and escapes the argument for us
+        line.push_str(&format!("{:?}", arg));
+    });
+    line
+}
+
+/// Returns `true` if `query` is a `MONITOR` query
+///
+/// `MONITOR` takes over the connection, so the connection handler has to check for it
+/// before handing the query off to the query engine
+pub fn is_monitor(query: &Query) -> bool {
+    match query {
+        Query::Simple(act) => act
+            .get_first()
+            .map(|first| first.eq_ignore_ascii_case("MONITOR"))
+            .unwrap_or(false),
+        Query::Pipelined(_) => false,
+    }
+}
+
+/// Run a `MONITOR` query
+///
+/// This streams queries to the client until it disconnects or the server shuts down.
+/// Anything else that the client sends in the meantime is ignored
+pub async fn monitor(
+    handle: &CoreDB,
+    con: &mut Connection,
+    act: Query,
+    terminator: &mut Terminator,
+) -> TResult<()> {
+    if let Query::Simple(act) = act {
+        if act.howmany() != 0 {
+            con.write_response(responses::fresp::R_ACTION_ERR.to_owned())
+                .await?;
+            return con.flush_stream().await;
+        }
+    }
+    let mut feed = handle.shared.monitor.subscribe();
+    con.write_response(responses::fresp::R_OKAY.to_owned())
+        .await?;
+    con.flush_stream().await?;
+    loop {
+        let line = tokio::select! {
+            line = feed.recv() => line,
+            query = con.read_query() => match query {
+                Ok(QueryResult::Q(_)) | Ok(QueryResult::E(_)) => continue,
+                Ok(QueryResult::Empty) | Ok(QueryResult::TooLarge) => return Ok(()),
+                Err(e) => return Err(e.into()),
+            },
+            _ = terminator.receive_signal() => return Ok(()),
+        };
+        let line = match line {
+            Ok(line) => line,
+            Err(RecvError::Lagged(missed)) => {
+                Bytes::from(format!("... missed {} queries (monitor too slow)", missed))
+            }
+            Err(RecvError::Closed) => return Ok(()),
+        };
+        con.write_response(GroupBegin(1)).await?;
+        con.write_response(BytesWrapper(line)).await?;
+        con.flush_stream().await?;
+    }
+}
+
+#[test]
+fn test_monitor_render() {
+    let time = Utc.timestamp(1_602_979_200, 1_500_000);
+    let act = ActionGroup::new(vec![
+        "SET".to_owned(),
+        "x".to_owned(),
+        "hello \"world\"".to_owned(),
+    ]);
+    assert_eq!(
+        render(time, Some("127.0.0.1:4000".parse().unwrap()), &act),
+        r#"1602979200.001500 [default 127.0.0.1:4000] "SET" "x" "hello \"world\"""#
+    );
+    assert_eq!(
+        render(time, None, &act),
+        r#"1602979200.001500 [default unknown] "SET" "x" "hello \"world\"""#
+    );
+}
+
+#[test]
+fn test_monitor_publishes_only_when_attached() {
+    let monitor = Monitor::new();
+    let query = Query::Simple(ActionGroup::new(vec!["GET".to_owned(), "x".to_owned()]));
+    assert!(!monitor.is_attached());
+    // Nobody's listening, so this shouldn't go anywhere
+    monitor.publish(&query, None);
+    let mut feed = monitor.subscribe();
+    assert!(monitor.is_attached());
+    monitor.publish(&query, None);
+    let line = feed.try_recv().unwrap();
+    assert!(line.ends_with(br#"[default unknown] "GET" "x""#));
+    assert!(feed.try_recv().is_err());
+    drop(feed);
+    assert!(!monitor.is_attached());
+}
+
+#[test]
+fn test_is_monitor() {
+    let q = |args: &[&str]| {
+        Query::Simple(ActionGroup::new(
+            args.iter().map(|arg| arg.to_string()).collect(),
+        ))
+    };
+    assert!(is_monitor(&q(&["MONITOR"])));
+    assert!(is_monitor(&q(&["monitor"])));
+    assert!(!is_monitor(&q(&["GET", "MONITOR"])));
+}
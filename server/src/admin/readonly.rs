@@ -0,0 +1,126 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # The `READONLY` action
+//!
+//! In read-only mode, the server refuses every action that changes the data with a
+//! _read-only error_ (code `8`), while the actions that only read the data keep
+//! working. `READONLY ON` switches the server into read-only mode, `READONLY OFF`
+//! switches it back and `READONLY` returns `ON` or `OFF`.
+//!
+//! `READONLY ON` only returns once the writes that were already running are done,
+//! so that no more changes are made to the data after it returns (a _write fence_)
+
+use crate::coredb::CoreDB;
+use crate::protocol::{responses, ActionGroup, Connection};
+use crate::resp::{BytesWrapper, GroupBegin};
+use bytes::Bytes;
+use libtdb::TResult;
+use tokio::sync::{RwLock, RwLockReadGuard};
+
+/// Switches the server in and out of read-only mode
+#[derive(Debug)]
+pub struct ReadOnlySwitch {
+    /// Whether the server is in read-only mode. Every write holds a read lock on this
+    /// while it runs, so that switching modes waits for the running writes
+    readonly: RwLock<bool>,
+}
+
+impl ReadOnlySwitch {
+    /// Create a new `ReadOnlySwitch`, which starts in read-only mode if `readonly` is set
+    pub fn new(readonly: bool) -> Self {
+        ReadOnlySwitch {
+            readonly: RwLock::new(readonly),
+        }
+    }
+    /// Returns true if the server is in read-only mode
+    pub async fn is_on(&self) -> bool {
+        *self.readonly.read().await
+    }
+    /// Switch read-only mode on or off, after the running writes are done
+    pub async fn set(&self, readonly: bool) {
+        *self.readonly.write().await = readonly;
+    }
+    /// Returns a permit to run a write, or `None` if the server is in read-only mode
+    ///
+    /// The mode can't be switched until the permit is dropped
+    pub async fn permit_write(&self) -> Option<RwLockReadGuard<'_, bool>> {
+        let permit = self.readonly.read().await;
+        if *permit {
+            None
+        } else {
+            Some(permit)
+        }
+    }
+}
+
+/// Run a `READONLY` query
+pub async fn readonly(handle: &CoreDB, con: &mut Connection, act: ActionGroup) -> TResult<()> {
+    let howmany = act.howmany();
+    let switch = &handle.shared.readonly;
+    let readonly = match (act.into_iter().next(), howmany) {
+        (None, 0) => {
+            let mode = if switch.is_on().await { "ON" } else { "OFF" };
+            con.write_response(GroupBegin(1)).await?;
+            return con
+                .write_response(BytesWrapper(Bytes::from_static(mode.as_bytes())))
+                .await;
+        }
+        (Some(mode), 1) if mode.eq_ignore_ascii_case("ON") => true,
+        (Some(mode), 1) if mode.eq_ignore_ascii_case("OFF") => false,
+        _ => {
+            return con
+                .write_response(responses::fresp::R_ACTION_ERR.to_owned())
+                .await
+        }
+    };
+    switch.set(readonly).await;
+    // Keep the effective configuration in line, for `CONFIG GET` and `CONFIG REWRITE`
+    handle.shared.config.write().readonly = readonly;
+    if let Ok(peer) = con.get_peer() {
+        log::info!(
+            "Read-only mode switched {} by client {}",
+            if readonly { "on" } else { "off" },
+            peer
+        );
+    }
+    con.write_response(responses::fresp::R_OKAY.to_owned())
+        .await
+}
+
+#[tokio::test]
+async fn test_readonly_switch() {
+    let switch = ReadOnlySwitch::new(false);
+    let permit = switch.permit_write().await;
+    assert!(permit.is_some());
+    // Switching modes waits for the running write
+    let set = switch.set(true);
+    tokio::pin!(set);
+    assert!(
+        tokio::time::timeout(std::time::Duration::from_millis(50), &mut set)
+            .await
+            .is_err()
+    );
+    drop(permit);
+    set.await;
+    assert!(switch.is_on().await);
+    assert!(switch.permit_write().await.is_none());
+}
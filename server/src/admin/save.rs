@@ -0,0 +1,190 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # The `SAVE`, `BGSAVE` and `LASTSAVE` actions
+//!
+//! These actions dump the in-memory table to disk on demand, instead of waiting for the
+//! BGSAVE timer or for the server to shut down:
+//! - `SAVE` saves the data before replying
+//! - `BGSAVE` wakes up the BGSAVE task and replies immediately
+//! - `LASTSAVE` returns the UNIX timestamp of the last successful save and whether the
+//!   most recent save succeeded
+//!
+//! Only one save can run at a time, since two saves would be writing to the same file
+
+use crate::coredb::CoreDB;
+use crate::protocol::{responses, ActionGroup, Connection};
+use crate::resp::{BytesWrapper, GroupBegin};
+use bytes::Bytes;
+use libtdb::TResult;
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Keeps track of saves to the dump file
+#[derive(Debug)]
+pub struct SaveTracker {
+    /// Whether a save is running right now
+    in_progress: AtomicBool,
+    /// The outcome of the previous saves
+    last: Mutex<LastSave>,
+}
+
+/// The outcome of the previous saves
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct LastSave {
+    /// The UNIX timestamp of the last successful save
+    success_at: Option<u64>,
+    /// Whether the most recent save succeeded
+    okay: Option<bool>,
+}
+
+/// Marks a save as running, until it is dropped
+#[derive(Debug)]
+pub struct SaveGuard<'a> {
+    tracker: &'a SaveTracker,
+}
+
+impl<'a> Drop for SaveGuard<'a> {
+    fn drop(&mut self) {
+        self.tracker.in_progress.store(false, Ordering::SeqCst);
+    }
+}
+
+impl SaveTracker {
+    /// Create a new `SaveTracker`
+    pub fn new() -> Self {
+        SaveTracker {
+            in_progress: AtomicBool::new(false),
+            last: Mutex::new(LastSave::default()),
+        }
+    }
+    /// Mark a save as running, returning `None` if another save is already running
+    pub fn try_begin(&self) -> Option<SaveGuard<'_>> {
+        self.in_progress
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .ok()
+            .map(|_| SaveGuard { tracker: self })
+    }
+    /// Check if a save is running right now
+    pub fn is_running(&self) -> bool {
+        self.in_progress.load(Ordering::SeqCst)
+    }
+    /// Record the outcome of a save that has just finished
+    pub fn finish(&self, okay: bool) {
+        let mut last = self.last.lock();
+        last.okay = Some(okay);
+        if okay {
+            last.success_at = Some(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0),
+            );
+        }
+    }
+    /// Returns the outcome of the previous saves
+    fn last(&self) -> LastSave {
+        *self.last.lock()
+    }
+}
+
+/// Run a `SAVE` query
+pub async fn save(handle: &CoreDB, con: &mut Connection, act: ActionGroup) -> TResult<()> {
+    if act.howmany() != 0 {
+        return con
+            .write_response(responses::fresp::R_ACTION_ERR.to_owned())
+            .await;
+    }
+    // The error can't be held across an `await`, so pick the response first
+    let response = match handle.shared.save() {
+        Some(Ok(())) => {
+            log::info!("SAVE completed successfully");
+            responses::fresp::R_OKAY.to_owned()
+        }
+        Some(Err(e)) => {
+            log::error!("SAVE failed with error: '{}'", e);
+            responses::fresp::R_SERVER_ERR.to_owned()
+        }
+        None => responses::fresp::R_SAVE_IN_PROGRESS.to_owned(),
+    };
+    con.write_response(response).await
+}
+
+/// Run a `BGSAVE` query
+pub async fn bgsave(handle: &CoreDB, con: &mut Connection, act: ActionGroup) -> TResult<()> {
+    if act.howmany() != 0 {
+        return con
+            .write_response(responses::fresp::R_ACTION_ERR.to_owned())
+            .await;
+    }
+    if handle.shared.saves.is_running() {
+        return con
+            .write_response(responses::fresp::R_SAVE_IN_PROGRESS.to_owned())
+            .await;
+    }
+    handle.shared.bgsave_task.notify();
+    con.write_response(responses::fresp::R_OKAY.to_owned())
+        .await
+}
+
+/// Run a `LASTSAVE` query
+///
+/// This returns the timestamp (0 if nothing has been saved yet) followed by the status
+/// of the most recent save: `ok`, `err` or `none`
+pub async fn lastsave(handle: &CoreDB, con: &mut Connection, act: ActionGroup) -> TResult<()> {
+    if act.howmany() != 0 {
+        return con
+            .write_response(responses::fresp::R_ACTION_ERR.to_owned())
+            .await;
+    }
+    let last = handle.shared.saves.last();
+    let status = match last.okay {
+        Some(true) => "ok",
+        Some(false) => "err",
+        None => "none",
+    };
+    con.write_response(GroupBegin(2)).await?;
+    con.write_response(last.success_at.unwrap_or(0) as usize)
+        .await?;
+    con.write_response(BytesWrapper(Bytes::from_static(status.as_bytes())))
+        .await
+}
+
+#[test]
+fn test_save_tracker() {
+    let tracker = SaveTracker::new();
+    assert_eq!(tracker.last(), LastSave::default());
+    let guard = tracker.try_begin().unwrap();
+    assert!(tracker.is_running());
+    assert!(tracker.try_begin().is_none());
+    tracker.finish(true);
+    drop(guard);
+    assert!(!tracker.is_running());
+    let last = tracker.last();
+    assert_eq!(last.okay, Some(true));
+    assert!(last.success_at.unwrap() > 0);
+    // A failed save keeps the time of the last successful one
+    let _guard = tracker.try_begin().unwrap();
+    tracker.finish(false);
+    assert_eq!(tracker.last().okay, Some(false));
+    assert_eq!(tracker.last().success_at, last.success_at);
+}
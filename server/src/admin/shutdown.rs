@@ -0,0 +1,91 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # The `SHUTDOWN` action
+//!
+//! `SHUTDOWN` shuts the server down just like a termination signal would, and
+//! `SHUTDOWN SAVE` is the same thing. `SHUTDOWN NOSAVE` shuts the server down without
+//! saving the in-memory table to disk
+
+use crate::coredb::CoreDB;
+use crate::protocol::{responses, ActionGroup, Connection};
+use libtdb::TResult;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::Notify;
+
+/// Lets clients ask the server to shut down
+#[derive(Debug)]
+pub struct ShutdownSwitch {
+    /// Notified when a shutdown has been requested
+    requested: Notify,
+    /// Whether the data should be saved before shutting down
+    save: AtomicBool,
+}
+
+impl ShutdownSwitch {
+    /// Create a new `ShutdownSwitch`
+    pub fn new() -> Self {
+        ShutdownSwitch {
+            requested: Notify::new(),
+            save: AtomicBool::new(true),
+        }
+    }
+    /// Ask the server to shut down
+    fn request(&self, save: bool) {
+        self.save.store(save, Ordering::SeqCst);
+        self.requested.notify();
+    }
+    /// Wait until a shutdown is requested, returning `true` if the data should be
+    /// saved before shutting down
+    pub async fn requested(&self) -> bool {
+        self.requested.notified().await;
+        self.save.load(Ordering::SeqCst)
+    }
+}
+
+/// Run a `SHUTDOWN` query
+pub async fn shutdown(handle: &CoreDB, con: &mut Connection, act: ActionGroup) -> TResult<()> {
+    let howmany = act.howmany();
+    let save = match (act.into_iter().next(), howmany) {
+        (None, 0) => true,
+        (Some(mode), 1) if mode.eq_ignore_ascii_case("SAVE") => true,
+        (Some(mode), 1) if mode.eq_ignore_ascii_case("NOSAVE") => false,
+        _ => {
+            return con
+                .write_response(responses::fresp::R_ACTION_ERR.to_owned())
+                .await
+        }
+    };
+    if let Ok(peer) = con.get_peer() {
+        log::info!("Shutdown requested by client {}", peer);
+    }
+    handle.shared.shutdown.request(save);
+    con.write_response(responses::fresp::R_OKAY.to_owned())
+        .await
+}
+
+#[tokio::test]
+async fn test_shutdown_switch() {
+    let switch = ShutdownSwitch::new();
+    // The request is remembered even if nobody is waiting for it yet
+    switch.request(false);
+    assert!(!switch.requested().await);
+}
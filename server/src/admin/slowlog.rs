@@ -0,0 +1,354 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # The slow query log
+//!
+//! Every action run by `queryengine::execute_simple` is timed, and the ones that take
+//! longer than the configured threshold end up in a bounded, in-memory ring. Once the ring is
+//! full, the oldest entry is evicted to make room for the new one.
+//!
+//! The log is accessed with the `SLOWLOG` action:
+//! - `SLOWLOG GET [count]` returns the `count` (10 by default) most recent entries, newest first
+//! - `SLOWLOG LEN` returns the number of entries in the log
+//! - `SLOWLOG RESET` clears the log
+//! - `SLOWLOG MAXLEN` returns the maximum number of entries, and `SLOWLOG MAXLEN <n>`
+//!   resizes the ring to `n` entries, evicting the oldest ones if there are more
+
+use crate::config::SlowlogConfig;
+use crate::coredb::CoreDB;
+use crate::protocol::{responses, ActionGroup, Connection};
+use crate::resp::{BytesWrapper, GroupBegin};
+use bytes::Bytes;
+use chrono::prelude::*;
+use libtdb::TResult;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// The maximum number of arguments that we'll keep for an entry
+const MAX_ARGS: usize = 32;
+/// The maximum number of bytes that we'll keep for each argument
+const MAX_ARG_LEN: usize = 128;
+/// The number of entries returned by `SLOWLOG GET` without a count
+const DEF_GET_COUNT: usize = 10;
+
+/// A truncated copy of a query
+///
+/// Queries can be huge, so we only keep the first `MAX_ARGS` arguments and the first
+/// `MAX_ARG_LEN` bytes of each argument
+#[derive(Debug, PartialEq, Clone)]
+pub struct QuerySummary(Vec<String>);
+
+impl QuerySummary {
+    /// Create a summary of the action group `act`
+    pub fn new(act: &ActionGroup) -> Self {
+        let args = act.get_ref();
+        let mut summary: Vec<String> = args
+            .iter()
+            .take(MAX_ARGS)
+            .map(|arg| {
+                if arg.len() > MAX_ARG_LEN {
+                    // Don't split a multi-byte character in half
+                    let mut end = MAX_ARG_LEN;
+                    while !arg.is_char_boundary(end) {
+                        end -= 1;
+                    }
+                    format!("{}... ({} more bytes)", &arg[..end], arg.len() - end)
+                } else {
+                    arg.clone()
+                }
+            })
+            .collect();
+        if args.len() > MAX_ARGS {
+            summary.push(format!("... ({} more arguments)", args.len() - MAX_ARGS));
+        }
+        QuerySummary(summary)
+    }
+}
+
+/// An entry in the slowlog
+#[derive(Debug)]
+struct SlowlogEntry {
+    /// A unique, increasing ID for the entry
+    id: u64,
+    /// When the action finished running
+    timestamp: DateTime<Utc>,
+    /// How long the action took
+    duration: Duration,
+    /// The client that ran the action (if we could find out who it was)
+    client: Option<SocketAddr>,
+    /// The action and its (truncated) arguments
+    query: QuerySummary,
+}
+
+impl SlowlogEntry {
+    /// Render the entry as a single line of text
+    fn render(&self) -> String {
+        format!(
+            "id={} time={} duration_us={} client={} query={}",
+            self.id,
+            self.timestamp.to_rfc3339_opts(SecondsFormat::Micros, true),
+            self.duration.as_micros(),
+            self.client
+                .map(|c| c.to_string())
+                .unwrap_or_else(|| "unknown".to_owned()),
+            self.query.0.join(" ")
+        )
+    }
+}
+
+#[derive(Debug)]
+struct Ring {
+    /// The entries, oldest first
+    entries: VecDeque<SlowlogEntry>,
+    /// The ID to be given to the next entry
+    next_id: u64,
+}
+
+/// The slow query log
+#[derive(Debug)]
+pub struct Slowlog {
+    /// Actions which take longer than `threshold` microseconds are logged
+    threshold: AtomicU64,
+    /// The maximum number of entries to keep
+    maxlen: AtomicUsize,
+    /// The ring of entries
+    ring: Mutex<Ring>,
+}
+
+impl Slowlog {
+    /// Create a new slowlog from the configuration
+    pub fn new(cfg: SlowlogConfig) -> Self {
+        let (threshold, maxlen) = cfg.decompose();
+        Slowlog {
+            threshold: AtomicU64::new(threshold),
+            maxlen: AtomicUsize::new(maxlen),
+            ring: Mutex::new(Ring {
+                entries: VecDeque::new(),
+                next_id: 0,
+            }),
+        }
+    }
+    /// Apply a new configuration, keeping the entries that are already in the log
+    ///
+    /// If the log now has more entries than `maxlen`, the oldest ones are evicted
+    pub fn reconfigure(&self, cfg: SlowlogConfig) {
+        let (threshold, maxlen) = cfg.decompose();
+        self.threshold.store(threshold, Ordering::Relaxed);
+        self.maxlen.store(maxlen, Ordering::Relaxed);
+        let mut ring = self.ring.lock();
+        let excess = ring.entries.len().saturating_sub(maxlen);
+        ring.entries.drain(..excess);
+    }
+    /// Returns the maximum number of entries to keep
+    pub fn maxlen(&self) -> usize {
+        self.maxlen.load(Ordering::Relaxed)
+    }
+    /// Returns `true` if anything can be logged at all
+    pub fn is_enabled(&self) -> bool {
+        self.maxlen.load(Ordering::Relaxed) != 0
+    }
+    /// Log the query, if `duration` exceeds the threshold
+    pub fn record(&self, query: QuerySummary, duration: Duration, client: Option<SocketAddr>) {
+        if (duration.as_micros() as u64) < self.threshold.load(Ordering::Relaxed) {
+            return;
+        }
+        let maxlen = self.maxlen.load(Ordering::Relaxed);
+        if maxlen == 0 {
+            return;
+        }
+        let mut ring = self.ring.lock();
+        while ring.entries.len() >= maxlen {
+            ring.entries.pop_front();
+        }
+        let id = ring.next_id;
+        ring.next_id += 1;
+        ring.entries.push_back(SlowlogEntry {
+            id,
+            timestamp: Utc::now(),
+            duration,
+            client,
+            query,
+        });
+    }
+    /// Returns the number of entries in the log
+    pub fn count(&self) -> usize {
+        self.ring.lock().entries.len()
+    }
+    /// Remove all the entries from the log
+    pub fn reset(&self) {
+        self.ring.lock().entries.clear()
+    }
+    /// Returns the `count` most recent entries (newest first), rendered as text
+    fn latest(&self, count: usize) -> Vec<String> {
+        self.ring
+            .lock()
+            .entries
+            .iter()
+            .rev()
+            .take(count)
+            .map(|entry| entry.render())
+            .collect()
+    }
+}
+
+/// Run a `SLOWLOG` query
+pub async fn slowlog(handle: &CoreDB, con: &mut Connection, act: ActionGroup) -> TResult<()> {
+    let howmany = act.howmany();
+    let mut args = act.into_iter();
+    let subaction = args.next().map(|arg| arg.to_uppercase());
+    match (subaction.as_deref(), howmany) {
+        (Some("GET"), 1) | (Some("GET"), 2) => {
+            let count = match args.next() {
+                Some(count) => match count.parse::<usize>() {
+                    Ok(count) => count,
+                    Err(_) => {
+                        return con
+                            .write_response(responses::fresp::R_ACTION_ERR.to_owned())
+                            .await
+                    }
+                },
+                None => DEF_GET_COUNT,
+            };
+            let entries = handle.shared.slowlog.latest(count);
+            if entries.is_empty() {
+                return con.write_response(responses::fresp::R_NIL.to_owned()).await;
+            }
+            con.write_response(GroupBegin(entries.len())).await?;
+            for entry in entries {
+                con.write_response(BytesWrapper(Bytes::from(entry))).await?;
+            }
+            Ok(())
+        }
+        (Some("LEN"), 1) => {
+            let len = handle.shared.slowlog.count();
+            con.write_response(GroupBegin(1)).await?;
+            con.write_response(len).await
+        }
+        (Some("MAXLEN"), 1) => {
+            let maxlen = handle.shared.slowlog.maxlen();
+            con.write_response(GroupBegin(1)).await?;
+            con.write_response(maxlen).await
+        }
+        (Some("MAXLEN"), 2) => {
+            // `howmany` says that there's another argument
+            let maxlen = args.next().unwrap_or_default();
+            if maxlen.parse::<usize>().is_err() {
+                return con
+                    .write_response(responses::fresp::R_ACTION_ERR.to_owned())
+                    .await;
+            }
+            // This is the same as `CONFIG SET slowlog.maxlen <n>`, so that `CONFIG GET`
+            // and `CONFIG REWRITE` see the new size
+            let changes = [("slowlog.maxlen".to_owned(), maxlen)];
+            match handle.shared.set_settings(&changes).await {
+                Ok(()) => {
+                    con.write_response(responses::fresp::R_OKAY.to_owned())
+                        .await
+                }
+                Err(e) => con.write_response(responses::other_error(&e)).await,
+            }
+        }
+        (Some("RESET"), 1) => {
+            handle.shared.slowlog.reset();
+            con.write_response(responses::fresp::R_OKAY.to_owned())
+                .await
+        }
+        _ => {
+            con.write_response(responses::fresp::R_ACTION_ERR.to_owned())
+                .await
+        }
+    }
+}
+
+#[cfg(test)]
+fn summary_of(query: &str) -> QuerySummary {
+    QuerySummary(query.split_whitespace().map(|s| s.to_owned()).collect())
+}
+
+#[test]
+fn test_slowlog_threshold_and_eviction() {
+    let log = Slowlog::new(SlowlogConfig::new(100, 2));
+    log.record(summary_of("GET x"), Duration::from_micros(99), None);
+    assert_eq!(log.count(), 0);
+    log.record(summary_of("SET x 1"), Duration::from_micros(100), None);
+    log.record(summary_of("SET y 2"), Duration::from_micros(150), None);
+    log.record(summary_of("SET z 3"), Duration::from_micros(200), None);
+    assert_eq!(log.count(), 2);
+    let latest = log.latest(10);
+    assert!(latest[0].starts_with("id=2 "));
+    assert!(latest[0].ends_with("duration_us=200 client=unknown query=SET z 3"));
+    assert!(latest[1].starts_with("id=1 "));
+    assert_eq!(log.latest(1).len(), 1);
+    log.reset();
+    assert_eq!(log.count(), 0);
+}
+
+#[test]
+fn test_slowlog_resize() {
+    let log = Slowlog::new(SlowlogConfig::new(0, 4));
+    for i in 0..4 {
+        log.record(
+            summary_of(&format!("SET x {}", i)),
+            Duration::from_micros(1),
+            None,
+        );
+    }
+    assert_eq!(log.count(), 4);
+    // Shrinking the ring evicts the oldest entries right away
+    log.reconfigure(SlowlogConfig::new(0, 2));
+    assert_eq!(log.maxlen(), 2);
+    assert_eq!(log.count(), 2);
+    let latest = log.latest(10);
+    assert!(latest[0].starts_with("id=3 "));
+    assert!(latest[1].starts_with("id=2 "));
+    // Growing it keeps the entries and makes room for more
+    log.reconfigure(SlowlogConfig::new(0, 3));
+    log.record(summary_of("SET y 1"), Duration::from_micros(1), None);
+    log.record(summary_of("SET z 1"), Duration::from_micros(1), None);
+    assert_eq!(log.count(), 3);
+    assert!(log.latest(1)[0].ends_with("query=SET z 1"));
+}
+
+#[test]
+fn test_slowlog_disabled() {
+    let log = Slowlog::new(SlowlogConfig::new(0, 0));
+    assert!(!log.is_enabled());
+    log.record(summary_of("GET x"), Duration::from_secs(1), None);
+    assert_eq!(log.count(), 0);
+}
+
+#[test]
+fn test_query_summary_truncation() {
+    let long = "x".repeat(MAX_ARG_LEN + 10);
+    let mut query = vec![String::from("MSET")];
+    query.extend((0..MAX_ARGS + 4).map(|_| long.clone()));
+    let summary = QuerySummary::new(&ActionGroup::new(query));
+    assert_eq!(summary.0.len(), MAX_ARGS + 1);
+    assert_eq!(summary.0[0], "MSET");
+    assert_eq!(
+        summary.0[1],
+        format!("{}... (10 more bytes)", "x".repeat(MAX_ARG_LEN))
+    );
+    assert_eq!(summary.0[MAX_ARGS], "... (5 more arguments)");
+}
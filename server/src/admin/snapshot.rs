@@ -0,0 +1,164 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # The `SNAPSHOT` action
+//!
+//! `SNAPSHOT` manages the snapshots in the snapshot directory:
+//! - `SNAPSHOT CREATE [name]` creates a snapshot and returns its name. Snapshots created
+//!   without a name are rotated out just like the ones created by the snapshot service
+//! - `SNAPSHOT LIST` returns one line for each snapshot, with its size and the time it
+//!   was written, oldest first
+//! - `SNAPSHOT DELETE <name>` deletes a snapshot
+//! - `SNAPSHOT RESTORE <name>` replaces all the data in the database with the data in
+//!   a snapshot. The restored data is written to the dump file by the next save. Replicas
+//!   can't restore snapshots, since all their data comes from the primary
+
+use crate::coredb::{CoreDB, Shared};
+use crate::diskstore::snapshot;
+use crate::protocol::{responses, ActionGroup, Connection};
+use crate::resp::{BytesWrapper, GroupBegin};
+use bytes::Bytes;
+use libtdb::TResult;
+
+/// The reply to a `SNAPSHOT` query
+enum Reply {
+    /// A complete, pre-compiled response
+    Code(Vec<u8>),
+    /// A group of strings
+    Strings(Vec<String>),
+}
+
+/// Run a `SNAPSHOT` query
+pub async fn snapshot(handle: &CoreDB, con: &mut Connection, act: ActionGroup) -> TResult<()> {
+    let howmany = act.howmany();
+    let mut args = act.into_iter();
+    let subaction = args.next().map(|arg| arg.to_uppercase());
+    let name = args.next();
+    let shared = &handle.shared;
+    // The errors from the snapshot engine can't be held across an `await`, so we
+    // work out the reply before writing anything
+    let reply = match (subaction.as_deref(), howmany, name) {
+        (Some("CREATE"), 1, None) => create(shared, None),
+        (Some("CREATE"), 2, Some(name)) => create(shared, Some(name)),
+        (Some("LIST"), 1, None) => list(shared),
+        (Some("DELETE"), 2, Some(name)) => delete(shared, name),
+        (Some("RESTORE"), 2, Some(name)) => restore(shared, name),
+        _ => Reply::Code(responses::fresp::R_ACTION_ERR.to_owned()),
+    };
+    match reply {
+        Reply::Code(code) => con.write_response(code).await,
+        Reply::Strings(strings) => {
+            con.write_response(GroupBegin(strings.len())).await?;
+            for string in strings {
+                con.write_response(BytesWrapper(Bytes::from(string)))
+                    .await?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn create(shared: &Shared, name: Option<String>) -> Reply {
+    if let Some(name) = &name {
+        // Timestamps are reserved for the snapshots in the rotation
+        if !snapshot::is_valid_name(name) || snapshot::is_unnamed(name) {
+            return Reply::Code(responses::fresp::R_ACTION_ERR.to_owned());
+        }
+    }
+    let mut engine = shared.snapshots.lock();
+    if let Some(name) = &name {
+        // Don't overwrite someone else's snapshot
+        if engine.exists(name) {
+            return Reply::Code(responses::fresp::R_OVERWRITE_ERR.to_owned());
+        }
+    }
+    match engine.create(shared, name.as_deref()) {
+        Some(Ok(snapname)) => {
+            log::info!("Created snapshot '{}'", snapname);
+            Reply::Strings(vec![snapname])
+        }
+        Some(Err(e)) => {
+            log::error!("Failed to create snapshot with error: '{}'", e);
+            Reply::Code(responses::fresp::R_SERVER_ERR.to_owned())
+        }
+        // The database is shutting down
+        None => Reply::Code(responses::fresp::R_SERVER_ERR.to_owned()),
+    }
+}
+
+fn list(shared: &Shared) -> Reply {
+    match shared.snapshots.lock().list() {
+        Ok(snapshots) if snapshots.is_empty() => {
+            Reply::Code(responses::fresp::R_NIL.to_owned())
+        }
+        Ok(snapshots) => Reply::Strings(
+            snapshots
+                .into_iter()
+                .map(|snapshot| {
+                    format!(
+                        "name={} size={} time={}",
+                        snapshot.name,
+                        snapshot.size,
+                        snapshot.modified.format("%Y-%m-%dT%H:%M:%SZ")
+                    )
+                })
+                .collect(),
+        ),
+        Err(e) => {
+            log::error!("Failed to list snapshots with error: '{}'", e);
+            Reply::Code(responses::fresp::R_SERVER_ERR.to_owned())
+        }
+    }
+}
+
+fn delete(shared: &Shared, name: String) -> Reply {
+    if !snapshot::is_valid_name(&name) {
+        return Reply::Code(responses::fresp::R_NIL.to_owned());
+    }
+    match shared.snapshots.lock().delete(&name) {
+        Ok(true) => {
+            log::info!("Deleted snapshot '{}'", name);
+            Reply::Code(responses::fresp::R_OKAY.to_owned())
+        }
+        Ok(false) => Reply::Code(responses::fresp::R_NIL.to_owned()),
+        Err(e) => {
+            log::error!("Failed to delete snapshot '{}' with error: '{}'", name, e);
+            Reply::Code(responses::fresp::R_SERVER_ERR.to_owned())
+        }
+    }
+}
+
+fn restore(shared: &Shared, name: String) -> Reply {
+    if !snapshot::is_valid_name(&name) {
+        return Reply::Code(responses::fresp::R_NIL.to_owned());
+    }
+    match shared.snapshots.lock().restore(shared, &name) {
+        Ok(true) => {
+            log::warn!("Replaced all data with the data in snapshot '{}'", name);
+            Reply::Code(responses::fresp::R_OKAY.to_owned())
+        }
+        Ok(false) => Reply::Code(responses::fresp::R_NIL.to_owned()),
+        Err(e) => {
+            log::error!("Failed to restore snapshot '{}' with error: '{}'", name, e);
+            Reply::Code(responses::fresp::R_SERVER_ERR.to_owned())
+        }
+    }
+}
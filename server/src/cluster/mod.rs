@@ -0,0 +1,787 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # Cluster
+//!
+//! In cluster mode, the keys are split into hash slots (see [`libtdb::cluster`]) and
+//! every node of the cluster serves some of the slots, so that the data can be larger
+//! than what a single node can hold. Every node keeps a map of the node that serves each
+//! slot, which is saved in the data directory. A query for keys in a slot that another
+//! node serves gets a redirect to that node, and a query for keys in several slots is
+//! rejected, since the slots could be served by different nodes.
+//!
+//! Slots are moved to another node with `CLUSTER MIGRATE`, while the nodes keep serving
+//! queries. The node that serves the slots stops accepting writes to them (and writes
+//! without keys, like `FLUSHDB`), waits for the running writes and tells the new node
+//! that they are coming with `CLUSTER IMPORTING`. It then copies their keys to the new
+//! node in batches: each batch is a `CLUSTER IMPORT <len>` query, and once the new node
+//! replies, `len` bytes with the keys in the format of the dump file. The new node only
+//! takes batches that are no larger than the largest query that it accepts, with keys in
+//! the slots that are coming. Once all the keys were copied, the new node is told to
+//! serve the slots with `CLUSTER SETSLOT`, and the old node removes the keys and
+//! redirects to the new node from then on. The other nodes are then told about the new
+//! node with `CLUSTER SETSLOT` too, and until they hear about it, they redirect to the
+//! old node, which redirects to the new node.
+//!
+//! Nodes have to be given the addresses that they were configured with (`addr` in the
+//! `[cluster]` section), since that's how a node knows that it is the one that serves
+//! a slot
+
+use crate::admin::auth;
+use crate::config::{ClusterNode, StorageConfig};
+use crate::coredb::{CoreDB, Data};
+use crate::diskstore::encryption::Keyring;
+use crate::diskstore::format;
+use crate::protocol::{responses, ActionGroup, Connection};
+use crate::resp::{BytesWrapper, GroupBegin};
+use bytes::Bytes;
+use libtdb::cluster::{self as slots, SlotMap, SLOTS};
+use libtdb::terrapipe;
+use libtdb::TResult;
+use parking_lot::{Mutex, RwLock};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Write};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::{RwLock as AsyncRwLock, RwLockReadGuard};
+use tokio::time::{self, Duration};
+
+/// The most keys that are copied to the new node in one batch during a migration
+const BATCH_SIZE: usize = 1000;
+/// The size of a batch (as a fraction of the largest query) that we stop adding keys at,
+/// which leaves room for the encoding of the keys
+const BATCH_SHARE: usize = 2;
+/// How long we wait for another node to accept a connection or to reply
+const NODE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The cluster state of a node
+#[derive(Debug)]
+pub struct Cluster {
+    /// The `host:port` at which this node is reached
+    addr: String,
+    /// The node that serves each slot
+    map: RwLock<SlotMap>,
+    /// The slots that are being migrated away from this node, which can't be written to
+    migrating: Mutex<HashSet<u16>>,
+    /// Every write holds a read lock on this while it runs, so that a migration can wait
+    /// for the writes that were let through before its slots were marked
+    fence: AsyncRwLock<()>,
+    /// The slots that are being migrated to this node, whose keys can be imported
+    importing: Mutex<HashSet<u16>>,
+    /// Where the slot map is saved
+    path: PathBuf,
+    /// The password that is sent with `AUTH` to the other nodes
+    password: Option<String>,
+}
+
+impl Cluster {
+    /// Create a new `Cluster` instance for the node at `addr`, which sends `password` to
+    /// the other nodes if it is set
+    ///
+    /// The slot map is read from the data directory. If there's no slot map yet, it is
+    /// made from the slots of the `nodes`
+    pub fn new(
+        addr: String,
+        nodes: Vec<ClusterNode>,
+        password: Option<String>,
+        storage: &StorageConfig,
+    ) -> TResult<Self> {
+        let path = storage.cluster_path();
+        let map = match fs::read_to_string(&path) {
+            Ok(saved) => decode_map(&saved)
+                .ok_or_else(|| format!("The slot map in '{}' is damaged", path.display()))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                let mut map = SlotMap::new();
+                for node in nodes {
+                    for range in &node.slots {
+                        let range = slots::parse_slots(range).ok_or_else(|| {
+                            format!("'{}' of node {} isn't a valid slot range", range, node.addr)
+                        })?;
+                        map.assign(range, &node.addr);
+                    }
+                }
+                map
+            }
+            Err(e) => return Err(format!("Failed to read the slot map: {}", e).into()),
+        };
+        let served: usize = map
+            .ranges()
+            .into_iter()
+            .filter(|(_, owner)| *owner == addr)
+            .map(|(range, _)| range.len())
+            .sum();
+        log::info!(
+            "Cluster node {} serves {} of the {} slots",
+            addr,
+            served,
+            SLOTS
+        );
+        Ok(Cluster {
+            addr,
+            map: RwLock::new(map),
+            migrating: Mutex::new(HashSet::new()),
+            fence: AsyncRwLock::new(()),
+            importing: Mutex::new(HashSet::new()),
+            path,
+            password,
+        })
+    }
+    /// Check that this node can run the query with `args` (which start with the action),
+    /// where `write` is set if the action changes the data
+    ///
+    /// Writes get a permit, which has to be held until the write is done, so that a
+    /// migration doesn't start copying keys while the write is running. The error is the
+    /// response to send instead, which is a redirect if the keys are in a slot that
+    /// another node serves
+    pub async fn route(
+        &self,
+        args: &[String],
+        write: bool,
+    ) -> Result<Option<RwLockReadGuard<'_, ()>>, Vec<u8>> {
+        // Take the permit before looking at the migrating slots, since a migration marks
+        // its slots before it waits for the running writes
+        let permit = if write {
+            Some(self.fence.read().await)
+        } else {
+            None
+        };
+        let mut keys = slots::query_keys(args);
+        let slot = match keys.next() {
+            Some(key) => slots::key_slot(key),
+            None => {
+                let cluster = args
+                    .first()
+                    .is_some_and(|action| action.eq_ignore_ascii_case("CLUSTER"));
+                // Actions like `FLUSHDB` change the keys of every slot
+                if write && !cluster && !self.migrating.lock().is_empty() {
+                    return Err(responses::other_error(
+                        "Slots are being migrated, try again later",
+                    ));
+                }
+                return Ok(permit);
+            }
+        };
+        if keys.any(|key| slots::key_slot(key) != slot) {
+            return Err(responses::fresp::R_CROSS_SLOT.to_owned());
+        }
+        match self.map.read().owner(slot) {
+            Some(owner) if owner == self.addr => {}
+            Some(owner) => return Err(responses::redirect(slot, owner)),
+            None => {
+                return Err(responses::other_error(&format!(
+                    "Slot {} isn't served by any node",
+                    slot
+                )))
+            }
+        }
+        if write && self.migrating.lock().contains(&slot) {
+            return Err(responses::other_error(&format!(
+                "Slot {} is being migrated, try again later",
+                slot
+            )));
+        }
+        Ok(permit)
+    }
+    /// Make the node at `addr` serve the `slots`, and save the slot map
+    ///
+    /// The slots aren't being migrated to this node any more, if they were
+    fn assign(&self, slots: RangeInclusive<u16>, addr: &str) {
+        let mut map = self.map.write();
+        self.importing.lock().retain(|slot| !slots.contains(slot));
+        map.assign(slots, addr);
+        if let Err(e) = self.save(&map) {
+            // The map is only read when the node starts, so this can wait until the next
+            // change is saved
+            log::error!("Failed to save the slot map with error: '{}'", e);
+        }
+    }
+    /// Let the keys in the `slots` be imported, since the slots are being migrated to
+    /// this node
+    fn begin_import(&self, slots: RangeInclusive<u16>) -> Result<(), String> {
+        let map = self.map.read();
+        if slots
+            .clone()
+            .any(|slot| map.owner(slot) == Some(self.addr.as_str()))
+        {
+            return Err("Some of the slots are already served by this node".to_owned());
+        }
+        self.importing.lock().extend(slots);
+        Ok(())
+    }
+    /// Save `map` to the data directory
+    fn save(&self, map: &SlotMap) -> io::Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(encode_map(map).as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp, &self.path)
+    }
+    /// Returns a line for each range of slots with the node that serves it
+    fn report(&self) -> Vec<String> {
+        self.map
+            .read()
+            .ranges()
+            .into_iter()
+            .map(|(range, owner)| {
+                let mut line = format!("{}-{} {}", range.start(), range.end(), owner);
+                if owner == self.addr {
+                    line.push_str(" (this node)");
+                }
+                line
+            })
+            .collect()
+    }
+    /// Move the `slots` to the node at `target`, returning the number of keys that
+    /// were moved
+    async fn migrate(
+        &self,
+        handle: &CoreDB,
+        slots: RangeInclusive<u16>,
+        target: String,
+    ) -> Result<usize, String> {
+        if target == self.addr {
+            return Err("The slots are already served by this node".to_owned());
+        }
+        if slots
+            .clone()
+            .any(|slot| self.map.read().owner(slot) != Some(self.addr.as_str()))
+        {
+            return Err("Some of the slots aren't served by this node".to_owned());
+        }
+        let _migration = Migration::begin(self, slots.clone())
+            .ok_or_else(|| "Some of the slots are already being migrated".to_owned())?;
+        // Writes that were let through before the slots were marked could still change
+        // their keys, so wait for them to finish before taking the keys
+        drop(self.fence.write().await);
+        let keys: Vec<String> = handle
+            .acquire_read()
+            .get_ref()
+            .keys()
+            .filter(|key| slots.contains(&slots::key_slot(key)))
+            .map(ToOwned::to_owned)
+            .collect();
+        let mut stream = connect(&target, self.password.as_deref()).await?;
+        let importing = terrapipe::proc_query(format!(
+            "CLUSTER IMPORTING {}-{}",
+            slots.start(),
+            slots.end()
+        ));
+        call(&mut stream, &importing).await?;
+        // The new node refuses batches that are larger than the largest query that it
+        // accepts, which we assume is the same as ours
+        let (_, _, max_query_size) = handle.shared.config.read().limits.decompose();
+        let max_batch_len = max_query_size / BATCH_SHARE;
+        let mut pending = keys.iter().peekable();
+        while pending.peek().is_some() {
+            let data = {
+                let table = handle.acquire_read();
+                let mut data = HashMap::new();
+                let mut len = 0;
+                while let Some(key) = pending.peek() {
+                    match table.get_ref().get(key) {
+                        Ok(Some(value)) => {
+                            let size = key.len() + value.get_blob().len();
+                            if data.len() == BATCH_SIZE
+                                || (!data.is_empty() && len + size > max_batch_len)
+                            {
+                                break;
+                            }
+                            len += size;
+                            data.insert((*key).clone(), value);
+                        }
+                        // The key is gone, so there's nothing to copy
+                        Ok(None) => {}
+                        Err(e) => return Err(format!("Failed to read '{}': {}", key, e)),
+                    }
+                    pending.next();
+                }
+                data
+            };
+            let dump = format::write_table(
+                Vec::new(),
+                handle.shared.storage.compression(),
+                &Keyring::none(),
+                &data,
+            )
+            .map_err(|e| format!("Failed to encode the keys: {}", e))?;
+            let import = terrapipe::proc_query(format!("CLUSTER IMPORT {}", dump.len()));
+            call(&mut stream, &import).await?;
+            call(&mut stream, &dump).await?;
+        }
+        let setslot = setslot_query(&slots, &target)?;
+        call(&mut stream, &setslot).await?;
+        // The new node serves the slots now, so we can let go of the keys
+        let moved = {
+            let mut table = handle.acquire_write();
+            self.assign(slots.clone(), &target);
+            let mut moved = 0;
+            for key in &keys {
+                match table.get_mut_ref().remove(key) {
+                    Ok(true) => {
+                        handle.shared.log_del(key);
+                        moved += 1;
+                    }
+                    Ok(false) => {}
+                    // The key is unreachable now, so this only wastes space
+                    Err(e) => log::error!("Failed to remove '{}' with error: '{}'", key, e),
+                }
+            }
+            moved
+        };
+        log::info!(
+            "Migrated slots {}-{} with {} key(s) to {}",
+            slots.start(),
+            slots.end(),
+            moved,
+            target
+        );
+        // Let the other nodes know, so that they don't redirect to us any more
+        let others: HashSet<String> = self
+            .map
+            .read()
+            .ranges()
+            .into_iter()
+            .map(|(_, owner)| owner.to_owned())
+            .filter(|owner| *owner != self.addr && *owner != target)
+            .collect();
+        for node in others {
+            let result = match connect(&node, self.password.as_deref()).await {
+                Ok(mut stream) => call(&mut stream, &setslot).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                log::warn!("Failed to tell {} about the migration: '{}'", node, e);
+            }
+        }
+        Ok(moved)
+    }
+}
+
+/// Marks some slots as being migrated, until it is dropped
+struct Migration<'a> {
+    cluster: &'a Cluster,
+    slots: RangeInclusive<u16>,
+}
+
+impl<'a> Migration<'a> {
+    /// Mark the `slots` as being migrated, returning `None` if some of them already are
+    fn begin(cluster: &'a Cluster, slots: RangeInclusive<u16>) -> Option<Self> {
+        let mut migrating = cluster.migrating.lock();
+        if slots.clone().any(|slot| migrating.contains(&slot)) {
+            return None;
+        }
+        migrating.extend(slots.clone());
+        Some(Migration { cluster, slots })
+    }
+}
+
+impl Drop for Migration<'_> {
+    fn drop(&mut self) {
+        let mut migrating = self.cluster.migrating.lock();
+        for slot in self.slots.clone() {
+            migrating.remove(&slot);
+        }
+    }
+}
+
+/// Encode `map` as a line for each range of slots with the node that serves it
+fn encode_map(map: &SlotMap) -> String {
+    map.ranges()
+        .into_iter()
+        .map(|(range, owner)| format!("{}-{} {}\n", range.start(), range.end(), owner))
+        .collect()
+}
+
+/// Decode a slot map that was encoded with `encode_map`
+fn decode_map(saved: &str) -> Option<SlotMap> {
+    let mut map = SlotMap::new();
+    for line in saved.lines() {
+        let mut parts = line.split(' ');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(range), Some(owner), None) => map.assign(slots::parse_slots(range)?, owner),
+            _ => return None,
+        }
+    }
+    Some(map)
+}
+
+/// Returns the `CLUSTER SETSLOT` query that makes the node at `target` serve the `slots`
+fn setslot_query(slots: &RangeInclusive<u16>, target: &str) -> Result<Vec<u8>, String> {
+    let (host, port) = match target.rfind(':') {
+        Some(colon) => (&target[..colon], &target[colon + 1..]),
+        None => return Err(format!("'{}' isn't a valid address", target)),
+    };
+    Ok(terrapipe::proc_query(format!(
+        "CLUSTER SETSLOT {}-{} {} {}",
+        slots.start(),
+        slots.end(),
+        host,
+        port
+    )))
+}
+
+/// Connect to the node at `addr`, sending it `password` if it is set
+async fn connect(addr: &str, password: Option<&str>) -> Result<TcpStream, String> {
+    let mut stream = match time::timeout(NODE_TIMEOUT, TcpStream::connect(addr)).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(e)) => return Err(format!("Failed to connect to {}: {}", addr, e)),
+        Err(_) => return Err(format!("Timed out while connecting to {}", addr)),
+    };
+    if let Some(password) = password {
+        auth::send_auth(&mut stream, password, NODE_TIMEOUT)
+            .await
+            .map_err(|e| format!("Failed to authenticate with {}: {}", addr, e))?;
+    }
+    Ok(stream)
+}
+
+/// Send `packet` to another node and wait for it to reply with an okay response
+async fn call(stream: &mut TcpStream, packet: &[u8]) -> Result<(), String> {
+    let result = time::timeout(NODE_TIMEOUT, async {
+        stream.write_all(packet).await?;
+        let mut reply = vec![0u8; responses::fresp::R_OKAY.len()];
+        stream.read_exact(&mut reply).await?;
+        Ok::<_, io::Error>(reply)
+    })
+    .await;
+    match result {
+        Ok(Ok(reply)) if reply == *responses::fresp::R_OKAY => Ok(()),
+        Ok(Ok(_)) => Err("The node refused the request".to_owned()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err("The node stopped responding".to_owned()),
+    }
+}
+
+/// Run a `CLUSTER` query
+///
+/// - `CLUSTER SLOTS` returns a line for each range of slots with the node that serves it
+/// - `CLUSTER KEYSLOT <key>` returns the slot of `key`
+/// - `CLUSTER MIGRATE <slots> <host> <port>` moves the `slots` (like `42` or `0-8191`)
+///   and their keys from this node to the node at `host:port`, returning the number of
+///   keys that were moved
+/// - `CLUSTER SETSLOT <slots> <host> <port>` records that the node at `host:port` serves
+///   the `slots`, without moving any keys
+/// - `CLUSTER IMPORTING <slots>` records that the `slots` are being migrated to this node
+/// - `CLUSTER IMPORT <len>` is followed by `len` bytes with keys that are being migrated
+///   to this node, in the format of the dump file, which are sent once this node replies
+pub async fn cluster(handle: &CoreDB, con: &mut Connection, act: ActionGroup) -> TResult<()> {
+    let cluster = match &handle.shared.cluster {
+        Some(cluster) => cluster,
+        None => {
+            return con
+                .write_response(responses::fresp::R_CLUSTER_DISABLED.to_owned())
+                .await
+        }
+    };
+    let args: Vec<String> = act.into_iter().collect();
+    let response = match args.as_slice() {
+        [subaction] if subaction.eq_ignore_ascii_case("SLOTS") => {
+            let lines = cluster.report();
+            con.write_response(GroupBegin(lines.len())).await?;
+            for line in lines {
+                con.write_response(BytesWrapper(Bytes::from(line))).await?;
+            }
+            return Ok(());
+        }
+        [subaction, key] if subaction.eq_ignore_ascii_case("KEYSLOT") => {
+            con.write_response(GroupBegin(1)).await?;
+            return con.write_response(slots::key_slot(key) as usize).await;
+        }
+        [subaction, which, host, port] if subaction.eq_ignore_ascii_case("MIGRATE") => {
+            match (slots::parse_slots(which), port.parse::<u16>()) {
+                (Some(range), Ok(port)) => {
+                    let target = format!("{}:{}", host, port);
+                    match cluster.migrate(handle, range, target).await {
+                        Ok(moved) => {
+                            con.write_response(GroupBegin(1)).await?;
+                            return con.write_response(moved).await;
+                        }
+                        Err(e) => {
+                            log::error!("Failed to migrate slots {}: '{}'", which, e);
+                            responses::other_error(&e)
+                        }
+                    }
+                }
+                _ => responses::fresp::R_ACTION_ERR.to_owned(),
+            }
+        }
+        [subaction, range, host, port] if subaction.eq_ignore_ascii_case("SETSLOT") => {
+            match (slots::parse_slots(range), port.parse::<u16>()) {
+                (Some(range), Ok(port)) => {
+                    cluster.assign(range, &format!("{}:{}", host, port));
+                    responses::fresp::R_OKAY.to_owned()
+                }
+                _ => responses::fresp::R_ACTION_ERR.to_owned(),
+            }
+        }
+        [subaction, range] if subaction.eq_ignore_ascii_case("IMPORTING") => {
+            match slots::parse_slots(range) {
+                Some(range) => match cluster.begin_import(range) {
+                    Ok(()) => responses::fresp::R_OKAY.to_owned(),
+                    Err(e) => responses::other_error(&e),
+                },
+                None => responses::fresp::R_ACTION_ERR.to_owned(),
+            }
+        }
+        [subaction, len] if subaction.eq_ignore_ascii_case("IMPORT") => match len.parse() {
+            Ok(len) => {
+                // The length comes from the network, so it is checked before anything is
+                // read
+                let (_, _, max_query_size) = handle.shared.config.read().limits.decompose();
+                if len > max_query_size {
+                    responses::fresp::R_QUERY_TOO_LARGE.to_owned()
+                } else if cluster.importing.lock().is_empty() {
+                    responses::other_error("No slots are being migrated to this node")
+                } else {
+                    con.write_response(responses::fresp::R_OKAY.to_owned())
+                        .await?;
+                    con.flush_stream().await?;
+                    let dump = con.read_raw(len).await?;
+                    import(handle, cluster, &dump)
+                }
+            }
+            Err(_) => responses::fresp::R_ACTION_ERR.to_owned(),
+        },
+        _ => responses::fresp::R_ACTION_ERR.to_owned(),
+    };
+    con.write_response(response).await
+}
+
+/// Store the keys in `dump`, which are being migrated to this node, returning the
+/// response
+///
+/// Nothing is stored unless all the keys are in slots that are being migrated to this node
+fn import(handle: &CoreDB, cluster: &Cluster, dump: &[u8]) -> Vec<u8> {
+    let data: HashMap<String, Data> = match format::read_table(dump, &Keyring::none()) {
+        Ok(data) => data,
+        Err(e) => {
+            log::error!("Failed to read migrated keys with error: '{}'", e);
+            return responses::fresp::R_ACTION_ERR.to_owned();
+        }
+    };
+    let stray = {
+        let importing = cluster.importing.lock();
+        data.keys()
+            .map(|key| slots::key_slot(key))
+            .find(|slot| !importing.contains(slot))
+    };
+    if let Some(slot) = stray {
+        return responses::other_error(&format!("Slot {} isn't being migrated to this node", slot));
+    }
+    let mut table = handle.acquire_write();
+    for (key, value) in data {
+        if let Err(e) = table.get_mut_ref().set(key.clone(), value.clone()) {
+            log::error!("Failed to store a migrated key with error: '{}'", e);
+            return responses::fresp::R_SERVER_ERR.to_owned();
+        }
+        // Only log the change once it has been made
+        handle.shared.log_set(&key, value.get_blob());
+    }
+    responses::fresp::R_OKAY.to_owned()
+}
+
+#[cfg(test)]
+/// Start the cluster node at `addr` with `nodes`, accepting connections on `listener`
+///
+/// This returns the node, a sender that stops the server and the data directory
+async fn start_test_node(
+    addr: String,
+    nodes: Vec<ClusterNode>,
+    listener: tokio::net::TcpListener,
+) -> (CoreDB, tokio::sync::oneshot::Sender<()>, PathBuf) {
+    use crate::config::{BGSave, ClusterConfig, ParsedConfig};
+    use crate::coredb::storage::Engine;
+    use crate::diskstore::format::Codec;
+    let port = addr.rsplit(':').next().unwrap();
+    let dir =
+        std::env::temp_dir().join(format!("tdb-test-cluster-{}-{}", std::process::id(), port));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let mut cfg = ParsedConfig::default();
+    cfg.bgsave = BGSave::Disabled;
+    cfg.storage = StorageConfig::new(
+        &dir,
+        "data.bin",
+        "snapshots",
+        false,
+        Codec::None,
+        Engine::Memory,
+    );
+    cfg.cluster = ClusterConfig::Enabled(addr, nodes, None);
+    let db = CoreDB::new(cfg, None, Keyring::none(), None).unwrap();
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    tokio::spawn(crate::dbnet::test_run(listener, db.clone(), stopped));
+    (db, stop, dir)
+}
+
+#[tokio::test]
+async fn test_cluster_redirect_and_migrate() {
+    use crate::replication::replica::run_query;
+    use tokio::net::TcpListener;
+    let first = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let second = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addrs = [
+        first.local_addr().unwrap().to_string(),
+        second.local_addr().unwrap().to_string(),
+    ];
+    let nodes = vec![
+        ClusterNode {
+            addr: addrs[0].clone(),
+            slots: vec!["0-8191".to_owned()],
+        },
+        ClusterNode {
+            addr: addrs[1].clone(),
+            slots: vec!["8192-16383".to_owned()],
+        },
+    ];
+    let (first, first_stop, first_dir) =
+        start_test_node(addrs[0].clone(), nodes.clone(), first).await;
+    let (second, second_stop, second_dir) = start_test_node(addrs[1].clone(), nodes, second).await;
+    // "bar" is in slot 5061 and "foo" is in slot 12182
+    assert_eq!(
+        run_query(&addrs[0], "CLUSTER KEYSLOT foo").await,
+        b"#2\n*1\n#2\n&1\n:5\n12182\n".to_vec()
+    );
+    assert_eq!(
+        run_query(&addrs[0], "SET bar 100").await,
+        *responses::fresp::R_OKAY
+    );
+    assert_eq!(
+        run_query(&addrs[0], "SET {bar}.baz 200").await,
+        *responses::fresp::R_OKAY
+    );
+    assert_eq!(
+        run_query(&addrs[0], "SET foo 300").await,
+        responses::redirect(12182, &addrs[1])
+    );
+    assert_eq!(
+        run_query(&addrs[1], "SET foo 300").await,
+        *responses::fresp::R_OKAY
+    );
+    assert_eq!(
+        run_query(&addrs[0], "MSET bar 1 foo 2").await,
+        *responses::fresp::R_CROSS_SLOT
+    );
+    // Move the slot of "bar" to the second node
+    let port = addrs[1].rsplit(':').next().unwrap();
+    assert_eq!(
+        run_query(
+            &addrs[0],
+            &format!("CLUSTER MIGRATE 5061 127.0.0.1 {}", port)
+        )
+        .await,
+        b"#2\n*1\n#2\n&1\n:1\n2\n".to_vec()
+    );
+    assert_eq!(
+        run_query(&addrs[0], "GET bar").await,
+        responses::redirect(5061, &addrs[1])
+    );
+    assert_eq!(
+        run_query(&addrs[1], "GET bar").await,
+        b"#2\n*1\n#2\n&1\n+3\n100\n".to_vec()
+    );
+    assert_eq!(
+        run_query(&addrs[1], "GET {bar}.baz").await,
+        b"#2\n*1\n#2\n&1\n+3\n200\n".to_vec()
+    );
+    assert_eq!(first.acquire_read().get_ref().len(), 0);
+    // Both nodes remember the new owner of the slot
+    for (node, dir) in &[(&first, &first_dir), (&second, &second_dir)] {
+        let saved = fs::read_to_string(dir.join("cluster.map")).unwrap();
+        let expected = format!(
+            "0-5060 {first}\n5061-5061 {second}\n5062-8191 {first}\n8192-16383 {second}\n",
+            first = addrs[0],
+            second = addrs[1]
+        );
+        assert_eq!(saved, expected);
+        assert_eq!(
+            node.shared.cluster.as_ref().unwrap().map.read().owner(5061),
+            Some(addrs[1].as_str())
+        );
+    }
+    // Keys can only be imported into slots that are being migrated to the node, and
+    // only in batches that it would take as a query
+    assert_eq!(
+        run_query(&addrs[1], "CLUSTER IMPORT 10").await,
+        responses::other_error("No slots are being migrated to this node")
+    );
+    assert_eq!(
+        run_query(&addrs[1], "CLUSTER IMPORT 1000000000").await,
+        *responses::fresp::R_QUERY_TOO_LARGE
+    );
+    assert_eq!(
+        run_query(&addrs[1], "CLUSTER IMPORTING 5061").await,
+        responses::other_error("Some of the slots are already served by this node")
+    );
+    let cluster = second.shared.cluster.as_ref().unwrap();
+    cluster.begin_import(0..=0).unwrap();
+    let mut stray = HashMap::new();
+    stray.insert("foo".to_owned(), Data::from_string("400".to_owned()));
+    let dump =
+        format::write_table(Vec::new(), format::Codec::None, &Keyring::none(), &stray).unwrap();
+    assert_eq!(
+        import(&second, cluster, &dump),
+        responses::other_error("Slot 12182 isn't being migrated to this node")
+    );
+    assert_eq!(
+        run_query(&addrs[1], "GET foo").await,
+        b"#2\n*1\n#2\n&1\n+3\n300\n".to_vec()
+    );
+    // Slots that this node doesn't serve can't be migrated
+    assert_eq!(
+        run_query(
+            &addrs[0],
+            &format!("CLUSTER MIGRATE 5061 127.0.0.1 {}", port)
+        )
+        .await,
+        responses::other_error("Some of the slots aren't served by this node")
+    );
+    // A migration waits for the writes that were let through before it began, and
+    // refuses the ones after
+    let args = |query: &str| -> Vec<String> { query.split(' ').map(ToOwned::to_owned).collect() };
+    let permit = cluster.route(&args("SET foo 500"), true).await.unwrap();
+    assert!(permit.is_some());
+    let migration = Migration::begin(cluster, 12182..=12182).unwrap();
+    assert!(
+        time::timeout(Duration::from_millis(50), cluster.fence.write())
+            .await
+            .is_err()
+    );
+    drop(permit);
+    drop(cluster.fence.write().await);
+    assert_eq!(
+        cluster.route(&args("SET foo 500"), true).await.unwrap_err(),
+        responses::other_error("Slot 12182 is being migrated, try again later")
+    );
+    assert_eq!(
+        run_query(&addrs[1], "FLUSHDB").await,
+        responses::other_error("Slots are being migrated, try again later")
+    );
+    assert!(cluster.route(&args("GET foo"), false).await.is_ok());
+    drop(migration);
+    let _ = first_stop.send(());
+    let _ = second_stop.send(());
+    let _ = fs::remove_dir_all(first_dir);
+    let _ = fs::remove_dir_all(second_dir);
+}
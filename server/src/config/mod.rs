@@ -21,19 +21,23 @@
 
 //! This module provides tools to handle configuration files and settings
 
+use crate::coredb::storage::Engine;
+use crate::diskstore::format::Codec;
+use crate::diskstore::writelog;
 use libtdb::TResult;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::error::Error;
 use std::fmt;
 use std::fs;
+use std::io::Write;
 #[cfg(test)]
 use std::net::Ipv6Addr;
-use std::net::{IpAddr, Ipv4Addr};
-use tokio::net::ToSocketAddrs;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
 use toml;
 
 /// This struct is an _object representation_ used for parsing the TOML file
-#[derive(Deserialize, Debug, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
 pub struct Config {
     /// The `server` key
     server: ConfigKeyServer,
@@ -46,10 +50,26 @@ pub struct Config {
     bgsave: Option<ConfigKeyBGSAVE>,
     /// The snapshot key
     snapshot: Option<ConfigKeySnapshot>,
+    /// The metrics key
+    metrics: Option<ConfigKeyMetrics>,
+    /// The slowlog key
+    slowlog: Option<ConfigKeySlowlog>,
+    /// The limits key
+    limits: Option<ConfigKeyLimits>,
+    /// The storage key
+    storage: Option<ConfigKeyStorage>,
+    /// The encryption key
+    encryption: Option<ConfigKeyEncryption>,
+    /// The replication key
+    replication: Option<ConfigKeyReplication>,
+    /// The Raft key
+    raft: Option<ConfigKeyRaft>,
+    /// The cluster key
+    cluster: Option<ConfigKeyCluster>,
 }
 
 /// The BGSAVE section in the config file
-#[derive(Deserialize, Debug, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
 pub struct ConfigKeyBGSAVE {
     /// Whether BGSAVE is enabled or not
     ///
@@ -66,7 +86,7 @@ pub struct ConfigKeyBGSAVE {
 ///
 /// If BGSAVE is enabled, then the duration (corresponding to `every`) is wrapped in the `Enabled`
 /// variant. Otherwise, the `Disabled` variant is to be used
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum BGSave {
     Enabled(u64),
     Disabled,
@@ -100,7 +120,7 @@ impl BGSave {
 }
 
 /// This struct represents the `server` key in the TOML file
-#[derive(Deserialize, Debug, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
 pub struct ConfigKeyServer {
     /// The host key is any valid IPv4/IPv6 address
     host: IpAddr,
@@ -109,10 +129,87 @@ pub struct ConfigKeyServer {
     /// The noart key is an `Option`al boolean value which is set to true
     /// for secure environments to disable terminal artwork
     noart: Option<bool>,
+    /// The readonly key is an `Option`al boolean value which is set to true to start
+    /// the server in read-only mode
+    readonly: Option<bool>,
+    /// The file with the certificate chain to serve TLS with, in PEM format
+    tls_cert: Option<String>,
+    /// The file with the private key of the certificate, in PEM format
+    tls_key: Option<String>,
+    /// The password that clients have to send with `AUTH` before anything else
+    password: Option<String>,
+    /// More addresses to listen on
+    listeners: Option<Vec<ConfigKeyListener>>,
+}
+
+/// An entry in the `listeners` key of the `server` section in the TOML file
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+pub struct ConfigKeyListener {
+    /// Any valid IPv4/IPv6 address
+    host: IpAddr,
+    /// Any valid port
+    port: u16,
+    /// The file with the certificate chain to serve TLS with, in PEM format
+    tls_cert: Option<String>,
+    /// The file with the private key of the certificate, in PEM format
+    tls_key: Option<String>,
+    /// The password that clients have to send with `AUTH` before anything else
+    password: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+/// An address that the server listens on, with what clients connecting to it have to
+/// do before they're served
+pub struct ListenerConfig {
+    /// The IPv4/IPv6 address to bind to
+    pub host: IpAddr,
+    /// The port to bind to
+    pub port: u16,
+    /// If this is set (along with `tls_key`), clients have to connect over TLS with the
+    /// certificate chain in this file
+    pub tls_cert: Option<PathBuf>,
+    /// The private key of the certificate
+    pub tls_key: Option<PathBuf>,
+    /// If this is set, clients have to send it with `AUTH` before anything else
+    pub password: Option<String>,
+}
+
+impl ListenerConfig {
+    /// Create a new `ListenerConfig` instance for a listener without TLS or a password
+    pub const fn new(host: IpAddr, port: u16) -> Self {
+        ListenerConfig {
+            host,
+            port,
+            tls_cert: None,
+            tls_key: None,
+            password: None,
+        }
+    }
+    /// Create a new `ListenerConfig` instance from the `server` section or an entry in
+    /// its `listeners` key
+    fn from_keys(
+        host: IpAddr,
+        port: u16,
+        tls_cert: Option<String>,
+        tls_key: Option<String>,
+        password: Option<String>,
+    ) -> Self {
+        ListenerConfig {
+            host,
+            port,
+            tls_cert: tls_cert.map(PathBuf::from),
+            tls_key: tls_key.map(PathBuf::from),
+            password,
+        }
+    }
+    /// Returns the address to bind to
+    pub const fn addr(&self) -> SocketAddr {
+        SocketAddr::new(self.host, self.port)
+    }
 }
 
 /// The snapshot section in the TOML file
-#[derive(Deserialize, Debug, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
 pub struct ConfigKeySnapshot {
     /// Whether snapshotting is enabled or not
     enabled: bool,
@@ -124,7 +221,262 @@ pub struct ConfigKeySnapshot {
     atmost: usize,
 }
 
-#[derive(Debug, PartialEq)]
+/// The metrics section in the TOML file
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct ConfigKeyMetrics {
+    /// Whether the metrics endpoint is enabled or not
+    ///
+    /// If this key is missing, then we can assume that the endpoint is enabled
+    enabled: Option<bool>,
+    /// The host to which the metrics endpoint should bind to
+    host: IpAddr,
+    /// The port to which the metrics endpoint should bind to
+    port: u16,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+/// The metrics endpoint configuration
+///
+/// If the endpoint is enabled, the `(host, port)` it should bind to is wrapped in the
+/// `Enabled` variant. Otherwise, the `Disabled` variant is to be used
+pub enum MetricsConfig {
+    Enabled(IpAddr, u16),
+    Disabled,
+}
+
+impl MetricsConfig {
+    /// The metrics endpoint is disabled by default
+    pub const fn default() -> Self {
+        MetricsConfig::Disabled
+    }
+}
+
+/// The slowlog section in the TOML file
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct ConfigKeySlowlog {
+    /// Actions that take longer than `threshold` microseconds are logged
+    threshold: Option<u64>,
+    /// The maximum number of entries to keep
+    ///
+    /// If maxlen is set to `0`, then nothing will be logged
+    maxlen: Option<usize>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+/// The slowlog configuration
+pub struct SlowlogConfig {
+    /// Log actions that take longer than `threshold` microseconds
+    threshold: u64,
+    /// The maximum number of entries to keep
+    maxlen: usize,
+}
+
+impl SlowlogConfig {
+    /// Create a new `SlowlogConfig` instance
+    pub const fn new(threshold: u64, maxlen: usize) -> Self {
+        SlowlogConfig { threshold, maxlen }
+    }
+    /// The default slowlog configuration
+    ///
+    /// Defaults:
+    /// - `threshold`: 10000 (10 milliseconds)
+    /// - `maxlen`: 128
+    pub const fn default() -> Self {
+        SlowlogConfig::new(10_000, 128)
+    }
+    /// Returns `threshold,maxlen` as a tuple for pattern matching
+    pub const fn decompose(self) -> (u64, usize) {
+        (self.threshold, self.maxlen)
+    }
+}
+
+/// The limits section in the TOML file
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct ConfigKeyLimits {
+    /// The maximum number of clients that can be connected at the same time
+    maxclients: Option<usize>,
+    /// Disconnect clients that haven't sent a query for `idle_timeout` seconds
+    ///
+    /// If idle_timeout is set to `0`, then clients are never disconnected
+    idle_timeout: Option<u64>,
+    /// The maximum size of a query packet, in bytes
+    max_query_size: Option<usize>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+/// The connection limits
+pub struct LimitsConfig {
+    /// The maximum number of clients that can be connected at the same time
+    maxclients: usize,
+    /// Disconnect clients that are idle for `idle_timeout` seconds (`0` to never disconnect)
+    idle_timeout: u64,
+    /// The maximum size of a query packet, in bytes
+    max_query_size: usize,
+}
+
+impl LimitsConfig {
+    /// Create a new `LimitsConfig` instance
+    pub const fn new(maxclients: usize, idle_timeout: u64, max_query_size: usize) -> Self {
+        LimitsConfig {
+            maxclients,
+            idle_timeout,
+            max_query_size,
+        }
+    }
+    /// The default limits
+    ///
+    /// Defaults:
+    /// - `maxclients`: 50000
+    /// - `idle_timeout`: 0 (never disconnect idle clients)
+    /// - `max_query_size`: 67108864 (64 MiB)
+    pub const fn default() -> Self {
+        LimitsConfig::new(50000, 0, 64 * 1024 * 1024)
+    }
+    /// Returns `maxclients,idle_timeout,max_query_size` as a tuple for pattern matching
+    pub const fn decompose(self) -> (usize, u64, usize) {
+        (self.maxclients, self.idle_timeout, self.max_query_size)
+    }
+}
+
+/// The storage section in the TOML file
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct ConfigKeyStorage {
+    /// The directory in which all the data is stored
+    data_dir: Option<String>,
+    /// The name of the file to which the in-memory table is flushed
+    dump_filename: Option<String>,
+    /// The directory in which snapshots are stored
+    ///
+    /// If this is a relative path, it is relative to `data_dir`
+    snapshot_dir: Option<String>,
+    /// Whether every change should be logged to the write log, for point-in-time recovery
+    writelog: Option<bool>,
+    /// The codec used to compress the dump file and snapshots
+    compression: Option<Codec>,
+    /// The storage engine
+    engine: Option<Engine>,
+}
+
+/// The name of the lock file in the data directory
+const LOCK_FILENAME: &str = "tdb.lock";
+/// The name of the write log directory in the data directory
+const WRITELOG_DIRNAME: &str = "writelog";
+/// The name of the log engine's segment directory in the data directory
+const SEGMENT_DIRNAME: &str = "segments";
+/// The name of the Raft directory in the data directory
+const RAFT_DIRNAME: &str = "raft";
+/// The name of the file with the cluster's slot map in the data directory
+const CLUSTER_FILENAME: &str = "cluster.map";
+
+#[derive(Debug, PartialEq, Clone)]
+/// The storage configuration
+pub struct StorageConfig {
+    /// The directory in which all the data is stored
+    data_dir: PathBuf,
+    /// The name of the file to which the in-memory table is flushed
+    dump_filename: String,
+    /// The directory in which snapshots are stored (relative to `data_dir`, unless it is
+    /// an absolute path)
+    snapshot_dir: PathBuf,
+    /// Whether the write log is enabled
+    writelog: bool,
+    /// The codec used to compress the dump file and snapshots
+    compression: Codec,
+    /// The storage engine
+    engine: Engine,
+}
+
+impl StorageConfig {
+    /// Create a new `StorageConfig` instance
+    pub fn new(
+        data_dir: impl Into<PathBuf>,
+        dump_filename: impl Into<String>,
+        snapshot_dir: impl Into<PathBuf>,
+        writelog: bool,
+        compression: Codec,
+        engine: Engine,
+    ) -> Self {
+        StorageConfig {
+            data_dir: data_dir.into(),
+            dump_filename: dump_filename.into(),
+            snapshot_dir: snapshot_dir.into(),
+            writelog,
+            compression,
+            engine,
+        }
+    }
+    /// The default storage configuration
+    ///
+    /// Defaults:
+    /// - `data_dir`: `.` (the directory in which the server was started)
+    /// - `dump_filename`: `data.bin`
+    /// - `snapshot_dir`: `snapshots`
+    /// - `writelog`: false
+    /// - `compression`: none
+    /// - `engine`: memory
+    pub fn default() -> Self {
+        StorageConfig::new(
+            ".",
+            "data.bin",
+            "snapshots",
+            false,
+            Codec::None,
+            Engine::Memory,
+        )
+    }
+    /// Replace the data directory, keeping everything else
+    pub fn set_data_dir(&mut self, data_dir: impl Into<PathBuf>) {
+        self.data_dir = data_dir.into();
+    }
+    /// Returns the data directory
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+    /// Returns the path of the file to which the in-memory table is flushed
+    pub fn dump_path(&self) -> PathBuf {
+        self.data_dir.join(&self.dump_filename)
+    }
+    /// Returns the path of the snapshot directory
+    pub fn snapshot_dir(&self) -> PathBuf {
+        // `join` just returns `snapshot_dir` if it is absolute
+        self.data_dir.join(&self.snapshot_dir)
+    }
+    /// Returns the path of the lock file which makes sure that only one server uses
+    /// the data directory
+    pub fn lock_path(&self) -> PathBuf {
+        self.data_dir.join(LOCK_FILENAME)
+    }
+    /// Check if the write log is enabled
+    pub const fn is_writelog_enabled(&self) -> bool {
+        self.writelog
+    }
+    /// Returns the path of the write log directory
+    pub fn writelog_dir(&self) -> PathBuf {
+        self.data_dir.join(WRITELOG_DIRNAME)
+    }
+    /// Returns the codec used to compress the dump file and snapshots
+    pub const fn compression(&self) -> Codec {
+        self.compression
+    }
+    /// Returns the storage engine
+    pub const fn engine(&self) -> Engine {
+        self.engine
+    }
+    /// Returns the path of the log engine's segment directory
+    pub fn segment_dir(&self) -> PathBuf {
+        self.data_dir.join(SEGMENT_DIRNAME)
+    }
+    /// Returns the path of the directory with the Raft log and state
+    pub fn raft_dir(&self) -> PathBuf {
+        self.data_dir.join(RAFT_DIRNAME)
+    }
+    /// Returns the path of the file with the cluster's slot map
+    pub fn cluster_path(&self) -> PathBuf {
+        self.data_dir.join(CLUSTER_FILENAME)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 /// The snapshot configuration
 ///
 pub struct SnapshotPref {
@@ -145,7 +497,7 @@ impl SnapshotPref {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 /// Snapshotting configuration
 ///
 /// The variant `Enabled` directly carries a `ConfigKeySnapshot` object that
@@ -173,23 +525,431 @@ impl SnapshotConfig {
             true
         }
     }
+    /// Returns the maximum number of snapshots to keep, where 0 means that all of them
+    /// are kept. Nothing is deleted if snapshotting is disabled
+    pub const fn atmost(&self) -> usize {
+        match self {
+            SnapshotConfig::Enabled(pref) => pref.atmost,
+            SnapshotConfig::Disabled => 0,
+        }
+    }
+}
+
+/// The encryption section in the TOML file
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct ConfigKeyEncryption {
+    /// The file with the key with which the dump file and snapshots are encrypted
+    key_file: Option<String>,
+    /// The environment variable with the key, if it isn't in a file
+    key_env: Option<String>,
+    /// Files with keys that were used before, so that the files encrypted with them can
+    /// still be read
+    old_key_files: Option<Vec<String>>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+/// The encryption configuration
+///
+/// This only says where the keys are. The keys are loaded when the server starts, with
+/// [`Keyring::load`](crate::diskstore::encryption::Keyring::load)
+pub struct EncryptionConfig {
+    /// The file with the current key
+    pub key_file: Option<PathBuf>,
+    /// The environment variable with the current key
+    pub key_env: Option<String>,
+    /// The files with the old keys
+    pub old_key_files: Vec<PathBuf>,
+}
+
+impl EncryptionConfig {
+    /// Create a new `EncryptionConfig` instance
+    pub const fn new(
+        key_file: Option<PathBuf>,
+        key_env: Option<String>,
+        old_key_files: Vec<PathBuf>,
+    ) -> Self {
+        EncryptionConfig {
+            key_file,
+            key_env,
+            old_key_files,
+        }
+    }
+    /// The default encryption configuration, which doesn't encrypt anything
+    pub const fn default() -> Self {
+        EncryptionConfig::new(None, None, Vec::new())
+    }
+    /// Check if a current key is set, which means that files are encrypted
+    pub const fn is_enabled(&self) -> bool {
+        self.key_file.is_some() || self.key_env.is_some()
+    }
+}
+
+/// The replication section in the TOML file
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct ConfigKeyReplication {
+    /// The `host:port` of the primary that this server should replicate
+    primary: Option<String>,
+    /// The size of the replication backlog, in bytes
+    backlog_size: Option<usize>,
+    /// The password that is sent with `AUTH` to the primary
+    password: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+/// The replication configuration
+pub struct ReplicationConfig {
+    /// The `host:port` of the primary, if this server is a replica
+    pub primary: Option<String>,
+    /// The largest that the replication backlog can get, in bytes
+    pub backlog_size: usize,
+    /// The password that is sent with `AUTH` to the primary, if its listener has one
+    pub password: Option<String>,
+}
+
+impl ReplicationConfig {
+    /// Create a new `ReplicationConfig` instance
+    pub const fn new(primary: Option<String>, backlog_size: usize) -> Self {
+        ReplicationConfig {
+            primary,
+            backlog_size,
+            password: None,
+        }
+    }
+    /// The default replication configuration, which makes this server a primary
+    ///
+    /// Defaults:
+    /// - `primary`: none
+    /// - `backlog_size`: 1048576 (1 MiB)
+    pub const fn default() -> Self {
+        ReplicationConfig::new(None, 1024 * 1024)
+    }
+}
+
+/// The Raft section in the TOML file
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct ConfigKeyRaft {
+    /// The ID of this node
+    id: u64,
+    /// The members of a new group
+    members: Option<Vec<RaftMember>>,
+    /// The election timeout, in milliseconds
+    election_timeout: Option<u64>,
+    /// How often the leader sends heartbeats, in milliseconds
+    heartbeat_interval: Option<u64>,
+    /// The number of applied entries after which the log is compacted
+    snapshot_after: Option<u64>,
+    /// The password that is sent with `AUTH` to the other members
+    password: Option<String>,
+}
+
+/// A member of a Raft group
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+pub struct RaftMember {
+    /// The ID of the member
+    pub id: u64,
+    /// The `host:port` on which the member accepts connections
+    pub addr: String,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+/// The Raft preferences
+pub struct RaftPref {
+    /// The ID of this node, which is unique within the group
+    pub id: u64,
+    /// The members of the group when it is first started. This is only used if the node
+    /// has no Raft state yet
+    pub members: Vec<RaftMember>,
+    /// A follower starts an election if it hasn't heard from a leader for between one
+    /// and two times this many milliseconds
+    pub election_timeout: u64,
+    /// The leader sends a heartbeat to its followers every this many milliseconds
+    pub heartbeat_interval: u64,
+    /// The log is compacted into a snapshot once this many entries were applied
+    /// after the last snapshot
+    pub snapshot_after: u64,
+    /// The password that is sent with `AUTH` to the other members, if their listeners
+    /// have one
+    pub password: Option<String>,
+}
+
+impl RaftPref {
+    /// Create a new `RaftPref` instance
+    pub const fn new(
+        id: u64,
+        members: Vec<RaftMember>,
+        election_timeout: u64,
+        heartbeat_interval: u64,
+        snapshot_after: u64,
+    ) -> Self {
+        RaftPref {
+            id,
+            members,
+            election_timeout,
+            heartbeat_interval,
+            snapshot_after,
+            password: None,
+        }
+    }
+    /// Create a new `RaftPref` instance with the default timings
+    ///
+    /// Defaults:
+    /// - `election_timeout`: 1000 (1 second)
+    /// - `heartbeat_interval`: 100
+    /// - `snapshot_after`: 10000
+    pub const fn with_defaults(id: u64, members: Vec<RaftMember>) -> Self {
+        RaftPref::new(id, members, 1000, 100, 10_000)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+/// The Raft configuration
+pub enum RaftConfig {
+    /// This server is a node in a Raft group
+    Enabled(RaftPref),
+    /// This server isn't part of a Raft group
+    Disabled,
+}
+
+impl RaftConfig {
+    /// Raft is disabled by default
+    pub const fn default() -> Self {
+        RaftConfig::Disabled
+    }
+}
+
+/// The cluster section in the TOML file
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct ConfigKeyCluster {
+    /// The `host:port` at which this node is reached
+    addr: String,
+    /// The nodes of a new cluster, with their slots
+    nodes: Option<Vec<ClusterNode>>,
+    /// The password that is sent with `AUTH` to the other nodes
+    password: Option<String>,
+}
+
+/// A node of a cluster
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+pub struct ClusterNode {
+    /// The `host:port` at which the node is reached
+    pub addr: String,
+    /// The slots that the node serves, like `"0-8191"` or `"42"`
+    pub slots: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+/// The cluster configuration
+pub enum ClusterConfig {
+    /// This server is a node in a cluster, which is reached at the `host:port` in the
+    /// first field. The nodes of the cluster are only used if the node has no slot map
+    /// yet. The last field is the password that is sent with `AUTH` to the other nodes,
+    /// if their listeners have one
+    Enabled(String, Vec<ClusterNode>, Option<String>),
+    /// This server isn't part of a cluster
+    Disabled,
+}
+
+impl ClusterConfig {
+    /// The cluster mode is disabled by default
+    pub const fn default() -> Self {
+        ClusterConfig::Disabled
+    }
+}
+
+/// The settings that can be changed while the server is running, with `CONFIG SET`
+/// or by reloading the configuration file. Changes to the other settings only take
+/// effect after a restart
+pub const HOT_SETTINGS: [&str; 11] = [
+    "server.readonly",
+    "bgsave.enabled",
+    "bgsave.every",
+    "snapshot.enabled",
+    "snapshot.every",
+    "snapshot.atmost",
+    "slowlog.threshold",
+    "slowlog.maxlen",
+    "limits.maxclients",
+    "limits.idle_timeout",
+    "limits.max_query_size",
+];
+
+/// Every setting in the configuration file, with what it does
+///
+/// Each of them can also be set with a flag (see [`flag_name`]) or an environment
+/// variable (see [`env_name`]). Flags take precedence over environment variables, which
+/// take precedence over the configuration file, which takes precedence over the defaults
+pub const OPTIONS: [(&str, &str); 42] = [
+    ("server.host", "The IP address to bind to"),
+    ("server.port", "The port to bind to"),
+    ("server.noart", "Don't show the terminal artwork"),
+    ("server.readonly", "Start in read-only mode"),
+    (
+        "server.tls_cert",
+        "The certificate chain to serve TLS with, in PEM format",
+    ),
+    (
+        "server.tls_key",
+        "The private key of the certificate, in PEM format",
+    ),
+    (
+        "server.password",
+        "The password that clients have to send with AUTH",
+    ),
+    ("server.listeners", "More addresses to listen on"),
+    ("bgsave.enabled", "Whether BGSAVE is enabled"),
+    ("bgsave.every", "Run BGSAVE every this many seconds"),
+    ("snapshot.enabled", "Whether snapshots are created"),
+    (
+        "snapshot.every",
+        "Create a snapshot every this many seconds",
+    ),
+    (
+        "snapshot.atmost",
+        "The number of snapshots to keep (0 to keep all of them)",
+    ),
+    ("metrics.enabled", "Whether the metrics endpoint is enabled"),
+    (
+        "metrics.host",
+        "The IP address that the metrics endpoint binds to",
+    ),
+    (
+        "metrics.port",
+        "The port that the metrics endpoint binds to",
+    ),
+    (
+        "slowlog.threshold",
+        "Log actions that take longer than this many microseconds",
+    ),
+    ("slowlog.maxlen", "The number of slowlog entries to keep"),
+    (
+        "limits.maxclients",
+        "The maximum number of connected clients",
+    ),
+    (
+        "limits.idle_timeout",
+        "Disconnect clients idle for this many seconds (0 to never disconnect)",
+    ),
+    (
+        "limits.max_query_size",
+        "The maximum size of a query packet, in bytes",
+    ),
+    (
+        "storage.data_dir",
+        "The directory in which all the data is stored",
+    ),
+    ("storage.dump_filename", "The name of the dump file"),
+    (
+        "storage.snapshot_dir",
+        "The directory in which snapshots are stored",
+    ),
+    (
+        "storage.writelog",
+        "Whether every change is logged for point-in-time recovery",
+    ),
+    (
+        "storage.compression",
+        "The codec used to compress the dump file and snapshots",
+    ),
+    ("storage.engine", "The storage engine"),
+    ("encryption.key_file", "The file with the encryption key"),
+    (
+        "encryption.key_env",
+        "The environment variable with the encryption key",
+    ),
+    (
+        "encryption.old_key_files",
+        "The files with the keys that were used before",
+    ),
+    (
+        "replication.primary",
+        "The host:port of the primary to replicate",
+    ),
+    (
+        "replication.backlog_size",
+        "The size of the replication backlog, in bytes",
+    ),
+    (
+        "replication.password",
+        "The password to send with AUTH to the primary",
+    ),
+    ("raft.id", "The ID of this node in the Raft group"),
+    ("raft.members", "The members of a new Raft group"),
+    (
+        "raft.election_timeout",
+        "The Raft election timeout, in milliseconds",
+    ),
+    (
+        "raft.heartbeat_interval",
+        "How often the Raft leader sends heartbeats, in milliseconds",
+    ),
+    (
+        "raft.snapshot_after",
+        "Compact the Raft log after this many applied entries",
+    ),
+    (
+        "raft.password",
+        "The password to send with AUTH to the other Raft members",
+    ),
+    (
+        "cluster.addr",
+        "The host:port at which this cluster node is reached",
+    ),
+    (
+        "cluster.nodes",
+        "The nodes of a new cluster, with their slots",
+    ),
+    (
+        "cluster.password",
+        "The password to send with AUTH to the other cluster nodes",
+    ),
+];
+
+/// What the passwords are replaced with when the configuration is shown
+const REDACTED: &str = "***";
+
+/// Returns the command line flag for the `section.key` setting `name`, like
+/// `bgsave-every` for `bgsave.every`
+pub fn flag_name(name: &str) -> String {
+    name.replace(&['.', '_'][..], "-")
+}
+
+/// Returns the environment variable for the `section.key` setting `name`, like
+/// `TDB_BGSAVE_EVERY` for `bgsave.every`
+pub fn env_name(name: &str) -> String {
+    format!("TDB_{}", name.replace('.', "_").to_uppercase())
 }
 
 /// A `ParsedConfig` which can be used by main::check_args_or_connect() to bind
 /// to a `TcpListener` and show the corresponding terminal output for the given
 /// configuration
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct ParsedConfig {
-    /// A valid IPv4/IPv6 address
-    host: IpAddr,
-    /// A valid port
-    port: u16,
+    /// The addresses to listen on. The first one is the one in the `server` section
+    pub listeners: Vec<ListenerConfig>,
     /// If `noart` is set to true, no terminal artwork should be displayed
     noart: bool,
+    /// If `readonly` is set to true, the server starts in read-only mode
+    pub readonly: bool,
     /// The BGSAVE configuration
     pub bgsave: BGSave,
     /// The snapshot configuration
     pub snapshot: SnapshotConfig,
+    /// The metrics endpoint configuration
+    pub metrics: MetricsConfig,
+    /// The slowlog configuration
+    pub slowlog: SlowlogConfig,
+    /// The connection limits
+    pub limits: LimitsConfig,
+    /// The storage configuration
+    pub storage: StorageConfig,
+    /// The encryption configuration
+    pub encryption: EncryptionConfig,
+    /// The replication configuration
+    pub replication: ReplicationConfig,
+    /// The Raft configuration
+    pub raft: RaftConfig,
+    /// The cluster configuration
+    pub cluster: ClusterConfig,
 }
 
 impl ParsedConfig {
@@ -200,21 +960,37 @@ impl ParsedConfig {
             Err(e) => return Err(ConfigError::OSError(e.into())),
         };
         match toml::from_str(&file) {
-            Ok(cfgfile) => Ok(ParsedConfig::from_config(cfgfile)),
+            Ok(cfgfile) => ParsedConfig::from_toml_value(cfgfile),
             Err(e) => return Err(ConfigError::SyntaxError(e.into())),
         }
     }
     /// Create a `ParsedConfig` instance from a `Config` object, which is a parsed
     /// TOML file (represented as an object)
-    const fn from_config(cfg: Config) -> Self {
+    fn from_config(cfg: Config) -> Self {
         ParsedConfig {
-            host: cfg.server.host,
-            port: cfg.server.port,
-            noart: if let Some(noart) = cfg.server.noart {
-                noart
-            } else {
-                false
+            listeners: {
+                let server = ListenerConfig::from_keys(
+                    cfg.server.host,
+                    cfg.server.port,
+                    cfg.server.tls_cert,
+                    cfg.server.tls_key,
+                    cfg.server.password,
+                );
+                let more = cfg.server.listeners.unwrap_or_default();
+                std::iter::once(server)
+                    .chain(more.into_iter().map(|listener| {
+                        ListenerConfig::from_keys(
+                            listener.host,
+                            listener.port,
+                            listener.tls_cert,
+                            listener.tls_key,
+                            listener.password,
+                        )
+                    }))
+                    .collect()
             },
+            noart: cfg.server.noart.unwrap_or(false),
+            readonly: cfg.server.readonly.unwrap_or(false),
             bgsave: if let Some(bgsave) = cfg.bgsave {
                 match (bgsave.enabled, bgsave.every) {
                     (Some(enabled), Some(every)) => BGSave::new(enabled, every),
@@ -234,76 +1010,750 @@ impl ParsedConfig {
             } else {
                 SnapshotConfig::default()
             },
+            metrics: if let Some(metrics) = cfg.metrics {
+                match metrics.enabled {
+                    Some(false) => MetricsConfig::Disabled,
+                    _ => MetricsConfig::Enabled(metrics.host, metrics.port),
+                }
+            } else {
+                MetricsConfig::default()
+            },
+            slowlog: if let Some(slowlog) = cfg.slowlog {
+                match (slowlog.threshold, slowlog.maxlen) {
+                    (Some(threshold), Some(maxlen)) => SlowlogConfig::new(threshold, maxlen),
+                    (Some(threshold), None) => SlowlogConfig::new(threshold, 128),
+                    (None, Some(maxlen)) => SlowlogConfig::new(10_000, maxlen),
+                    (None, None) => SlowlogConfig::default(),
+                }
+            } else {
+                SlowlogConfig::default()
+            },
+            limits: if let Some(limits) = cfg.limits {
+                let (maxclients, idle_timeout, max_query_size) =
+                    LimitsConfig::default().decompose();
+                LimitsConfig::new(
+                    limits.maxclients.unwrap_or(maxclients),
+                    limits.idle_timeout.unwrap_or(idle_timeout),
+                    limits.max_query_size.unwrap_or(max_query_size),
+                )
+            } else {
+                LimitsConfig::default()
+            },
+            storage: if let Some(storage) = cfg.storage {
+                let StorageConfig {
+                    data_dir,
+                    dump_filename,
+                    snapshot_dir,
+                    writelog,
+                    compression,
+                    engine,
+                } = StorageConfig::default();
+                StorageConfig::new(
+                    storage.data_dir.map(PathBuf::from).unwrap_or(data_dir),
+                    storage.dump_filename.unwrap_or(dump_filename),
+                    storage
+                        .snapshot_dir
+                        .map(PathBuf::from)
+                        .unwrap_or(snapshot_dir),
+                    storage.writelog.unwrap_or(writelog),
+                    storage.compression.unwrap_or(compression),
+                    storage.engine.unwrap_or(engine),
+                )
+            } else {
+                StorageConfig::default()
+            },
+            encryption: if let Some(encryption) = cfg.encryption {
+                EncryptionConfig::new(
+                    encryption.key_file.map(PathBuf::from),
+                    encryption.key_env,
+                    encryption
+                        .old_key_files
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(PathBuf::from)
+                        .collect(),
+                )
+            } else {
+                EncryptionConfig::default()
+            },
+            replication: if let Some(replication) = cfg.replication {
+                ReplicationConfig {
+                    password: replication.password,
+                    ..ReplicationConfig::new(
+                        replication.primary,
+                        replication
+                            .backlog_size
+                            .unwrap_or(ReplicationConfig::default().backlog_size),
+                    )
+                }
+            } else {
+                ReplicationConfig::default()
+            },
+            raft: if let Some(raft) = cfg.raft {
+                let RaftPref {
+                    election_timeout,
+                    heartbeat_interval,
+                    snapshot_after,
+                    ..
+                } = RaftPref::with_defaults(raft.id, Vec::new());
+                RaftConfig::Enabled(RaftPref {
+                    password: raft.password,
+                    ..RaftPref::new(
+                        raft.id,
+                        raft.members.unwrap_or_default(),
+                        raft.election_timeout.unwrap_or(election_timeout),
+                        raft.heartbeat_interval.unwrap_or(heartbeat_interval),
+                        raft.snapshot_after.unwrap_or(snapshot_after),
+                    )
+                })
+            } else {
+                RaftConfig::default()
+            },
+            cluster: if let Some(cluster) = cfg.cluster {
+                ClusterConfig::Enabled(
+                    cluster.addr,
+                    cluster.nodes.unwrap_or_default(),
+                    cluster.password,
+                )
+            } else {
+                ClusterConfig::default()
+            },
         }
     }
     #[cfg(test)]
     /// Create a new `ParsedConfig` from a `TOML` string
     pub fn new_from_toml_str(tomlstr: String) -> TResult<Self> {
-        Ok(ParsedConfig::from_config(toml::from_str(&tomlstr)?))
+        ParsedConfig::from_toml_value(toml::from_str(&tomlstr)?)
+            .map_err(|e| e.to_string().trim_end().into())
     }
     /// Create a new `ParsedConfig` with the default `host` and `noart` settngs
     /// and a supplied `port`
-    pub const fn default_with_port(port: u16) -> Self {
+    pub fn default_with_port(port: u16) -> Self {
         ParsedConfig {
-            host: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
-            port,
+            listeners: vec![ListenerConfig::new(
+                IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                port,
+            )],
             noart: false,
+            readonly: false,
             bgsave: BGSave::default(),
             snapshot: SnapshotConfig::default(),
+            metrics: MetricsConfig::default(),
+            slowlog: SlowlogConfig::default(),
+            limits: LimitsConfig::default(),
+            storage: StorageConfig::default(),
+            encryption: EncryptionConfig::default(),
+            replication: ReplicationConfig::default(),
+            raft: RaftConfig::default(),
+            cluster: ClusterConfig::default(),
         }
     }
     /// Create a new `ParsedConfig` with the default `port` and `noart` settngs
     /// and a supplied `host`
-    pub const fn default_with_host(host: IpAddr) -> Self {
+    pub fn default_with_host(host: IpAddr) -> Self {
         ParsedConfig::new(
-            host,
-            2003,
+            vec![ListenerConfig::new(host, 2003)],
+            false,
             false,
             BGSave::default(),
             SnapshotConfig::default(),
+            MetricsConfig::default(),
+            SlowlogConfig::default(),
+            LimitsConfig::default(),
+            StorageConfig::default(),
+            EncryptionConfig::default(),
+            ReplicationConfig::default(),
+            RaftConfig::default(),
+            ClusterConfig::default(),
         )
     }
+    #[allow(clippy::too_many_arguments)]
     /// Create a new `ParsedConfig` with all the fields
-    pub const fn new(
-        host: IpAddr,
-        port: u16,
+    pub fn new(
+        listeners: Vec<ListenerConfig>,
         noart: bool,
+        readonly: bool,
         bgsave: BGSave,
         snapshot: SnapshotConfig,
+        metrics: MetricsConfig,
+        slowlog: SlowlogConfig,
+        limits: LimitsConfig,
+        storage: StorageConfig,
+        encryption: EncryptionConfig,
+        replication: ReplicationConfig,
+        raft: RaftConfig,
+        cluster: ClusterConfig,
     ) -> Self {
         ParsedConfig {
-            host,
-            port,
+            listeners,
             noart,
+            readonly,
             bgsave,
             snapshot,
+            metrics,
+            slowlog,
+            limits,
+            storage,
+            encryption,
+            replication,
+            raft,
+            cluster,
         }
     }
     /// Create a default `ParsedConfig` with the following setup defaults:
     /// - `host`: 127.0.0.1
     /// - `port` : 2003
     /// - `noart` : false
+    /// - `readonly` : false
     /// - `bgsave_enabled` : true
     /// - `bgsave_duration` : 120
-    pub const fn default() -> Self {
+    /// - `metrics` : disabled
+    /// - `slowlog_threshold` : 10000
+    /// - `slowlog_maxlen` : 128
+    /// - `maxclients` : 50000
+    /// - `idle_timeout` : 0
+    /// - `max_query_size` : 64 MiB
+    /// - `data_dir` : `.`
+    pub fn default() -> Self {
         ParsedConfig {
-            host: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
-            port: 2003,
+            listeners: vec![ListenerConfig::new(
+                IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                2003,
+            )],
             noart: false,
+            readonly: false,
             bgsave: BGSave::default(),
             snapshot: SnapshotConfig::default(),
+            metrics: MetricsConfig::default(),
+            slowlog: SlowlogConfig::default(),
+            limits: LimitsConfig::default(),
+            storage: StorageConfig::default(),
+            encryption: EncryptionConfig::default(),
+            replication: ReplicationConfig::default(),
+            raft: RaftConfig::default(),
+            cluster: ClusterConfig::default(),
+        }
+    }
+    /// Returns `false` if `noart` is enabled. Otherwise it returns `true`
+    pub const fn is_artful(&self) -> bool {
+        !self.noart
+    }
+    /// Returns the `Config` object which results in this configuration when it is parsed
+    ///
+    /// Every setting is spelled out, even if it has its default value. The snapshot,
+    /// metrics, Raft and cluster sections are left out if they are disabled
+    fn to_config(&self) -> Config {
+        let path = |path: &Path| path.to_string_lossy().into_owned();
+        let (server, more) = self.listeners.split_first().expect("There's no listener");
+        Config {
+            server: ConfigKeyServer {
+                host: server.host,
+                port: server.port,
+                noart: Some(self.noart),
+                readonly: Some(self.readonly),
+                tls_cert: server.tls_cert.as_deref().map(path),
+                tls_key: server.tls_key.as_deref().map(path),
+                password: server.password.clone(),
+                listeners: if more.is_empty() {
+                    None
+                } else {
+                    Some(
+                        more.iter()
+                            .map(|listener| ConfigKeyListener {
+                                host: listener.host,
+                                port: listener.port,
+                                tls_cert: listener.tls_cert.as_deref().map(path),
+                                tls_key: listener.tls_key.as_deref().map(path),
+                                password: listener.password.clone(),
+                            })
+                            .collect(),
+                    )
+                },
+            },
+            bgsave: Some(match self.bgsave {
+                BGSave::Enabled(every) => ConfigKeyBGSAVE {
+                    enabled: Some(true),
+                    every: Some(every),
+                },
+                BGSave::Disabled => ConfigKeyBGSAVE {
+                    enabled: Some(false),
+                    every: None,
+                },
+            }),
+            snapshot: match self.snapshot {
+                SnapshotConfig::Enabled(pref) => Some(ConfigKeySnapshot {
+                    enabled: true,
+                    every: pref.every,
+                    atmost: pref.atmost,
+                }),
+                SnapshotConfig::Disabled => None,
+            },
+            metrics: match self.metrics {
+                MetricsConfig::Enabled(host, port) => Some(ConfigKeyMetrics {
+                    enabled: Some(true),
+                    host,
+                    port,
+                }),
+                MetricsConfig::Disabled => None,
+            },
+            slowlog: Some(ConfigKeySlowlog {
+                threshold: Some(self.slowlog.threshold),
+                maxlen: Some(self.slowlog.maxlen),
+            }),
+            limits: Some(ConfigKeyLimits {
+                maxclients: Some(self.limits.maxclients),
+                idle_timeout: Some(self.limits.idle_timeout),
+                max_query_size: Some(self.limits.max_query_size),
+            }),
+            storage: Some(ConfigKeyStorage {
+                data_dir: Some(path(&self.storage.data_dir)),
+                dump_filename: Some(self.storage.dump_filename.clone()),
+                snapshot_dir: Some(path(&self.storage.snapshot_dir)),
+                writelog: Some(self.storage.writelog),
+                compression: Some(self.storage.compression),
+                engine: Some(self.storage.engine),
+            }),
+            encryption: Some(ConfigKeyEncryption {
+                key_file: self.encryption.key_file.as_deref().map(path),
+                key_env: self.encryption.key_env.clone(),
+                old_key_files: Some(
+                    self.encryption
+                        .old_key_files
+                        .iter()
+                        .map(|file| path(file))
+                        .collect(),
+                ),
+            }),
+            replication: Some(ConfigKeyReplication {
+                primary: self.replication.primary.clone(),
+                backlog_size: Some(self.replication.backlog_size),
+                password: self.replication.password.clone(),
+            }),
+            raft: match &self.raft {
+                RaftConfig::Enabled(pref) => Some(ConfigKeyRaft {
+                    id: pref.id,
+                    members: Some(pref.members.clone()),
+                    election_timeout: Some(pref.election_timeout),
+                    heartbeat_interval: Some(pref.heartbeat_interval),
+                    snapshot_after: Some(pref.snapshot_after),
+                    password: pref.password.clone(),
+                }),
+                RaftConfig::Disabled => None,
+            },
+            cluster: match &self.cluster {
+                ClusterConfig::Enabled(addr, nodes, password) => Some(ConfigKeyCluster {
+                    addr: addr.clone(),
+                    nodes: Some(nodes.clone()),
+                    password: password.clone(),
+                }),
+                ClusterConfig::Disabled => None,
+            },
+        }
+    }
+    /// Returns the `Config` object for this configuration with the passwords replaced,
+    /// for showing it
+    fn to_redacted_config(&self) -> Config {
+        let mut config = self.to_config();
+        let redact = |password: &mut Option<String>| {
+            if let Some(password) = password {
+                *password = REDACTED.to_owned();
+            }
+        };
+        redact(&mut config.server.password);
+        for listener in config.server.listeners.iter_mut().flatten() {
+            redact(&mut listener.password);
+        }
+        for replication in config.replication.iter_mut() {
+            redact(&mut replication.password);
+        }
+        for raft in config.raft.iter_mut() {
+            redact(&mut raft.password);
+        }
+        for cluster in config.cluster.iter_mut() {
+            redact(&mut cluster.password);
+        }
+        config
+    }
+    /// Returns this configuration rendered as a TOML file, with the passwords replaced
+    /// by `***`
+    pub fn to_toml(&self) -> TResult<String> {
+        // Going through a `Value` puts the keys of a section before its tables, which
+        // TOML requires
+        Ok(toml::to_string(&toml::Value::try_from(
+            self.to_redacted_config(),
+        )?)?)
+    }
+    /// Returns every setting as a `section.key` name and its value, sorted by name
+    ///
+    /// Strings are returned as they are, and other values are rendered as TOML values.
+    /// The passwords are replaced by `***`
+    pub fn settings(&self) -> TResult<Vec<(String, String)>> {
+        let mut settings = Vec::new();
+        if let toml::Value::Table(sections) = toml::Value::try_from(self.to_redacted_config())? {
+            for (section, keys) in sections {
+                if let toml::Value::Table(keys) = keys {
+                    for (key, value) in keys {
+                        let value = match value {
+                            toml::Value::String(value) => value,
+                            value => render_value(&value),
+                        };
+                        settings.push((format!("{}.{}", section, key), value));
+                    }
+                }
+            }
+        }
+        Ok(settings)
+    }
+    /// Returns a copy of this configuration with the `section.key` settings in `changes`
+    /// set to the given values
+    ///
+    /// Only the settings in [`HOT_SETTINGS`] can be changed. Values are parsed as TOML
+    /// values, and as strings if they aren't valid TOML. If a setting can't be changed
+    /// or a value is invalid, the error explains why
+    pub fn with_settings(&self, changes: &[(String, String)]) -> Result<Self, String> {
+        for (name, _) in changes {
+            if !HOT_SETTINGS.contains(&name.as_str()) {
+                let known = OPTIONS.iter().any(|(setting, _)| setting == name);
+                return Err(if known {
+                    format!("'{}' can't be changed while the server is running", name)
+                } else {
+                    format!("Unknown setting '{}'", name)
+                });
+            }
+        }
+        self.with_options(changes)
+    }
+    /// Returns a copy of this configuration with the `section.key` settings in `changes`,
+    /// which can be any of the [`OPTIONS`], set to the given values
+    fn with_options(&self, changes: &[(String, String)]) -> Result<Self, String> {
+        let mut config = toml::Value::try_from(self.to_config()).map_err(|e| e.to_string())?;
+        set_options(&mut config, changes);
+        match ParsedConfig::from_toml_value(config) {
+            Ok(config) => Ok(config),
+            Err(ConfigError::Invalid(problems)) => {
+                Err(format!("Invalid setting: {}", problems.join(", ")))
+            }
+            Err(e) => Err(format!("Invalid setting: {}", e.to_string().trim_end())),
+        }
+    }
+    /// Returns every problem with the settings in this configuration, each starting with
+    /// the setting's path in the TOML file
+    ///
+    /// This catches values that are contradictory or can't work, like a BGSAVE interval
+    /// of 0. Values that the server can only check once it starts, like whether the key
+    /// files exist, aren't checked here
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let BGSave::Enabled(0) = self.bgsave {
+            problems.push("bgsave.every: must be greater than 0 when BGSAVE is enabled".to_owned());
+        }
+        if let SnapshotConfig::Enabled(SnapshotPref { every: 0, .. }) = self.snapshot {
+            problems.push(
+                "snapshot.every: must be greater than 0 when snapshots are enabled".to_owned(),
+            );
+        }
+        for (i, listener) in self.listeners.iter().enumerate() {
+            // The first listener is the one in the `server` section
+            let path = match i {
+                0 => "server".to_owned(),
+                i => format!("server.listeners[{}]", i - 1),
+            };
+            match (&listener.tls_cert, &listener.tls_key) {
+                (Some(_), None) => problems.push(format!(
+                    "{}.tls_key: must be set along with `tls_cert`",
+                    path
+                )),
+                (None, Some(_)) => problems.push(format!(
+                    "{}.tls_cert: must be set along with `tls_key`",
+                    path
+                )),
+                _ => (),
+            }
+            if listener.password.as_deref() == Some("") {
+                problems.push(format!("{}.password: can't be empty", path));
+            }
+            let used = self.listeners[..i]
+                .iter()
+                .any(|l| l.addr() == listener.addr());
+            if listener.port != 0 && used {
+                problems.push(format!(
+                    "{}: {} is used by another listener",
+                    path,
+                    listener.addr()
+                ));
+            }
+        }
+        if let MetricsConfig::Enabled(host, port) = self.metrics {
+            let addr = SocketAddr::new(host, port);
+            if port != 0 && self.listeners.iter().any(|l| l.addr() == addr) {
+                problems.push(format!("metrics.port: {} is used by a listener", addr));
+            }
+        }
+        let (maxclients, _, max_query_size) = self.limits.decompose();
+        if maxclients == 0 {
+            problems.push("limits.maxclients: must be greater than 0".to_owned());
+        }
+        if max_query_size == 0 {
+            problems.push("limits.max_query_size: must be greater than 0".to_owned());
+        }
+        if self.storage.data_dir.as_os_str().is_empty() {
+            problems.push("storage.data_dir: can't be empty".to_owned());
+        }
+        if self.storage.dump_filename.is_empty() {
+            problems.push("storage.dump_filename: can't be empty".to_owned());
+        }
+        if self.storage.writelog {
+            // The write log is only replayed on top of a snapshot, and its old segments
+            // are only deleted when a snapshot is created
+            if !self.snapshot.is_enabled() {
+                problems.push("storage.writelog: needs snapshots to be enabled".to_owned());
+            }
+            if self.storage.engine == Engine::Log {
+                problems.push("storage.writelog: can't be used with the log engine".to_owned());
+            }
+        }
+        if self.encryption.key_file.is_some() && self.encryption.key_env.is_some() {
+            problems.push("encryption.key_env: can't be set along with `key_file`".to_owned());
+        }
+        if self.encryption.is_enabled() {
+            // These write the values to the data directory without encrypting them
+            if self.storage.writelog {
+                problems.push(
+                    "storage.writelog: can't be used with encryption, since the write log isn't encrypted"
+                        .to_owned(),
+                );
+            }
+            if self.replication.primary.is_some() {
+                problems.push(
+                    "replication.primary: can't be used with encryption, since the copy of the primary's data isn't encrypted"
+                        .to_owned(),
+                );
+            }
+            if let RaftConfig::Enabled(_) = self.raft {
+                problems.push(
+                    "raft: can't be used with encryption, since the Raft log isn't encrypted"
+                        .to_owned(),
+                );
+            }
+        }
+        if self.replication.password.as_deref() == Some("") {
+            problems.push("replication.password: can't be empty".to_owned());
+        }
+        if let Some(primary) = &self.replication.primary {
+            if !is_host_port(primary) {
+                problems.push(format!(
+                    "replication.primary: '{}' isn't a `host:port` address",
+                    primary
+                ));
+            }
+        }
+        if let RaftConfig::Enabled(pref) = &self.raft {
+            if !pref.members.is_empty() && !pref.members.iter().any(|m| m.id == pref.id) {
+                problems.push(format!(
+                    "raft.members: must include this node (with the ID {})",
+                    pref.id
+                ));
+            }
+            for (i, member) in pref.members.iter().enumerate() {
+                if pref.members[..i].iter().any(|m| m.id == member.id) {
+                    problems.push(format!(
+                        "raft.members[{}].id: {} is the ID of another member",
+                        i, member.id
+                    ));
+                }
+                if !is_host_port(&member.addr) {
+                    problems.push(format!(
+                        "raft.members[{}].addr: '{}' isn't a `host:port` address",
+                        i, member.addr
+                    ));
+                }
+            }
+            if pref.heartbeat_interval == 0 {
+                problems.push("raft.heartbeat_interval: must be greater than 0".to_owned());
+            }
+            if pref.election_timeout <= pref.heartbeat_interval {
+                problems.push(
+                    "raft.election_timeout: must be greater than `heartbeat_interval`".to_owned(),
+                );
+            }
+            if pref.snapshot_after == 0 {
+                problems.push("raft.snapshot_after: must be greater than 0".to_owned());
+            }
+            if pref.password.as_deref() == Some("") {
+                problems.push("raft.password: can't be empty".to_owned());
+            }
+            if self.storage.engine != Engine::Memory {
+                problems
+                    .push("storage.engine: Raft mode only works with the memory engine".to_owned());
+            }
+            if self.replication.primary.is_some() {
+                problems.push(
+                    "replication.primary: a node in a Raft group can't replicate a primary"
+                        .to_owned(),
+                );
+            }
+            if let ClusterConfig::Enabled(..) = self.cluster {
+                problems.push(
+                    "cluster: a node in a Raft group can't be a node in a cluster".to_owned(),
+                );
+            }
+        }
+        if let ClusterConfig::Enabled(addr, nodes, password) = &self.cluster {
+            if !is_host_port(addr) {
+                problems.push(format!(
+                    "cluster.addr: '{}' isn't a `host:port` address",
+                    addr
+                ));
+            }
+            if password.as_deref() == Some("") {
+                problems.push("cluster.password: can't be empty".to_owned());
+            }
+            for (i, node) in nodes.iter().enumerate() {
+                for (j, range) in node.slots.iter().enumerate() {
+                    if libtdb::cluster::parse_slots(range).is_none() {
+                        problems.push(format!(
+                            "cluster.nodes[{}].slots[{}]: '{}' isn't a valid slot range",
+                            i, j, range
+                        ));
+                    }
+                }
+            }
+        }
+        problems
+    }
+    /// Create a new `ParsedConfig` from a parsed TOML file, checking that every section
+    /// and key in it is known and that the settings make sense
+    ///
+    /// If they don't, every problem is returned, with its path in the TOML file
+    fn from_toml_value(value: toml::Value) -> Result<Self, ConfigError> {
+        let mut problems = unknown_keys(&value);
+        match value.try_into() {
+            Ok(cfg) => {
+                let cfg = ParsedConfig::from_config(cfg);
+                problems.extend(cfg.problems());
+                if problems.is_empty() {
+                    Ok(cfg)
+                } else {
+                    Err(ConfigError::Invalid(problems))
+                }
+            }
+            Err(e) if problems.is_empty() => Err(ConfigError::SyntaxError(e.into())),
+            Err(e) => {
+                problems.push(e.to_string());
+                Err(ConfigError::Invalid(problems))
+            }
+        }
+    }
+    /// Copy the settings in [`HOT_SETTINGS`] from `other`, keeping everything else
+    pub fn set_hot_settings(&mut self, other: &ParsedConfig) {
+        self.readonly = other.readonly;
+        self.bgsave = other.bgsave;
+        self.snapshot = other.snapshot;
+        self.slowlog = other.slowlog;
+        self.limits = other.limits;
+    }
+}
+
+/// Set the `section.key` settings in `changes` in the parsed TOML file `config`
+///
+/// Values are parsed as TOML values, and as strings if they aren't valid TOML
+fn set_options(config: &mut toml::Value, changes: &[(String, String)]) {
+    for (name, value) in changes {
+        // Every option is a `section.key` name
+        let dot = name.find('.').unwrap_or_default();
+        let (section, key) = (&name[..dot], &name[dot + 1..]);
+        if let toml::Value::Table(sections) = config {
+            let section = sections
+                .entry(section)
+                .or_insert_with(|| toml::Value::Table(toml::value::Table::new()));
+            if let toml::Value::Table(keys) = section {
+                keys.insert(key.to_owned(), parse_value(value));
+            }
+        }
+    }
+}
+
+/// Render `value` as an inline TOML value
+fn render_value(value: &toml::Value) -> String {
+    match value {
+        toml::Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(render_value).collect();
+            format!("[{}]", items.join(", "))
+        }
+        toml::Value::Table(keys) => {
+            let keys: Vec<String> = keys
+                .iter()
+                .map(|(key, value)| format!("{} = {}", key, render_value(value)))
+                .collect();
+            format!("{{ {} }}", keys.join(", "))
+        }
+        value => value.to_string(),
+    }
+}
+
+/// Returns a problem for every section and key in `config` (a parsed TOML file) that
+/// isn't one of the [`OPTIONS`], with its path in the file
+fn unknown_keys(config: &toml::Value) -> Vec<String> {
+    let mut problems = Vec::new();
+    let sections = match config {
+        toml::Value::Table(sections) => sections,
+        _ => return problems,
+    };
+    for (section, keys) in sections {
+        let prefix = format!("{}.", section);
+        if !OPTIONS.iter().any(|(name, _)| name.starts_with(&prefix)) {
+            problems.push(format!("{}: unknown section", section));
+            continue;
+        }
+        let keys = match keys {
+            toml::Value::Table(keys) => keys,
+            // The type is checked when the file is deserialized
+            _ => continue,
+        };
+        for (key, value) in keys {
+            let name = format!("{}.{}", section, key);
+            if !OPTIONS.iter().any(|(option, _)| *option == name) {
+                problems.push(format!("{}: unknown key", name));
+                continue;
+            }
+            let fields: &[&str] = match name.as_str() {
+                "server.listeners" => &["host", "port", "tls_cert", "tls_key", "password"],
+                "raft.members" => &["id", "addr"],
+                "cluster.nodes" => &["addr", "slots"],
+                _ => continue,
+            };
+            if let toml::Value::Array(items) = value {
+                for (i, item) in items.iter().enumerate() {
+                    if let toml::Value::Table(item) = item {
+                        for field in item.keys() {
+                            if !fields.contains(&field.as_str()) {
+                                problems.push(format!("{}[{}].{}: unknown key", name, i, field));
+                            }
+                        }
+                    }
+                }
+            }
         }
     }
-    /// Return a (host, port) tuple which can be bound to with `TcpListener`
-    pub fn get_host_port_tuple(&self) -> impl ToSocketAddrs {
-        ((self.host), self.port)
-    }
-    /// Returns `false` if `noart` is enabled. Otherwise it returns `true`
-    pub const fn is_artful(&self) -> bool {
-        !self.noart
+    problems
+}
+
+/// Check if `addr` looks like a `host:port` address
+fn is_host_port(addr: &str) -> bool {
+    match addr.rfind(':') {
+        Some(colon) => colon > 0 && addr[colon + 1..].parse::<u16>().is_ok(),
+        None => false,
     }
 }
 
+/// Parse the value of a setting as a TOML value, or as a string if it isn't one
+fn parse_value(value: &str) -> toml::Value {
+    toml::from_str::<toml::value::Table>(&format!("value = {}", value))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_owned()))
+}
+
 #[test]
 #[cfg(test)]
 fn test_config_toml_okayport() {
@@ -356,22 +1806,113 @@ fn test_config_file_err() {
     let cfg = ParsedConfig::new_from_file(file);
     assert!(cfg.is_err());
 }
-use clap::{load_yaml, App};
+use clap::{load_yaml, App, Arg};
 
 /// The type of configuration:
 /// - We either used a custom configuration file given to us by the user (`Custom`) OR
 /// - We used the default configuration (`Def`)
 pub enum ConfigType<T> {
     Def(T),
-    Custom(T),
+    /// The configuration, and the file that it was read from
+    Custom(T, ConfigFile),
+}
+
+/// A configuration file, along with the settings from the command line and the
+/// environment that override the ones in it
+#[derive(Debug, PartialEq, Clone)]
+pub struct ConfigFile {
+    /// The path to the file
+    path: String,
+    /// The `section.key` settings that override the file, and their values
+    overrides: Vec<(String, String)>,
+}
+
+impl ConfigFile {
+    /// Create a new `ConfigFile` instance
+    pub fn new(path: String, overrides: Vec<(String, String)>) -> Self {
+        ConfigFile { path, overrides }
+    }
+    /// Read the file and apply the overrides to it
+    ///
+    /// The settings are only checked once the overrides are applied, so an override can
+    /// fix a setting in the file
+    pub fn load(&self) -> Result<ParsedConfig, ConfigError> {
+        let mut config = self.read()?;
+        set_options(&mut config, &self.overrides);
+        ParsedConfig::from_toml_value(config)
+    }
+    /// Write the settings in `effective` that were changed while the server was running
+    /// to the file, replacing the file in one go so that it is never left half-written
+    ///
+    /// A setting was changed if its effective value isn't what the file and the overrides
+    /// make it, like after a `CONFIG SET`. Everything else is written as it is in the
+    /// file (without the comments), so the overrides don't end up in it
+    pub fn rewrite(&self, effective: &ParsedConfig) -> TResult<()> {
+        let problem = |e: ConfigError| e.to_string().trim_end().to_owned();
+        let mut config = self.read().map_err(problem)?;
+        let loaded = self.load().map_err(problem)?.settings()?;
+        let effective = effective.settings()?;
+        let value_of = |settings: &[(String, String)], name: &str| {
+            settings
+                .iter()
+                .find(|(setting, _)| setting == name)
+                .map(|(_, value)| value.clone())
+        };
+        let changes: Vec<(String, String)> = HOT_SETTINGS
+            .iter()
+            .filter_map(|name| {
+                let value = value_of(&effective, name);
+                if value == value_of(&loaded, name) {
+                    return None;
+                }
+                match value {
+                    Some(value) => Some(((*name).to_owned(), value)),
+                    // The section is left out when it is disabled
+                    None if name.ends_with(".enabled") => {
+                        Some(((*name).to_owned(), "false".to_owned()))
+                    }
+                    None => None,
+                }
+            })
+            .collect();
+        set_options(&mut config, &changes);
+        let tmp = Path::new(&self.path).with_extension("tmp");
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(toml::to_string(&config)?.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+    /// Read the file as a TOML value
+    fn read(&self) -> Result<toml::Value, ConfigError> {
+        let file = fs::read_to_string(&self.path).map_err(|e| ConfigError::OSError(e.into()))?;
+        toml::from_str(&file).map_err(|e| ConfigError::SyntaxError(e.into()))
+    }
+}
+
+impl AsRef<Path> for ConfigFile {
+    fn as_ref(&self) -> &Path {
+        Path::new(&self.path)
+    }
+}
+
+impl fmt::Display for ConfigFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path)
+    }
 }
 
 /// Type of configuration error:
 /// - The config file was not found (`OSError`)
 /// - THe config file was invalid (`SyntaxError`)
+/// - A command line argument was invalid (`ArgError`)
+/// - The settings were invalid (`Invalid`)
 pub enum ConfigError {
     OSError(Box<dyn Error>),
     SyntaxError(Box<dyn Error>),
+    ArgError(String),
+    /// The configuration has unknown sections or keys, or settings that don't make sense
+    Invalid(Vec<String>),
 }
 
 impl fmt::Display for ConfigError {
@@ -379,32 +1920,151 @@ impl fmt::Display for ConfigError {
         match self {
             ConfigError::OSError(e) => write!(f, "error: {}\n", e),
             ConfigError::SyntaxError(e) => write!(f, "syntax error in configuration file: {}\n", e),
+            ConfigError::ArgError(e) => write!(f, "error: {}\n", e),
+            ConfigError::Invalid(problems) => {
+                writeln!(f, "invalid configuration:")?;
+                for problem in problems {
+                    writeln!(f, "    {}", problem)?;
+                }
+                Ok(())
+            }
         }
     }
 }
 
+/// Point-in-time recovery or salvage, as requested on the command line
+#[derive(Debug, PartialEq)]
+pub enum RecoveryMode {
+    /// Recover the data to the given time (a UNIX timestamp in milliseconds) and then
+    /// start the server (`--recover-to`)
+    OnStartup(u64),
+    /// Recover the data to `target`, write it to `output` (or the dump file) and exit
+    /// without starting the server (`tdb recover`)
+    Offline {
+        target: u64,
+        output: Option<PathBuf>,
+    },
+    /// Salvage the intact records in `input` (or the dump file), write them to `output`
+    /// (or back to the input) and exit without starting the server (`tdb salvage`)
+    Salvage {
+        input: Option<PathBuf>,
+        output: Option<PathBuf>,
+    },
+}
+
+/// What should be done with the configuration once it's loaded, as asked for on the
+/// command line
+pub struct ConfigFlags {
+    /// Print the configuration when the server starts (`--print-config`)
+    pub print_config: bool,
+    /// Only check the configuration, without starting the server (`--check-config`)
+    pub check_config: bool,
+}
+
 /// This function returns a  `ConfigType<ParsedConfig>`
 ///
 /// This parses a configuration file if it is supplied as a command line argument
 /// or it returns the default configuration. **If** the configuration file
 /// contains an error, then this returns it as an `Err` variant
-pub fn get_config_file_or_return_cfg() -> Result<ConfigType<ParsedConfig>, ConfigError> {
+///
+/// The [`OPTIONS`] set with flags or environment variables override the ones in the
+/// file. This also returns the point-in-time recovery or salvage that was asked for, if
+/// any, and what should be done with the configuration once it's loaded
+pub fn get_config_file_or_return_cfg(
+) -> Result<(ConfigType<ParsedConfig>, Option<RecoveryMode>, ConfigFlags), ConfigError> {
+    get_config_from_args(std::env::args_os())
+}
+
+/// Does what [`get_config_file_or_return_cfg`] does, with the given command line
+/// arguments
+fn get_config_from_args<I, T>(
+    args: I,
+) -> Result<(ConfigType<ParsedConfig>, Option<RecoveryMode>, ConfigFlags), ConfigError>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
     let cfg_layout = load_yaml!("../cli.yml");
-    let matches = App::from_yaml(cfg_layout).get_matches();
-    let filename = matches.value_of("config");
-    if let Some(filename) = filename {
-        match ParsedConfig::new_from_file(filename.to_owned()) {
-            Ok(cfg) => {
-                if cfg.bgsave.is_disabled() {
-                    log::warn!("BGSAVE is disabled: If this system crashes unexpectedly, it may lead to the loss of data");
+    let names: Vec<(String, String)> = OPTIONS
+        .iter()
+        .map(|(name, _)| (flag_name(name), env_name(name)))
+        .collect();
+    let options = OPTIONS.iter().zip(&names).map(|((_, help), (flag, env))| {
+        Arg::with_name(flag)
+            .long(flag)
+            .env(env)
+            .value_name("value")
+            .help(help)
+            .takes_value(true)
+    });
+    let matches = App::from_yaml(cfg_layout)
+        .args(&options.collect::<Vec<_>>())
+        .get_matches_from(args);
+    let overrides: Vec<(String, String)> = OPTIONS
+        .iter()
+        .zip(&names)
+        .filter_map(|((name, _), (flag, _))| {
+            matches
+                .value_of(flag)
+                .map(|value| ((*name).to_owned(), value.to_owned()))
+        })
+        .collect();
+    if !overrides.is_empty() {
+        log::info!(
+            "Using {} setting(s) from the command line or the environment",
+            overrides.len()
+        );
+    }
+    let (cfg, cfg_file) = if let Some(filename) = matches.value_of("config") {
+        let cfg_file = ConfigFile::new(filename.to_owned(), overrides);
+        (cfg_file.load()?, Some(cfg_file))
+    } else {
+        let cfg = ParsedConfig::default()
+            .with_options(&overrides)
+            .map_err(ConfigError::ArgError)?;
+        (cfg, None)
+    };
+    if cfg.bgsave.is_disabled() {
+        log::warn!("BGSAVE is disabled: If this system crashes unexpectedly, it may lead to the loss of data");
+    }
+    let mut cfg = match cfg_file {
+        Some(cfg_file) => ConfigType::Custom(cfg, cfg_file),
+        None => ConfigType::Def(cfg),
+    };
+    let parse_target =
+        |target: &str| writelog::parse_target(target).map_err(ConfigError::ArgError);
+    let mut set_data_dir = |data_dir: Option<&str>| {
+        if let Some(data_dir) = data_dir {
+            match &mut cfg {
+                ConfigType::Def(cfg) | ConfigType::Custom(cfg, _) => {
+                    cfg.storage.set_data_dir(data_dir)
                 }
-                return Ok(ConfigType::Custom(cfg));
             }
-            Err(e) => return Err(e),
         }
+    };
+    let recovery = if let Some(recover) = matches.subcommand_matches("recover") {
+        set_data_dir(recover.value_of("data-dir"));
+        Some(RecoveryMode::Offline {
+            // `to` is a required argument
+            target: parse_target(recover.value_of("to").unwrap())?,
+            output: recover.value_of("output").map(PathBuf::from),
+        })
+    } else if let Some(salvage) = matches.subcommand_matches("salvage") {
+        set_data_dir(salvage.value_of("data-dir"));
+        Some(RecoveryMode::Salvage {
+            input: salvage.value_of("input").map(PathBuf::from),
+            output: salvage.value_of("output").map(PathBuf::from),
+        })
+    } else if let Some(target) = matches.value_of("recover-to") {
+        Some(RecoveryMode::OnStartup(parse_target(target)?))
     } else {
-        Ok(ConfigType::Def(ParsedConfig::default()))
-    }
+        None
+    };
+    let flags = ConfigFlags {
+        print_config: matches.is_present("print-config"),
+        check_config: matches.is_present("check-config"),
+    };
+    Ok((cfg, recovery, flags))
 }
 
 #[test]
@@ -419,6 +2079,80 @@ fn test_args() {
     assert_eq!(cfg, ParsedConfig::default());
 }
 
+#[test]
+#[cfg(test)]
+fn test_args_options() {
+    assert_eq!(flag_name("limits.max_query_size"), "limits-max-query-size");
+    assert_eq!(
+        env_name("limits.max_query_size"),
+        "TDB_LIMITS_MAX_QUERY_SIZE"
+    );
+    for file in &[
+        "template.toml",
+        "raft1.toml",
+        "cluster1.toml",
+        "encryption.toml",
+    ] {
+        let file = get_toml_from_examples_dir((*file).to_owned()).unwrap();
+        let cfg = ParsedConfig::new_from_toml_str(file).unwrap();
+        for (name, _) in cfg.settings().unwrap() {
+            assert!(
+                OPTIONS.iter().any(|(option, _)| *option == name),
+                "{}",
+                name
+            );
+        }
+    }
+    // Flags take precedence over the environment, which takes precedence over the file
+    std::env::set_var("TDB_SLOWLOG_THRESHOLD", "5");
+    std::env::set_var("TDB_SLOWLOG_MAXLEN", "9");
+    let args = vec![
+        "tdb",
+        "-c",
+        "../examples/config-files/slowlog.toml",
+        "--slowlog-maxlen",
+        "7",
+        "--raft-id",
+        "1",
+        "--raft-members",
+        r#"[{ id = 1, addr = "127.0.0.1:2003" }]"#,
+        "--storage-data-dir",
+        "/var/lib/tdb",
+        "--print-config",
+    ];
+    let (cfg, recovery, flags) = get_config_from_args(args).ok().unwrap();
+    std::env::remove_var("TDB_SLOWLOG_THRESHOLD");
+    std::env::remove_var("TDB_SLOWLOG_MAXLEN");
+    let cfg = match cfg {
+        ConfigType::Custom(cfg, cfg_file) => {
+            // Reloading the file keeps the settings from the command line
+            assert_eq!(cfg_file.load().ok().unwrap(), cfg);
+            cfg
+        }
+        ConfigType::Def(_) => panic!("The configuration file wasn't used"),
+    };
+    assert_eq!(cfg.slowlog, SlowlogConfig::new(5, 7));
+    assert_eq!(
+        cfg.raft,
+        RaftConfig::Enabled(RaftPref::with_defaults(
+            1,
+            vec![RaftMember {
+                id: 1,
+                addr: "127.0.0.1:2003".to_owned()
+            }]
+        ))
+    );
+    assert_eq!(cfg.storage.data_dir(), Path::new("/var/lib/tdb"));
+    assert_eq!(recovery, None);
+    assert!(flags.print_config && !flags.check_config);
+    // A section still needs all of its required keys
+    let args = vec!["tdb", "--metrics-port", "2004"];
+    match get_config_from_args(args) {
+        Err(ConfigError::ArgError(e)) => assert!(e.starts_with("Invalid setting")),
+        _ => panic!("Expected an error"),
+    }
+}
+
 #[test]
 #[cfg(test)]
 fn test_config_file_noart() {
@@ -427,15 +2161,165 @@ fn test_config_file_noart() {
     assert_eq!(
         cfg,
         ParsedConfig {
-            port: 2003,
-            host: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            listeners: vec![ListenerConfig::new(
+                IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                2003
+            )],
             noart: true,
+            readonly: false,
+            bgsave: BGSave::default(),
+            snapshot: SnapshotConfig::default(),
+            metrics: MetricsConfig::default(),
+            slowlog: SlowlogConfig::default(),
+            limits: LimitsConfig::default(),
+            storage: StorageConfig::default(),
+            encryption: EncryptionConfig::default(),
+            replication: ReplicationConfig::default(),
+            raft: RaftConfig::default(),
+            cluster: ClusterConfig::default(),
+        }
+    );
+}
+
+#[test]
+#[cfg(test)]
+fn test_config_file_readonly() {
+    let file = get_toml_from_examples_dir("readonly.toml".to_owned()).unwrap();
+    let cfg = ParsedConfig::new_from_toml_str(file).unwrap();
+    assert_eq!(
+        cfg,
+        ParsedConfig {
+            listeners: vec![ListenerConfig::new(
+                IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                2003
+            )],
+            noart: false,
+            readonly: true,
             bgsave: BGSave::default(),
             snapshot: SnapshotConfig::default(),
+            metrics: MetricsConfig::default(),
+            slowlog: SlowlogConfig::default(),
+            limits: LimitsConfig::default(),
+            storage: StorageConfig::default(),
+            encryption: EncryptionConfig::default(),
+            replication: ReplicationConfig::default(),
+            raft: RaftConfig::default(),
+            cluster: ClusterConfig::default(),
         }
     );
 }
 
+#[test]
+#[cfg(test)]
+fn test_config_file_listeners() {
+    let file = get_toml_from_examples_dir("listeners.toml".to_owned()).unwrap();
+    let cfg = ParsedConfig::new_from_toml_str(file).unwrap();
+    assert_eq!(
+        cfg.listeners,
+        vec![
+            ListenerConfig::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 2003),
+            ListenerConfig::new(IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0x1)), 2003),
+            ListenerConfig {
+                host: IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
+                port: 2005,
+                tls_cert: Some(PathBuf::from("cert.pem")),
+                tls_key: Some(PathBuf::from("key.pem")),
+                password: Some("hunter2".to_owned()),
+            },
+        ]
+    );
+    // The listeners should survive being rendered, except for their passwords
+    let rendered = cfg.to_toml().unwrap();
+    assert!(!rendered.contains("hunter2"));
+    let mut redacted = cfg.clone();
+    redacted.listeners[2].password = Some("***".to_owned());
+    assert_eq!(ParsedConfig::new_from_toml_str(rendered).unwrap(), redacted);
+    let file = r#"
+        [server]
+        host = "127.0.0.1"
+        port = 2003
+        tls_cert = "cert.pem"
+        listeners = [
+            { host = "127.0.0.1", port = 2003, password = "" },
+            { host = "::1", port = 2004, tls_key = "key.pem" },
+        ]
+
+        [metrics]
+        host = "::1"
+        port = 2004
+    "#;
+    let problems = match ParsedConfig::from_toml_value(toml::from_str(file).unwrap()) {
+        Err(ConfigError::Invalid(problems)) => problems,
+        _ => panic!("Expected the configuration to be invalid"),
+    };
+    assert_eq!(
+        problems,
+        vec![
+            "server.tls_key: must be set along with `tls_cert`",
+            "server.listeners[0].password: can't be empty",
+            "server.listeners[0]: 127.0.0.1:2003 is used by another listener",
+            "server.listeners[1].tls_cert: must be set along with `tls_key`",
+            "metrics.port: [::1]:2004 is used by a listener",
+        ]
+    );
+}
+
+#[test]
+fn test_config_link_passwords() {
+    let file = r#"
+        [server]
+        host = "127.0.0.1"
+        port = 2003
+        password = "hunter2"
+
+        [replication]
+        primary = "127.0.0.1:2004"
+        password = "hunter2"
+
+        [cluster]
+        addr = "127.0.0.1:2003"
+        password = "hunter2"
+    "#;
+    let cfg = ParsedConfig::new_from_toml_str(file.to_owned()).unwrap();
+    assert_eq!(cfg.replication.password.as_deref(), Some("hunter2"));
+    assert_eq!(
+        cfg.cluster,
+        ClusterConfig::Enabled(
+            "127.0.0.1:2003".to_owned(),
+            Vec::new(),
+            Some("hunter2".to_owned())
+        )
+    );
+    // The passwords for the links are hidden like the ones of the listeners
+    let settings = cfg.settings().unwrap();
+    for name in &["replication.password", "cluster.password"] {
+        assert!(settings.contains(&(name.to_string(), "***".to_owned())));
+    }
+    let file = r#"
+        [server]
+        host = "127.0.0.1"
+        port = 2003
+
+        [replication]
+        password = ""
+
+        [raft]
+        id = 1
+        password = ""
+    "#;
+    let problems = match ParsedConfig::from_toml_value(toml::from_str(file).unwrap()) {
+        Err(ConfigError::Invalid(problems)) => problems,
+        _ => panic!("Expected the configuration to be invalid"),
+    };
+    assert_eq!(
+        problems,
+        vec![
+            "replication.password: can't be empty",
+            "raft.password: can't be empty",
+        ]
+    );
+}
+
 #[test]
 #[cfg(test)]
 fn test_config_file_ipv6() {
@@ -444,11 +2328,22 @@ fn test_config_file_ipv6() {
     assert_eq!(
         cfg,
         ParsedConfig {
-            port: 2003,
-            host: IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0x1)),
+            listeners: vec![ListenerConfig::new(
+                IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0x1)),
+                2003
+            )],
             noart: false,
+            readonly: false,
             bgsave: BGSave::default(),
             snapshot: SnapshotConfig::default(),
+            metrics: MetricsConfig::default(),
+            slowlog: SlowlogConfig::default(),
+            limits: LimitsConfig::default(),
+            storage: StorageConfig::default(),
+            encryption: EncryptionConfig::default(),
+            replication: ReplicationConfig::default(),
+            raft: RaftConfig::default(),
+            cluster: ClusterConfig::default(),
         }
     );
 }
@@ -477,11 +2372,22 @@ fn test_config_file_custom_bgsave() {
     assert_eq!(
         cfg,
         ParsedConfig {
-            host: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
-            port: 2003,
+            listeners: vec![ListenerConfig::new(
+                IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                2003
+            )],
             noart: false,
+            readonly: false,
             bgsave: BGSave::new(true, 600),
-            snapshot: SnapshotConfig::default()
+            snapshot: SnapshotConfig::default(),
+            metrics: MetricsConfig::default(),
+            slowlog: SlowlogConfig::default(),
+            limits: LimitsConfig::default(),
+            storage: StorageConfig::default(),
+            encryption: EncryptionConfig::default(),
+            replication: ReplicationConfig::default(),
+            raft: RaftConfig::default(),
+            cluster: ClusterConfig::default(),
         }
     );
 }
@@ -497,11 +2403,22 @@ fn test_config_file_bgsave_enabled_only() {
     assert_eq!(
         cfg,
         ParsedConfig {
-            host: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
-            port: 2003,
+            listeners: vec![ListenerConfig::new(
+                IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                2003
+            )],
             noart: false,
+            readonly: false,
             bgsave: BGSave::default(),
             snapshot: SnapshotConfig::default(),
+            metrics: MetricsConfig::default(),
+            slowlog: SlowlogConfig::default(),
+            limits: LimitsConfig::default(),
+            storage: StorageConfig::default(),
+            encryption: EncryptionConfig::default(),
+            replication: ReplicationConfig::default(),
+            raft: RaftConfig::default(),
+            cluster: ClusterConfig::default(),
         }
     )
 }
@@ -517,11 +2434,22 @@ fn test_config_file_bgsave_every_only() {
     assert_eq!(
         cfg,
         ParsedConfig {
-            host: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
-            port: 2003,
+            listeners: vec![ListenerConfig::new(
+                IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                2003
+            )],
             noart: false,
+            readonly: false,
             bgsave: BGSave::new(true, 600),
-            snapshot: SnapshotConfig::default()
+            snapshot: SnapshotConfig::default(),
+            metrics: MetricsConfig::default(),
+            slowlog: SlowlogConfig::default(),
+            limits: LimitsConfig::default(),
+            storage: StorageConfig::default(),
+            encryption: EncryptionConfig::default(),
+            replication: ReplicationConfig::default(),
+            raft: RaftConfig::default(),
+            cluster: ClusterConfig::default(),
         }
     )
 }
@@ -535,9 +2463,496 @@ fn test_config_file_snapshot() {
         ParsedConfig {
             snapshot: SnapshotConfig::Enabled(SnapshotPref::new(3600, 4)),
             bgsave: BGSave::default(),
-            host: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
-            port: 2003,
+            listeners: vec![ListenerConfig::new(
+                IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                2003
+            )],
+            noart: false,
+            readonly: false,
+            metrics: MetricsConfig::default(),
+            slowlog: SlowlogConfig::default(),
+            limits: LimitsConfig::default(),
+            storage: StorageConfig::default(),
+            encryption: EncryptionConfig::default(),
+            replication: ReplicationConfig::default(),
+            raft: RaftConfig::default(),
+            cluster: ClusterConfig::default(),
+        }
+    );
+}
+
+#[test]
+fn test_config_file_metrics() {
+    let file = get_toml_from_examples_dir("metrics.toml".to_owned()).unwrap();
+    let cfg = ParsedConfig::new_from_toml_str(file).unwrap();
+    assert_eq!(
+        cfg,
+        ParsedConfig {
+            snapshot: SnapshotConfig::default(),
+            bgsave: BGSave::default(),
+            listeners: vec![ListenerConfig::new(
+                IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                2003
+            )],
+            noart: false,
+            readonly: false,
+            metrics: MetricsConfig::Enabled(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 2004),
+            slowlog: SlowlogConfig::default(),
+            limits: LimitsConfig::default(),
+            storage: StorageConfig::default(),
+            encryption: EncryptionConfig::default(),
+            replication: ReplicationConfig::default(),
+            raft: RaftConfig::default(),
+            cluster: ClusterConfig::default(),
+        }
+    );
+}
+
+#[test]
+fn test_config_file_slowlog() {
+    let file = get_toml_from_examples_dir("slowlog.toml".to_owned()).unwrap();
+    let cfg = ParsedConfig::new_from_toml_str(file).unwrap();
+    assert_eq!(
+        cfg,
+        ParsedConfig {
+            snapshot: SnapshotConfig::default(),
+            bgsave: BGSave::default(),
+            listeners: vec![ListenerConfig::new(
+                IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                2003
+            )],
+            noart: false,
+            readonly: false,
+            metrics: MetricsConfig::default(),
+            slowlog: SlowlogConfig::new(500, 64),
+            limits: LimitsConfig::default(),
+            storage: StorageConfig::default(),
+            encryption: EncryptionConfig::default(),
+            replication: ReplicationConfig::default(),
+            raft: RaftConfig::default(),
+            cluster: ClusterConfig::default(),
+        }
+    );
+}
+
+#[test]
+fn test_config_file_limits() {
+    let file = get_toml_from_examples_dir("limits.toml".to_owned()).unwrap();
+    let cfg = ParsedConfig::new_from_toml_str(file).unwrap();
+    assert_eq!(
+        cfg,
+        ParsedConfig {
+            snapshot: SnapshotConfig::default(),
+            bgsave: BGSave::default(),
+            listeners: vec![ListenerConfig::new(
+                IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                2003
+            )],
             noart: false,
+            readonly: false,
+            metrics: MetricsConfig::default(),
+            slowlog: SlowlogConfig::default(),
+            limits: LimitsConfig::new(1000, 300, 1024 * 1024),
+            storage: StorageConfig::default(),
+            encryption: EncryptionConfig::default(),
+            replication: ReplicationConfig::default(),
+            raft: RaftConfig::default(),
+            cluster: ClusterConfig::default(),
         }
     );
 }
+
+#[test]
+fn test_config_file_storage() {
+    let file = get_toml_from_examples_dir("storage.toml".to_owned()).unwrap();
+    let cfg = ParsedConfig::new_from_toml_str(file).unwrap();
+    assert_eq!(
+        cfg.storage,
+        StorageConfig::new(
+            "/var/lib/tdb",
+            "tdb.bin",
+            "snapshots",
+            true,
+            Codec::Zstd,
+            Engine::Memory
+        )
+    );
+    assert_eq!(cfg.storage.dump_path(), Path::new("/var/lib/tdb/tdb.bin"));
+    assert_eq!(
+        cfg.storage.snapshot_dir(),
+        Path::new("/var/lib/tdb/snapshots")
+    );
+    assert_eq!(cfg.storage.lock_path(), Path::new("/var/lib/tdb/tdb.lock"));
+    assert_eq!(
+        cfg.storage.writelog_dir(),
+        Path::new("/var/lib/tdb/writelog")
+    );
+    let absolute = StorageConfig::new(
+        "/var/lib/tdb",
+        "data.bin",
+        "/backups/tdb",
+        false,
+        Codec::None,
+        Engine::Memory,
+    );
+    assert_eq!(absolute.snapshot_dir(), Path::new("/backups/tdb"));
+}
+
+#[test]
+fn test_config_file_logstore() {
+    let file = get_toml_from_examples_dir("logstore.toml".to_owned()).unwrap();
+    let cfg = ParsedConfig::new_from_toml_str(file).unwrap();
+    assert_eq!(cfg.storage.engine(), Engine::Log);
+    assert_eq!(
+        cfg.storage.segment_dir(),
+        Path::new("/var/lib/tdb/segments")
+    );
+}
+
+#[test]
+fn test_config_file_encryption() {
+    let file = get_toml_from_examples_dir("encryption.toml".to_owned()).unwrap();
+    let cfg = ParsedConfig::new_from_toml_str(file).unwrap();
+    assert_eq!(
+        cfg.encryption,
+        EncryptionConfig::new(
+            Some(PathBuf::from("/etc/tdb/tdb.key")),
+            None,
+            vec![PathBuf::from("/etc/tdb/tdb-2025.key")]
+        )
+    );
+}
+
+#[test]
+fn test_config_file_replica() {
+    let file = get_toml_from_examples_dir("replica.toml".to_owned()).unwrap();
+    let cfg = ParsedConfig::new_from_toml_str(file).unwrap();
+    assert_eq!(
+        cfg.replication,
+        ReplicationConfig::new(Some("127.0.0.1:2003".to_owned()), 4 * 1024 * 1024)
+    );
+}
+
+#[test]
+fn test_config_file_raft() {
+    let file = get_toml_from_examples_dir("raft2.toml".to_owned()).unwrap();
+    let cfg = ParsedConfig::new_from_toml_str(file).unwrap();
+    let members = (1..=3)
+        .map(|id| RaftMember {
+            id,
+            addr: format!("127.0.0.1:{}", 2002 + id),
+        })
+        .collect();
+    assert_eq!(
+        cfg.raft,
+        RaftConfig::Enabled(RaftPref::with_defaults(2, members))
+    );
+    assert_eq!(cfg.storage.raft_dir(), Path::new("./raft2/raft"));
+}
+
+#[test]
+fn test_config_file_cluster() {
+    let file = get_toml_from_examples_dir("cluster2.toml".to_owned()).unwrap();
+    let cfg = ParsedConfig::new_from_toml_str(file).unwrap();
+    let nodes = vec![
+        ClusterNode {
+            addr: "127.0.0.1:2003".to_owned(),
+            slots: vec!["0-8191".to_owned()],
+        },
+        ClusterNode {
+            addr: "127.0.0.1:2004".to_owned(),
+            slots: vec!["8192-16383".to_owned()],
+        },
+    ];
+    assert_eq!(
+        cfg.cluster,
+        ClusterConfig::Enabled("127.0.0.1:2004".to_owned(), nodes, None)
+    );
+    assert_eq!(cfg.storage.cluster_path(), Path::new("./cluster2/cluster.map"));
+}
+
+#[test]
+fn test_config_to_toml() {
+    // Rendering a configuration and parsing it again gives the same configuration
+    for filename in &[
+        "template.toml",
+        "snapshot.toml",
+        "metrics.toml",
+        "encryption.toml",
+        "replica.toml",
+        "raft2.toml",
+        "cluster2.toml",
+        "bgsave-justenabled.toml",
+    ] {
+        let file = get_toml_from_examples_dir(filename.to_string()).unwrap();
+        let cfg = ParsedConfig::new_from_toml_str(file).unwrap();
+        let rendered = ParsedConfig::new_from_toml_str(cfg.to_toml().unwrap()).unwrap();
+        assert_eq!(rendered, cfg, "{}", filename);
+    }
+}
+
+#[test]
+#[cfg(test)]
+fn test_config_file_overrides_and_rewrite() {
+    let dir = std::env::temp_dir().join(format!("tdb-test-config-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("tdb.toml");
+    let file = |overrides: &[(&str, &str)]| {
+        let overrides = overrides
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        ConfigFile::new(path.to_string_lossy().into_owned(), overrides)
+    };
+    // The settings are checked once the overrides are applied
+    fs::write(
+        &path,
+        "[server]\nhost = \"127.0.0.1\"\nport = 2003\n\n[bgsave]\nevery = 0\n",
+    )
+    .unwrap();
+    assert!(file(&[]).load().is_err());
+    let cfg = file(&[("bgsave.every", "60")]).load().ok().unwrap();
+    assert_eq!(cfg.bgsave, BGSave::Enabled(60));
+    // Only the settings that were changed while the server was running are written
+    fs::write(
+        &path,
+        "[server]\nhost = \"127.0.0.1\"\nport = 2003\n\n[slowlog]\nmaxlen = 10\n",
+    )
+    .unwrap();
+    let cfg_file = file(&[("limits.maxclients", "10")]);
+    let cfg = cfg_file.load().ok().unwrap();
+    assert_eq!(cfg.limits.maxclients, 10);
+    let changes = vec![("slowlog.maxlen".to_owned(), "50".to_owned())];
+    cfg_file
+        .rewrite(&cfg.with_settings(&changes).unwrap())
+        .unwrap();
+    assert!(!fs::read_to_string(&path).unwrap().contains("maxclients"));
+    let rewritten = file(&[]).load().ok().unwrap();
+    assert_eq!(rewritten.slowlog.maxlen, 50);
+    assert_eq!(rewritten.limits, LimitsConfig::default());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_config_settings() {
+    let cfg = ParsedConfig::default();
+    let settings = cfg.settings().unwrap();
+    let get = |name: &str| {
+        settings
+            .iter()
+            .find(|(setting, _)| setting == name)
+            .map(|(_, value)| value.as_str())
+    };
+    assert_eq!(get("server.host"), Some("127.0.0.1"));
+    assert_eq!(get("server.port"), Some("2003"));
+    assert_eq!(get("bgsave.every"), Some("120"));
+    assert_eq!(get("storage.compression"), Some("none"));
+    assert_eq!(get("encryption.old_key_files"), Some("[]"));
+    let file = get_toml_from_examples_dir("raft2.toml".to_owned()).unwrap();
+    let raft = ParsedConfig::new_from_toml_str(file).unwrap();
+    assert!(raft.settings().unwrap().contains(&(
+        "raft.members".to_owned(),
+        "[{ addr = \"127.0.0.1:2003\", id = 1 }, { addr = \"127.0.0.1:2004\", id = 2 }, \
+         { addr = \"127.0.0.1:2005\", id = 3 }]"
+            .to_owned()
+    )));
+    // Disabled sections are left out
+    assert_eq!(get("snapshot.every"), None);
+    // Passwords aren't shown
+    let file = get_toml_from_examples_dir("listeners.toml".to_owned()).unwrap();
+    let secure = ParsedConfig::new_from_toml_str(file).unwrap();
+    let settings = secure.settings().unwrap();
+    assert!(settings.iter().all(|(_, value)| !value.contains("hunter2")));
+    let changes = |changes: &[(&str, &str)]| -> Vec<(String, String)> {
+        changes
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    };
+    let changed = cfg
+        .with_settings(&changes(&[
+            ("bgsave.every", "60"),
+            ("snapshot.enabled", "true"),
+            ("snapshot.every", "3600"),
+            ("snapshot.atmost", "4"),
+            ("limits.idle_timeout", "300"),
+            ("server.readonly", "true"),
+        ]))
+        .unwrap();
+    assert_eq!(changed.bgsave, BGSave::Enabled(60));
+    assert_eq!(
+        changed.snapshot,
+        SnapshotConfig::Enabled(SnapshotPref::new(3600, 4))
+    );
+    assert_eq!(
+        changed.limits,
+        LimitsConfig::new(50000, 300, 64 * 1024 * 1024)
+    );
+    assert!(changed.readonly);
+    let mut hot = ParsedConfig::default();
+    hot.set_hot_settings(&changed);
+    assert_eq!(hot, changed);
+    // Only the hot-reloadable settings can be changed
+    assert_eq!(
+        cfg.with_settings(&changes(&[("server.port", "2004")])),
+        Err("'server.port' can't be changed while the server is running".to_owned())
+    );
+    assert_eq!(
+        cfg.with_settings(&changes(&[("bgsave.evry", "60")])),
+        Err("Unknown setting 'bgsave.evry'".to_owned())
+    );
+    assert!(cfg
+        .with_settings(&changes(&[("bgsave.every", "often")]))
+        .is_err());
+    // Snapshots can't be enabled without an interval
+    assert!(cfg
+        .with_settings(&changes(&[("snapshot.enabled", "true")]))
+        .is_err());
+    assert_eq!(
+        cfg.with_settings(&changes(&[("bgsave.every", "0")])),
+        Err(
+            "Invalid setting: bgsave.every: must be greater than 0 when BGSAVE is enabled"
+                .to_owned()
+        )
+    );
+}
+
+#[test]
+#[cfg(test)]
+fn test_config_problems() {
+    let file = r#"
+        [server]
+        host = "127.0.0.1"
+        port = 2003
+        colour = "blue"
+
+        [bgsvae]
+        every = 10
+
+        [snapshot]
+        enabled = true
+        every = 0
+        atmost = 4
+
+        [limits]
+        maxclients = 0
+
+        [encryption]
+        key_file = "tdb.key"
+        key_env = "TDB_KEY"
+
+        [raft]
+        id = 4
+        heartbeat_interval = 1000
+        members = [
+            { id = 1, addr = "127.0.0.1:2003", role = "leader" },
+            { id = 1, addr = "nowhere" },
+        ]
+    "#;
+    let problems = match ParsedConfig::from_toml_value(toml::from_str(file).unwrap()) {
+        Err(ConfigError::Invalid(problems)) => problems,
+        _ => panic!("Expected the configuration to be invalid"),
+    };
+    assert_eq!(
+        problems,
+        vec![
+            "bgsvae: unknown section",
+            "raft.members[0].role: unknown key",
+            "server.colour: unknown key",
+            "snapshot.every: must be greater than 0 when snapshots are enabled",
+            "limits.maxclients: must be greater than 0",
+            "encryption.key_env: can't be set along with `key_file`",
+            "raft: can't be used with encryption, since the Raft log isn't encrypted",
+            "raft.members: must include this node (with the ID 4)",
+            "raft.members[1].id: 1 is the ID of another member",
+            "raft.members[1].addr: 'nowhere' isn't a `host:port` address",
+            "raft.election_timeout: must be greater than `heartbeat_interval`",
+        ]
+    );
+    // A Raft node can't be a cluster node or use the log engine
+    let file = r#"
+        [server]
+        host = "127.0.0.1"
+        port = 2003
+
+        [storage]
+        engine = "log"
+
+        [raft]
+        id = 1
+
+        [cluster]
+        addr = "127.0.0.1:2003"
+        nodes = [{ addr = "127.0.0.1:2003", slots = ["0-16384"] }]
+    "#;
+    let problems = match ParsedConfig::from_toml_value(toml::from_str(file).unwrap()) {
+        Err(ConfigError::Invalid(problems)) => problems,
+        _ => panic!("Expected the configuration to be invalid"),
+    };
+    assert_eq!(
+        problems,
+        vec![
+            "storage.engine: Raft mode only works with the memory engine",
+            "cluster: a node in a Raft group can't be a node in a cluster",
+            "cluster.nodes[0].slots[0]: '0-16384' isn't a valid slot range",
+        ]
+    );
+    // The write log needs snapshots and the memory engine
+    let file = r#"
+        [server]
+        host = "127.0.0.1"
+        port = 2003
+
+        [storage]
+        engine = "log"
+        writelog = true
+    "#;
+    let problems = match ParsedConfig::from_toml_value(toml::from_str(file).unwrap()) {
+        Err(ConfigError::Invalid(problems)) => problems,
+        _ => panic!("Expected the configuration to be invalid"),
+    };
+    assert_eq!(
+        problems,
+        vec![
+            "storage.writelog: needs snapshots to be enabled",
+            "storage.writelog: can't be used with the log engine",
+        ]
+    );
+    // Only the dump file and snapshots are encrypted
+    let file = r#"
+        [server]
+        host = "127.0.0.1"
+        port = 2003
+
+        [snapshot]
+        enabled = true
+        every = 3600
+        atmost = 4
+
+        [storage]
+        writelog = true
+
+        [encryption]
+        key_env = "TDB_KEY"
+
+        [replication]
+        primary = "127.0.0.1:2004"
+
+        [raft]
+        id = 1
+    "#;
+    let problems = match ParsedConfig::from_toml_value(toml::from_str(file).unwrap()) {
+        Err(ConfigError::Invalid(problems)) => problems,
+        _ => panic!("Expected the configuration to be invalid"),
+    };
+    assert_eq!(
+        problems,
+        vec![
+            "storage.writelog: can't be used with encryption, since the write log isn't encrypted",
+            "replication.primary: can't be used with encryption, since the copy of the primary's data isn't encrypted",
+            "raft: can't be used with encryption, since the Raft log isn't encrypted",
+            "replication.primary: a node in a Raft group can't replicate a primary",
+        ]
+    );
+}
@@ -0,0 +1,942 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # The log storage engine
+//!
+//! Only the keys are kept in memory, along with where their values are. The values are
+//! kept in append-only segment files in the segment directory, so the data can be much
+//! larger than RAM.
+//!
+//! Every change is appended to the newest segment as an entry:
+//! ```text
+//! | CRC32 (4 bytes) | kind (1 byte) | key length (4 bytes) | value length (8 bytes) | key | value |
+//! ```
+//! The CRC covers everything after it. Deleting a key appends a tombstone and `FLUSHDB`
+//! appends a "clear" entry, so that older segments can't bring keys back when the
+//! segments are read on startup. Once the newest segment is larger than the segment
+//! size, a new one is started.
+//!
+//! Overwritten values, deleted values, tombstones and clears are garbage. Once at least
+//! half of the older segments is garbage, [`compaction_service`] rewrites the oldest
+//! segment with garbage in it, keeping only its live values (or deletes it if none are
+//! left). None of the segments before it have garbage, so they have no values that its
+//! tombstones and clears could be hiding, and those can just be dropped. The values are
+//! copied into a new file without holding the table lock, which is only taken to swap
+//! the new file in.
+
+use super::storage::Storage;
+use super::{CoreDB, Data};
+use crate::config::StorageConfig;
+use crate::diskstore;
+use crate::diskstore::encryption::Keyring;
+use crate::diskstore::format::Codec;
+use crate::diskstore::records::Table;
+use bytes::Bytes;
+use libtdb::TResult;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use tokio::time::{self, Duration};
+
+/// The size after which a new segment is started
+pub const SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+/// How often the compaction service checks for garbage, in seconds
+const COMPACTION_INTERVAL: u64 = 30;
+/// The extension of segment files
+const SEGMENT_EXTENSION: &str = "seg";
+/// The extension of the file that a segment is rewritten into while it is compacted
+const COMPACTION_EXTENSION: &str = "compact";
+/// The length of everything in an entry except the key and the value
+const ENTRY_HEADER_LEN: u64 = 4 + 1 + 4 + 8;
+/// An entry which sets a key
+const KIND_SET: u8 = 0;
+/// A tombstone, for a key that was deleted
+const KIND_DELETE: u8 = 1;
+/// An entry which removes every key before it
+const KIND_CLEAR: u8 = 2;
+
+/// Where the latest value of a key is
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Location {
+    /// The ID of the segment
+    segment: u64,
+    /// Where the entry starts in the segment
+    offset: u64,
+    key_len: u32,
+    value_len: u64,
+}
+
+impl Location {
+    const fn entry_len(&self) -> u64 {
+        ENTRY_HEADER_LEN + self.key_len as u64 + self.value_len
+    }
+}
+
+/// A segment file
+struct Segment {
+    file: fs::File,
+    /// The length of the file
+    size: u64,
+    /// How many bytes of the file are garbage
+    garbage: u64,
+}
+
+/// An entry read from a segment
+struct Entry {
+    kind: u8,
+    key: String,
+    value: Vec<u8>,
+}
+
+/// A planned compaction of a segment, which is done without holding the table lock
+pub struct Compaction {
+    /// The ID of the segment
+    segment: u64,
+    /// The segment file
+    file: fs::File,
+    /// The length of the segment when the compaction was planned
+    size: u64,
+    /// Where the live entries start in the segment
+    live: HashSet<u64>,
+    /// Where the live entries are copied to
+    path: PathBuf,
+}
+
+/// A compacted copy of a segment, which still has to replace the segment
+pub struct Compacted {
+    /// The ID of the segment
+    segment: u64,
+    /// The copy
+    path: PathBuf,
+    /// The length of the copy
+    size: u64,
+    /// The keys that were copied, with where their entries were and where they start in
+    /// the copy
+    moved: Vec<(String, Location, u64)>,
+}
+
+impl Compaction {
+    /// Copy the live entries of the segment into a new file
+    pub fn run(self) -> io::Result<Compacted> {
+        let mut reader = BufReader::new(self.file);
+        let mut copy = BufWriter::new(fs::File::create(&self.path)?);
+        let (mut offset, mut size) = (0, 0);
+        let mut moved = Vec::with_capacity(self.live.len());
+        let mut buf = Vec::new();
+        while let Some(entry) = read_entry(&mut reader, self.size - offset)? {
+            let location = Location {
+                segment: self.segment,
+                offset,
+                key_len: entry.key.len() as u32,
+                value_len: entry.value.len() as u64,
+            };
+            offset += location.entry_len();
+            if entry.kind == KIND_SET && self.live.contains(&location.offset) {
+                buf.clear();
+                encode_entry(&mut buf, KIND_SET, &entry.key, &entry.value);
+                copy.write_all(&buf)?;
+                moved.push((entry.key, location, size));
+                size += location.entry_len();
+            }
+        }
+        // The copy has to be on disk before it replaces the segment
+        copy.into_inner()?.sync_data()?;
+        Ok(Compacted {
+            segment: self.segment,
+            path: self.path,
+            size,
+            moved,
+        })
+    }
+}
+
+/// The log storage engine
+pub struct LogStore {
+    /// The segment directory
+    dir: PathBuf,
+    /// The size after which a new segment is started
+    segment_size: u64,
+    /// Where the latest value of every key is
+    index: HashMap<String, Location>,
+    /// The segments, oldest first
+    segments: BTreeMap<u64, Segment>,
+    /// The ID of the segment that entries are appended to
+    active: u64,
+}
+
+impl fmt::Debug for LogStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LogStore")
+            .field("dir", &self.dir)
+            .field("keys", &self.index.len())
+            .field("segments", &self.segments.len())
+            .field("active", &self.active)
+            .finish()
+    }
+}
+
+impl LogStore {
+    /// Open the segments in `dir` (creating it if it doesn't exist), reading every
+    /// entry to build the index
+    ///
+    /// If the newest segment ends with a damaged entry, the server probably crashed while
+    /// writing it, so it is cut off. Damage anywhere else is an error
+    pub fn open(dir: impl Into<PathBuf>, segment_size: u64) -> TResult<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)
+            .map_err(|e| format!("Couldn't create '{}': {}", dir.display(), e))?;
+        let mut ids = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|ext| ext == COMPACTION_EXTENSION)
+            {
+                // The server was stopped during a compaction, which never took effect
+                fs::remove_file(&path)?;
+                continue;
+            }
+            if path.extension().is_some_and(|ext| ext == SEGMENT_EXTENSION) {
+                if let Some(id) = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse::<u64>().ok())
+                {
+                    ids.push(id);
+                }
+            }
+        }
+        ids.sort_unstable();
+        let mut store = LogStore {
+            dir,
+            segment_size,
+            index: HashMap::new(),
+            segments: BTreeMap::new(),
+            active: ids.last().copied().unwrap_or(1),
+        };
+        for (i, &id) in ids.iter().enumerate() {
+            store.load_segment(id, i == ids.len() - 1)?;
+        }
+        if ids.is_empty() {
+            store.create_segment(store.active)?;
+        }
+        Ok(store)
+    }
+    fn segment_path(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("{:010}.{}", id, SEGMENT_EXTENSION))
+    }
+    fn compaction_path(&self, id: u64) -> PathBuf {
+        self.dir
+            .join(format!("{:010}.{}", id, COMPACTION_EXTENSION))
+    }
+    fn open_segment(&self, id: u64) -> io::Result<fs::File> {
+        fs::OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(self.segment_path(id))
+    }
+    fn create_segment(&mut self, id: u64) -> io::Result<()> {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create_new(true)
+            .open(self.segment_path(id))?;
+        self.segments.insert(
+            id,
+            Segment {
+                file,
+                size: 0,
+                garbage: 0,
+            },
+        );
+        Ok(())
+    }
+    /// Read every entry in the segment `id` into the index
+    fn load_segment(&mut self, id: u64, newest: bool) -> TResult<()> {
+        let path = self.segment_path(id);
+        let file = self
+            .open_segment(id)
+            .map_err(|e| format!("Couldn't open '{}': {}", path.display(), e))?;
+        let len = file.metadata()?.len();
+        self.segments.insert(
+            id,
+            Segment {
+                file,
+                size: 0,
+                garbage: 0,
+            },
+        );
+        let mut reader = BufReader::new(fs::File::open(&path)?);
+        let mut offset = 0;
+        loop {
+            match read_entry(&mut reader, len - offset) {
+                Ok(Some(entry)) => {
+                    let location = Location {
+                        segment: id,
+                        offset,
+                        key_len: entry.key.len() as u32,
+                        value_len: entry.value.len() as u64,
+                    };
+                    offset += location.entry_len();
+                    self.segments.get_mut(&id).unwrap().size = offset;
+                    self.apply(entry.kind, entry.key, location);
+                }
+                Ok(None) => return Ok(()),
+                Err(e) if e.kind() == ErrorKind::InvalidData && newest => {
+                    log::warn!(
+                        "Dropped a damaged entry at the end of '{}' ({}). The server was probably stopped while writing it",
+                        path.display(),
+                        e
+                    );
+                    self.segments[&id].file.set_len(offset)?;
+                    return Ok(());
+                }
+                Err(e) => {
+                    return Err(format!(
+                        "'{}' is damaged at byte {}: {}",
+                        path.display(),
+                        offset,
+                        e
+                    )
+                    .into())
+                }
+            }
+        }
+    }
+    /// Update the index and the garbage counts for an entry that was just appended or read
+    fn apply(&mut self, kind: u8, key: String, location: Location) {
+        let old = match kind {
+            KIND_SET => self.index.insert(key, location),
+            KIND_DELETE => {
+                self.add_garbage(&location);
+                self.index.remove(&key)
+            }
+            _ => {
+                self.add_garbage(&location);
+                for (_, old) in mem::take(&mut self.index) {
+                    self.add_garbage(&old);
+                }
+                None
+            }
+        };
+        if let Some(old) = old {
+            self.add_garbage(&old);
+        }
+    }
+    fn add_garbage(&mut self, location: &Location) {
+        if let Some(segment) = self.segments.get_mut(&location.segment) {
+            segment.garbage += location.entry_len();
+        }
+    }
+    /// Append an entry to the active segment, starting a new segment first if it is full
+    fn append(&mut self, kind: u8, key: &str, value: &[u8]) -> io::Result<Location> {
+        self.append_all(&[(kind, key, value)])
+            .map(|locations| locations[0])
+    }
+    /// Append `entries` to the active segment with a single write, so that either all of
+    /// them are appended or none are, starting a new segment first if it is full
+    fn append_all(&mut self, entries: &[(u8, &str, &[u8])]) -> io::Result<Vec<Location>> {
+        if self.segments[&self.active].size >= self.segment_size {
+            // Make sure that the full segment is on disk before moving on from it
+            self.segments[&self.active].file.sync_data()?;
+            self.create_segment(self.active + 1)?;
+            self.active += 1;
+        }
+        let segment = self.segments.get_mut(&self.active).unwrap();
+        let mut buf = Vec::new();
+        let mut locations = Vec::with_capacity(entries.len());
+        for &(kind, key, value) in entries {
+            let start = buf.len();
+            encode_entry(&mut buf, kind, key, value);
+            locations.push(Location {
+                segment: self.active,
+                offset: segment.size + start as u64,
+                key_len: key.len() as u32,
+                value_len: value.len() as u64,
+            });
+        }
+        if let Err(e) = segment.file.write_all(&buf) {
+            // Don't leave part of the entries behind, since the next ones would be
+            // appended after them
+            let _ = segment.file.set_len(segment.size);
+            return Err(e);
+        }
+        segment.size += buf.len() as u64;
+        Ok(locations)
+    }
+    /// Read the entry at `location`
+    fn read(&self, location: &Location) -> io::Result<Entry> {
+        read_at(&self.segments[&location.segment].file, location)
+    }
+}
+
+/// Read the entry at `location` from its segment `file`
+fn read_at(file: &fs::File, location: &Location) -> io::Result<Entry> {
+    let mut buf = vec![0; location.entry_len() as usize];
+    read_exact_at(file, &mut buf, location.offset)?;
+    match read_entry(&mut &buf[..], buf.len() as u64)? {
+        Some(entry) => Ok(entry),
+        None => Err(io::Error::new(
+            ErrorKind::UnexpectedEof,
+            "the entry is empty",
+        )),
+    }
+}
+
+/// A copy of the index, which reads the values from the segments without holding the
+/// table lock
+///
+/// Compaction and `FLUSHDB` replace or delete segment files instead of changing what's
+/// in them, so the files that we keep open still have the entries that the copy points at
+struct Frozen {
+    /// Every key with where its value is, in the order that they are in the segments
+    locations: Vec<(String, Location)>,
+    segments: HashMap<u64, fs::File>,
+}
+
+impl Table for Frozen {
+    fn for_each_pair(&self, f: &mut dyn FnMut(&str, &[u8]) -> io::Result<()>) -> io::Result<()> {
+        for (key, location) in &self.locations {
+            f(key, &read_at(&self.segments[&location.segment], location)?.value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Open the log engine in the segment directory of `storage`, checking that the rest of
+/// the configuration works with it
+///
+/// The first time the engine is used (when there's no segment directory), the dump file
+/// is imported into it, so that switching from the memory engine keeps the data. If
+/// `recovered` is set, it replaces whatever is in the segments
+pub fn open(
+    storage: &StorageConfig,
+    keys: &Keyring,
+    recovered: Option<HashMap<String, Data>>,
+) -> TResult<LogStore> {
+    if storage.is_writelog_enabled() {
+        return Err("The write log can't be used with the log storage engine. Set `writelog = false` or `engine = \"memory\"` in the [storage] section".into());
+    }
+    if keys.current().is_some() {
+        return Err("The log storage engine doesn't encrypt its segments, so it can't be used with encryption. Remove the key from the [encryption] section or set `engine = \"memory\"` in the [storage] section".into());
+    }
+    let dir = storage.segment_dir();
+    let import = if recovered.is_none() && !dir.exists() {
+        diskstore::get_saved(Some(storage.dump_path()), keys)?
+    } else {
+        None
+    };
+    let mut store = LogStore::open(dir, SEGMENT_SIZE)?;
+    if let Some(table) = import {
+        log::info!(
+            "Importing {} keys from '{}' into the log storage engine",
+            table.len(),
+            storage.dump_path().display()
+        );
+        store.replace(table)?;
+    }
+    if let Some(table) = recovered {
+        store.replace(table)?;
+    }
+    log::info!(
+        "Using the log storage engine with {} keys in {} segments",
+        store.len(),
+        store.segments.len()
+    );
+    Ok(store)
+}
+
+impl Storage for LogStore {
+    fn get(&self, key: &str) -> io::Result<Option<Data>> {
+        match self.index.get(key) {
+            Some(location) => {
+                let entry = self.read(location)?;
+                Ok(Some(Data::from_blob(Bytes::from(entry.value))))
+            }
+            None => Ok(None),
+        }
+    }
+    fn value_len(&self, key: &str) -> Option<usize> {
+        self.index
+            .get(key)
+            .map(|location| location.value_len as usize)
+    }
+    fn contains_key(&self, key: &str) -> bool {
+        self.index.contains_key(key)
+    }
+    fn len(&self) -> usize {
+        self.index.len()
+    }
+    fn set(&mut self, key: String, value: Data) -> io::Result<()> {
+        let location = self.append(KIND_SET, &key, value.get_blob())?;
+        self.apply(KIND_SET, key, location);
+        Ok(())
+    }
+    fn set_all(&mut self, pairs: Vec<(String, Data)>) -> io::Result<()> {
+        let entries: Vec<(u8, &str, &[u8])> = pairs
+            .iter()
+            .map(|(key, value)| (KIND_SET, key.as_str(), &value.get_blob()[..]))
+            .collect();
+        let locations = self.append_all(&entries)?;
+        for ((key, _), location) in pairs.into_iter().zip(locations) {
+            self.apply(KIND_SET, key, location);
+        }
+        Ok(())
+    }
+    fn remove(&mut self, key: &str) -> io::Result<bool> {
+        if !self.index.contains_key(key) {
+            return Ok(false);
+        }
+        let location = self.append(KIND_DELETE, key, &[])?;
+        self.apply(KIND_DELETE, key.to_owned(), location);
+        Ok(true)
+    }
+    fn remove_all(&mut self, keys: &[String]) -> io::Result<()> {
+        let entries: Vec<(u8, &str, &[u8])> = keys
+            .iter()
+            .filter(|key| self.index.contains_key(key.as_str()))
+            .map(|key| (KIND_DELETE, key.as_str(), &[][..]))
+            .collect();
+        let locations = self.append_all(&entries)?;
+        for ((_, key, _), location) in entries.into_iter().zip(locations) {
+            self.apply(KIND_DELETE, key.to_owned(), location);
+        }
+        Ok(())
+    }
+    fn clear(&mut self) -> io::Result<()> {
+        let location = self.append(KIND_CLEAR, "", &[])?;
+        // The clear has to be on disk before the older segments are deleted, otherwise
+        // a crash could bring back some of the keys in the active segment
+        self.segments[&self.active].file.sync_data()?;
+        self.apply(KIND_CLEAR, String::new(), location);
+        let older: Vec<u64> = self.segments.range(..self.active).map(|(&id, _)| id).collect();
+        for id in older {
+            fs::remove_file(self.segment_path(id))?;
+            self.segments.remove(&id);
+        }
+        Ok(())
+    }
+    fn replace(&mut self, table: HashMap<String, Data>) -> io::Result<()> {
+        self.clear()?;
+        for (key, value) in table {
+            self.set(key, value)?;
+        }
+        Ok(())
+    }
+    fn to_table(&self) -> io::Result<HashMap<String, Data>> {
+        self.index
+            .iter()
+            .map(|(key, location)| {
+                let entry = self.read(location)?;
+                Ok((key.clone(), Data::from_blob(Bytes::from(entry.value))))
+            })
+            .collect()
+    }
+    /// Only the index is copied, and the values are read from the segments later
+    fn freeze(&self) -> io::Result<Box<dyn Table + Send>> {
+        let mut locations: Vec<(String, Location)> = self
+            .index
+            .iter()
+            .map(|(key, location)| (key.clone(), *location))
+            .collect();
+        locations.sort_unstable_by_key(|(_, location)| (location.segment, location.offset));
+        let segments = self
+            .segments
+            .iter()
+            .map(|(&id, segment)| Ok((id, segment.file.try_clone()?)))
+            .collect::<io::Result<_>>()?;
+        Ok(Box::new(Frozen {
+            locations,
+            segments,
+        }))
+    }
+    fn keys(&self) -> Box<dyn Iterator<Item = &str> + '_> {
+        Box::new(self.index.keys().map(String::as_str))
+    }
+    fn memory_usage(&self) -> usize {
+        let slots = self.index.capacity() * (mem::size_of::<String>() + mem::size_of::<Location>());
+        let keys: usize = self.index.keys().map(|key| key.len()).sum();
+        slots + keys
+    }
+    /// The segments are the data, so this only makes sure that they're on disk
+    fn save(&self, _: &StorageConfig, _: &Keyring) -> TResult<()> {
+        self.segments[&self.active].file.sync_data()?;
+        Ok(())
+    }
+    /// The values are read one at a time, so this doesn't need much memory
+    fn dump(&self, filename: &Path, codec: Codec, keys: &Keyring) -> TResult<()> {
+        diskstore::flush_data(filename, self, codec, keys)
+    }
+    /// Plan the compaction of the oldest segment with garbage in it, if at least half of
+    /// the older segments is garbage
+    fn plan_compaction(&self) -> io::Result<Option<Compaction>> {
+        let mut older = self.segments.range(..self.active);
+        let (size, garbage) = older.clone().fold((0, 0), |(size, garbage), (_, segment)| {
+            (size + segment.size, garbage + segment.garbage)
+        });
+        if size == 0 || garbage * 2 < size {
+            return Ok(None);
+        }
+        let (&id, segment) = match older.find(|(_, segment)| segment.garbage > 0) {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+        let live = self
+            .index
+            .values()
+            .filter(|location| location.segment == id)
+            .map(|location| location.offset)
+            .collect();
+        Ok(Some(Compaction {
+            segment: id,
+            file: fs::File::open(self.segment_path(id))?,
+            size: segment.size,
+            live,
+            path: self.compaction_path(id),
+        }))
+    }
+    /// Replace the segment with its compacted copy, and point the keys that weren't
+    /// changed in the meantime at their entries in the copy
+    fn finish_compaction(&mut self, compacted: Compacted) -> io::Result<()> {
+        let Compacted {
+            segment: id,
+            path,
+            size,
+            moved,
+        } = compacted;
+        if !self.segments.contains_key(&id) {
+            // A `FLUSHDB` deleted the segment in the meantime
+            return fs::remove_file(path);
+        }
+        if moved.is_empty() {
+            fs::remove_file(path)?;
+            fs::remove_file(self.segment_path(id))?;
+            self.segments.remove(&id);
+            log::info!("Deleted segment {}, which was all garbage", id);
+            return Ok(());
+        }
+        fs::rename(&path, self.segment_path(id))?;
+        let file = self.open_segment(id)?;
+        let mut garbage = 0;
+        for (key, old, offset) in moved {
+            let new = Location { offset, ..old };
+            match self.index.get_mut(&key) {
+                Some(location) if *location == old => *location = new,
+                // The key was changed while its value was being copied
+                _ => garbage += new.entry_len(),
+            }
+        }
+        self.segments.insert(
+            id,
+            Segment {
+                file,
+                size,
+                garbage,
+            },
+        );
+        log::info!("Compacted segment {}", id);
+        Ok(())
+    }
+}
+
+/// The values are read one at a time, in the order that they are in the segments
+impl Table for LogStore {
+    fn for_each_pair(&self, f: &mut dyn FnMut(&str, &[u8]) -> io::Result<()>) -> io::Result<()> {
+        let mut locations: Vec<(&String, &Location)> = self.index.iter().collect();
+        locations.sort_unstable_by_key(|(_, location)| (location.segment, location.offset));
+        for (key, location) in locations {
+            f(key, &self.read(location)?.value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Append an entry for `key` to `buf`
+fn encode_entry(buf: &mut Vec<u8>, kind: u8, key: &str, value: &[u8]) {
+    let start = buf.len();
+    buf.extend_from_slice(&[0; 4]);
+    buf.push(kind);
+    buf.extend_from_slice(&(key.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    buf.extend_from_slice(key.as_bytes());
+    buf.extend_from_slice(value);
+    let crc = crc32fast::hash(&buf[start + 4..]);
+    buf[start..start + 4].copy_from_slice(&crc.to_le_bytes());
+}
+
+/// Read an entry from `reader`, which has `remaining` bytes left. This returns `None`
+/// if there are no bytes left, and an `InvalidData` error if the entry is damaged
+fn read_entry(reader: &mut impl Read, remaining: u64) -> io::Result<Option<Entry>> {
+    if remaining == 0 {
+        return Ok(None);
+    }
+    let damaged = |what: &str| io::Error::new(ErrorKind::InvalidData, what.to_owned());
+    if remaining < ENTRY_HEADER_LEN {
+        return Err(damaged("the entry was cut short"));
+    }
+    let mut header = [0u8; ENTRY_HEADER_LEN as usize];
+    reader.read_exact(&mut header)?;
+    let crc = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    let kind = header[4];
+    let mut key_len = [0u8; 4];
+    key_len.copy_from_slice(&header[5..9]);
+    let mut value_len = [0u8; 8];
+    value_len.copy_from_slice(&header[9..17]);
+    let (key_len, value_len) = (u32::from_le_bytes(key_len), u64::from_le_bytes(value_len));
+    if (key_len as u64).saturating_add(value_len) > remaining - ENTRY_HEADER_LEN {
+        return Err(damaged("the entry was cut short"));
+    }
+    let mut key = vec![0; key_len as usize];
+    reader.read_exact(&mut key)?;
+    let mut value = vec![0; value_len as usize];
+    reader.read_exact(&mut value)?;
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&header[4..]);
+    hasher.update(&key);
+    hasher.update(&value);
+    if hasher.finalize() != crc {
+        return Err(damaged("the checksum doesn't match"));
+    }
+    if kind > KIND_CLEAR {
+        return Err(damaged("the entry has an unknown kind"));
+    }
+    let key = String::from_utf8(key).map_err(|_| damaged("the key isn't valid UTF-8"))?;
+    Ok(Some(Entry { kind, key, value }))
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &fs::File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &fs::File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// The compaction service compacts the log engine's segments every
+/// `COMPACTION_INTERVAL` seconds, until the database is shutting down
+pub async fn compaction_service(handle: CoreDB) {
+    let duration = Duration::from_secs(COMPACTION_INTERVAL);
+    while !handle.shared.is_termsig() {
+        handle.shared.compact();
+        tokio::select! {
+            _ = time::delay_until(time::Instant::now() + duration) => {}
+            _ = handle.shared.compaction_task.notified() => {}
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("tdb-test-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+#[cfg(test)]
+fn value(data: &str) -> Data {
+    Data::from_string(data.to_owned())
+}
+
+#[cfg(test)]
+/// Do a step of compaction, returning `false` if there was nothing to do
+fn compact(store: &mut LogStore) -> bool {
+    match store.plan_compaction().unwrap() {
+        Some(compaction) => {
+            let compacted = compaction.run().unwrap();
+            store.finish_compaction(compacted).unwrap();
+            true
+        }
+        None => false,
+    }
+}
+
+#[test]
+fn test_logstore_reopen() {
+    let dir = test_dir("logstore-reopen");
+    let mut store = LogStore::open(&dir, SEGMENT_SIZE).unwrap();
+    store.set("a".to_owned(), value("1")).unwrap();
+    store.set("b".to_owned(), value("2")).unwrap();
+    store.set("a".to_owned(), value("3")).unwrap();
+    assert!(store.remove("b").unwrap());
+    assert!(!store.remove("b").unwrap());
+    assert_eq!(store.get("a").unwrap(), Some(value("3")));
+    assert_eq!(store.value_len("a"), Some(1));
+    drop(store);
+    // Tombstones keep deleted keys deleted
+    let mut store = LogStore::open(&dir, SEGMENT_SIZE).unwrap();
+    assert_eq!(store.len(), 1);
+    assert_eq!(store.get("a").unwrap(), Some(value("3")));
+    assert_eq!(store.get("b").unwrap(), None);
+    store.set("c".to_owned(), value("4")).unwrap();
+    store.clear().unwrap();
+    store.set("d".to_owned(), value("5")).unwrap();
+    drop(store);
+    let store = LogStore::open(&dir, SEGMENT_SIZE).unwrap();
+    let mut expected = HashMap::new();
+    expected.insert("d".to_owned(), value("5"));
+    assert_eq!(store.to_table().unwrap(), expected);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_logstore_freeze() {
+    let dir = test_dir("logstore-freeze");
+    let mut store = LogStore::open(&dir, 64).unwrap();
+    store.set("a".to_owned(), value("1")).unwrap();
+    store.set("b".to_owned(), value("2")).unwrap();
+    store.set("c".to_owned(), value("3")).unwrap();
+    let frozen = store.freeze().unwrap();
+    let expected = store.to_table().unwrap();
+    // Changes, compaction and `FLUSHDB` after the copy was taken don't show up in it
+    store.set("a".to_owned(), value("4")).unwrap();
+    while compact(&mut store) {}
+    store.clear().unwrap();
+    let mut copy = HashMap::new();
+    frozen
+        .for_each_pair(&mut |key, value| {
+            copy.insert(
+                key.to_owned(),
+                Data::from_blob(Bytes::copy_from_slice(value)),
+            );
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(copy, expected);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_logstore_batches() {
+    let dir = test_dir("logstore-batches");
+    let mut store = LogStore::open(&dir, SEGMENT_SIZE).unwrap();
+    let pairs = vec![
+        ("a".to_owned(), value("1")),
+        ("b".to_owned(), value("2")),
+        ("a".to_owned(), value("3")),
+    ];
+    store.set_all(pairs).unwrap();
+    assert_eq!(store.len(), 2);
+    assert_eq!(store.get("a").unwrap(), Some(value("3")));
+    // A batch is a single write, so the entries are next to each other
+    let size = store.segments[&store.active].size;
+    store.remove_all(&["b".to_owned(), "c".to_owned()]).unwrap();
+    assert_eq!(
+        store.segments[&store.active].size,
+        size + ENTRY_HEADER_LEN + 1
+    );
+    drop(store);
+    let store = LogStore::open(&dir, SEGMENT_SIZE).unwrap();
+    let mut expected = HashMap::new();
+    expected.insert("a".to_owned(), value("3"));
+    assert_eq!(store.to_table().unwrap(), expected);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_logstore_damaged_tail() {
+    let dir = test_dir("logstore-damaged");
+    let mut store = LogStore::open(&dir, SEGMENT_SIZE).unwrap();
+    store.set("a".to_owned(), value("1")).unwrap();
+    store.set("b".to_owned(), value("2")).unwrap();
+    let path = store.segment_path(store.active);
+    drop(store);
+    // Cut the last entry in half, as if the server crashed while writing it
+    let len = fs::metadata(&path).unwrap().len();
+    fs::OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_len(len - 3)
+        .unwrap();
+    let mut store = LogStore::open(&dir, SEGMENT_SIZE).unwrap();
+    assert_eq!(store.len(), 1);
+    assert_eq!(store.get("a").unwrap(), Some(value("1")));
+    // New entries go after the last intact one
+    store.set("c".to_owned(), value("3")).unwrap();
+    drop(store);
+    let store = LogStore::open(&dir, SEGMENT_SIZE).unwrap();
+    assert_eq!(store.len(), 2);
+    assert_eq!(store.get("c").unwrap(), Some(value("3")));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_logstore_compaction() {
+    let dir = test_dir("logstore-compaction");
+    // Small segments, so that every few entries start a new one
+    let mut store = LogStore::open(&dir, 100).unwrap();
+    for i in 0..50 {
+        store.set(format!("key{}", i % 5), value(&i.to_string())).unwrap();
+    }
+    store.set("kept".to_owned(), value("forever")).unwrap();
+    store.remove("key0").unwrap();
+    let segments = store.segments.len();
+    assert!(segments > 5);
+    let expected = store.to_table().unwrap();
+    while compact(&mut store) {}
+    assert!(store.segments.len() < segments);
+    assert_eq!(store.to_table().unwrap(), expected);
+    drop(store);
+    let store = LogStore::open(&dir, 100).unwrap();
+    assert_eq!(store.to_table().unwrap(), expected);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_logstore_compaction_with_writes() {
+    let dir = test_dir("logstore-compaction-writes");
+    let mut store = LogStore::open(&dir, 100).unwrap();
+    store.set("moved".to_owned(), value("old")).unwrap();
+    store.set("changed".to_owned(), value("old")).unwrap();
+    for i in 0..20 {
+        store.set("garbage".to_owned(), value(&i.to_string())).unwrap();
+    }
+    let compaction = store.plan_compaction().unwrap().unwrap();
+    let compacted = compaction.run().unwrap();
+    // A client changes a key while its value is being copied
+    store.set("changed".to_owned(), value("new")).unwrap();
+    store.finish_compaction(compacted).unwrap();
+    assert_eq!(store.get("moved").unwrap(), Some(value("old")));
+    assert_eq!(store.get("changed").unwrap(), Some(value("new")));
+    let expected = store.to_table().unwrap();
+    drop(store);
+    let store = LogStore::open(&dir, 100).unwrap();
+    assert_eq!(store.to_table().unwrap(), expected);
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -21,22 +21,44 @@
 
 //! # The core database engine
 
-use crate::config::BGSave;
-use crate::config::SnapshotConfig;
+use crate::admin::clients::ClientRegistry;
+use crate::admin::monitor::Monitor;
+use crate::admin::readonly::ReadOnlySwitch;
+use crate::admin::save::SaveTracker;
+use crate::admin::shutdown::ShutdownSwitch;
+use crate::admin::slowlog::Slowlog;
+use crate::cluster::Cluster;
+use crate::config::ClusterConfig;
+use crate::config::ConfigFile;
+use crate::config::ParsedConfig;
+use crate::config::RaftConfig;
+use crate::config::StorageConfig;
 use crate::diskstore;
+use crate::diskstore::encryption::Keyring;
+use crate::diskstore::snapshot::SnapshotEngine;
+use crate::diskstore::writelog::{self, Change, WriteLog};
+use crate::metrics::METRICS;
 use crate::protocol::Connection;
 use crate::protocol::Query;
 use crate::queryengine;
+use crate::raft::{self, Raft};
+use crate::replication::{self, Replication};
 use bytes::Bytes;
-use diskstore::PERSIST_FILE;
 use libtdb::TResult;
+use parking_lot::Mutex;
 use parking_lot::RwLock;
 use parking_lot::RwLockReadGuard;
 use parking_lot::RwLockWriteGuard;
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::path::Path;
 use std::sync::Arc;
+use std::time::Instant;
+use storage::{Engine, Storage};
 use tokio;
 use tokio::sync::Notify;
+pub mod logstore;
+pub mod storage;
 
 /// This is a thread-safe database handle, which on cloning simply
 /// gives another atomic reference to the `shared` which is a `Shared` object
@@ -50,7 +72,9 @@ pub struct CoreDB {
     /// This is used by the `Drop` implementation to avoid killing the database in the event
     /// that a background service is still working. The calculation is pretty straightforward:
     /// ```text
-    /// 1 (for the current process) + if bgsave is running + if snapshotting is enabled
+    /// 1 (for the current process) + 1 (for the replica service) + 1 (for the BGSAVE
+    ///     scheduler) + 1 (for the snapshot service) + if the log storage engine is used
+    ///     + if Raft is enabled
     /// ```
     /// This should **not be changed** during runtime, and should only be initialized when `CoreDB`
     /// is first initialized
@@ -65,8 +89,48 @@ pub struct Shared {
     pub bgsave_task: Notify,
     /// The snapshot service notifier
     pub snapshot_service: Notify,
+    /// Wakes up the BGSAVE scheduler when its settings have changed
+    pub bgsave_reconfigured: Notify,
+    /// Wakes up the snapshot service when its settings have changed
+    pub snapshot_reconfigured: Notify,
+    /// The compaction service notifier
+    pub compaction_task: Notify,
     /// A `Coretable` wrapped in a R/W lock
     pub table: RwLock<Coretable>,
+    /// The slow query log
+    pub slowlog: Slowlog,
+    /// The live command stream
+    pub monitor: Monitor,
+    /// The connected clients
+    pub clients: ClientRegistry,
+    /// Used by `SHUTDOWN` to shut the server down
+    pub shutdown: ShutdownSwitch,
+    /// Used by `READONLY` to refuse writes
+    pub readonly: ReadOnlySwitch,
+    /// The effective configuration, whose hot-reloadable settings can be changed while
+    /// the server is running
+    pub config: RwLock<ParsedConfig>,
+    /// Held while the hot-reloadable settings are changed, so that concurrent changes
+    /// don't overwrite each other
+    reconfiguring: tokio::sync::Mutex<()>,
+    /// The configuration file, if the server was started with one
+    pub config_file: Option<ConfigFile>,
+    /// Where the data is stored
+    pub storage: StorageConfig,
+    /// The keys with which the dump file and snapshots are encrypted
+    pub keys: Keyring,
+    /// Saves to the dump file
+    pub saves: SaveTracker,
+    /// The snapshots
+    pub snapshots: Mutex<SnapshotEngine>,
+    /// The log of changes, used for point-in-time recovery
+    pub writelog: WriteLog,
+    /// The replication state
+    pub replication: Replication,
+    /// The Raft node, if the server is part of a Raft group
+    pub raft: Option<Arc<Raft>>,
+    /// The cluster state, if the server is a node in a cluster
+    pub cluster: Option<Cluster>,
 }
 
 impl Shared {
@@ -81,37 +145,136 @@ impl Shared {
             return false;
         }
         // Kick in BGSAVE
-        match diskstore::flush_data(PERSIST_FILE, rlock.get_ref()) {
-            Ok(_) => log::info!("BGSAVE completed successfully"),
-            Err(e) => log::error!("BGSAVE failed with error: '{}'", e),
+        match self.save_table(&rlock) {
+            Some(Ok(_)) => log::info!("BGSAVE completed successfully"),
+            Some(Err(e)) => log::error!("BGSAVE failed with error: '{}'", e),
+            None => log::info!("Skipping BGSAVE since another save is running"),
         }
         true
     }
+    /// Save the in-memory table to the dump file right away
+    ///
+    /// This returns `None` if another save is already running
+    pub fn save(&self) -> Option<TResult<()>> {
+        self.save_table(&self.table.read())
+    }
+    /// Save `table` to disk, unless another save is already running
+    fn save_table(&self, table: &Coretable) -> Option<TResult<()>> {
+        let _guard = self.saves.try_begin()?;
+        let start = Instant::now();
+        let result = table.get_ref().save(&self.storage, &self.keys);
+        METRICS.bgsave.record(start.elapsed(), result.is_ok());
+        self.saves.finish(result.is_ok());
+        Some(result)
+    }
+    /// Log that `key` was set to `value` in the write log and send the change to
+    /// the replicas
+    pub fn log_set(&self, key: &str, value: &[u8]) {
+        self.writelog.set(key, value);
+        self.replication
+            .publish(&Change::Set(Cow::Borrowed(key), Cow::Borrowed(value)));
+    }
+    /// Log that `key` was deleted in the write log and send the change to the replicas
+    pub fn log_del(&self, key: &str) {
+        self.writelog.del(key);
+        self.replication.publish(&Change::Del(Cow::Borrowed(key)));
+    }
+    /// Log that all the keys were deleted in the write log and send the change to
+    /// the replicas
+    pub fn log_clear(&self) {
+        self.writelog.clear();
+        self.replication.publish(&Change::Clear);
+    }
+    /// Log that the whole table was replaced with `table` in the write log and send the
+    /// change to the replicas
+    pub fn log_reset_to(&self, table: &HashMap<String, Data>) {
+        self.writelog.reset_to(table);
+        self.replication.publish_reset(table);
+    }
+    /// Apply the hot-reloadable settings (see [`HOT_SETTINGS`](crate::config::HOT_SETTINGS))
+    /// in `cfg`, ignoring the others
+    pub async fn reconfigure(&self, cfg: &ParsedConfig) {
+        let _reconfiguring = self.reconfiguring.lock().await;
+        self.apply_hot_settings(cfg).await;
+    }
+    /// Change the `section.key` settings in `changes` (see
+    /// [`ParsedConfig::with_settings`]), returning why they can't be changed if they can't
+    ///
+    /// The new configuration is built and applied in one go, so a concurrent change can't
+    /// be lost in between
+    pub async fn set_settings(&self, changes: &[(String, String)]) -> Result<(), String> {
+        let _reconfiguring = self.reconfiguring.lock().await;
+        let cfg = self.config.read().with_settings(changes)?;
+        self.apply_hot_settings(&cfg).await;
+        Ok(())
+    }
+    /// Apply the hot-reloadable settings in `cfg`, which must be done while
+    /// `reconfiguring` is held
+    async fn apply_hot_settings(&self, cfg: &ParsedConfig) {
+        // This waits for the running writes if the server is switching to read-only mode
+        self.readonly.set(cfg.readonly).await;
+        self.slowlog.reconfigure(cfg.slowlog);
+        self.snapshots.lock().set_atmost(cfg.snapshot.atmost());
+        self.config.write().set_hot_settings(cfg);
+        // Let the background services pick up their new settings
+        self.bgsave_reconfigured.notify();
+        self.snapshot_reconfigured.notify();
+    }
     /// Check if the server has received a termination signal
     pub fn is_termsig(&self) -> bool {
         self.table.read().terminate
     }
+    /// Compact the storage engine's files
+    ///
+    /// This is done one step at a time. Copying the live values is the slow part of a
+    /// step, so it is done without holding the table lock, which is only taken to plan
+    /// the step and to make it take effect
+    pub fn compact(&self) {
+        loop {
+            let planned = {
+                let table = self.table.read();
+                if table.terminate {
+                    return;
+                }
+                table.get_ref().plan_compaction()
+            };
+            let result = match planned {
+                Ok(Some(compaction)) => compaction.run().and_then(|compacted| {
+                    self.table
+                        .write()
+                        .get_mut_ref()
+                        .finish_compaction(compacted)
+                }),
+                Ok(None) => return,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                log::error!("Compaction failed with error: '{}'", e);
+                return;
+            }
+        }
+    }
 }
 
-/// The `Coretable` holds all the key-value pairs in a `HashMap`
+/// The `Coretable` holds all the key-value pairs in a storage engine
 /// and the `terminate` field, which when set to true will cause all other
 /// background tasks to terminate
 #[derive(Debug)]
 pub struct Coretable {
-    /// The core table contain key-value pairs
-    coremap: HashMap<String, Data>,
+    /// The storage engine which contains the key-value pairs
+    coremap: Box<dyn Storage>,
     /// The termination signal flag
     pub terminate: bool,
 }
 
 impl Coretable {
-    /// Get a reference to the inner `HashMap`
-    pub const fn get_ref<'a>(&'a self) -> &'a HashMap<String, Data> {
-        &self.coremap
+    /// Get a reference to the storage engine
+    pub fn get_ref(&self) -> &dyn Storage {
+        &*self.coremap
     }
-    /// Get a **mutable** reference to the inner `HashMap`
-    pub fn get_mut_ref<'a>(&'a mut self) -> &'a mut HashMap<String, Data> {
-        &mut self.coremap
+    /// Get a **mutable** reference to the storage engine
+    pub fn get_mut_ref(&mut self) -> &mut dyn Storage {
+        &mut *self.coremap
     }
 }
 
@@ -143,7 +306,7 @@ impl CoreDB {
     #[cfg(debug_assertions)]
     /// Flush the coretable entries when in debug mode
     pub fn print_debug_table(&self) {
-        if self.acquire_read().coremap.len() == 0 {
+        if self.acquire_read().coremap.is_empty() {
             println!("In-memory table is empty");
         } else {
             println!("{:#?}", self.acquire_read());
@@ -166,48 +329,212 @@ impl CoreDB {
         con.flush_stream().await
     }
 
-    /// Create a new `CoreDB` instance
+    /// Create a new `CoreDB` instance with the configuration `cfg`, which was read from
+    /// `cfg_file` (if there is one)
     ///
     /// This also checks if a local backup of previously saved data is available.
     /// If it is - it restores the data. Otherwise it creates a new in-memory table
-    pub fn new(bgsave: BGSave, snapshot_cfg: SnapshotConfig) -> TResult<Self> {
-        let coretable = diskstore::get_saved(Some(PERSIST_FILE))?;
-        let background_tasks: usize =
-            snapshot_cfg.is_enabled() as usize + !bgsave.is_disabled() as usize;
-        let db = if let Some(coretable) = coretable {
-            CoreDB {
-                shared: Arc::new(Shared {
-                    bgsave_task: Notify::new(),
-                    table: RwLock::new(Coretable {
-                        coremap: coretable,
-                        terminate: false,
-                    }),
-                    snapshot_service: Notify::new(),
-                }),
-                background_tasks,
+    ///
+    /// If `recover_to` is set, the data is instead recovered to the state it was in at
+    /// that time (a UNIX timestamp in milliseconds) from the snapshots and the write log
+    ///
+    /// Encrypted files are decrypted with `keys`, which are also used to encrypt them
+    ///
+    /// The data is kept by the storage engine set in the storage configuration. If the
+    /// replication configuration has a primary, the data is then replaced with the
+    /// primary's data
+    ///
+    /// If Raft is enabled, the data instead comes from the latest snapshot of the Raft log
+    /// and the committed entries after it
+    ///
+    /// If the cluster mode is enabled, the server only serves the keys in its hash slots
+    pub fn new(
+        cfg: ParsedConfig,
+        cfg_file: Option<ConfigFile>,
+        keys: Keyring,
+        recover_to: Option<u64>,
+    ) -> TResult<Self> {
+        let storage_cfg = &cfg.storage;
+        if cfg.replication.primary.is_some() && keys.current().is_some() {
+            return Err("The copy of the primary's data isn't encrypted, so replication can't be used with encryption. Remove the key from the [encryption] section or `primary` from the [replication] section".into());
+        }
+        let raft = match &cfg.raft {
+            RaftConfig::Enabled(pref) => {
+                if recover_to.is_some() {
+                    return Err("Point-in-time recovery isn't possible in Raft mode".into());
+                }
+                if storage_cfg.engine() != Engine::Memory {
+                    return Err("Raft mode only works with the memory engine".into());
+                }
+                if cfg.replication.primary.is_some() {
+                    return Err("A node in a Raft group can't replicate a primary".into());
+                }
+                if keys.current().is_some() {
+                    return Err("The Raft log isn't encrypted, so Raft mode can't be used with encryption. Remove the key from the [encryption] section or the [raft] section".into());
+                }
+                Some(Arc::new(Raft::new(
+                    pref.clone(),
+                    storage_cfg,
+                    keys.clone(),
+                )?))
             }
-        } else {
-            CoreDB::new_empty(background_tasks)
+            RaftConfig::Disabled => None,
         };
+        let cluster = match &cfg.cluster {
+            ClusterConfig::Enabled(addr, nodes, password) => {
+                if raft.is_some() {
+                    return Err("A node in a Raft group can't be a node in a cluster".into());
+                }
+                Some(Cluster::new(
+                    addr.clone(),
+                    nodes.clone(),
+                    password.clone(),
+                    storage_cfg,
+                )?)
+            }
+            ClusterConfig::Disabled => None,
+        };
+        let recovered = match recover_to {
+            Some(target) => {
+                let (table, recovery) = writelog::recover(
+                    &storage_cfg.snapshot_dir(),
+                    &storage_cfg.writelog_dir(),
+                    target,
+                    &keys,
+                )?;
+                log::info!(
+                    "Recovered the data from snapshot '{}' and {} logged changes",
+                    recovery.snapshot,
+                    recovery.replayed
+                );
+                Some(table)
+            }
+            None => None,
+        };
+        let engine = storage_cfg.engine();
+        let (coretable, writelog): (Box<dyn Storage>, WriteLog) = match engine {
+            Engine::Memory => {
+                let coretable = match (&raft, recovered) {
+                    (Some(raft), _) => raft.load_table()?,
+                    (None, Some(table)) => table,
+                    (None, None) => diskstore::get_saved(Some(storage_cfg.dump_path()), &keys)?
+                        .unwrap_or_default(),
+                };
+                let writelog = if storage_cfg.is_writelog_enabled() {
+                    // Old segments are only deleted when a snapshot is created, and the
+                    // log can't be replayed without one either
+                    if !cfg.snapshot.is_enabled() {
+                        return Err("The write log needs snapshots, or it grows without bound and can't be used for recovery. Enable the [snapshot] section or set `writelog = false` in the [storage] section".into());
+                    }
+                    if keys.current().is_some() {
+                        return Err("The write log isn't encrypted, so it can't be used with encryption. Remove the key from the [encryption] section or set `writelog = false` in the [storage] section".into());
+                    }
+                    let writelog = WriteLog::open(storage_cfg.writelog_dir())?;
+                    // The dump can be older than the log if the server crashed, so the log
+                    // starts from whatever we're starting with
+                    writelog.reset_to(&coretable);
+                    writelog
+                } else {
+                    WriteLog::disabled()
+                };
+                (Box::new(coretable), writelog)
+            }
+            Engine::Log => (
+                Box::new(logstore::open(storage_cfg, &keys, recovered)?),
+                WriteLog::disabled(),
+            ),
+        };
+        // BGSAVE and snapshots can be switched on while the server is running, so their
+        // services always run
+        let background_tasks: usize =
+            3 + (engine == Engine::Log) as usize + raft.is_some() as usize;
+        let db = CoreDB::new_with_table(
+            coretable,
+            background_tasks,
+            cfg,
+            cfg_file,
+            keys,
+            writelog,
+            raft,
+            cluster,
+        );
         // Spawn the background save task in a separate task
-        tokio::spawn(diskstore::bgsave_scheduler(db.clone(), bgsave));
+        tokio::spawn(diskstore::bgsave_scheduler(db.clone()));
         // Spawn the snapshot service in a separate task
-        tokio::spawn(diskstore::snapshot::snapshot_service(
-            db.clone(),
-            snapshot_cfg,
-        ));
+        tokio::spawn(diskstore::snapshot::snapshot_service(db.clone()));
+        if engine == Engine::Log {
+            // Spawn the compaction service in a separate task
+            tokio::spawn(logstore::compaction_service(db.clone()));
+        }
+        if let Some(primary) = db.shared.replication.primary() {
+            log::info!("Replicating primary {}", primary);
+        }
+        // Spawn the replica service in a separate task
+        tokio::spawn(replication::replica::replica_service(db.clone()));
+        if db.shared.raft.is_some() {
+            // Spawn the Raft service in a separate task
+            tokio::spawn(raft::raft_service(db.clone()));
+        }
         Ok(db)
     }
+    #[cfg(test)]
     /// Create an empty in-memory table
     pub fn new_empty(background_tasks: usize) -> Self {
+        CoreDB::new_with_table(
+            Box::new(HashMap::<String, Data>::new()),
+            background_tasks,
+            ParsedConfig::default(),
+            None,
+            Keyring::none(),
+            WriteLog::disabled(),
+            None,
+            None,
+        )
+    }
+    /// Create a `CoreDB` instance around an existing table, with the configuration `cfg`
+    /// (read from `cfg_file`, if there is one)
+    ///
+    /// The server is a node in a Raft group if there's a `raft`, and it is a node in a
+    /// cluster if there's a `cluster`
+    #[allow(clippy::too_many_arguments)]
+    fn new_with_table(
+        coremap: Box<dyn Storage>,
+        background_tasks: usize,
+        cfg: ParsedConfig,
+        cfg_file: Option<ConfigFile>,
+        keys: Keyring,
+        writelog: WriteLog,
+        raft: Option<Arc<Raft>>,
+        cluster: Option<Cluster>,
+    ) -> Self {
+        let snapshots = SnapshotEngine::new(cfg.snapshot.atmost(), cfg.storage.snapshot_dir());
         CoreDB {
             shared: Arc::new(Shared {
                 bgsave_task: Notify::new(),
                 table: RwLock::new(Coretable {
-                    coremap: HashMap::<String, Data>::new(),
+                    coremap,
                     terminate: false,
                 }),
                 snapshot_service: Notify::new(),
+                bgsave_reconfigured: Notify::new(),
+                snapshot_reconfigured: Notify::new(),
+                compaction_task: Notify::new(),
+                slowlog: Slowlog::new(cfg.slowlog),
+                monitor: Monitor::new(),
+                clients: ClientRegistry::new(),
+                shutdown: ShutdownSwitch::new(),
+                readonly: ReadOnlySwitch::new(cfg.readonly),
+                storage: cfg.storage.clone(),
+                keys,
+                saves: SaveTracker::new(),
+                snapshots: Mutex::new(snapshots),
+                writelog,
+                replication: Replication::new(cfg.replication.clone()),
+                raft,
+                cluster,
+                config: RwLock::new(cfg),
+                reconfiguring: tokio::sync::Mutex::new(()),
+                config_file: cfg_file,
             }),
             background_tasks,
         }
@@ -223,8 +550,16 @@ impl CoreDB {
     /// Flush the contents of the in-memory table onto disk
     pub fn flush_db(&self) -> TResult<()> {
         let data = &self.acquire_write();
-        diskstore::flush_data(PERSIST_FILE, &data.coremap)?;
-        Ok(())
+        data.coremap.save(&self.shared.storage, &self.shared.keys)
+    }
+    /// Flush the contents of the in-memory table into the file at `filename`
+    pub fn flush_db_to(&self, filename: impl AsRef<Path>) -> TResult<()> {
+        let data = &self.acquire_write();
+        data.coremap.dump(
+            filename.as_ref(),
+            self.shared.storage.compression(),
+            &self.shared.keys,
+        )
     }
 
     /// Get a deep copy of the `HashMap`
@@ -232,14 +567,14 @@ impl CoreDB {
     /// **⚠ Do note**: This is super inefficient since it performs an actual
     /// clone of the `HashMap` and doesn't do any `Arc`-business! This function
     /// can be used by test functions and the server, but **use with caution!**
-    pub fn get_hashmap_deep_clone(&self) -> HashMap<String, Data> {
-        (*self.acquire_read().get_ref()).clone()
+    pub fn get_hashmap_deep_clone(&self) -> TResult<HashMap<String, Data>> {
+        Ok(self.acquire_read().get_ref().to_table()?)
     }
 
     #[cfg(test)]
     /// **⚠⚠⚠ This deletes everything stored in the in-memory table**
     pub fn finish_db(&self) {
-        self.acquire_write().coremap.clear()
+        self.acquire_write().coremap.clear().unwrap()
     }
 }
 
@@ -262,6 +597,11 @@ impl Drop for CoreDB {
             // Notify the background tasks to quit
             self.shared.bgsave_task.notify();
             self.shared.snapshot_service.notify();
+            self.shared.compaction_task.notify();
+            self.shared.replication.wake_service();
+            if let Some(raft) = &self.shared.raft {
+                raft.stop();
+            }
         }
     }
 }
@@ -0,0 +1,179 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # Storage engines
+//!
+//! The key/value pairs in the `Coretable` are kept by a storage engine, which is anything
+//! that implements [`Storage`]. The actions in `kvengine` only use this trait, so they
+//! work the same way with every engine:
+//! - The memory engine is a plain `HashMap`, which is saved to the dump file
+//! - The log engine (see the [`logstore`](super::logstore) module) only keeps the keys
+//!   in memory and the values in segment files, so the data can be larger than RAM
+
+use super::logstore::{Compacted, Compaction};
+use super::Data;
+use crate::config::StorageConfig;
+use crate::diskstore;
+use crate::diskstore::encryption::Keyring;
+use crate::diskstore::format::Codec;
+use crate::diskstore::records::Table;
+use libtdb::TResult;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::mem;
+use std::path::Path;
+
+/// The storage engine, which is set with `engine` in the `[storage]` section
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Engine {
+    /// Everything is kept in memory and saved to the dump file
+    Memory,
+    /// The values are kept in append-only segment files
+    Log,
+}
+
+/// A storage engine
+///
+/// Writes can fail with the log engine, in which case the change isn't made
+pub trait Storage: fmt::Debug + Send + Sync {
+    /// Returns the value of `key`
+    fn get(&self, key: &str) -> io::Result<Option<Data>>;
+    /// Returns the length of the value of `key`, without reading the value
+    fn value_len(&self, key: &str) -> Option<usize>;
+    /// Check if `key` exists
+    fn contains_key(&self, key: &str) -> bool;
+    /// Returns the number of keys
+    fn len(&self) -> usize;
+    /// Check if there are no keys
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Set `key` to `value`, replacing its old value if there is one
+    fn set(&mut self, key: String, value: Data) -> io::Result<()>;
+    /// Set every key in `pairs` to its value, making either all of the changes or none
+    /// of them
+    fn set_all(&mut self, pairs: Vec<(String, Data)>) -> io::Result<()>;
+    /// Remove `key`, returning `false` if it didn't exist
+    fn remove(&mut self, key: &str) -> io::Result<bool>;
+    /// Remove every key in `keys` that exists, making either all of the changes or none
+    /// of them
+    fn remove_all(&mut self, keys: &[String]) -> io::Result<()>;
+    /// Remove every key
+    fn clear(&mut self) -> io::Result<()>;
+    /// Replace every key/value pair with the ones in `table`
+    fn replace(&mut self, table: HashMap<String, Data>) -> io::Result<()>;
+    /// Returns a copy of every key/value pair
+    fn to_table(&self) -> io::Result<HashMap<String, Data>>;
+    /// Returns a copy of every key/value pair which can be read without holding the table
+    /// lock, so that it can be written out in the background
+    fn freeze(&self) -> io::Result<Box<dyn Table + Send>>;
+    /// Returns every key, in no particular order
+    fn keys(&self) -> Box<dyn Iterator<Item = &str> + '_>;
+    /// Returns an estimate of the memory used, in bytes
+    fn memory_usage(&self) -> usize;
+    /// Make sure that the data is on disk, for `SAVE`, BGSAVE and the final save
+    fn save(&self, storage: &StorageConfig, keys: &Keyring) -> TResult<()>;
+    /// Write every key/value pair into the dump file at `filename`, compressing it with
+    /// `codec` and encrypting it with the current key in `keys` (if there is one)
+    fn dump(&self, filename: &Path, codec: Codec, keys: &Keyring) -> TResult<()>;
+    /// Plan a step of background maintenance, returning `None` if there's nothing to do
+    ///
+    /// The slow part of the step is done by [`Compaction::run`] without holding the table
+    /// lock, and then `finish_compaction` makes it take effect
+    fn plan_compaction(&self) -> io::Result<Option<Compaction>> {
+        Ok(None)
+    }
+    /// Make a step of background maintenance that was planned with `plan_compaction`
+    /// take effect
+    fn finish_compaction(&mut self, _compacted: Compacted) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The memory engine
+impl Storage for HashMap<String, Data> {
+    fn get(&self, key: &str) -> io::Result<Option<Data>> {
+        Ok(HashMap::get(self, key).cloned())
+    }
+    fn value_len(&self, key: &str) -> Option<usize> {
+        HashMap::get(self, key).map(|value| value.get_blob().len())
+    }
+    fn contains_key(&self, key: &str) -> bool {
+        HashMap::contains_key(self, key)
+    }
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+    fn set(&mut self, key: String, value: Data) -> io::Result<()> {
+        self.insert(key, value);
+        Ok(())
+    }
+    fn set_all(&mut self, pairs: Vec<(String, Data)>) -> io::Result<()> {
+        self.extend(pairs);
+        Ok(())
+    }
+    fn remove(&mut self, key: &str) -> io::Result<bool> {
+        Ok(HashMap::remove(self, key).is_some())
+    }
+    fn remove_all(&mut self, keys: &[String]) -> io::Result<()> {
+        for key in keys {
+            HashMap::remove(self, key);
+        }
+        Ok(())
+    }
+    fn clear(&mut self) -> io::Result<()> {
+        HashMap::clear(self);
+        Ok(())
+    }
+    fn replace(&mut self, table: HashMap<String, Data>) -> io::Result<()> {
+        *self = table;
+        Ok(())
+    }
+    fn to_table(&self) -> io::Result<HashMap<String, Data>> {
+        Ok(self.clone())
+    }
+    /// The values are shared with the table, so only the keys are copied
+    fn freeze(&self) -> io::Result<Box<dyn Table + Send>> {
+        Ok(Box::new(self.clone()))
+    }
+    fn keys(&self) -> Box<dyn Iterator<Item = &str> + '_> {
+        Box::new(HashMap::keys(self).map(String::as_str))
+    }
+    /// This is only an estimate, since the `HashMap`'s control bytes and the allocator's
+    /// overhead aren't counted
+    fn memory_usage(&self) -> usize {
+        let slots = self.capacity() * (mem::size_of::<String>() + mem::size_of::<Data>());
+        let payload: usize = self
+            .iter()
+            .map(|(key, value)| key.len() + value.get_blob().len())
+            .sum();
+        slots + payload
+    }
+    fn save(&self, storage: &StorageConfig, keys: &Keyring) -> TResult<()> {
+        diskstore::flush_data(storage.dump_path(), self, storage.compression(), keys)
+    }
+    fn dump(&self, filename: &Path, codec: Codec, keys: &Keyring) -> TResult<()> {
+        diskstore::flush_data(filename, self, codec, keys)
+    }
+}
@@ -19,18 +19,33 @@
  *
 */
 
-use crate::config::BGSave;
-use crate::config::SnapshotConfig;
-use crate::protocol::{Connection, QueryResult::*};
+use crate::admin::{auth, monitor};
+use crate::config::ConfigFile;
+use crate::config::ListenerConfig;
+use crate::config::MetricsConfig;
+use crate::config::ParsedConfig;
+use crate::config::StorageConfig;
+use crate::diskstore::encryption::Keyring;
+use crate::diskstore::flock::FileLock;
+use crate::metrics::{exporter::Exporter, METRICS};
+use crate::protocol::stream::{self, TlsAcceptor};
+use crate::protocol::{responses, Connection, QueryResult::*, Stream};
+use crate::raft;
+use crate::replication;
 use crate::CoreDB;
+use chrono::prelude::*;
 use libtdb::util::terminal;
 use libtdb::TResult;
-use std::future::Future;
+use std::env;
+use std::fs;
+use std::future::{self as stdfuture, Future};
+use std::io;
+use std::net::SocketAddr;
 use std::process;
 use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
 use tokio::net::TcpListener;
 use tokio::net::TcpStream;
-use tokio::sync::Semaphore;
 use tokio::sync::{broadcast, mpsc};
 use tokio::time::{self, Duration};
 
@@ -39,6 +54,8 @@ use tokio::time::{self, Duration};
 pub struct Terminator {
     terminate: bool,
     signal: broadcast::Receiver<()>,
+    /// Fires when just this client has been killed, with `CLIENT KILL`
+    kill_switch: Option<broadcast::Receiver<()>>,
 }
 
 impl Terminator {
@@ -48,6 +65,19 @@ impl Terminator {
             // Don't terminate on creation!
             terminate: false,
             signal,
+            kill_switch: None,
+        }
+    }
+    /// Create a new `Terminator` instance for a client, which also fires when the
+    /// client is killed
+    pub const fn new_with_kill_switch(
+        signal: broadcast::Receiver<()>,
+        kill_switch: broadcast::Receiver<()>,
+    ) -> Self {
+        Terminator {
+            terminate: false,
+            signal,
+            kill_switch: Some(kill_switch),
         }
     }
     /// Check if the signal is a termination signal
@@ -61,21 +91,193 @@ impl Terminator {
         if self.terminate {
             return;
         }
-        let _ = self.signal.recv().await;
+        let Terminator {
+            signal,
+            kill_switch,
+            ..
+        } = self;
+        match kill_switch {
+            Some(kill_switch) => {
+                tokio::select! {
+                    _ = signal.recv() => {}
+                    _ = kill_switch.recv() => {}
+                }
+            }
+            None => {
+                let _ = signal.recv().await;
+            }
+        }
         self.terminate = true;
     }
 }
 
+/// What clients have to do before they're served on a listener
+struct Security {
+    /// Clients have to connect over TLS with this, if it's set
+    tls: Option<TlsAcceptor>,
+    /// Clients have to send this with `AUTH` before anything else, if it's set
+    password: Option<Arc<str>>,
+}
+
+/// A bound listener, which doesn't accept connections until the server runs
+pub struct Binding {
+    listener: TcpListener,
+    security: Arc<Security>,
+}
+
+/// How long a client has to finish the TLS handshake
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The size of the queue of pending connections for each listener
+const BACKLOG: i32 = 1024;
+
+impl Binding {
+    /// Bind to the address in `cfg`, and load its TLS certificate if it has one
+    ///
+    /// IPv6 addresses only take IPv6 connections, so that an IPv4 address and an IPv6
+    /// address can be bound to the same port
+    pub fn bind(cfg: &ListenerConfig) -> TResult<Self> {
+        let tls = match (&cfg.tls_cert, &cfg.tls_key) {
+            (Some(cert), Some(key)) => Some(stream::tls_acceptor(cert, key)?),
+            _ => None,
+        };
+        let addr = cfg.addr();
+        let listener = bind_socket(addr)
+            .map_err(|e| format!("Failed to bind to '{}' with error: '{}'", addr, e))?;
+        log::info!(
+            "Listening on {}{}{}",
+            addr,
+            if tls.is_some() { " over TLS" } else { "" },
+            if cfg.password.is_some() {
+                " with a password"
+            } else {
+                ""
+            }
+        );
+        Ok(Binding {
+            listener,
+            security: Arc::new(Security {
+                tls,
+                password: cfg.password.as_deref().map(Arc::from),
+            }),
+        })
+    }
+    /// Create a new `Binding` instance for a listener without TLS or a password
+    pub fn plain(listener: TcpListener) -> Self {
+        Binding {
+            listener,
+            security: Arc::new(Security {
+                tls: None,
+                password: None,
+            }),
+        }
+    }
+    /// Create a new `Binding` instance for a listener that has a password
+    #[cfg(test)]
+    pub fn with_password(listener: TcpListener, password: &str) -> Self {
+        Binding {
+            listener,
+            security: Arc::new(Security {
+                tls: None,
+                password: Some(Arc::from(password)),
+            }),
+        }
+    }
+}
+
+/// Bind a listening socket to `addr`
+fn bind_socket(addr: SocketAddr) -> io::Result<TcpListener> {
+    use socket2::{Domain, Protocol, Socket, Type};
+    let domain = if addr.is_ipv6() {
+        Domain::ipv6()
+    } else {
+        Domain::ipv4()
+    };
+    let socket = Socket::new(domain, Type::stream(), Some(Protocol::tcp()))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    // Don't wait for the connections of an earlier run to time out, like the standard
+    // library does
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(BACKLOG)?;
+    let listener = socket.into_tcp_listener();
+    listener.set_nonblocking(true)?;
+    TcpListener::from_std(listener)
+}
+
+/// A connection accepted on one of the listeners, with what its client has to do before
+/// it's served
+type Accepted = (TcpStream, Arc<Security>);
+
+/// Accept connections on every binding, until the server shuts down
+fn accept_all(
+    bindings: Vec<Binding>,
+    signal: &broadcast::Sender<()>,
+) -> mpsc::Receiver<io::Result<Accepted>> {
+    let (incoming_tx, incoming) = mpsc::channel(bindings.len().max(1));
+    for binding in bindings {
+        tokio::spawn(accept_on(
+            binding,
+            incoming_tx.clone(),
+            Terminator::new(signal.subscribe()),
+        ));
+    }
+    incoming
+}
+
+/// Accept connections on `binding` and send them to the listener, until the server shuts
+/// down (which closes the port)
+async fn accept_on(
+    mut binding: Binding,
+    mut incoming: mpsc::Sender<io::Result<Accepted>>,
+    mut terminator: Terminator,
+) {
+    loop {
+        let accepted = tokio::select! {
+            accepted = accept(&mut binding.listener) => accepted,
+            _ = terminator.receive_signal() => return,
+        };
+        let failed = accepted.is_err();
+        let accepted = accepted.map(|stream| (stream, binding.security.clone()));
+        if incoming.send(accepted).await.is_err() || failed {
+            return;
+        }
+    }
+}
+
+/// Accept an incoming connection
+async fn accept(listener: &mut TcpListener) -> io::Result<TcpStream> {
+    // We will steal the idea of Ethernet's backoff for connection errors
+    let mut backoff = 1;
+    loop {
+        match listener.accept().await {
+            // We don't need the bindaddr
+            Ok((stream, _)) => return Ok(stream),
+            Err(e) => {
+                if backoff > 64 {
+                    // Too many retries, goodbye user
+                    return Err(e);
+                }
+            }
+        }
+        // Wait for the `backoff` duration
+        time::delay_for(Duration::from_secs(backoff)).await;
+        // We're using exponential backoff
+        backoff *= 2;
+    }
+}
+
 // We'll use the idea of gracefully shutting down from tokio
 
 /// A listener
 pub struct Listener {
     /// An atomic reference to the coretable
     db: CoreDB,
-    /// The incoming connection listener (binding)
-    listener: TcpListener,
-    /// The maximum number of connections
-    climit: Arc<Semaphore>,
+    /// The connections accepted on every binding
+    incoming: mpsc::Receiver<io::Result<Accepted>>,
     /// The shutdown broadcaster
     signal: broadcast::Sender<()>,
     // When all `Sender`s are dropped - the `Receiver` gets a `None` value
@@ -88,69 +290,153 @@ pub struct Listener {
 struct CHandler {
     db: CoreDB,
     con: Connection,
-    climit: Arc<Semaphore>,
+    /// The ID of the client in the client registry
+    client_id: u64,
     terminator: Terminator,
+    /// The password of the listener that the client connected to, if it has one
+    password: Option<Arc<str>>,
+    /// Whether the client has sent the password (or doesn't have to)
+    authenticated: bool,
     _term_sig_tx: mpsc::Sender<()>,
 }
 
 impl Listener {
-    /// Accept an incoming connection
-    async fn accept(&mut self) -> TResult<TcpStream> {
-        // We will steal the idea of Ethernet's backoff for connection errors
-        let mut backoff = 1;
-        loop {
-            match self.listener.accept().await {
-                // We don't need the bindaddr
-                Ok((stream, _)) => return Ok(stream),
-                Err(e) => {
-                    if backoff > 64 {
-                        // Too many retries, goodbye user
-                        return Err(e.into());
-                    }
-                }
-            }
-            // Wait for the `backoff` duration
-            time::delay_for(Duration::from_secs(backoff)).await;
-            // We're using exponential backoff
-            backoff *= 2;
-        }
-    }
     /// Run the server
     pub async fn run(&mut self) -> TResult<()> {
-        loop {
-            // Take the permit first, but we won't use it right now
-            // that's why we will forget it
-            self.climit.acquire().await.forget();
-            let stream = self.accept().await?;
-            let mut chandle = CHandler {
-                db: self.db.clone(),
-                con: Connection::new(stream),
-                climit: self.climit.clone(),
-                terminator: Terminator::new(self.signal.subscribe()),
-                _term_sig_tx: self.terminate_tx.clone(),
-            };
+        while let Some(accepted) = self.incoming.recv().await {
+            let (stream, security) = accepted?;
+            let db = self.db.clone();
+            let signal = self.signal.subscribe();
+            let term_sig_tx = self.terminate_tx.clone();
             tokio::spawn(async move {
-                if let Err(e) = chandle.run().await {
+                if let Err(e) = serve(db, stream, security, signal, term_sig_tx).await {
                     eprintln!("Error: {}", e);
                 }
             });
         }
+        Ok(())
     }
 }
 
+/// Finish the TLS handshake with a newly accepted client (if the listener uses TLS) and
+/// then serve it
+async fn serve(
+    db: CoreDB,
+    stream: TcpStream,
+    security: Arc<Security>,
+    mut signal: broadcast::Receiver<()>,
+    term_sig_tx: mpsc::Sender<()>,
+) -> TResult<()> {
+    let peer = stream.peer_addr().ok();
+    let mut stream = match &security.tls {
+        Some(tls) => {
+            let handshake = tokio::select! {
+                handshake = time::timeout(TLS_HANDSHAKE_TIMEOUT, tls.accept(stream)) => handshake,
+                _ = signal.recv() => return Ok(()),
+            };
+            match handshake {
+                Ok(Ok(stream)) => Stream::Tls(Box::new(stream)),
+                Ok(Err(e)) => {
+                    log::warn!(
+                        "TLS handshake with {} failed with error: '{}'",
+                        peer.map(|peer| peer.to_string())
+                            .unwrap_or_else(|| "unknown".to_owned()),
+                        e
+                    );
+                    return Ok(());
+                }
+                // The client took too long, so it's probably not a TLS client at all
+                Err(_) => return Ok(()),
+            }
+        }
+        None => Stream::Plain(stream),
+    };
+    // The limits can be changed while the server is running
+    let (maxclients, _, max_query_size) = db.shared.config.read().limits.decompose();
+    let (client, kill_switch) = match db.shared.clients.register(peer, maxclients) {
+        Some(client) => client,
+        None => {
+            // We're full, so tell the client instead of leaving it hanging
+            stream
+                .write_all(&responses::fresp::R_TOO_MANY_CLIENTS)
+                .await?;
+            stream.flush().await?;
+            return Ok(());
+        }
+    };
+    METRICS.connection_opened();
+    let mut chandle = CHandler {
+        db,
+        client_id: client.id(),
+        con: Connection::new(stream, client, max_query_size),
+        terminator: Terminator::new_with_kill_switch(signal, kill_switch),
+        authenticated: security.password.is_none(),
+        password: security.password.clone(),
+        _term_sig_tx: term_sig_tx,
+    };
+    chandle.run().await
+}
+
 impl CHandler {
     /// Process the incoming connection
     async fn run(&mut self) -> TResult<()> {
         while !self.terminator.is_termination_signal() {
+            // The limits can be changed while the server is running
+            let (_, idle_timeout, max_query_size) = self.db.shared.config.read().limits.decompose();
+            self.con.set_max_query_size(max_query_size);
+            let idle_timeout = if idle_timeout == 0 {
+                None
+            } else {
+                Some(Duration::from_secs(idle_timeout))
+            };
             let try_df = tokio::select! {
                 tdf = self.con.read_query() => tdf,
+                _ = idle(idle_timeout) => {
+                    // The client hasn't sent a query in a while, so it's time to let go
+                    return Ok(());
+                }
                 _ = self.terminator.receive_signal() => {
                     return Ok(());
                 }
             };
             match try_df {
-                Ok(Q(s)) => self.db.execute_query(s, &mut self.con).await?,
+                Ok(Q(s)) => {
+                    if auth::is_auth(&s) {
+                        let password = self.password.as_deref();
+                        self.authenticated |= auth::auth(&mut self.con, s, password).await?;
+                        continue;
+                    }
+                    if !self.authenticated {
+                        self.con
+                            .close_conn_with_error(responses::fresp::R_AUTH_REQUIRED.to_owned())
+                            .await?;
+                        continue;
+                    }
+                    if monitor::is_monitor(&s) {
+                        // This connection now belongs to the monitor
+                        return monitor::monitor(&self.db, &mut self.con, s, &mut self.terminator)
+                            .await;
+                    }
+                    self.db.shared.monitor.publish(&s, self.con.get_peer().ok());
+                    if replication::is_sync(&s) {
+                        // This connection now belongs to a replica
+                        return replication::sync(&self.db, &mut self.con, s, &mut self.terminator)
+                            .await;
+                    }
+                    if raft::peer::is_peer(&s) {
+                        // This connection now belongs to another member of the Raft group
+                        return raft::peer::serve(&self.db, &mut self.con, s, &mut self.terminator)
+                            .await;
+                    }
+                    self.db.execute_query(s, &mut self.con).await?
+                }
                 Ok(E(r)) => self.con.close_conn_with_error(r).await?,
+                Ok(TooLarge) => {
+                    return self
+                        .con
+                        .close_conn_with_error(responses::fresp::R_QUERY_TOO_LARGE.to_owned())
+                        .await
+                }
                 Ok(Empty) => return Ok(()),
                 Err(e) => return Err(e.into()),
             }
@@ -159,74 +445,232 @@ impl CHandler {
     }
 }
 
+/// Wait for `timeout` to elapse, or forever if there's no timeout
+async fn idle(timeout: Option<Duration>) {
+    match timeout {
+        Some(timeout) => time::delay_for(timeout).await,
+        None => stdfuture::pending().await,
+    }
+}
+
 impl Drop for CHandler {
     fn drop(&mut self) {
-        // Make sure that the permit is returned to the semaphore
+        // Make sure that the client is deregistered (which makes room for another one)
         // in the case that there is a panic inside
-        self.climit.add_permits(1);
+        METRICS.connection_closed();
+        self.db.shared.clients.deregister(self.client_id);
     }
 }
-use std::io::{self, prelude::*};
 
-/// Start the server waiting for incoming connections or a CTRL+C signal
+/// The number of times we'll try to save the data when shutting down
+const SHUTDOWN_SAVE_ATTEMPTS: u64 = 5;
+
+/// Start the server waiting for incoming connections, a termination signal or a
+/// `SHUTDOWN` query
+///
+/// `cfg_file` is the configuration file that is reloaded on SIGHUP, and `recover_to` is
+/// the time to recover the data to (if any) before accepting connections. Every one of
+/// the `bindings` serves the same database, and they're all closed when the server shuts
+/// down. An error is returned if the data couldn't be saved on shutdown
 pub async fn run(
-    listener: TcpListener,
-    bgsave_cfg: BGSave,
-    snapshot_cfg: SnapshotConfig,
+    bindings: Vec<Binding>,
+    cfg: ParsedConfig,
+    cfg_file: Option<ConfigFile>,
+    recover_to: Option<u64>,
     sig: impl Future,
-) {
+) -> TResult<()> {
+    // Hold on to the data directory until we're done with it
+    let _lock = match lock_data_dir(&cfg.storage) {
+        Ok(lock) => lock,
+        Err(e) => {
+            log::error!("{}", e);
+            process::exit(1);
+        }
+    };
+    let keys = match Keyring::load(&cfg.encryption) {
+        Ok(keys) => keys,
+        Err(e) => {
+            log::error!("{}", e);
+            process::exit(1);
+        }
+    };
+    if keys.current().is_some() {
+        log::info!("The dump file and snapshots are encrypted");
+    }
     let (signal, _) = broadcast::channel(1);
     let (terminate_tx, terminate_rx) = mpsc::channel(1);
-    let db = match CoreDB::new(bgsave_cfg, snapshot_cfg) {
+    let metrics_cfg = cfg.metrics;
+    let db = match CoreDB::new(cfg, cfg_file, keys, recover_to) {
         Ok(d) => d,
         Err(e) => {
-            eprintln!("ERROR: {}", e);
-            process::exit(0x100);
+            log::error!("{}", e);
+            process::exit(1);
         }
     };
+    if let MetricsConfig::Enabled(host, port) = metrics_cfg {
+        let exporter = match TcpListener::bind((host, port)).await {
+            Ok(l) => Exporter::new(
+                db.clone(),
+                l,
+                Terminator::new(signal.subscribe()),
+                terminate_tx.clone(),
+            ),
+            Err(e) => {
+                log::error!("Failed to bind metrics endpoint with error: '{}'", e);
+                process::exit(1);
+            }
+        };
+        log::info!("Serving metrics on http://{}:{}/metrics", host, port);
+        tokio::spawn(exporter.run());
+    }
     let mut server = Listener {
-        listener,
-        db,
-        climit: Arc::new(Semaphore::new(50000)),
+        incoming: accept_all(bindings, &signal),
+        db: db.clone(),
         signal,
         terminate_tx,
         terminate_rx,
     };
-    tokio::select! {
-        _ = server.run() => {}
-        _ = sig => {
-            log::info!("Signalling all workers to shut down");
-        }
-    }
+    #[cfg(unix)]
+    tokio::spawn(reload_on_hangup(
+        db.clone(),
+        Terminator::new(server.signal.subscribe()),
+    ));
+    let save = tokio::select! {
+        _ = server.run() => true,
+        _ = sig => true,
+        save = db.shared.shutdown.requested() => save,
+    };
+    log::info!("Signalling all workers to shut down");
     let Listener {
         mut terminate_rx,
         terminate_tx,
         signal,
-        db,
         ..
     } = server;
-    if let Ok(_) = db.flush_db() {
-        log::info!("Successfully saved data to disk");
-        ()
+    // Wait for the workers to finish up, so that nothing is written after the final save
+    drop(signal);
+    drop(terminate_tx);
+    let _ = terminate_rx.recv().await;
+    if save {
+        save_on_shutdown(&db).await?;
     } else {
-        log::error!("Failed to flush data to disk");
-        loop {
-            // Keep looping until we successfully write the in-memory table to disk
-            log::warn!("Press enter to try again...");
-            io::stdout().flush().unwrap();
-            io::stdin().read(&mut [0]).unwrap();
-            if let Ok(_) = db.flush_db() {
+        log::warn!("Not saving data to disk, as requested by SHUTDOWN NOSAVE");
+    }
+    terminal::write_info("Goodbye :)\n").unwrap();
+    Ok(())
+}
+
+/// Create the data directory (if it doesn't exist) and lock it, so that no other server
+/// can use it while we're running
+fn lock_data_dir(storage_cfg: &StorageConfig) -> TResult<FileLock> {
+    let data_dir = storage_cfg.data_dir();
+    if let Err(e) = fs::create_dir_all(data_dir) {
+        return Err(format!(
+            "Failed to create data directory '{}' with error: '{}'",
+            data_dir.display(),
+            e
+        )
+        .into());
+    }
+    match FileLock::lock(storage_cfg.lock_path()) {
+        Ok(lock) => {
+            log::info!("Using data directory '{}'", data_dir.display());
+            Ok(lock)
+        }
+        Err(e) => Err(format!(
+            "Failed to lock '{}' with error: '{}'. Is another server using the data directory?",
+            storage_cfg.lock_path().display(),
+            e
+        )
+        .into()),
+    }
+}
+
+/// Save the in-memory table to disk before shutting down
+///
+/// If the save keeps failing, we'll try writing an emergency dump into the system's
+/// temporary directory instead. Either way, an error is returned if the data couldn't
+/// be saved where it's supposed to be, so that the server exits with a non-zero code
+async fn save_on_shutdown(db: &CoreDB) -> TResult<()> {
+    // We'll steal the idea of Ethernet's backoff for retries too
+    let mut backoff = 1;
+    for attempt in 1..=SHUTDOWN_SAVE_ATTEMPTS {
+        match db.flush_db() {
+            Ok(_) => {
                 log::info!("Successfully saved data to disk");
-                break;
-            } else {
+                return Ok(());
+            }
+            Err(e) => log::error!(
+                "Failed to flush data to disk (attempt {}/{}) with error: '{}'",
+                attempt,
+                SHUTDOWN_SAVE_ATTEMPTS,
+                e
+            ),
+        }
+        if attempt != SHUTDOWN_SAVE_ATTEMPTS {
+            log::warn!("Trying again in {} second(s)", backoff);
+            time::delay_for(Duration::from_secs(backoff)).await;
+            backoff *= 2;
+        }
+    }
+    let dump_path = env::temp_dir().join(format!(
+        "tdb-emergency-{}.bin",
+        Utc::now().format("%Y%m%d-%H%M%S")
+    ));
+    match db.flush_db_to(&dump_path) {
+        Ok(_) => Err(format!(
+            "Failed to save data to disk, but an emergency dump was written to '{}'",
+            dump_path.display()
+        )
+        .into()),
+        Err(e) => Err(format!(
+            "Failed to save data to disk and to write an emergency dump to '{}' with error: '{}'",
+            dump_path.display(),
+            e
+        )
+        .into()),
+    }
+}
+
+/// Reload the configuration file whenever we receive a SIGHUP signal, until the server
+/// shuts down
+///
+/// Only the hot-reloadable settings are applied, and the others take effect after a restart
+#[cfg(unix)]
+async fn reload_on_hangup(db: CoreDB, mut terminator: Terminator) {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut sighup = match signal(SignalKind::hangup()) {
+        Ok(sighup) => sighup,
+        Err(e) => {
+            log::warn!("Failed to listen for SIGHUP with error: '{}'", e);
+            return;
+        }
+    };
+    while !terminator.is_termination_signal() {
+        tokio::select! {
+            _ = sighup.recv() => {}
+            _ = terminator.receive_signal() => return,
+        }
+        let cfg_file = match &db.shared.config_file {
+            Some(cfg_file) => cfg_file,
+            None => {
+                log::warn!("Received SIGHUP, but there's no configuration file to reload");
                 continue;
             }
+        };
+        let cfg = match cfg_file.load() {
+            Ok(cfg) => cfg,
+            Err(e) => {
+                log::error!("Failed to reload the configuration file: {}", e);
+                continue;
+            }
+        };
+        db.shared.reconfigure(&cfg).await;
+        log::info!("Reloaded the configuration file '{}'", cfg_file);
+        if *db.shared.config.read() != cfg {
+            log::warn!("Changes to some of the settings will take effect after a restart");
         }
     }
-    drop(signal);
-    drop(terminate_tx);
-    let _ = terminate_rx.recv().await;
-    terminal::write_info("Goodbye :)\n").unwrap();
 }
 
 /// This is a **test only** function
@@ -235,12 +679,18 @@ pub async fn run(
 /// > **This is not for release builds in any way!**
 #[cfg(test)]
 pub async fn test_run(listener: TcpListener, db: CoreDB, sig: impl Future) {
+    test_run_bindings(vec![Binding::plain(listener)], db, sig).await
+}
+
+/// This is a **test only** function, like `test_run`, which serves the database on
+/// every one of the `bindings`
+#[cfg(test)]
+pub async fn test_run_bindings(bindings: Vec<Binding>, db: CoreDB, sig: impl Future) {
     let (signal, _) = broadcast::channel(1);
     let (terminate_tx, terminate_rx) = mpsc::channel(1);
     let mut server = Listener {
-        listener,
+        incoming: accept_all(bindings, &signal),
         db,
-        climit: Arc::new(Semaphore::new(50000)),
         signal,
         terminate_tx,
         terminate_rx,
@@ -0,0 +1,421 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # Encryption at rest
+//!
+//! The dump file and snapshots can be encrypted with XChaCha20-Poly1305, which is an
+//! authenticated cipher: a file that was modified (or encrypted with another key) fails
+//! to decrypt instead of being read as garbage.
+//!
+//! Files are encrypted in chunks of [`CHUNK_SIZE`] bytes with the STREAM construction,
+//! so that they can be written and read without holding the whole file in memory. The
+//! encrypted data starts with the ID of the key and a random nonce prefix:
+//! ```text
+//! | key ID (8 bytes) | nonce prefix (19 bytes) | chunk | chunk | ... | last chunk |
+//! ```
+//! STREAM marks the last chunk, so a file that was cut short is detected too.
+//!
+//! Keys are 32 bytes, written as 64 hexadecimal characters (for example, the output
+//! of `openssl rand -hex 32`). To rotate keys, the current key is moved to
+//! `old_key_files` and a new key takes its place. Files encrypted with an old key can
+//! still be read, and they're encrypted with the new key the next time they're written
+
+use crate::config::EncryptionConfig;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::stream::{DecryptorBE32, EncryptorBE32};
+use chacha20poly1305::aead::{generic_array::GenericArray, KeyInit, OsRng};
+use chacha20poly1305::XChaCha20Poly1305;
+use libtdb::TResult;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// The length of a key, in bytes
+const KEY_LEN: usize = 32;
+/// The length of a key ID, in bytes
+const KEY_ID_LEN: usize = 8;
+/// The length of the nonce prefix. STREAM uses the last 5 bytes of the 24 byte nonce
+/// for the chunk counter and the "last chunk" flag
+const NONCE_PREFIX_LEN: usize = 19;
+/// The size of a chunk of plaintext
+const CHUNK_SIZE: usize = 64 * 1024;
+/// The size of the authentication tag at the end of every encrypted chunk
+const TAG_LEN: usize = 16;
+
+/// An encryption key
+#[derive(Clone)]
+pub struct Key {
+    /// The key
+    key: [u8; KEY_LEN],
+    /// The ID of the key, which is stored in the files encrypted with it
+    id: [u8; KEY_ID_LEN],
+}
+
+impl Key {
+    /// Create a key from its hexadecimal representation
+    pub fn from_hex(hex: &str) -> Result<Self, String> {
+        let hex = hex.trim();
+        if hex.len() != KEY_LEN * 2 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(format!(
+                "an encryption key must be {} hexadecimal characters",
+                KEY_LEN * 2
+            ));
+        }
+        let mut key = [0u8; KEY_LEN];
+        for (i, byte) in key.iter_mut().enumerate() {
+            // This can't fail, since we've checked all the characters
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap();
+        }
+        Ok(Key::new(key))
+    }
+    /// Create a key from its bytes
+    fn new(key: [u8; KEY_LEN]) -> Self {
+        // The ID is a hash of the key, so that it doesn't give the key away
+        let digest = Sha256::new()
+            .chain_update(b"tdb-key-id")
+            .chain_update(key)
+            .finalize();
+        let mut id = [0u8; KEY_ID_LEN];
+        id.copy_from_slice(&digest[..KEY_ID_LEN]);
+        Key { key, id }
+    }
+    /// Read a key from the file at `path`
+    fn from_file(path: &Path) -> TResult<Self> {
+        let hex = fs::read_to_string(path).map_err(|e| {
+            format!(
+                "Failed to read the encryption key from '{}': {}",
+                path.display(),
+                e
+            )
+        })?;
+        Ok(Key::from_hex(&hex).map_err(|e| format!("In '{}': {}", path.display(), e))?)
+    }
+    /// Read a key from the environment variable `var`
+    fn from_env(var: &str) -> TResult<Self> {
+        let hex = std::env::var(var).map_err(|_| {
+            format!(
+                "The environment variable '{}' with the encryption key isn't set",
+                var
+            )
+        })?;
+        Ok(Key::from_hex(&hex).map_err(|e| format!("In '{}': {}", var, e))?)
+    }
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(GenericArray::from_slice(&self.key))
+    }
+}
+
+/// Key IDs are shown in hex, and the key itself is never shown
+fn fmt_id(id: &[u8]) -> String {
+    id.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Key({})", fmt_id(&self.id))
+    }
+}
+
+/// The keys that the server knows about
+#[derive(Debug, Clone)]
+pub struct Keyring {
+    /// The key with which files are encrypted. Files aren't encrypted if this is `None`
+    current: Option<Key>,
+    /// Keys that were used before, which are only used to decrypt files
+    old: Vec<Key>,
+}
+
+impl Keyring {
+    /// A keyring without any keys, so that nothing is encrypted
+    pub const fn none() -> Self {
+        Keyring {
+            current: None,
+            old: Vec::new(),
+        }
+    }
+    /// Load the keys in the encryption configuration
+    ///
+    /// If only old keys are set, files are decrypted but not encrypted again, which is
+    /// how encryption is turned off
+    pub fn load(cfg: &EncryptionConfig) -> TResult<Self> {
+        let current = match (&cfg.key_file, &cfg.key_env) {
+            (Some(_), Some(_)) => {
+                return Err(
+                    "Only one of `key_file` and `key_env` can be set in the [encryption] section"
+                        .into(),
+                )
+            }
+            (Some(path), None) => Some(Key::from_file(path)?),
+            (None, Some(var)) => Some(Key::from_env(var)?),
+            (None, None) => None,
+        };
+        let old = cfg
+            .old_key_files
+            .iter()
+            .map(|path| Key::from_file(path))
+            .collect::<TResult<_>>()?;
+        Ok(Keyring { current, old })
+    }
+    /// Returns the key with which files should be encrypted
+    pub const fn current(&self) -> Option<&Key> {
+        self.current.as_ref()
+    }
+    /// Find the key with the ID `id`
+    fn find(&self, id: &[u8]) -> Option<&Key> {
+        self.current
+            .iter()
+            .chain(self.old.iter())
+            .find(|key| key.id == id)
+    }
+}
+
+/// A writer which encrypts everything that is written to it
+///
+/// [`EncryptWriter::finish`] has to be called to write the last chunk
+pub struct EncryptWriter<W: Write> {
+    inner: W,
+    /// This is only `None` once the last chunk has been written
+    stream: Option<EncryptorBE32<XChaCha20Poly1305>>,
+    /// The plaintext which hasn't been encrypted yet
+    buf: Vec<u8>,
+}
+
+impl<W: Write> EncryptWriter<W> {
+    /// Start encrypting into `inner` with `key`
+    pub fn new(mut inner: W, key: &Key) -> io::Result<Self> {
+        let mut prefix = [0u8; NONCE_PREFIX_LEN];
+        OsRng.fill_bytes(&mut prefix);
+        inner.write_all(&key.id)?;
+        inner.write_all(&prefix)?;
+        Ok(EncryptWriter {
+            inner,
+            stream: Some(EncryptorBE32::from_aead(
+                key.cipher(),
+                GenericArray::from_slice(&prefix),
+            )),
+            buf: Vec::with_capacity(CHUNK_SIZE),
+        })
+    }
+    /// Encrypt the last chunk, returning the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        let stream = self.stream.take().expect("The stream was already finished");
+        let chunk = stream.encrypt_last(&self.buf[..]).map_err(encryption_error)?;
+        self.inner.write_all(&chunk)?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for EncryptWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        // A full chunk is only encrypted once we know that it isn't the last one
+        if self.buf.len() == CHUNK_SIZE {
+            let stream = self.stream.as_mut().expect("The stream was already finished");
+            let chunk = stream.encrypt_next(&self.buf[..]).map_err(encryption_error)?;
+            self.inner.write_all(&chunk)?;
+            self.buf.clear();
+        }
+        let len = data.len().min(CHUNK_SIZE - self.buf.len());
+        self.buf.extend_from_slice(&data[..len]);
+        Ok(len)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn encryption_error(_: chacha20poly1305::aead::Error) -> io::Error {
+    io::Error::other("Failed to encrypt data")
+}
+
+/// A reader which decrypts what [`EncryptWriter`] wrote
+pub struct DecryptReader<R: Read> {
+    inner: R,
+    /// This is only `None` once the last chunk has been decrypted
+    stream: Option<DecryptorBE32<XChaCha20Poly1305>>,
+    /// The encrypted data that has been read, but not decrypted yet
+    pending: Vec<u8>,
+    /// The decrypted chunk
+    plain: Vec<u8>,
+    /// How much of `plain` has been read
+    pos: usize,
+}
+
+impl<R: Read> DecryptReader<R> {
+    /// Start decrypting `inner` with the key it was encrypted with, from `keys`
+    pub fn new(mut inner: R, keys: &Keyring) -> TResult<Self> {
+        let mut id = [0u8; KEY_ID_LEN];
+        let mut prefix = [0u8; NONCE_PREFIX_LEN];
+        inner.read_exact(&mut id)?;
+        inner.read_exact(&mut prefix)?;
+        let key = match keys.find(&id) {
+            Some(key) => key,
+            None if keys.current().is_none() && keys.old.is_empty() => {
+                return Err("The data is encrypted, but no encryption key is set".into())
+            }
+            None => {
+                return Err(format!(
+                    "The data was encrypted with a key (ID {}) that is neither the current key nor one of the old keys",
+                    fmt_id(&id)
+                )
+                .into())
+            }
+        };
+        if keys.current().map(|current| current.id) != Some(key.id) {
+            log::info!(
+                "Reading data encrypted with the old key {}",
+                fmt_id(&key.id)
+            );
+        }
+        Ok(DecryptReader {
+            inner,
+            stream: Some(DecryptorBE32::from_aead(
+                key.cipher(),
+                GenericArray::from_slice(&prefix),
+            )),
+            pending: Vec::with_capacity(CHUNK_SIZE + TAG_LEN + 1),
+            plain: Vec::new(),
+            pos: 0,
+        })
+    }
+    /// Decrypt the next chunk into `plain`
+    fn next_chunk(&mut self) -> io::Result<()> {
+        // We read one byte more than a chunk, to find out if this chunk is the last one
+        let want = CHUNK_SIZE + TAG_LEN + 1;
+        while self.pending.len() < want {
+            let len = self.pending.len();
+            self.pending.resize(want, 0);
+            match self.inner.read(&mut self.pending[len..]) {
+                Ok(n) => {
+                    self.pending.truncate(len + n);
+                    if n == 0 {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    self.pending.truncate(len);
+                    if e.kind() != io::ErrorKind::Interrupted {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        let plain = if self.pending.len() == want {
+            let stream = self.stream.as_mut().unwrap();
+            let plain = stream.decrypt_next(&self.pending[..want - 1]);
+            self.pending.drain(..want - 1);
+            plain
+        } else {
+            let stream = self.stream.take().unwrap();
+            let plain = stream.decrypt_last(&self.pending[..]);
+            self.pending.clear();
+            plain
+        };
+        self.plain = plain.map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The encrypted data failed authentication, so it was modified or is damaged",
+            )
+        })?;
+        self.pos = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecryptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.plain.len() {
+            if self.stream.is_none() {
+                return Ok(0);
+            }
+            self.next_chunk()?;
+        }
+        let len = buf.len().min(self.plain.len() - self.pos);
+        buf[..len].copy_from_slice(&self.plain[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+fn encrypt(data: &[u8], key: &Key) -> Vec<u8> {
+    let mut writer = EncryptWriter::new(Vec::new(), key).unwrap();
+    writer.write_all(data).unwrap();
+    writer.finish().unwrap()
+}
+
+#[cfg(test)]
+fn decrypt(data: &[u8], keys: &Keyring) -> TResult<Vec<u8>> {
+    let mut plain = Vec::new();
+    DecryptReader::new(data, keys)?.read_to_end(&mut plain)?;
+    Ok(plain)
+}
+
+#[test]
+fn test_encryption_roundtrip() {
+    let key = Key::from_hex(&"ab".repeat(KEY_LEN)).unwrap();
+    let keys = Keyring {
+        current: Some(key.clone()),
+        old: vec![],
+    };
+    // Empty data, exactly one chunk, and a few chunks with a bit left over
+    for &len in &[0, CHUNK_SIZE, CHUNK_SIZE * 3 + 17] {
+        let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+        let encrypted = encrypt(&data, &key);
+        assert_eq!(&encrypted[..KEY_ID_LEN], &key.id);
+        assert_eq!(decrypt(&encrypted, &keys).unwrap(), data);
+    }
+    let data = vec![b'x'; CHUNK_SIZE * 2];
+    let encrypted = encrypt(&data, &key);
+    // The same data is never encrypted the same way twice
+    assert_ne!(encrypt(&data, &key), encrypted);
+    // Modified data
+    let mut modified = encrypted.clone();
+    modified[KEY_ID_LEN + NONCE_PREFIX_LEN + 5] ^= 1;
+    assert!(decrypt(&modified, &keys).is_err());
+    // Data that was cut short, right after a chunk
+    let cut = KEY_ID_LEN + NONCE_PREFIX_LEN + CHUNK_SIZE + TAG_LEN;
+    assert!(decrypt(&encrypted[..cut], &keys).is_err());
+    // No keys, and the wrong key
+    assert!(decrypt(&encrypted, &Keyring::none()).is_err());
+    let other = Keyring {
+        current: Some(Key::from_hex(&"cd".repeat(KEY_LEN)).unwrap()),
+        old: vec![],
+    };
+    assert!(decrypt(&encrypted, &other).is_err());
+    // The key was rotated
+    let rotated = Keyring {
+        current: other.current.clone(),
+        old: vec![key],
+    };
+    assert_eq!(decrypt(&encrypted, &rotated).unwrap(), data);
+}
+
+#[test]
+fn test_key_from_hex() {
+    let key = Key::from_hex(&format!("{}\n", "0F".repeat(KEY_LEN))).unwrap();
+    assert_eq!(key.key, [0x0F; KEY_LEN]);
+    // The key never shows up in logs
+    assert!(!format!("{:?}", key).contains("0f0f0f"));
+    assert!(Key::from_hex("abcd").is_err());
+    assert!(Key::from_hex(&"zz".repeat(KEY_LEN)).is_err());
+}
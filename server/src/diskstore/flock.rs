@@ -0,0 +1,96 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # Data directory locking
+//!
+//! Two servers flushing to the same files would silently overwrite each other's data, so
+//! the server takes an exclusive lock on a lock file in the data directory on startup.
+//! The lock is held by the OS (`flock` on Unix, an unshared handle on Windows), so it is
+//! released when the process exits, even if it crashes. This means that a lock file left
+//! behind never has to be cleaned up by hand
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+/// An exclusive lock on a lock file. The lock is released when this is dropped
+#[derive(Debug)]
+pub struct FileLock {
+    /// The locked file
+    _file: File,
+}
+
+impl FileLock {
+    /// Lock the file at `path`, creating it if it doesn't exist
+    ///
+    /// This fails immediately (instead of waiting) if someone else holds the lock
+    pub fn lock(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut file = open_locked(path)?;
+        // Leave our PID in the file, to help whoever runs into the lock
+        file.set_len(0)?;
+        writeln!(file, "{}", std::process::id())?;
+        file.sync_all()?;
+        Ok(FileLock { _file: file })
+    }
+}
+
+#[cfg(unix)]
+fn open_locked(path: &Path) -> io::Result<File> {
+    use std::os::unix::io::AsRawFd;
+    // Don't truncate the file before we hold the lock, since it has the PID of the holder
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(path)?;
+    // This is safe as `file` is a valid, open file descriptor
+    let ret = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+    if ret == 0 {
+        Ok(file)
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(windows)]
+fn open_locked(path: &Path) -> io::Result<File> {
+    use std::os::windows::fs::OpenOptionsExt;
+    // Nobody else can open the file while we have it open
+    OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .share_mode(0)
+        .open(path)
+}
+
+#[test]
+fn test_lock_is_exclusive() {
+    let path = std::env::temp_dir().join(format!("tdb-test-{}.lock", std::process::id()));
+    let lock = FileLock::lock(&path).unwrap();
+    assert!(FileLock::lock(&path).is_err());
+    drop(lock);
+    // The lock file is left behind, but it can be locked again
+    let lock = FileLock::lock(&path).unwrap();
+    drop(lock);
+    std::fs::remove_file(path).unwrap();
+}
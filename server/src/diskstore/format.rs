@@ -0,0 +1,421 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # The format of the files written by `flush_data`
+//!
+//! Files start with a header:
+//! ```text
+//! | MAGIC (4 bytes) | version (1 byte) | codec (1 byte) | cipher (1 byte) |
+//! ```
+//! which is followed by the table as checksummed records (see the
+//! [`records`](super::records) module), compressed with the codec and then encrypted
+//! (see the [`encryption`](super::encryption) module).
+//!
+//! Older files are still read: files without a header are plain `bincode`, and files
+//! with version 1 of the header have compressed and encrypted `bincode` instead of
+//! records. Neither of them has checksums.
+//!
+//! The data is compressed, encrypted, decrypted and decompressed as it is written and
+//! read, so the whole file is never held in memory (except by [`salvage_table`])
+
+use super::encryption::{DecryptReader, EncryptWriter, Keyring};
+use super::records::{self, SalvageReport, Table};
+use crate::coredb::Data;
+use bytes::Bytes;
+use libtdb::TResult;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+/// The first bytes of a file with a header
+///
+/// A plain `bincode` file starts with the number of keys as a little-endian `u64`, and
+/// these bytes would mean that there are more than a billion keys, so they are never
+/// mistaken for a plain file
+pub const MAGIC: [u8; 4] = [0xFF, b'T', b'D', b'B'];
+/// The version of the header with `bincode` after it
+const VERSION_BINCODE: u8 = 1;
+/// The version of the header with records after it
+const VERSION: u8 = 2;
+/// The zstd compression level. This is zstd's default, which is a good balance
+/// between speed and size
+const ZSTD_LEVEL: i32 = 3;
+/// The ID of "no encryption" in the header
+const CIPHER_NONE: u8 = 0;
+/// The ID of XChaCha20-Poly1305 in the header
+const CIPHER_XCHACHA20POLY1305: u8 = 1;
+
+/// The way the in-memory table was stored by older versions
+type DiskStore = (Vec<String>, Vec<Vec<u8>>);
+
+/// The codec used to compress a file
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    /// No compression
+    None,
+    /// LZ4 (frame format), which is very fast
+    Lz4,
+    /// zstd, which compresses better than LZ4 but is slower
+    Zstd,
+}
+
+impl Codec {
+    /// Returns the ID of the codec in the header
+    const fn id(self) -> u8 {
+        match self {
+            Codec::None => 0,
+            Codec::Lz4 => 1,
+            Codec::Zstd => 2,
+        }
+    }
+    /// Returns the codec with the ID `id`
+    const fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Codec::None),
+            1 => Some(Codec::Lz4),
+            2 => Some(Codec::Zstd),
+            _ => None,
+        }
+    }
+}
+
+/// Write `data` into `writer`, compressing it with `codec` and encrypting it with the
+/// current key in `keys` (if there is one)
+///
+/// This returns `writer`, which should be flushed by the caller
+pub fn write_table<W: Write>(
+    mut writer: W,
+    codec: Codec,
+    keys: &Keyring,
+    data: &dyn Table,
+) -> TResult<W> {
+    let key = keys.current();
+    let cipher = if key.is_some() {
+        CIPHER_XCHACHA20POLY1305
+    } else {
+        CIPHER_NONE
+    };
+    writer.write_all(&MAGIC)?;
+    writer.write_all(&[VERSION, codec.id(), cipher])?;
+    match key {
+        Some(key) => {
+            let writer = EncryptWriter::new(writer, key)?;
+            Ok(compress_into(writer, codec, data)?.finish()?)
+        }
+        None => compress_into(writer, codec, data),
+    }
+}
+
+fn compress_into<W: Write>(writer: W, codec: Codec, data: &dyn Table) -> TResult<W> {
+    match codec {
+        Codec::None => Ok(records::write_table(writer, data)?),
+        Codec::Lz4 => {
+            let encoder = lz4_flex::frame::FrameEncoder::new(writer);
+            Ok(records::write_table(encoder, data)?.finish()?)
+        }
+        Codec::Zstd => {
+            let encoder = zstd::Encoder::new(writer, ZSTD_LEVEL)?;
+            Ok(records::write_table(encoder, data)?.finish()?)
+        }
+    }
+}
+
+/// Write `data` into `writer` as plain `bincode`, which is what versions before the
+/// header was added wrote and read
+///
+/// Such files have no checksums and can't be compressed or encrypted, so this is only
+/// useful for going back to an older version
+pub fn write_legacy_table<W: Write>(mut writer: W, data: &HashMap<String, Data>) -> TResult<W> {
+    let (keys, values): DiskStore = data
+        .iter()
+        .map(|(key, value)| (key.clone(), value.get_blob().to_vec()))
+        .unzip();
+    bincode::serialize_into(&mut writer, &(keys, values))?;
+    Ok(writer)
+}
+
+/// What the header of a file says about it
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FileFormat {
+    /// Plain `bincode`, without a header
+    Legacy,
+    /// A file with a header
+    Header {
+        /// The version of the header (1 for `bincode`, 2 for records)
+        version: u8,
+        /// The codec the data is compressed with
+        codec: Codec,
+        /// Whether the data is encrypted
+        encrypted: bool,
+    },
+}
+
+/// Read the header from `reader` (if there is one) without reading the data
+pub fn inspect<R: Read>(mut reader: R) -> TResult<FileFormat> {
+    let mut header = [0u8; MAGIC.len() + 3];
+    let got = read_upto(&mut reader, &mut header)?;
+    if got < MAGIC.len() || header[..MAGIC.len()] != MAGIC {
+        return Ok(FileFormat::Legacy);
+    }
+    if got < header.len() {
+        return Err("the header was cut short".into());
+    }
+    let [version, codec, cipher] = [header[4], header[5], header[6]];
+    if version != VERSION_BINCODE && version != VERSION {
+        return Err(format!("unsupported file format version {}", version).into());
+    }
+    let codec =
+        Codec::from_id(codec).ok_or_else(|| format!("unknown compression codec {}", codec))?;
+    let encrypted = match cipher {
+        CIPHER_NONE => false,
+        CIPHER_XCHACHA20POLY1305 => true,
+        _ => return Err(format!("unknown cipher {}", cipher).into()),
+    };
+    Ok(FileFormat::Header {
+        version,
+        codec,
+        encrypted,
+    })
+}
+
+/// What comes after the header
+enum Layout {
+    /// `bincode`, without checksums
+    Bincode,
+    /// Checksummed records
+    Records,
+}
+
+/// Read the header, returning a reader for the decrypted and decompressed data after it
+fn open<'a, R: Read + 'a>(mut reader: R, keys: &Keyring) -> TResult<(Layout, Box<dyn Read + 'a>)> {
+    let mut magic = [0u8; MAGIC.len()];
+    let got = read_upto(&mut reader, &mut magic)?;
+    if got < MAGIC.len() || magic != MAGIC {
+        // This is a plain file, so put back the bytes that we've just read
+        let reader = io::Cursor::new(magic[..got].to_vec()).chain(reader);
+        return Ok((Layout::Bincode, Box::new(reader)));
+    }
+    let mut header = [0u8; 3];
+    reader
+        .read_exact(&mut header)
+        .map_err(|_| "the header was cut short")?;
+    let [version, codec, cipher] = header;
+    let layout = match version {
+        VERSION_BINCODE => Layout::Bincode,
+        VERSION => Layout::Records,
+        _ => return Err(format!("unsupported file format version {}", version).into()),
+    };
+    let codec =
+        Codec::from_id(codec).ok_or_else(|| format!("unknown compression codec {}", codec))?;
+    let reader: Box<dyn Read + 'a> = match cipher {
+        CIPHER_NONE => Box::new(reader),
+        CIPHER_XCHACHA20POLY1305 => Box::new(DecryptReader::new(reader, keys)?),
+        _ => return Err(format!("unknown cipher {}", cipher).into()),
+    };
+    let reader: Box<dyn Read + 'a> = match codec {
+        Codec::None => reader,
+        Codec::Lz4 => Box::new(lz4_flex::frame::FrameDecoder::new(reader)),
+        Codec::Zstd => Box::new(zstd::Decoder::new(reader)?),
+    };
+    Ok((layout, reader))
+}
+
+/// The records after the header are damaged, so [`salvage_table`] can recover the
+/// intact ones
+#[derive(Debug)]
+pub struct Damaged(String);
+
+impl fmt::Display for Damaged {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for Damaged {}
+
+/// Read a table from `reader`, decrypting it with a key from `keys` and decompressing
+/// it with the codec in its header
+///
+/// Any damage to the data is an error which says what is damaged and where
+pub fn read_table<R: Read>(reader: R, keys: &Keyring) -> TResult<HashMap<String, Data>> {
+    match open(reader, keys)? {
+        (Layout::Records, reader) => {
+            records::read_table(reader).map_err(|e| Damaged(e.to_string()).into())
+        }
+        (Layout::Bincode, reader) => {
+            let (keys, values): DiskStore = bincode::deserialize_from(reader)?;
+            Ok(keys
+                .into_iter()
+                .zip(values)
+                .map(|(key, value)| (key, Data::from_blob(Bytes::from(value))))
+                .collect())
+        }
+    }
+}
+
+/// Read every intact record from a damaged table in `reader`
+///
+/// If the compressed or encrypted data is damaged, everything before the damage is
+/// salvaged. Older files don't have records, so they can't be salvaged
+pub fn salvage_table<R: Read>(
+    reader: R,
+    keys: &Keyring,
+) -> TResult<(HashMap<String, Data>, SalvageReport)> {
+    let mut reader = match open(reader, keys)? {
+        (Layout::Records, reader) => reader,
+        (Layout::Bincode, _) => {
+            return Err(
+                "the file was written by an older version without checksums, so it can't be salvaged"
+                    .into(),
+            )
+        }
+    };
+    let mut data = Vec::new();
+    let mut buf = [0u8; 8192];
+    let mut read_error = None;
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => data.extend_from_slice(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => {
+                read_error = Some(e.to_string());
+                break;
+            }
+        }
+    }
+    let (table, mut report) = records::salvage(&data);
+    report.read_error = read_error;
+    Ok((table, report))
+}
+
+/// Fill as much of `buf` as possible, returning the number of bytes read. This only
+/// reads less than `buf.len()` bytes if the reader runs out of data
+fn read_upto(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut got = 0;
+    while got < buf.len() {
+        match reader.read(&mut buf[got..]) {
+            Ok(0) => break,
+            Ok(n) => got += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(got)
+}
+
+#[cfg(test)]
+fn test_table() -> HashMap<String, Data> {
+    (0..100)
+        .map(|i| (format!("key{}", i), Data::from_blob(Bytes::from(vec![b'a'; i * 10]))))
+        .collect()
+}
+
+#[test]
+fn test_codecs_roundtrip() {
+    let table = test_table();
+    let none = Keyring::none();
+    let plain = write_table(Vec::new(), Codec::None, &none, &table).unwrap();
+    for &codec in &[Codec::None, Codec::Lz4, Codec::Zstd] {
+        let written = write_table(Vec::new(), codec, &none, &table).unwrap();
+        assert_eq!(written[..4], MAGIC);
+        assert_eq!(written[4..7], [VERSION, codec.id(), CIPHER_NONE]);
+        assert_eq!(
+            inspect(&written[..]).unwrap(),
+            FileFormat::Header {
+                version: VERSION,
+                codec,
+                encrypted: false
+            }
+        );
+        if codec != Codec::None {
+            assert!(written.len() < plain.len());
+        }
+        assert_eq!(read_table(&written[..], &none).unwrap(), table);
+    }
+    // An unknown codec is an error, not garbage, and not damage that can be salvaged
+    let mut written = write_table(Vec::new(), Codec::Lz4, &none, &table).unwrap();
+    written[5] = 42;
+    assert!(!read_table(&written[..], &none).unwrap_err().is::<Damaged>());
+    let mut written = plain.clone();
+    let last = written.len() - 1;
+    written[last] ^= 0xFF;
+    assert!(read_table(&written[..], &none).unwrap_err().is::<Damaged>());
+    // Files written by older versions are still read
+    let keys: Vec<String> = table.keys().cloned().collect();
+    let values: Vec<Vec<u8>> = keys.iter().map(|k| table[k].get_blob().to_vec()).collect();
+    let old: DiskStore = (keys, values);
+    let legacy = bincode::serialize(&old).unwrap();
+    assert_eq!(read_table(&legacy[..], &none).unwrap(), table);
+    let written = write_legacy_table(Vec::new(), &table).unwrap();
+    assert_eq!(inspect(&written[..]).unwrap(), FileFormat::Legacy);
+    assert_eq!(read_table(&written[..], &none).unwrap(), table);
+    let mut v1 = MAGIC.to_vec();
+    v1.extend_from_slice(&[VERSION_BINCODE, Codec::Zstd.id(), CIPHER_NONE]);
+    let mut v1 = zstd::Encoder::new(v1, ZSTD_LEVEL).unwrap();
+    bincode::serialize_into(&mut v1, &old).unwrap();
+    let v1 = v1.finish().unwrap();
+    assert_eq!(read_table(&v1[..], &none).unwrap(), table);
+    // ...but they can't be salvaged
+    assert!(salvage_table(&legacy[..], &none).is_err());
+}
+
+#[test]
+fn test_encrypted_roundtrip() {
+    use crate::config::EncryptionConfig;
+    let keyfile = std::env::temp_dir().join(format!("tdb-test-{}.key", std::process::id()));
+    std::fs::write(&keyfile, "42".repeat(32)).unwrap();
+    let keys = Keyring::load(&EncryptionConfig::new(Some(keyfile.clone()), None, vec![])).unwrap();
+    let mut table = HashMap::new();
+    table.insert("secret".to_owned(), Data::from_string("value".to_owned()));
+    for &codec in &[Codec::None, Codec::Lz4, Codec::Zstd] {
+        let written = write_table(Vec::new(), codec, &keys, &table).unwrap();
+        assert_eq!(written[6], CIPHER_XCHACHA20POLY1305);
+        // Nothing is readable without the key
+        assert!(!written.windows(6).any(|w| w == b"secret"));
+        assert!(!read_table(&written[..], &Keyring::none())
+            .unwrap_err()
+            .is::<Damaged>());
+        assert_eq!(read_table(&written[..], &keys).unwrap(), table);
+    }
+    std::fs::remove_file(keyfile).unwrap();
+}
+
+#[test]
+fn test_salvage_compressed() {
+    let table = test_table();
+    let none = Keyring::none();
+    let written = write_table(Vec::new(), Codec::None, &none, &table).unwrap();
+    let (salvaged, report) = salvage_table(&written[..], &none).unwrap();
+    assert!(report.is_intact());
+    assert_eq!(salvaged, table);
+    // If the compressed data is cut short, everything before that is salvaged
+    let written = write_table(Vec::new(), Codec::Zstd, &none, &table).unwrap();
+    let (salvaged, report) = salvage_table(&written[..written.len() / 2], &none).unwrap();
+    assert!(!report.is_intact());
+    assert!(report.truncated);
+    for (key, value) in &salvaged {
+        assert_eq!(table[key], *value);
+    }
+}
@@ -21,96 +21,155 @@
 
 //! This module provides tools for handling persistently stored data
 
-use crate::config::BGSave;
+use crate::config::{BGSave, StorageConfig};
 use crate::coredb::{self, Data};
-use bincode;
-use bytes::Bytes;
+use encryption::Keyring;
+use flock::FileLock;
+use format::Codec;
+use records::{SalvageReport, Table};
 use libtdb::TResult;
 use std::collections::HashMap;
 use std::fs;
-use std::io::{ErrorKind, Write};
-use std::iter::FromIterator;
+use std::io::{BufReader, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use tokio::time;
 mod cyansfw;
+pub mod encryption;
+pub mod flock;
+pub mod format;
+pub mod records;
 pub mod snapshot;
+pub mod writelog;
 
-type DiskStore = (Vec<String>, Vec<Vec<u8>>);
 pub const PERSIST_FILE: &'static str = "./data.bin";
 
 /// Try to get the saved data from disk. This returns `None`, if the `data.bin` wasn't found
 /// otherwise the `data.bin` file is deserialized and parsed into a `HashMap`
-pub fn get_saved(location: Option<&str>) -> TResult<Option<HashMap<String, Data>>> {
-    let file = match fs::read(if let Some(loc) = location {
-        loc
-    } else {
-        PERSIST_FILE
-    }) {
+///
+/// Compressed and encrypted files are detected from their header, and they are decrypted
+/// (with a key from `keys`) and decompressed while they are read
+pub fn get_saved<P: AsRef<Path>>(
+    location: Option<P>,
+    keys: &Keyring,
+) -> TResult<Option<HashMap<String, Data>>> {
+    let location = location
+        .as_ref()
+        .map(|loc| loc.as_ref())
+        .unwrap_or_else(|| Path::new(PERSIST_FILE));
+    let file = match fs::File::open(location) {
         Ok(f) => f,
         Err(e) => match e.kind() {
             ErrorKind::NotFound => return Ok(None),
-            _ => return Err("Couldn't read flushed data from disk".into()),
+            _ => return Err(format!("Couldn't open '{}': {}", location.display(), e).into()),
         },
     };
-    let parsed: DiskStore = bincode::deserialize(&file)?;
-    let parsed: HashMap<String, Data> = HashMap::from_iter(
-        parsed
-            .0
-            .into_iter()
-            .zip(parsed.1.into_iter())
-            .map(|(key, value)| {
-                let data = Data::from_blob(Bytes::from(value));
-                (key, data)
-            }),
-    );
-    Ok(Some(parsed))
+    let table = format::read_table(BufReader::new(file), keys).map_err(|e| {
+        // Salvaging doesn't help with a missing key or a file that we can't read at all
+        let hint = if e.is::<format::Damaged>() {
+            ". `tdb salvage` can recover the intact records from a damaged file"
+        } else {
+            ""
+        };
+        format!("Failed to read '{}': {}{}", location.display(), e, hint)
+    })?;
+    Ok(Some(table))
 }
 
 /// Flush the in-memory table onto disk
 ///
 /// This functions takes the entire in-memory table and writes it to the disk,
-/// more specifically, the `data.bin` file
-pub fn flush_data(filename: &str, data: &HashMap<String, Data>) -> TResult<()> {
-    let ds: DiskStore = (
-        data.keys().into_iter().map(|val| val.to_string()).collect(),
-        data.values().map(|val| val.get_blob().to_vec()).collect(),
-    );
-    let encoded = bincode::serialize(&ds)?;
-    let mut file = fs::File::create(filename)?;
-    file.write_all(&encoded)?;
+/// more specifically, the `data.bin` file. The data is compressed with `codec` and
+/// encrypted with the current key in `keys` (if there is one) as it is written
+pub fn flush_data(
+    filename: impl AsRef<Path>,
+    data: &dyn Table,
+    codec: Codec,
+    keys: &Keyring,
+) -> TResult<()> {
+    let file = BufWriter::new(fs::File::create(filename)?);
+    let mut file = format::write_table(file, codec, keys, data)?;
+    file.flush()?;
     Ok(())
 }
 
+/// Salvage the intact records in `input` (the dump file by default) into `output`
+/// (the input by default), returning what was salvaged and what was lost
+///
+/// If the output is the input, the damaged file is kept by adding `.damaged` to its
+/// name. This takes the lock on the data directory, so it fails if a server is using it
+pub fn salvage(
+    storage: &StorageConfig,
+    keys: &Keyring,
+    input: Option<PathBuf>,
+    output: Option<PathBuf>,
+) -> TResult<SalvageReport> {
+    let _lock = FileLock::lock(storage.lock_path()).map_err(|e| {
+        format!(
+            "Failed to lock '{}' with error: '{}'. Is a server using the data directory?",
+            storage.lock_path().display(),
+            e
+        )
+    })?;
+    let input = input.unwrap_or_else(|| storage.dump_path());
+    let output = output.unwrap_or_else(|| input.clone());
+    let file = fs::File::open(&input)
+        .map_err(|e| format!("Couldn't open '{}': {}", input.display(), e))?;
+    let (table, report) = format::salvage_table(BufReader::new(file), keys)
+        .map_err(|e| format!("Failed to salvage '{}': {}", input.display(), e))?;
+    if output == input {
+        let mut damaged = input.clone().into_os_string();
+        damaged.push(".damaged");
+        fs::rename(&input, &damaged)?;
+        log::info!(
+            "Moved the damaged file to '{}'",
+            Path::new(&damaged).display()
+        );
+    }
+    flush_data(&output, &table, storage.compression(), keys)?;
+    Ok(report)
+}
+
 /// The bgsave_scheduler calls the bgsave task in `CoreDB` after `every` seconds
 ///
-/// The time after which the scheduler will wake up the BGSAVE task is determined by
-/// `bgsave_cfg` which is to be passed as an argument. If BGSAVE is disabled, this function
-/// immediately returns
-pub async fn bgsave_scheduler(handle: coredb::CoreDB, bgsave_cfg: BGSave) {
-    let duration = match bgsave_cfg {
-        BGSave::Disabled => {
+/// The time after which the scheduler will wake up the BGSAVE task is determined by the
+/// BGSAVE settings in the effective configuration, which are read again whenever the
+/// scheduler is woken up with `bgsave_reconfigured`. If BGSAVE is disabled, this function
+/// only runs BGSAVE when it is woken up by the `BGSAVE` action
+pub async fn bgsave_scheduler(handle: coredb::CoreDB) {
+    // The next save is due `every` seconds after the last one
+    let mut last_save = None;
+    while !handle.shared.is_termsig() {
+        let bgsave_cfg = handle.shared.config.read().bgsave;
+        let due = match bgsave_cfg {
+            BGSave::Enabled(every) => Some(match last_save {
+                Some(last) => last + Duration::from_secs(every),
+                None => time::Instant::now(),
+            }),
             // So, there's no BGSAVE! Looks like our user's pretty confident
             // that there won't be any power failures! Never mind, we'll just
-            // shut down the BGSAVE task, and immediately return
-            handle.shared.bgsave_task.notified().await;
-            return;
-        }
-        BGSave::Enabled(duration) => {
-            // If we're here - the user doesn't trust his power supply or just values
-            // his data - which is good! So we'll turn this into a `Duration`
-            Duration::from_secs(duration)
+            // wait until someone asks for a save, or until the database is shutting down
+            BGSave::Disabled => None,
+        };
+        tokio::select! {
+            // Sleep until the next save is due
+            _ = wait_until(due) => {}
+            // Otherwise wait for a notification
+            _ = handle.shared.bgsave_task.notified() => {}
+            // The settings have changed, so work out when the next save is due again
+            _ = handle.shared.bgsave_reconfigured.notified() => continue,
         }
-    };
-    while !handle.shared.is_termsig() {
-        if handle.shared.run_bgsave() {
-            tokio::select! {
-                // Sleep until `duration` from the current time instant
-                _ = time::delay_until(time::Instant::now() + duration) => {}
-                // Otherwise wait for a notification
-                _ = handle.shared.bgsave_task.notified() => {}
-            }
-        } else {
-            handle.shared.bgsave_task.notified().await
+        if !handle.shared.run_bgsave() {
+            return;
         }
+        last_save = Some(time::Instant::now());
+    }
+}
+
+/// Wait until `deadline`, or forever if there's no deadline
+pub async fn wait_until(deadline: Option<time::Instant>) {
+    match deadline {
+        Some(deadline) => time::delay_until(deadline).await,
+        None => std::future::pending().await,
     }
 }
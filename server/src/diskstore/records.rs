@@ -0,0 +1,446 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # Checksummed records
+//!
+//! The table is stored as one record for every key, followed by an end record. All
+//! integers are little-endian:
+//! ```text
+//! record: | RECORD_MARKER | key length (u32) | value length (u64) | header CRC (u32) |
+//!         | data CRC (u32) | key | value |
+//! end:    | END_MARKER | number of records (u64) | CRC of everything before (u32) |
+//! ```
+//! The header CRC covers the two lengths, so that a damaged length is caught before we
+//! try to read that many bytes, and the data CRC covers the key and the value. The end
+//! record catches files that were cut short, or that lost whole records.
+//!
+//! Since every record starts with a marker, the intact records in a damaged file can
+//! be found by skipping to the next marker, which is what [`salvage`] does
+
+use crate::coredb::Data;
+use bytes::Bytes;
+use crc32fast::Hasher;
+use libtdb::TResult;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+/// The first bytes of every record
+const RECORD_MARKER: [u8; 4] = [0xA5, b'R', b'E', b'C'];
+/// The first bytes of the end record
+const END_MARKER: [u8; 4] = [0xA5, b'E', b'N', b'D'];
+/// The size of everything in a record before the key
+const RECORD_HEADER_LEN: usize = 4 + 4 + 8 + 4 + 4;
+/// The size of the end record
+const END_LEN: usize = 4 + 8 + 4;
+
+fn crc32(parts: &[&[u8]]) -> u32 {
+    let mut hasher = Hasher::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize()
+}
+
+/// Writes a table as records
+pub struct RecordWriter<W: Write> {
+    inner: W,
+    /// The CRC of everything that has been written
+    crc: Hasher,
+    /// The number of records that have been written
+    count: u64,
+}
+
+impl<W: Write> RecordWriter<W> {
+    pub fn new(inner: W) -> Self {
+        RecordWriter {
+            inner,
+            crc: Hasher::new(),
+            count: 0,
+        }
+    }
+    /// Write a record for `key`
+    pub fn write_record(&mut self, key: &str, value: &[u8]) -> io::Result<()> {
+        let key = key.as_bytes();
+        let key_len = (key.len() as u32).to_le_bytes();
+        let value_len = (value.len() as u64).to_le_bytes();
+        let mut header = [0u8; RECORD_HEADER_LEN];
+        header[..4].copy_from_slice(&RECORD_MARKER);
+        header[4..8].copy_from_slice(&key_len);
+        header[8..16].copy_from_slice(&value_len);
+        header[16..20].copy_from_slice(&crc32(&[&key_len, &value_len]).to_le_bytes());
+        header[20..24].copy_from_slice(&crc32(&[key, value]).to_le_bytes());
+        for part in &[&header[..], key, value] {
+            self.inner.write_all(part)?;
+            self.crc.update(part);
+        }
+        self.count += 1;
+        Ok(())
+    }
+    /// Write the end record, returning the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        let crc = self.crc.finalize();
+        self.inner.write_all(&END_MARKER)?;
+        self.inner.write_all(&self.count.to_le_bytes())?;
+        self.inner.write_all(&crc.to_le_bytes())?;
+        Ok(self.inner)
+    }
+}
+
+/// A table which can be written as records one key/value pair at a time, so that it
+/// never has to be in memory all at once
+pub trait Table {
+    /// Call `f` with every key/value pair, stopping at the first error
+    fn for_each_pair(&self, f: &mut dyn FnMut(&str, &[u8]) -> io::Result<()>) -> io::Result<()>;
+}
+
+impl Table for HashMap<String, Data> {
+    fn for_each_pair(&self, f: &mut dyn FnMut(&str, &[u8]) -> io::Result<()>) -> io::Result<()> {
+        for (key, value) in self {
+            f(key, value.get_blob())?;
+        }
+        Ok(())
+    }
+}
+
+/// Write all of `data` as records into `writer`
+pub fn write_table<W: Write>(writer: W, data: &dyn Table) -> io::Result<W> {
+    let mut writer = RecordWriter::new(writer);
+    data.for_each_pair(&mut |key, value| writer.write_record(key, value))?;
+    writer.finish()
+}
+
+/// Reads records, keeping track of where we are so that errors can say where the
+/// damage is
+struct RecordReader<R: Read> {
+    inner: R,
+    crc: Hasher,
+    /// How many bytes have been read
+    offset: u64,
+    /// How many records have been read
+    count: u64,
+}
+
+impl<R: Read> RecordReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> TResult<()> {
+        match self.inner.read_exact(buf) {
+            Ok(()) => {
+                self.offset += buf.len() as u64;
+                Ok(())
+            }
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Err(self.cut_short()),
+            Err(e) => Err(e.into()),
+        }
+    }
+    fn cut_short(&self) -> Box<dyn std::error::Error> {
+        format!(
+            "the table ends early, after {} intact records (at byte {}). The file was cut short",
+            self.count, self.offset
+        )
+        .into()
+    }
+    /// Read `len` bytes
+    ///
+    /// The buffer only grows as the bytes come in, so a damaged length can't make us
+    /// allocate more than what is left of the data
+    fn read_vec(&mut self, len: u64) -> TResult<Vec<u8>> {
+        let mut buf = Vec::new();
+        let got = (&mut self.inner).take(len).read_to_end(&mut buf)?;
+        self.offset += got as u64;
+        if (got as u64) < len {
+            return Err(self.cut_short());
+        }
+        Ok(buf)
+    }
+    /// Read a record or the end record, updating the CRC. This returns `None` at the
+    /// end record
+    fn next(&mut self) -> TResult<Option<(String, Vec<u8>)>> {
+        let (number, start) = (self.count + 1, self.offset);
+        let corrupt = |what: &str| -> Box<dyn std::error::Error> {
+            format!("record {} (at byte {} of the table) is corrupt: {}", number, start, what).into()
+        };
+        let mut marker = [0u8; 4];
+        self.read(&mut marker)?;
+        if marker == END_MARKER {
+            return Ok(None);
+        }
+        if marker != RECORD_MARKER {
+            return Err(corrupt("it doesn't start with a record marker"));
+        }
+        let mut header = [0u8; RECORD_HEADER_LEN - 4];
+        self.read(&mut header)?;
+        let (lens, crcs) = header.split_at(12);
+        if crc32(&[lens]).to_le_bytes() != crcs[..4] {
+            return Err(corrupt("the checksum of its header doesn't match"));
+        }
+        let key = self.read_vec(u32_at(lens, 0) as u64)?;
+        let value = self.read_vec(u64_at(lens, 4))?;
+        if crc32(&[&key, &value]).to_le_bytes() != crcs[4..] {
+            return Err(corrupt("the checksum of its key and value doesn't match"));
+        }
+        let key = String::from_utf8(key).map_err(|_| corrupt("its key isn't valid UTF-8"))?;
+        for part in &[&marker[..], &header[..], key.as_bytes(), &value[..]] {
+            self.crc.update(part);
+        }
+        self.count += 1;
+        Ok(Some((key, value)))
+    }
+}
+
+fn u32_at(buf: &[u8], at: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&buf[at..at + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn u64_at(buf: &[u8], at: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&buf[at..at + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// Read a table that was written by [`write_table`], checking every record and the
+/// whole table
+///
+/// Any damage is an error which says what is damaged and where
+pub fn read_table<R: Read>(reader: R) -> TResult<HashMap<String, Data>> {
+    let mut reader = RecordReader {
+        inner: reader,
+        crc: Hasher::new(),
+        offset: 0,
+        count: 0,
+    };
+    let mut table = HashMap::new();
+    while let Some((key, value)) = reader.next()? {
+        table.insert(key, Data::from_blob(Bytes::from(value)));
+    }
+    let mut end = [0u8; END_LEN - 4];
+    reader.read(&mut end)?;
+    let (count, crc) = (u64_at(&end, 0), u32_at(&end, 8));
+    if count != reader.count {
+        return Err(format!(
+            "the data should have {} records, but it has {}",
+            count, reader.count
+        )
+        .into());
+    }
+    if crc != reader.crc.finalize() {
+        return Err("the checksum of the whole table doesn't match".into());
+    }
+    Ok(table)
+}
+
+/// What [`salvage`] found
+#[derive(Debug, Default, PartialEq)]
+pub struct SalvageReport {
+    /// The number of intact records
+    pub salvaged: usize,
+    /// The number of records that the end record says there should be, if the end
+    /// record is intact
+    pub expected: Option<u64>,
+    /// The byte ranges (start and end) that were skipped, since they were damaged
+    pub damaged: Vec<(usize, usize)>,
+    /// Whether the end record is missing
+    pub truncated: bool,
+    /// The error which stopped us from reading the rest of the data, if any
+    pub read_error: Option<String>,
+}
+
+impl SalvageReport {
+    /// Check if nothing was lost
+    pub fn is_intact(&self) -> bool {
+        self.damaged.is_empty()
+            && !self.truncated
+            && self.read_error.is_none()
+            && self.expected == Some(self.salvaged as u64)
+    }
+}
+
+impl fmt::Display for SalvageReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "salvaged {} records", self.salvaged)?;
+        match self.expected {
+            Some(expected) if expected > self.salvaged as u64 => {
+                write!(f, ", lost {} of {}", expected - self.salvaged as u64, expected)?
+            }
+            Some(_) => {}
+            None => write!(f, ", the number of lost records is unknown")?,
+        }
+        for (start, end) in &self.damaged {
+            write!(f, "; skipped damaged bytes {}..{} of the table", start, end)?;
+        }
+        if self.truncated {
+            write!(f, "; the data was cut short")?;
+        }
+        if let Some(e) = &self.read_error {
+            write!(f, "; the rest of the data couldn't be read: {}", e)?;
+        }
+        Ok(())
+    }
+}
+
+/// Find every intact record in `data`, skipping over the damaged parts
+///
+/// If the same key shows up in more than one intact record, the last one wins
+pub fn salvage(data: &[u8]) -> (HashMap<String, Data>, SalvageReport) {
+    let mut table = HashMap::new();
+    let mut report = SalvageReport::default();
+    let mut damaged_from = None;
+    let mut pos = 0;
+    while pos < data.len() {
+        let rest = &data[pos..];
+        if let Some((key, value, len)) = parse_record(rest) {
+            if let Some(start) = damaged_from.take() {
+                report.damaged.push((start, pos));
+            }
+            table.insert(key, Data::from_blob(Bytes::copy_from_slice(value)));
+            report.salvaged += 1;
+            pos += len;
+            continue;
+        }
+        if rest.starts_with(&END_MARKER) && rest.len() >= END_LEN {
+            if let Some(start) = damaged_from.take() {
+                report.damaged.push((start, pos));
+            }
+            report.expected = Some(u64_at(rest, 4));
+            break;
+        }
+        // Skip to the next thing that looks like a marker
+        damaged_from.get_or_insert(pos);
+        pos += 1 + rest[1..]
+            .iter()
+            .position(|&b| b == RECORD_MARKER[0])
+            .unwrap_or(rest.len() - 1);
+    }
+    if pos >= data.len() {
+        report.truncated = true;
+    }
+    if let Some(start) = damaged_from {
+        report.damaged.push((start, data.len()));
+    }
+    (table, report)
+}
+
+/// Parse the record at the start of `data`, returning the key, the value and the
+/// length of the record if it is intact
+fn parse_record(data: &[u8]) -> Option<(String, &[u8], usize)> {
+    if data.len() < RECORD_HEADER_LEN || !data.starts_with(&RECORD_MARKER) {
+        return None;
+    }
+    let lens = &data[4..16];
+    if crc32(&[lens]) != u32_at(data, 16) {
+        return None;
+    }
+    let key_len = u32_at(lens, 0) as usize;
+    let value_len = u64_at(lens, 4) as usize;
+    let len = RECORD_HEADER_LEN
+        .checked_add(key_len)?
+        .checked_add(value_len)?;
+    if data.len() < len {
+        return None;
+    }
+    let key = &data[RECORD_HEADER_LEN..RECORD_HEADER_LEN + key_len];
+    let value = &data[RECORD_HEADER_LEN + key_len..len];
+    if crc32(&[key, value]) != u32_at(data, 20) {
+        return None;
+    }
+    let key = String::from_utf8(key.to_vec()).ok()?;
+    Some((key, value, len))
+}
+
+#[cfg(test)]
+fn test_table(len: usize) -> HashMap<String, Data> {
+    (0..len)
+        .map(|i| (format!("key{}", i), Data::from_string(format!("value{}", i))))
+        .collect()
+}
+
+#[test]
+fn test_records_roundtrip_and_corruption() {
+    let table = test_table(10);
+    let written = write_table(Vec::new(), &table).unwrap();
+    assert_eq!(read_table(&written[..]).unwrap(), table);
+    let (salvaged, report) = salvage(&written);
+    assert_eq!(salvaged, table);
+    assert!(report.is_intact());
+    // A flipped bit in a value is caught, and the error says which record it is in
+    let mut damaged = written.clone();
+    damaged[RECORD_HEADER_LEN + 2] ^= 1;
+    let e = read_table(&damaged[..]).unwrap_err().to_string();
+    assert!(e.starts_with("record 1 (at byte 0 of the table) is corrupt"), "{}", e);
+    // A file that was cut short
+    let e = read_table(&written[..written.len() - 1])
+        .unwrap_err()
+        .to_string();
+    assert!(e.contains("cut short"), "{}", e);
+    // A length that is larger than the rest of the data isn't allocated up front
+    let mut huge = written.clone();
+    let lens = [&1u32.to_le_bytes()[..], &u64::MAX.to_le_bytes()[..]].concat();
+    huge[4..16].copy_from_slice(&lens);
+    huge[16..20].copy_from_slice(&crc32(&[&lens]).to_le_bytes());
+    let e = read_table(&huge[..]).unwrap_err().to_string();
+    assert!(e.contains("cut short"), "{}", e);
+    // A whole record that went missing is caught by the end record
+    let first = parse_record(&written).unwrap().2;
+    let mut missing = written[first..].to_vec();
+    let e = read_table(&missing[..]).unwrap_err().to_string();
+    assert!(e.contains("should have 10 records, but it has 9"), "{}", e);
+    // ...and so is a changed end record
+    let len = missing.len();
+    missing[len - 1] ^= 1;
+    assert!(read_table(&missing[..]).is_err());
+}
+
+#[test]
+fn test_records_salvage() {
+    let table = test_table(10);
+    let written = write_table(Vec::new(), &table).unwrap();
+    let first = parse_record(&written).unwrap().2;
+    let second = first + parse_record(&written[first..]).unwrap().2;
+    // Damage the length of the second record, and garble the middle of the third
+    let mut damaged = written.clone();
+    damaged[first + 5] ^= 0xFF;
+    damaged[second + RECORD_HEADER_LEN] ^= 0xFF;
+    let (salvaged, report) = salvage(&damaged);
+    assert_eq!(report.salvaged, 8);
+    assert_eq!(salvaged.len(), 8);
+    assert_eq!(report.expected, Some(10));
+    assert_eq!(report.damaged.len(), 1);
+    assert_eq!(report.damaged[0].0, first);
+    assert!(!report.truncated);
+    assert!(!report.is_intact());
+    for (key, value) in &salvaged {
+        assert_eq!(table[key], *value);
+    }
+    // Everything before the damage is salvaged from a file that was cut short
+    let (salvaged, report) = salvage(&written[..second + 3]);
+    assert_eq!(salvaged.len(), 2);
+    assert!(report.truncated);
+    assert_eq!(report.expected, None);
+    assert_eq!(
+        report.to_string(),
+        format!(
+            "salvaged 2 records, the number of lost records is unknown; skipped damaged bytes {}..{} of the table; the data was cut short",
+            second,
+            second + 3
+        )
+    );
+}
@@ -20,160 +20,409 @@
 */
 
 //! Tools for creating snapshots
+//!
+//! Snapshots are stored in the snapshot directory as `<name>.snapshot`. Snapshots that are
+//! created without a name (including the ones created by the snapshot service) are named
+//! after the time at which they were created and are rotated out once there are more than
+//! `atmost` of them. Named snapshots are kept until they are deleted
 
 use crate::config::SnapshotConfig;
-use crate::coredb::CoreDB;
+use crate::coredb::{CoreDB, Shared};
 use crate::diskstore;
+use crate::metrics::METRICS;
 use chrono::prelude::*;
 use libtdb::TResult;
 use std::fs;
 use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::time;
 
-const DIR_SNAPSHOT: &'static str = "snapshots";
 /// The default snapshot count is 12, assuming that the user would take a snapshot
 /// every 2 hours (or 7200 seconds)
 const DEF_SNAPSHOT_COUNT: usize = 12;
+/// The extension of snapshot files
+const SNAPSHOT_EXT: &str = "snapshot";
+/// The format of the names of snapshots that were created without a name
+const SNAPSHOT_NAME_FORMAT: &str = "%Y%m%d-%H%M%S";
 
 /// # Snapshot Engine
 ///
-/// This object provides methods to create and delete snapshots. There should be a
-/// `snapshot_scheduler` which should hold an instance of this object, on startup.
-/// Whenever the duration expires, the caller should call `mksnap()`
-pub struct SnapshotEngine<'a> {
-    /// File names of the snapshots (relative paths)
+/// This object provides methods to create, list, delete and restore snapshots. The engine
+/// lives in `Shared`, so that it can be used by both the `snapshot_service` and the
+/// `SNAPSHOT` action
+#[derive(Debug)]
+pub struct SnapshotEngine {
+    /// Names of the snapshots that are rotated out, oldest first
     snaps: queue::Queue,
-    /// An atomic reference to the coretable
-    dbref: &'a CoreDB,
+    /// The directory in which the snapshots are stored
+    snapdir: PathBuf,
+    /// Whether the snapshots left behind by previous runs have been picked up yet
+    scanned: bool,
+}
+
+/// A snapshot on disk
+#[derive(Debug, PartialEq)]
+pub struct SnapshotInfo {
+    /// The name of the snapshot
+    pub name: String,
+    /// The size of the snapshot in bytes
+    pub size: u64,
+    /// When the snapshot was last written to
+    pub modified: DateTime<Utc>,
 }
 
-impl<'a> SnapshotEngine<'a> {
-    /// Create a new `Snapshot` instance
+impl SnapshotEngine {
+    /// Create a new `SnapshotEngine` which keeps at most `maxtop` unnamed snapshots
+    /// in `snapdir`, or all of them if `maxtop` is 0
     ///
-    /// This also attempts to check if the snapshots directory exists;
-    /// If the directory doesn't exist, then it is created
-    pub fn new<'b: 'a>(maxtop: usize, dbref: &'b CoreDB) -> TResult<Self> {
-        match fs::create_dir(DIR_SNAPSHOT) {
-            Ok(_) => (),
-            Err(e) => match e.kind() {
-                ErrorKind::AlreadyExists => (),
-                _ => return Err(e.into()),
-            },
-        }
-        Ok(SnapshotEngine {
-            snaps: queue::Queue::new(if maxtop == 0 {
-                (DEF_SNAPSHOT_COUNT, true)
-            } else {
-                (maxtop, false)
-            }),
-            dbref,
-        })
-    }
-    /// Generate the snapshot name
-    fn get_snapname(&self) -> String {
-        Utc::now()
-            .format("./snapshots/%Y%m%d-%H%M%S.snapshot")
-            .to_string()
-    }
-    /// Create a snapshot
-    pub fn mksnap(&mut self) -> bool {
-        let rlock = self.dbref.acquire_read();
-        if rlock.terminate {
-            // The database is shutting down, don't create a snapshot
-            return false;
+    /// This doesn't touch the disk; the directory is only created (and scanned) when the
+    /// engine is first used
+    pub fn new(maxtop: usize, snapdir: PathBuf) -> Self {
+        SnapshotEngine {
+            snaps: queue::Queue::new(queue_limits(maxtop)),
+            snapdir,
+            scanned: false,
+        }
+    }
+    /// Keep at most `maxtop` unnamed snapshots from now on, or all of them if `maxtop`
+    /// is 0, deleting the oldest ones right away if there are too many
+    pub fn set_atmost(&mut self, maxtop: usize) {
+        for old_snapshot in self.snaps.resize(queue_limits(maxtop)) {
+            self.remove_rotated(&old_snapshot);
+        }
+    }
+    /// Create the snapshot directory if it doesn't exist and pick up the unnamed snapshots
+    /// left behind by previous runs, deleting the oldest ones if there are too many
+    ///
+    /// This only does something the first time it is called
+    pub fn scan(&mut self) -> TResult<()> {
+        if self.scanned {
+            return Ok(());
         }
-        let snapname = self.get_snapname();
-        if let Err(e) = diskstore::flush_data(&snapname, &rlock.get_ref()) {
-            log::error!("Snapshotting failed with error: '{}'", e);
-            return true;
+        fs::create_dir_all(&self.snapdir)?;
+        let mut unnamed: Vec<String> = self
+            .read_dir()?
+            .into_iter()
+            .map(|snapshot| snapshot.name)
+            .filter(|name| is_unnamed(name))
+            .collect();
+        // The names are timestamps, so sorting them puts the oldest first
+        unnamed.sort();
+        for name in unnamed {
+            self.rotate_in(name);
+        }
+        self.scanned = true;
+        Ok(())
+    }
+    /// Returns the path of the snapshot called `name`
+    pub fn path_of(&self, name: &str) -> PathBuf {
+        path_in(&self.snapdir, name)
+    }
+    /// Check if a snapshot called `name` exists
+    pub fn exists(&self, name: &str) -> bool {
+        self.path_of(name).is_file()
+    }
+    /// Add an unnamed snapshot to the rotation, deleting the oldest one if there are
+    /// too many
+    fn rotate_in(&mut self, name: String) {
+        // Two snapshots created in the same second have the same name
+        if self.snaps.contains(&name) {
+            return;
+        }
+        if let Some(old_snapshot) = self.snaps.add(name) {
+            self.remove_rotated(&old_snapshot);
+        }
+    }
+    /// Delete a snapshot that was rotated out
+    fn remove_rotated(&self, old_snapshot: &str) {
+        if let Err(e) = fs::remove_file(self.path_of(old_snapshot)) {
+            log::error!(
+                "Failed to delete snapshot '{}' with error '{}'",
+                old_snapshot,
+                e
+            );
         } else {
-            log::info!("Successfully created snapshot");
+            log::info!("Successfully removed old snapshot '{}'", old_snapshot);
+        }
+    }
+    /// Create a snapshot of the in-memory table, returning the name of the snapshot
+    ///
+    /// If `name` is `None`, the snapshot is named after the current time and is added to
+    /// the rotation. This returns `None` if the database is shutting down
+    pub fn create(&mut self, shared: &Shared, name: Option<&str>) -> Option<TResult<String>> {
+        if let Err(e) = self.scan() {
+            return Some(Err(e));
+        }
+        let rlock = shared.table.read();
+        if rlock.terminate {
+            // The database is shutting down, don't create a snapshot
+            return None;
         }
+        let snapname = match name {
+            Some(name) => name.to_owned(),
+            None => Utc::now().format(SNAPSHOT_NAME_FORMAT).to_string(),
+        };
+        let start = Instant::now();
+        let result = rlock.get_ref().dump(
+            &self.path_of(&snapname),
+            shared.storage.compression(),
+            &shared.keys,
+        );
+        METRICS.snapshot.record(start.elapsed(), result.is_ok());
         // Release the read lock for the poor clients who are waiting for a write lock
         drop(rlock);
-        log::info!("Snapshot created");
-        if let Some(old_snapshot) = self.snaps.add(snapname.clone()) {
-            if let Err(e) = fs::remove_file(old_snapshot) {
-                log::error!(
-                    "Failed to delete snapshot '{}' with error '{}'",
-                    snapname,
-                    e
-                );
-            } else {
-                log::info!("Successfully removed old snapshot");
+        if let Err(e) = result {
+            return Some(Err(e));
+        }
+        if name.is_none() {
+            self.rotate_in(snapname.clone());
+            // The write log only has to go back as far as the oldest snapshot in the rotation
+            if let Some(oldest) = self.snaps.iter().next().and_then(|name| created_at(name)) {
+                shared.writelog.rotate(oldest);
             }
         }
-        true
+        Some(Ok(snapname))
     }
-    /// Delete all snapshots
+    /// Create a snapshot for the snapshot service
+    ///
+    /// This returns `false` if the database is shutting down
+    pub fn mksnap(&mut self, shared: &Shared) -> bool {
+        match self.create(shared, None) {
+            Some(Ok(snapname)) => {
+                log::info!("Successfully created snapshot '{}'", snapname);
+                true
+            }
+            Some(Err(e)) => {
+                log::error!("Snapshotting failed with error: '{}'", e);
+                true
+            }
+            None => false,
+        }
+    }
+    /// Returns every snapshot in the snapshot directory, oldest first
+    pub fn list(&mut self) -> TResult<Vec<SnapshotInfo>> {
+        self.scan()?;
+        let mut snapshots = self.read_dir()?;
+        snapshots.sort_by(|a, b| (a.modified, &a.name).cmp(&(b.modified, &b.name)));
+        Ok(snapshots)
+    }
+    /// Delete the snapshot called `name`, returning `false` if it doesn't exist
+    pub fn delete(&mut self, name: &str) -> TResult<bool> {
+        self.scan()?;
+        self.snaps.remove(name);
+        match fs::remove_file(self.path_of(name)) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+    /// Replace the in-memory table with the contents of the snapshot called `name`,
+    /// returning `false` if it doesn't exist
+    pub fn restore(&self, shared: &Shared, name: &str) -> TResult<bool> {
+        match diskstore::get_saved(Some(self.path_of(name)), &shared.keys)? {
+            Some(data) => {
+                let mut table = shared.table.write();
+                table.get_mut_ref().replace(data.clone())?;
+                shared.log_reset_to(&data);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+    /// Delete all snapshots in the rotation
     pub fn clearall(&mut self) -> TResult<()> {
         for snap in self.snaps.iter() {
-            fs::remove_file(snap)?;
+            fs::remove_file(self.path_of(snap))?;
         }
         Ok(())
     }
-    /// Get the name of snapshots
+    /// Get the names of the snapshots in the rotation
     pub fn get_snapshots(&self) -> std::slice::Iter<String> {
         self.snaps.iter()
     }
+    /// Read the snapshots in the snapshot directory
+    fn read_dir(&self) -> TResult<Vec<SnapshotInfo>> {
+        let mut snapshots = Vec::new();
+        for entry in fs::read_dir(&self.snapdir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(SNAPSHOT_EXT) {
+                continue;
+            }
+            let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(name) => name.to_owned(),
+                None => continue,
+            };
+            let metadata = fs::metadata(&path)?;
+            if !metadata.is_file() {
+                continue;
+            }
+            snapshots.push(SnapshotInfo {
+                name,
+                size: metadata.len(),
+                modified: DateTime::from(metadata.modified()?),
+            });
+        }
+        Ok(snapshots)
+    }
+}
+
+/// Returns the path of the snapshot called `name` in the snapshot directory `snapdir`
+pub fn path_in(snapdir: &Path, name: &str) -> PathBuf {
+    snapdir.join(format!("{}.{}", name, SNAPSHOT_EXT))
+}
+
+/// Check if `name` is the name of an unnamed snapshot, that is, a timestamp
+///
+/// These names are reserved for the snapshots in the rotation
+pub fn is_unnamed(name: &str) -> bool {
+    NaiveDateTime::parse_from_str(name, SNAPSHOT_NAME_FORMAT).is_ok()
+}
+
+/// Returns the time at which the unnamed snapshot called `name` was created, as a UNIX
+/// timestamp in milliseconds
+fn created_at(name: &str) -> Option<u64> {
+    NaiveDateTime::parse_from_str(name, SNAPSHOT_NAME_FORMAT)
+        .ok()
+        .map(|time| time.timestamp_millis() as u64)
+}
+
+/// Check if `name` is a valid snapshot name
+///
+/// Names can only have letters, digits, `-` and `_`, so that they can't point outside
+/// the snapshot directory
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
 }
 
 #[test]
 fn test_snapshot() {
     let db = CoreDB::new_empty(3);
     let mut write = db.acquire_write();
-    let _ = write.get_mut_ref().insert(
-        String::from("ohhey"),
-        crate::coredb::Data::from_string(String::from("heya!")),
-    );
+    write
+        .get_mut_ref()
+        .set(
+            String::from("ohhey"),
+            crate::coredb::Data::from_string(String::from("heya!")),
+        )
+        .unwrap();
     drop(write);
-    let mut snapengine = SnapshotEngine::new(4, &db).unwrap();
-    let _ = snapengine.mksnap();
+    let snapdir = std::env::temp_dir().join(format!("tdb-test-snapshot-{}", std::process::id()));
+    let mut snapengine = SnapshotEngine::new(4, snapdir.clone());
+    assert!(snapengine.mksnap(&db.shared));
     let current = snapengine.get_snapshots().next().unwrap();
-    let read_hmap = diskstore::get_saved(Some(current)).unwrap().unwrap();
-    let dbhmap = db.get_hashmap_deep_clone();
+    let read_hmap = diskstore::get_saved(Some(snapengine.path_of(current)), &diskstore::encryption::Keyring::none())
+        .unwrap()
+        .unwrap();
+    let dbhmap = db.get_hashmap_deep_clone().unwrap();
     assert_eq!(read_hmap, dbhmap);
     snapengine.clearall().unwrap();
+    fs::remove_dir_all(snapdir).unwrap();
+}
+
+#[test]
+fn test_snapshot_scan_list_delete_restore() {
+    let db = CoreDB::new_empty(3);
+    let snapdir = std::env::temp_dir().join(format!("tdb-test-snapscan-{}", std::process::id()));
+    fs::create_dir_all(&snapdir).unwrap();
+    // Leave some snapshots behind, like a previous run would
+    let mut data = std::collections::HashMap::new();
+    data.insert(
+        String::from("restored"),
+        crate::coredb::Data::from_string(String::from("yes")),
+    );
+    for name in &["20200101-000000", "20200102-000000", "20200103-000000", "mine"] {
+        // Compressed snapshots are read just like plain ones
+        diskstore::flush_data(
+            snapdir.join(format!("{}.snapshot", name)),
+            &data,
+            diskstore::format::Codec::Lz4,
+            &diskstore::encryption::Keyring::none(),
+        )
+        .unwrap();
+    }
+    let mut snapengine = SnapshotEngine::new(2, snapdir.clone());
+    snapengine.scan().unwrap();
+    // The oldest unnamed snapshot was rotated out, but the named one is kept
+    assert!(!snapengine.exists("20200101-000000"));
+    assert!(snapengine.exists("mine"));
+    assert_eq!(snapengine.list().unwrap().len(), 3);
+    assert!(snapengine.restore(&db.shared, "mine").unwrap());
+    assert_eq!(db.get_hashmap_deep_clone().unwrap(), data);
+    assert!(!snapengine.restore(&db.shared, "nothere").unwrap());
+    assert!(snapengine.delete("mine").unwrap());
+    assert!(!snapengine.delete("mine").unwrap());
+    // A new snapshot rotates out the oldest one that was picked up
+    let created = snapengine.create(&db.shared, None).unwrap().unwrap();
+    assert!(is_unnamed(&created));
+    assert!(!snapengine.exists("20200102-000000"));
+    assert_eq!(snapengine.list().unwrap().len(), 2);
+    fs::remove_dir_all(snapdir).unwrap();
+}
+
+#[test]
+fn test_snapshot_names() {
+    assert!(is_valid_name("before-deploy_2"));
+    assert!(!is_valid_name(""));
+    assert!(!is_valid_name("../data"));
+    assert!(!is_valid_name("with space"));
+    assert!(is_valid_name("20201018-120000"));
+    assert!(is_unnamed("20201018-120000"));
+    assert!(!is_unnamed("before-deploy_2"));
 }
 
-use std::time::Duration;
-use tokio::time;
 /// The snapshot service
 ///
-/// This service calls `SnapEngine::mksnap()` periodically to create snapshots. Whenever
+/// This service calls `SnapshotEngine::mksnap()` periodically to create snapshots. Whenever
 /// the interval for snapshotting expires or elapses, we create a snapshot. The snapshot service
 /// keeps creating snapshots, as long as the database keeps running, i.e `CoreDB` does return true for
 /// `is_termsig()`
-pub async fn snapshot_service(handle: CoreDB, ss_config: SnapshotConfig) {
-    match ss_config {
-        SnapshotConfig::Disabled => {
-            // since snapshotting is disabled, we'll imediately return
-            handle.shared.bgsave_task.notified().await;
-            return;
-        }
-        SnapshotConfig::Enabled(configuration) => {
-            let (duration, atmost) = configuration.decompose();
-            let duration = Duration::from_secs(duration);
-            let mut sengine = match SnapshotEngine::new(atmost, &handle) {
-                Ok(ss) => ss,
-                Err(e) => {
-                    log::error!("Failed to initialize snapshot service with error: '{}'", e);
+///
+/// The snapshot settings are read from the effective configuration, and the service
+/// picks up new ones when it is woken up with `snapshot_reconfigured`
+pub async fn snapshot_service(handle: CoreDB) {
+    // The next snapshot is due `every` seconds after the last one
+    let mut last_snapshot = None;
+    while !handle.shared.is_termsig() {
+        // The settings can change while the server is running, so they're read each time
+        let snapshot_cfg = handle.shared.config.read().snapshot;
+        let due = match snapshot_cfg {
+            SnapshotConfig::Enabled(configuration) => {
+                let (every, _) = configuration.decompose();
+                Some(match last_snapshot {
+                    Some(last) => last + Duration::from_secs(every),
+                    None => time::Instant::now(),
+                })
+            }
+            // Snapshotting is disabled, so we'll wait until it is enabled, or until the
+            // database is shutting down
+            SnapshotConfig::Disabled => None,
+        };
+        tokio::select! {
+            _ = diskstore::wait_until(due) => {
+                if !handle.shared.snapshots.lock().mksnap(&handle.shared) {
                     return;
                 }
-            };
-            while !handle.shared.is_termsig() {
-                if sengine.mksnap() {
-                    tokio::select! {
-                        _ = time::delay_until(time::Instant::now() + duration) => {},
-                        _ = handle.shared.bgsave_task.notified() => {}
-                    }
-                } else {
-                    handle.shared.bgsave_task.notified().await;
-                }
+                last_snapshot = Some(time::Instant::now());
             }
+            _ = handle.shared.snapshot_service.notified() => {}
+            _ = handle.shared.snapshot_reconfigured.notified() => {}
         }
     }
 }
 
+/// Returns the `(maxlen, dontpop)` limits of the snapshot queue for `maxtop`
+const fn queue_limits(maxtop: usize) -> (usize, bool) {
+    if maxtop == 0 {
+        (DEF_SNAPSHOT_COUNT, true)
+    } else {
+        (maxtop, false)
+    }
+}
+
 mod queue {
     //! An extremely simple queue implementation which adds more items to the queue
     //! freely and once the threshold limit is reached, it pops off the oldest element and returns it
@@ -212,10 +461,28 @@ mod queue {
                 x
             }
         }
+        /// Change the limits of the queue, returning the oldest items that no longer fit
+        pub fn resize(&mut self, (maxlen, dontpop): (usize, bool)) -> Vec<String> {
+            self.maxlen = maxlen;
+            self.dontpop = dontpop;
+            if dontpop || self.queue.len() <= maxlen {
+                return Vec::new();
+            }
+            let overflow = self.queue.len() - maxlen;
+            self.queue.drain(..overflow).collect()
+        }
         /// Returns an iterator over the slice of strings
         pub fn iter(&self) -> Iter<String> {
             self.queue.iter()
         }
+        /// Check if `item` is in the queue
+        pub fn contains(&self, item: &str) -> bool {
+            self.queue.iter().any(|queued| queued == item)
+        }
+        /// Remove `item` from the queue, if it is there
+        pub fn remove(&mut self, item: &str) {
+            self.queue.retain(|queued| queued != item);
+        }
         /// Check if we have reached the maximum queue size limit
         fn is_overflow(&self) -> bool {
             self.queue.len() == self.maxlen
@@ -241,6 +508,21 @@ mod queue {
         assert_eq!(q.add(String::from("snap6")), Some(String::from("snap2")));
     }
 
+    #[test]
+    fn test_queue_resize() {
+        let mut q = Queue::new((4, false));
+        for snap in &["snap1", "snap2", "snap3", "snap4"] {
+            assert!(q.add(snap.to_string()).is_none());
+        }
+        assert_eq!(q.resize((2, false)), vec!["snap1", "snap2"]);
+        assert_eq!(q.add(String::from("snap5")), Some(String::from("snap3")));
+        // Keeping everything doesn't pop anything
+        assert!(q.resize((4, true)).is_empty());
+        assert!(q.add(String::from("snap6")).is_none());
+        assert!(q.add(String::from("snap7")).is_none());
+        assert!(q.add(String::from("snap8")).is_none());
+    }
+
     #[test]
     fn test_queue_dontpop() {
         // This means that items can only be added or all of them can be deleted
@@ -0,0 +1,598 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # The write log
+//!
+//! When the write log is enabled, every change to the in-memory table is appended to a
+//! log in the `writelog` directory of the data directory, along with the time at which
+//! it was made. Together with the snapshots, this lets us recover the data to the state
+//! that it was in at any point in time: we load the latest snapshot that was created
+//! before that time and replay the changes that were made after it.
+//!
+//! The log holds the _effect_ of every change (the new value of a key, a deleted key or a
+//! cleared table) instead of the query that caused it. This means that replaying a change
+//! that is already in a snapshot does nothing, so replaying can safely start a little
+//! before the snapshot was created.
+//!
+//! The log is split into segments named after the time (in milliseconds) at which they
+//! were started. A new segment is started whenever an unnamed snapshot is created, and the
+//! segments that end before the oldest unnamed snapshot are deleted.
+//!
+//! Every record is framed with its length and checksums. All integers are little-endian:
+//! ```text
+//! | payload length (u64) | length CRC (u32) | payload CRC (u32) | payload (bincode) |
+//! ```
+//! The length CRC catches a damaged length before we try to read that many bytes. Only
+//! the last record of the last segment can be cut short (by a server that was killed
+//! while appending to it), so damage anywhere else stops replaying at the record before
+//! it, and the recovery says so
+
+use crate::config::StorageConfig;
+use crate::coredb::Data;
+use crate::diskstore::encryption::Keyring;
+use crate::diskstore::flock::FileLock;
+use crate::diskstore::snapshot::SnapshotEngine;
+use bytes::Bytes;
+use crc32fast::Hasher;
+use libtdb::TResult;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The extension of write log segments
+const SEGMENT_EXT: &str = "log";
+/// How far (in milliseconds) before a snapshot's time replaying starts, to make up for
+/// the coarse timestamps of snapshot files
+const REPLAY_SLACK: u64 = 1000;
+/// The size of the frame in front of every record
+const FRAME_LEN: usize = 8 + 4 + 4;
+
+/// A change to the in-memory table
+///
+/// This is also what the primary sends to its replicas
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum Change<'a> {
+    /// A key was set to a value
+    Set(Cow<'a, str>, Cow<'a, [u8]>),
+    /// A key was deleted
+    Del(Cow<'a, str>),
+    /// All the keys were deleted
+    Clear,
+}
+
+/// An entry in the write log
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Record<'a> {
+    /// When the change was made, as a UNIX timestamp in milliseconds
+    at: u64,
+    /// The change
+    change: Change<'a>,
+}
+
+/// The write log
+///
+/// Changes have to be logged while the write lock on the table is held, so that they end
+/// up in the log in the same order in which they were made
+#[derive(Debug)]
+pub struct WriteLog {
+    /// The directory holding the segments, if the write log is enabled
+    dir: Option<PathBuf>,
+    /// The segment that is being written to
+    segment: Mutex<Option<BufWriter<File>>>,
+}
+
+impl WriteLog {
+    /// A write log that doesn't log anything
+    pub fn disabled() -> Self {
+        WriteLog {
+            dir: None,
+            segment: Mutex::new(None),
+        }
+    }
+    /// Open the write log in `dir`, creating the directory if it doesn't exist and
+    /// starting a new segment
+    pub fn open(dir: impl Into<PathBuf>) -> TResult<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        let segment = new_segment(&dir)?;
+        Ok(WriteLog {
+            dir: Some(dir),
+            segment: Mutex::new(Some(segment)),
+        })
+    }
+    /// Check if the write log is enabled
+    pub fn is_enabled(&self) -> bool {
+        self.dir.is_some()
+    }
+    /// Log that `key` was set to `value`
+    pub fn set(&self, key: &str, value: &[u8]) {
+        if self.is_enabled() {
+            self.append(Change::Set(Cow::Borrowed(key), Cow::Borrowed(value)));
+        }
+    }
+    /// Log that `key` was deleted
+    pub fn del(&self, key: &str) {
+        if self.is_enabled() {
+            self.append(Change::Del(Cow::Borrowed(key)));
+        }
+    }
+    /// Log that all the keys were deleted
+    pub fn clear(&self) {
+        if self.is_enabled() {
+            self.append(Change::Clear);
+        }
+    }
+    /// Log that the whole table was replaced with `table`
+    ///
+    /// This is used when the table is replaced from somewhere else, like a snapshot
+    pub fn reset_to(&self, table: &HashMap<String, Data>) {
+        if self.is_enabled() {
+            self.append(Change::Clear);
+            for (key, value) in table {
+                self.append(Change::Set(
+                    Cow::Borrowed(key),
+                    Cow::Borrowed(value.get_blob()),
+                ));
+            }
+        }
+    }
+    /// Append a change to the current segment
+    ///
+    /// The change has already been made by the time it is logged, so a failure can only
+    /// be reported in the server's log
+    fn append(&self, change: Change) {
+        let mut segment = self.segment.lock();
+        if let Some(segment) = segment.as_mut() {
+            let record = Record {
+                at: now_millis(),
+                change,
+            };
+            let result = bincode::serialize(&record)
+                .map_err(|e| e.to_string())
+                .and_then(|payload| {
+                    segment
+                        .write_all(&frame(&payload))
+                        .and_then(|_| segment.write_all(&payload))
+                        .and_then(|_| segment.flush())
+                        .map_err(|e| e.to_string())
+                });
+            if let Err(e) = result {
+                log::error!("Failed to append to the write log with error: '{}'", e);
+            }
+        }
+    }
+    /// Start a new segment and delete the segments that aren't needed to recover from
+    /// the snapshots created at or after `oldest_snapshot` (a UNIX timestamp in milliseconds)
+    pub fn rotate(&self, oldest_snapshot: u64) {
+        let keep_from = oldest_snapshot.saturating_sub(REPLAY_SLACK);
+        let dir = match &self.dir {
+            Some(dir) => dir,
+            None => return,
+        };
+        {
+            let mut segment = self.segment.lock();
+            match new_segment(dir) {
+                Ok(new) => *segment = Some(new),
+                Err(e) => {
+                    log::error!("Failed to start a new write log segment with error: '{}'", e);
+                    return;
+                }
+            }
+        }
+        let segments = match list_segments(dir) {
+            Ok(segments) => segments,
+            Err(e) => {
+                log::error!("Failed to list the write log segments with error: '{}'", e);
+                return;
+            }
+        };
+        // A segment ends where the next one starts
+        for pair in segments.windows(2) {
+            let ((_, old), (next_start, _)) = (&pair[0], &pair[1]);
+            if *next_start > keep_from {
+                break;
+            }
+            if let Err(e) = fs::remove_file(old) {
+                log::error!(
+                    "Failed to delete write log segment '{}' with error: '{}'",
+                    old.display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut hasher = Hasher::new();
+    hasher.update(data);
+    hasher.finalize()
+}
+
+/// Returns the frame that goes in front of `payload`
+fn frame(payload: &[u8]) -> [u8; FRAME_LEN] {
+    let len = (payload.len() as u64).to_le_bytes();
+    let mut frame = [0u8; FRAME_LEN];
+    frame[..8].copy_from_slice(&len);
+    frame[8..12].copy_from_slice(&crc32(&len).to_le_bytes());
+    frame[12..16].copy_from_slice(&crc32(payload).to_le_bytes());
+    frame
+}
+
+/// Returns the current time as a UNIX timestamp in milliseconds
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Create a new segment in `dir`, named after the current time
+fn new_segment(dir: &Path) -> TResult<BufWriter<File>> {
+    let path = dir.join(format!("{}.{}", now_millis(), SEGMENT_EXT));
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    Ok(BufWriter::new(file))
+}
+
+/// Returns the start time and path of every segment in `dir`, oldest first
+fn list_segments(dir: &Path) -> TResult<Vec<(u64, PathBuf)>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some(SEGMENT_EXT) {
+            continue;
+        }
+        if let Some(start) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<u64>().ok())
+        {
+            segments.push((start, path));
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+/// Where reading a segment ended
+#[derive(Debug, PartialEq)]
+enum SegmentEnd {
+    /// All the records were read
+    Done,
+    /// The caller stopped reading
+    Stopped,
+    /// The record at this offset was cut short
+    Torn(u64),
+    /// The record at this offset is damaged
+    Damaged(u64, String),
+}
+
+/// Read the records of the segment at `path` in order, calling `f` with each of them
+/// until it returns false
+fn read_segment(path: &Path, f: &mut dyn FnMut(Record) -> bool) -> TResult<SegmentEnd> {
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+    let mut offset = 0;
+    let mut header = [0u8; FRAME_LEN];
+    let mut payload = Vec::new();
+    while offset < size {
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(SegmentEnd::Torn(offset)),
+            Err(e) => return Err(e.into()),
+        }
+        let mut len = [0u8; 8];
+        len.copy_from_slice(&header[..8]);
+        if header[8..12] != crc32(&len).to_le_bytes() {
+            return Ok(SegmentEnd::Damaged(
+                offset,
+                "the length doesn't match its checksum".to_owned(),
+            ));
+        }
+        let len = u64::from_le_bytes(len);
+        // The length is intact, so a record that runs past the end was cut short
+        if len > size - offset - FRAME_LEN as u64 {
+            return Ok(SegmentEnd::Torn(offset));
+        }
+        payload.resize(len as usize, 0);
+        reader.read_exact(&mut payload)?;
+        if header[12..16] != crc32(&payload).to_le_bytes() {
+            return Ok(SegmentEnd::Damaged(
+                offset,
+                "the record doesn't match its checksum".to_owned(),
+            ));
+        }
+        let record = match bincode::deserialize(&payload) {
+            Ok(record) => record,
+            Err(e) => return Ok(SegmentEnd::Damaged(offset, e.to_string())),
+        };
+        if !f(record) {
+            return Ok(SegmentEnd::Stopped);
+        }
+        offset += FRAME_LEN as u64 + len;
+    }
+    Ok(SegmentEnd::Done)
+}
+
+/// The outcome of a recovery
+#[derive(Debug, PartialEq)]
+pub struct Recovery {
+    /// The name of the snapshot that the recovery started from
+    pub snapshot: String,
+    /// The number of changes that were replayed on top of the snapshot
+    pub replayed: usize,
+    /// Where the write log is damaged, if it is. Replaying stopped at the change before
+    /// the damage, so the table is as it was then instead of at the target
+    pub damaged: Option<String>,
+}
+
+/// Rebuild the table as it was at `target` (a UNIX timestamp in milliseconds) from the
+/// snapshots in `snapdir` and the write log in `logdir`. Encrypted snapshots are
+/// decrypted with `keys`
+pub fn recover(
+    snapdir: &Path,
+    logdir: &Path,
+    target: u64,
+    keys: &Keyring,
+) -> TResult<(HashMap<String, Data>, Recovery)> {
+    // The latest snapshot that was created before the target
+    let mut engine = SnapshotEngine::new(0, snapdir.to_path_buf());
+    let snapshot = engine
+        .list()?
+        .into_iter()
+        .rev()
+        .find(|snapshot| snapshot.modified.timestamp_millis() as u64 <= target)
+        .ok_or("There is no snapshot from before the recovery target")?;
+    let taken_at = snapshot.modified.timestamp_millis() as u64;
+    let mut table = super::get_saved(Some(engine.path_of(&snapshot.name)), keys)?
+        .ok_or("The snapshot disappeared while it was being read")?;
+    let segments = list_segments(logdir)?;
+    match segments.first() {
+        Some((start, _)) if *start <= taken_at => {}
+        _ => {
+            return Err(format!(
+                "The write log doesn't go back as far as snapshot '{}'",
+                snapshot.name
+            )
+            .into())
+        }
+    }
+    let replay_from = taken_at.saturating_sub(REPLAY_SLACK);
+    let mut replayed = 0;
+    let mut damaged = None;
+    let mut apply = |record: Record| {
+        if record.at > target {
+            return false;
+        }
+        if record.at >= replay_from {
+            match record.change {
+                Change::Set(key, value) => {
+                    table.insert(
+                        key.into_owned(),
+                        Data::from_blob(Bytes::from(value.into_owned())),
+                    );
+                }
+                Change::Del(key) => {
+                    table.remove(key.as_ref());
+                }
+                Change::Clear => table.clear(),
+            }
+            replayed += 1;
+        }
+        true
+    };
+    for (i, (_, path)) in segments.iter().enumerate() {
+        // Skip the segments that end before we start replaying
+        if let Some((next_start, _)) = segments.get(i + 1) {
+            if *next_start <= replay_from {
+                continue;
+            }
+        }
+        match read_segment(path, &mut apply)? {
+            SegmentEnd::Done => {}
+            SegmentEnd::Stopped => break,
+            // The server was killed while it was appending to the log
+            SegmentEnd::Torn(_) if i == segments.len() - 1 => {
+                log::warn!(
+                    "Skipping the last record of write log segment '{}', which was cut short",
+                    path.display()
+                );
+            }
+            SegmentEnd::Torn(offset) => {
+                damaged = Some(format!(
+                    "the record at byte {} of write log segment '{}' was cut short",
+                    offset,
+                    path.display()
+                ));
+                break;
+            }
+            SegmentEnd::Damaged(offset, e) => {
+                damaged = Some(format!(
+                    "the record at byte {} of write log segment '{}' is damaged: {}",
+                    offset,
+                    path.display(),
+                    e
+                ));
+                break;
+            }
+        }
+    }
+    if let Some(damage) = &damaged {
+        log::warn!(
+            "Replaying stopped before the recovery target, since {}",
+            damage
+        );
+    }
+    Ok((
+        table,
+        Recovery {
+            snapshot: snapshot.name,
+            replayed,
+            damaged,
+        },
+    ))
+}
+
+/// Recover the data in the data directory to the state it was in at `target` (a UNIX
+/// timestamp in milliseconds) and write it to `output`, or to the dump file if `output`
+/// is `None`
+///
+/// This takes the lock on the data directory, so it fails if a server is using it. The
+/// output is encrypted with the current key in `keys`, just like the server would
+pub fn recover_offline(
+    storage: &StorageConfig,
+    keys: &Keyring,
+    target: u64,
+    output: Option<PathBuf>,
+) -> TResult<()> {
+    let _lock = FileLock::lock(storage.lock_path()).map_err(|e| {
+        format!(
+            "Failed to lock '{}' with error: '{}'. Is a server using the data directory?",
+            storage.lock_path().display(),
+            e
+        )
+    })?;
+    let (table, recovery) = recover(&storage.snapshot_dir(), &storage.writelog_dir(), target, keys)?;
+    let output = output.unwrap_or_else(|| storage.dump_path());
+    super::flush_data(&output, &table, storage.compression(), keys)?;
+    log::info!(
+        "Recovered {} keys from snapshot '{}' and {} logged changes into '{}'",
+        table.len(),
+        recovery.snapshot,
+        recovery.replayed,
+        output.display()
+    );
+    Ok(())
+}
+
+/// Parse a recovery target, which is either an RFC 3339 timestamp (like
+/// `2020-10-18T14:30:00Z`) or a UNIX timestamp in seconds, into a UNIX timestamp
+/// in milliseconds
+pub fn parse_target(target: &str) -> Result<u64, String> {
+    if let Ok(secs) = target.parse::<u64>() {
+        return Ok(secs * 1000);
+    }
+    chrono::DateTime::parse_from_rfc3339(target)
+        .map(|time| time.timestamp_millis() as u64)
+        .map_err(|_| {
+            format!(
+                "Bad recovery target '{}': expected an RFC 3339 or UNIX timestamp",
+                target
+            )
+        })
+}
+
+#[test]
+fn test_writelog_recover() {
+    let dir = std::env::temp_dir().join(format!("tdb-test-writelog-{}", std::process::id()));
+    let (snapdir, logdir) = (dir.join("snapshots"), dir.join("writelog"));
+    fs::create_dir_all(&snapdir).unwrap();
+    let log = WriteLog::open(&logdir).unwrap();
+    log.set("before", b"snapshot");
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    // The snapshot already has the first change
+    let mut table = HashMap::new();
+    table.insert(
+        "before".to_owned(),
+        Data::from_blob(Bytes::from_static(b"snapshot")),
+    );
+    super::flush_data(
+        snapdir.join("base.snapshot"),
+        &table,
+        super::format::Codec::Zstd,
+        &Keyring::none(),
+    )
+    .unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    log.set("after", b"1");
+    log.del("before");
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    let target = now_millis();
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    log.clear();
+    let (recovered, recovery) = recover(&snapdir, &logdir, target, &Keyring::none()).unwrap();
+    assert_eq!(recovery.snapshot, "base");
+    assert_eq!(recovered.len(), 1);
+    assert_eq!(recovered["after"].get_blob().as_ref(), b"1");
+    // Nothing can be recovered from before the first snapshot
+    assert!(recover(&snapdir, &logdir, 1000, &Keyring::none()).is_err());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_writelog_recover_damaged() {
+    let dir =
+        std::env::temp_dir().join(format!("tdb-test-writelog-damaged-{}", std::process::id()));
+    let (snapdir, logdir) = (dir.join("snapshots"), dir.join("writelog"));
+    fs::create_dir_all(&snapdir).unwrap();
+    let log = WriteLog::open(&logdir).unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    super::flush_data(
+        snapdir.join("base.snapshot"),
+        &HashMap::new(),
+        super::format::Codec::None,
+        &Keyring::none(),
+    )
+    .unwrap();
+    log.set("first", b"1");
+    log.set("second", b"2");
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    log.rotate(0);
+    log.set("third", b"3");
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    let target = now_millis();
+    let segments = list_segments(&logdir).unwrap();
+    let (first, last) = (&segments[0].1, &segments[segments.len() - 1].1);
+    // A record that was cut short at the end of the last segment is skipped
+    let intact = fs::read(last).unwrap();
+    fs::write(last, [&intact[..], &intact[..FRAME_LEN + 2]].concat()).unwrap();
+    let (recovered, recovery) = recover(&snapdir, &logdir, target, &Keyring::none()).unwrap();
+    assert_eq!(recovered.len(), 3);
+    assert_eq!(recovery.damaged, None);
+    // Damage anywhere else stops replaying at the change before it
+    let mut data = fs::read(first).unwrap();
+    let last_byte = data.len() - 1;
+    data[last_byte] ^= 0xFF;
+    fs::write(first, data).unwrap();
+    let (recovered, recovery) = recover(&snapdir, &logdir, target, &Keyring::none()).unwrap();
+    assert_eq!(recovery.replayed, 1);
+    assert!(recovered.contains_key("first"));
+    assert!(!recovered.contains_key("third"));
+    let damage = recovery.damaged.unwrap();
+    assert!(damage.contains("doesn't match its checksum"));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_writelog_parse_target() {
+    assert_eq!(parse_target("1600000000"), Ok(1_600_000_000_000));
+    assert_eq!(
+        parse_target("2020-09-13T12:26:40Z"),
+        Ok(1_600_000_000_000)
+    );
+    assert!(parse_target("yesterday").is_err());
+}
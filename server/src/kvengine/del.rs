@@ -26,6 +26,7 @@ use crate::coredb::CoreDB;
 use crate::protocol::{responses, ActionGroup, Connection};
 use crate::resp::GroupBegin;
 use libtdb::TResult;
+use std::io;
 
 /// Run a `DEL` query
 ///
@@ -41,16 +42,22 @@ pub async fn del(handle: &CoreDB, con: &mut Connection, act: ActionGroup) -> TRe
     // Write #<m>\n#<n>\n&<howmany>\n to the stream
     con.write_response(GroupBegin(1)).await?;
     let mut done_howmany = 0usize;
-    {
+    let result: io::Result<()> = (|| {
         let mut whandle = handle.acquire_write();
         let cmap = (*whandle).get_mut_ref();
-        act.into_iter().for_each(|key| {
-            if cmap.remove(&key).is_some() {
+        for key in act.into_iter() {
+            if cmap.remove(&key)? {
+                handle.shared.log_del(&key);
                 done_howmany += 1
             }
-        });
-        drop(cmap);
-        drop(whandle);
+        }
+        Ok(())
+    })();
+    if let Err(e) = result {
+        log::error!("Failed to delete a key with error: '{}'", e);
+        return con
+            .write_response(responses::groups::SERVER_ERR.to_owned())
+            .await;
     }
     con.write_response(done_howmany).await?;
     #[cfg(debug_assertions)]
@@ -30,8 +30,20 @@ pub async fn flushdb(handle: &CoreDB, con: &mut Connection, act: ActionGroup) ->
             .write_response(responses::fresp::R_ACTION_ERR.to_owned())
             .await;
     }
-    {
-        handle.acquire_write().get_mut_ref().clear()
+    let result = {
+        let mut whandle = handle.acquire_write();
+        let result = whandle.get_mut_ref().clear();
+        // Only log the change once it has been made
+        if result.is_ok() {
+            handle.shared.log_clear();
+        }
+        result
+    };
+    if let Err(e) = result {
+        log::error!("Failed to delete the keys with error: '{}'", e);
+        return con
+            .write_response(responses::fresp::R_SERVER_ERR.to_owned())
+            .await;
     }
     con.write_response(responses::fresp::R_OKAY.to_owned())
         .await?;
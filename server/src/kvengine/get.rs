@@ -27,6 +27,7 @@ use crate::protocol::{responses, ActionGroup, Connection};
 use crate::resp::{BytesWrapper, GroupBegin};
 use bytes::Bytes;
 use libtdb::TResult;
+use std::io;
 
 /// Run a `GET` query
 pub async fn get(handle: &CoreDB, con: &mut Connection, act: ActionGroup) -> TResult<()> {
@@ -38,22 +39,28 @@ pub async fn get(handle: &CoreDB, con: &mut Connection, act: ActionGroup) -> TRe
     }
     // Write #<m>\n#<n>\n&1\n to the stream
     con.write_response(GroupBegin(1)).await?;
-    let res: Option<Bytes> = {
+    let res: io::Result<Option<Bytes>> = {
         let rhandle = handle.acquire_read();
         let reader = rhandle.get_ref();
         unsafe {
             reader
                 .get(act.get_ref().get_unchecked(1))
-                .map(|b| b.get_blob().clone())
+                .map(|value| value.map(|b| b.get_blob().clone()))
         }
     };
-    if let Some(value) = res {
+    match res {
         // Good, we got the value, write it off to the stream
-        con.write_response(BytesWrapper(value)).await?;
-    } else {
+        Ok(Some(value)) => con.write_response(BytesWrapper(value)).await?,
         // Ah, couldn't find that key
-        con.write_response(responses::groups::NIL.to_owned())
-            .await?;
+        Ok(None) => {
+            con.write_response(responses::groups::NIL.to_owned())
+                .await?
+        }
+        Err(e) => {
+            log::error!("Failed to read a value with error: '{}'", e);
+            con.write_response(responses::groups::SERVER_ERR.to_owned())
+                .await?
+        }
     }
     Ok(())
 }
@@ -38,11 +38,7 @@ pub async fn keylen(handle: &CoreDB, con: &mut Connection, act: ActionGroup) ->
     let res: Option<usize> = {
         let rhandle = handle.acquire_read();
         let reader = rhandle.get_ref();
-        unsafe {
-            reader
-                .get(act.get_ref().get_unchecked(1))
-                .map(|b| b.get_blob().len())
-        }
+        unsafe { reader.value_len(act.get_ref().get_unchecked(1)) }
     };
     if let Some(value) = res {
         // Good, we got the key's length, write it off to the stream
@@ -25,6 +25,7 @@ use crate::resp::{BytesWrapper, GroupBegin};
 use bytes::Bytes;
 use libtdb::terrapipe::RespCodes;
 use libtdb::TResult;
+use std::io;
 
 /// Run an `MGET` query
 ///
@@ -39,17 +40,22 @@ pub async fn mget(handle: &CoreDB, con: &mut Connection, act: ActionGroup) -> TR
     con.write_response(GroupBegin(howmany)).await?;
     let mut keys = act.into_iter();
     while let Some(key) = keys.next() {
-        let res: Option<Bytes> = {
+        let res: io::Result<Option<Bytes>> = {
             let rhandle = handle.acquire_read();
             let reader = rhandle.get_ref();
-            reader.get(&key).map(|b| b.get_blob().clone())
+            reader
+                .get(&key)
+                .map(|value| value.map(|b| b.get_blob().clone()))
         };
-        if let Some(value) = res {
+        match res {
             // Good, we got the value, write it off to the stream
-            con.write_response(BytesWrapper(value)).await?;
-        } else {
+            Ok(Some(value)) => con.write_response(BytesWrapper(value)).await?,
             // Ah, couldn't find that key
-            con.write_response(RespCodes::NotFound).await?;
+            Ok(None) => con.write_response(RespCodes::NotFound).await?,
+            Err(e) => {
+                log::error!("Failed to read a value with error: '{}'", e);
+                con.write_response(RespCodes::ServerError).await?
+            }
         }
     }
     drop(handle);
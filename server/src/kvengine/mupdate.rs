@@ -23,7 +23,7 @@ use crate::coredb::{self, CoreDB};
 use crate::protocol::{responses, ActionGroup, Connection};
 use crate::resp::GroupBegin;
 use libtdb::TResult;
-use std::collections::hash_map::Entry;
+use std::io;
 
 /// Run an `MUPDATE` query
 pub async fn mupdate(handle: &CoreDB, con: &mut Connection, act: ActionGroup) -> TResult<()> {
@@ -41,17 +41,25 @@ pub async fn mupdate(handle: &CoreDB, con: &mut Connection, act: ActionGroup) ->
     con.write_response(GroupBegin(1)).await?;
     let mut kviter = act.into_iter();
     let mut done_howmany = 0usize;
-    {
+    let result: io::Result<()> = (|| {
         let mut whandle = handle.acquire_write();
         let writer = whandle.get_mut_ref();
         while let (Some(key), Some(val)) = (kviter.next(), kviter.next()) {
-            if let Entry::Occupied(mut v) = writer.entry(key) {
-                let _ = v.insert(coredb::Data::from_string(val));
+            if writer.contains_key(&key) {
+                let val = coredb::Data::from_string(val);
+                // Only log the change once it has been made
+                writer.set(key.clone(), val.clone())?;
+                handle.shared.log_set(&key, val.get_blob());
                 done_howmany += 1;
             }
         }
-        drop(writer);
-        drop(whandle);
+        Ok(())
+    })();
+    if let Err(e) = result {
+        log::error!("Failed to write a value with error: '{}'", e);
+        return con
+            .write_response(responses::groups::SERVER_ERR.to_owned())
+            .await;
     }
     con.write_response(done_howmany).await?;
     #[cfg(debug_assertions)]
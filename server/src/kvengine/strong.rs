@@ -34,6 +34,7 @@ use crate::coredb::{CoreDB, Data};
 use crate::protocol::{responses, ActionGroup, Connection};
 use libtdb::TResult;
 use std::hint::unreachable_unchecked;
+use std::io;
 
 /// Run an `SSET` query
 ///
@@ -46,8 +47,8 @@ pub async fn sset(handle: &CoreDB, con: &mut Connection, act: ActionGroup) -> TR
             .write_response(responses::fresp::R_ACTION_ERR.to_owned())
             .await;
     }
-    let mut failed = false;
-    {
+    let result: io::Result<bool> = (|| {
+        let mut failed = false;
         // We use this additional scope to tell the compiler that the write lock
         // doesn't go beyond the scope of this function - and is never used across
         // an await: cause, the compiler ain't as smart as we are ;)
@@ -74,22 +75,29 @@ pub async fn sset(handle: &CoreDB, con: &mut Connection, act: ActionGroup) -> TR
         }
         if !failed {
             // Since the failed flag is false, none of the keys existed
-            // So we can safely set the keys
-            let mut iter = act.into_iter();
-            while let (Some(key), Some(value)) = (iter.next(), iter.next()) {
-                if mut_table.insert(key, Data::from_string(value)).is_some() {
-                    // Tell the compiler that this will never be the case
-                    unsafe { unreachable_unchecked() }
-                }
+            // So we can safely set the keys, all at once
+            let pairs = into_pairs(act);
+            mut_table.set_all(pairs.clone())?;
+            for (key, value) in &pairs {
+                handle.shared.log_set(key, value.get_blob());
             }
         }
-    }
-    if failed {
-        con.write_response(responses::fresp::R_OVERWRITE_ERR.to_owned())
-            .await
-    } else {
-        con.write_response(responses::fresp::R_OKAY.to_owned())
-            .await
+        Ok(failed)
+    })();
+    match result {
+        Ok(true) => {
+            con.write_response(responses::fresp::R_OVERWRITE_ERR.to_owned())
+                .await
+        }
+        Ok(false) => {
+            con.write_response(responses::fresp::R_OKAY.to_owned())
+                .await
+        }
+        Err(e) => {
+            log::error!("Failed to write a value with error: '{}'", e);
+            con.write_response(responses::fresp::R_SERVER_ERR.to_owned())
+                .await
+        }
     }
 }
 
@@ -104,8 +112,8 @@ pub async fn sdel(handle: &CoreDB, con: &mut Connection, act: ActionGroup) -> TR
             .write_response(responses::fresp::R_ACTION_ERR.to_owned())
             .await;
     }
-    let mut failed = false;
-    {
+    let result: io::Result<bool> = (|| {
+        let mut failed = false;
         // We use this additional scope to tell the compiler that the write lock
         // doesn't go beyond the scope of this function - and is never used across
         // an await: cause, the compiler ain't as smart as we are ;)
@@ -127,21 +135,26 @@ pub async fn sdel(handle: &CoreDB, con: &mut Connection, act: ActionGroup) -> TR
         }
         if !failed {
             // Since the failed flag is false, all of the keys exist
-            // So we can safely delete the keys
-            act.into_iter().for_each(|key| {
-                // Since we've already checked that the keys don't exist
-                // We'll tell the compiler to optimize this
-                let _ = mut_table
-                    .remove(&key)
-                    .unwrap_or_else(|| unsafe { unreachable_unchecked() });
-            });
+            // So we can safely delete the keys, all at once
+            let keys: Vec<String> = act.into_iter().collect();
+            mut_table.remove_all(&keys)?;
+            for key in &keys {
+                handle.shared.log_del(key);
+            }
+        }
+        Ok(failed)
+    })();
+    match result {
+        Ok(true) => con.write_response(responses::fresp::R_NIL.to_owned()).await,
+        Ok(false) => {
+            con.write_response(responses::fresp::R_OKAY.to_owned())
+                .await
+        }
+        Err(e) => {
+            log::error!("Failed to write a value with error: '{}'", e);
+            con.write_response(responses::fresp::R_SERVER_ERR.to_owned())
+                .await
         }
-    }
-    if failed {
-        con.write_response(responses::fresp::R_NIL.to_owned()).await
-    } else {
-        con.write_response(responses::fresp::R_OKAY.to_owned())
-            .await
     }
 }
 
@@ -156,8 +169,8 @@ pub async fn supdate(handle: &CoreDB, con: &mut Connection, act: ActionGroup) ->
             .write_response(responses::fresp::R_ACTION_ERR.to_owned())
             .await;
     }
-    let mut failed = false;
-    {
+    let result: io::Result<bool> = (|| {
+        let mut failed = false;
         // We use this additional scope to tell the compiler that the write lock
         // doesn't go beyond the scope of this function - and is never used across
         // an await: cause, the compiler ain't as smart as we are ;)
@@ -183,21 +196,36 @@ pub async fn supdate(handle: &CoreDB, con: &mut Connection, act: ActionGroup) ->
                 .unwrap_or_else(|| unsafe { unreachable_unchecked() });
         }
         if !failed {
-            // Since the failed flag is false, none of the keys existed
-            // So we can safely update the keys
-            let mut iter = act.into_iter();
-            while let (Some(key), Some(value)) = (iter.next(), iter.next()) {
-                if mut_table.insert(key, Data::from_string(value)).is_none() {
-                    // Tell the compiler that this will never be the case
-                    unsafe { unreachable_unchecked() }
-                }
+            // Since the failed flag is false, all of the keys exist
+            // So we can safely update the keys, all at once
+            let pairs = into_pairs(act);
+            mut_table.set_all(pairs.clone())?;
+            for (key, value) in &pairs {
+                handle.shared.log_set(key, value.get_blob());
             }
         }
+        Ok(failed)
+    })();
+    match result {
+        Ok(true) => con.write_response(responses::fresp::R_NIL.to_owned()).await,
+        Ok(false) => {
+            con.write_response(responses::fresp::R_OKAY.to_owned())
+                .await
+        }
+        Err(e) => {
+            log::error!("Failed to write a value with error: '{}'", e);
+            con.write_response(responses::fresp::R_SERVER_ERR.to_owned())
+                .await
+        }
     }
-    if failed {
-        con.write_response(responses::fresp::R_NIL.to_owned()).await
-    } else {
-        con.write_response(responses::fresp::R_OKAY.to_owned())
-            .await
+}
+
+/// Collect the key/value pairs in `act`
+fn into_pairs(act: ActionGroup) -> Vec<(String, Data)> {
+    let mut pairs = Vec::with_capacity(act.howmany() / 2);
+    let mut iter = act.into_iter();
+    while let (Some(key), Some(value)) = (iter.next(), iter.next()) {
+        pairs.push((key, Data::from_string(value)));
     }
+    pairs
 }
@@ -26,7 +26,7 @@ use crate::coredb::{self, CoreDB};
 use crate::protocol::{responses, ActionGroup, Connection};
 use coredb::Data;
 use libtdb::TResult;
-use std::collections::hash_map::Entry;
+use std::io;
 use std::hint::unreachable_unchecked;
 
 /// Run an `UPDATE` query
@@ -39,28 +39,40 @@ pub async fn update(handle: &CoreDB, con: &mut Connection, act: ActionGroup) ->
             .await;
     }
     let mut it = act.into_iter();
-    let did_we = {
+    let did_we: io::Result<bool> = {
         let mut whandle = handle.acquire_write();
         let writer = whandle.get_mut_ref();
-        if let Entry::Occupied(mut e) = writer.entry(
-            it.next()
-                .unwrap_or_else(|| unsafe { unreachable_unchecked() }),
-        ) {
-            e.insert(Data::from_string(
-                it.next()
-                    .unwrap_or_else(|| unsafe { unreachable_unchecked() }),
-            ));
-            true
+        let key = it
+            .next()
+            .unwrap_or_else(|| unsafe { unreachable_unchecked() });
+        if !writer.contains_key(&key) {
+            Ok(false)
         } else {
-            false
+            let value = it
+                .next()
+                .unwrap_or_else(|| unsafe { unreachable_unchecked() });
+            let value = Data::from_string(value);
+            // Only log the change once it has been made
+            writer.set(key.clone(), value.clone()).map(|_| {
+                handle.shared.log_set(&key, value.get_blob());
+                true
+            })
         }
     };
-    if did_we {
-        con.write_response(responses::fresp::R_OKAY.to_owned())
-            .await?;
-    } else {
-        con.write_response(responses::fresp::R_NIL.to_owned())
-            .await?;
+    match did_we {
+        Ok(true) => {
+            con.write_response(responses::fresp::R_OKAY.to_owned())
+                .await?
+        }
+        Ok(false) => {
+            con.write_response(responses::fresp::R_NIL.to_owned())
+                .await?
+        }
+        Err(e) => {
+            log::error!("Failed to write a value with error: '{}'", e);
+            con.write_response(responses::fresp::R_SERVER_ERR.to_owned())
+                .await?
+        }
     }
     #[cfg(debug_assertions)]
     {
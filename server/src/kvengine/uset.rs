@@ -23,6 +23,7 @@ use crate::coredb::{self, CoreDB};
 use crate::protocol::{responses, ActionGroup, Connection};
 use crate::resp::GroupBegin;
 use libtdb::TResult;
+use std::io;
 
 /// Run an `USET` query
 ///
@@ -41,14 +42,22 @@ pub async fn uset(handle: &CoreDB, con: &mut Connection, act: ActionGroup) -> TR
     // It is howmany/2 since we will be writing howmany/2 number of responses
     con.write_response(GroupBegin(1)).await?;
     let mut kviter = act.into_iter();
-    {
+    let result: io::Result<()> = (|| {
         let mut whandle = handle.acquire_write();
         let writer = whandle.get_mut_ref();
         while let (Some(key), Some(val)) = (kviter.next(), kviter.next()) {
-            let _ = writer.insert(key, coredb::Data::from_string(val));
+            let val = coredb::Data::from_string(val);
+            // Only log the change once it has been made
+            writer.set(key.clone(), val.clone())?;
+            handle.shared.log_set(&key, val.get_blob());
         }
-        drop(writer);
-        drop(whandle);
+        Ok(())
+    })();
+    if let Err(e) = result {
+        log::error!("Failed to write a value with error: '{}'", e);
+        return con
+            .write_response(responses::groups::SERVER_ERR.to_owned())
+            .await;
     }
     con.write_response(howmany / 2).await?;
     #[cfg(debug_assertions)]
@@ -0,0 +1,43 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # TerrabaseDB
+//!
+//! The server as a library. The `tdb` binary is a thin wrapper around [`dbnet::run`], and tools
+//! like `tdb-tool` use [`diskstore`] and [`config`] to work with the server's files directly.
+//! `tdb-proxy` uses the query parser and the responses in [`protocol`]
+
+mod admin;
+mod cluster;
+pub mod config;
+pub mod coredb;
+pub mod dbnet;
+pub mod diskstore;
+mod kvengine;
+mod metrics;
+pub mod protocol;
+mod queryengine;
+mod raft;
+mod replication;
+mod resp;
+pub use coredb::CoreDB;
+#[cfg(test)]
+mod tests;
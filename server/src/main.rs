@@ -19,24 +19,14 @@
  *
 */
 
-use crate::config::BGSave;
-use crate::config::SnapshotConfig;
-use tokio::net::TcpListener;
-mod config;
-use std::env;
-mod coredb;
-mod dbnet;
-mod diskstore;
-mod kvengine;
-mod protocol;
-mod queryengine;
-mod resp;
-use coredb::CoreDB;
-use dbnet::run;
 use env_logger::*;
+use std::env;
+use std::path::PathBuf;
+use std::process;
+use tdb::config::{self, ConfigFile, ParsedConfig, RecoveryMode};
+use tdb::dbnet::{run, Binding};
+use tdb::diskstore;
 use tokio::signal;
-#[cfg(test)]
-mod tests;
 
 #[cfg(not(target_env = "msvc"))]
 use jemallocator::Jemalloc;
@@ -63,55 +53,142 @@ async fn main() {
     Builder::new()
         .parse_filters(&env::var("TDB_LOG").unwrap_or("info".to_owned()))
         .init();
-    // Start the server which asynchronously waits for a CTRL+C signal
+    // Start the server which asynchronously waits for a CTRL+C or SIGTERM signal
     // which will safely shut down the server
-    let (tcplistener, bgsave_config, snapshot_config) = check_args_or_connect().await;
-    run(
-        tcplistener,
-        bgsave_config,
-        snapshot_config,
-        signal::ctrl_c(),
-    )
-    .await;
+    let (bindings, cfg, cfg_file, recover_to) = check_args_or_connect().await;
+    if let Err(e) = run(bindings, cfg, cfg_file, recover_to, termination_signal()).await {
+        log::error!("{}", e);
+        process::exit(1);
+    }
+}
+
+/// Wait for a CTRL+C signal or (on Unix) a SIGTERM signal, which is what `docker stop`
+/// and service managers send
+async fn termination_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = signal::ctrl_c() => {}
+                    _ = sigterm.recv() => {}
+                }
+                return;
+            }
+            Err(e) => log::warn!("Failed to listen for SIGTERM with error: '{}'", e),
+        }
+    }
+    let _ = signal::ctrl_c().await;
 }
 
-/// This function checks the command line arguments and binds to an appropriate
-/// port and host, as per the supplied configuration options
-async fn check_args_or_connect() -> (TcpListener, BGSave, SnapshotConfig) {
-    let cfg = config::get_config_file_or_return_cfg();
-    let binding_and_cfg = match cfg {
-        Ok(config::ConfigType::Custom(cfg)) => {
+/// This function checks the command line arguments and binds to every one of the
+/// listeners, as per the supplied configuration options
+///
+/// This also returns the configuration file, if one was used, and the time
+/// to recover the data to, if `--recover-to` was passed. If an offline recovery was asked
+/// for, or a salvage, this runs it and exits instead, and so does `--check-config`
+async fn check_args_or_connect() -> (Vec<Binding>, ParsedConfig, Option<ConfigFile>, Option<u64>) {
+    let (cfg, recovery, flags) = match config::get_config_file_or_return_cfg() {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if flags.check_config {
+        let cfg = match &cfg {
+            config::ConfigType::Custom(cfg, _) | config::ConfigType::Def(cfg) => cfg,
+        };
+        if flags.print_config {
+            print_config(cfg);
+        }
+        println!("The configuration is valid");
+        process::exit(0);
+    }
+    let recover_to = match recovery {
+        Some(RecoveryMode::OnStartup(target)) => Some(target),
+        Some(RecoveryMode::Offline { target, output }) => {
+            let cfg = match &cfg {
+                config::ConfigType::Custom(cfg, _) | config::ConfigType::Def(cfg) => cfg,
+            };
+            recover_offline(cfg, target, output)
+        }
+        Some(RecoveryMode::Salvage { input, output }) => {
+            let cfg = match &cfg {
+                config::ConfigType::Custom(cfg, _) | config::ConfigType::Def(cfg) => cfg,
+            };
+            salvage(cfg, input, output)
+        }
+        None => None,
+    };
+    let (cfg, cfg_file) = match cfg {
+        config::ConfigType::Custom(cfg, cfg_file) => {
             if cfg.is_artful() {
                 println!("{}\n{}", TEXT, MSG);
             } else {
                 println!("{}", MSG);
             }
             log::info!("Using settings from config file");
-            (
-                TcpListener::bind(cfg.get_host_port_tuple()).await,
-                cfg.bgsave,
-                cfg.snapshot,
-            )
+            (cfg, Some(cfg_file))
         }
-        Ok(config::ConfigType::Def(cfg)) => {
+        config::ConfigType::Def(cfg) => {
             println!("{}\n{}", TEXT, MSG);
             log::warn!("No configuration file supplied. Using default settings");
-            (
-                TcpListener::bind(cfg.get_host_port_tuple()).await,
-                cfg.bgsave,
-                cfg.snapshot,
-            )
+            (cfg, None)
         }
+    };
+    if flags.print_config {
+        print_config(&cfg);
+    }
+    let bindings = match cfg.listeners.iter().map(Binding::bind).collect() {
+        Ok(bindings) => bindings,
         Err(e) => {
             log::error!("{}", e);
-            std::process::exit(0x100);
+            std::process::exit(1);
         }
     };
-    match binding_and_cfg {
-        (Ok(b), bgsave_cfg, snapshot_cfg) => (b, bgsave_cfg, snapshot_cfg),
-        (Err(e), _, _) => {
-            log::error!("Failed to bind to socket with error: '{}'", e);
-            std::process::exit(0x100);
+    (bindings, cfg, cfg_file, recover_to)
+}
+
+/// Print the configuration as a TOML file (`--print-config`)
+fn print_config(cfg: &ParsedConfig) {
+    match cfg.to_toml() {
+        Ok(toml) => println!("{}", toml),
+        Err(e) => log::error!("Failed to print the configuration with error: '{}'", e),
+    }
+}
+
+/// Run a `tdb recover` and exit, without starting the server
+fn recover_offline(cfg: &ParsedConfig, target: u64, output: Option<PathBuf>) -> ! {
+    let recovered = diskstore::encryption::Keyring::load(&cfg.encryption).and_then(|keys| {
+        diskstore::writelog::recover_offline(&cfg.storage, &keys, target, output)
+    });
+    match recovered {
+        Ok(()) => process::exit(0),
+        Err(e) => {
+            log::error!("Recovery failed with error: '{}'", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Salvage the intact records in a damaged file and exit, without starting the server
+fn salvage(cfg: &ParsedConfig, input: Option<PathBuf>, output: Option<PathBuf>) -> ! {
+    let salvaged = diskstore::encryption::Keyring::load(&cfg.encryption)
+        .and_then(|keys| diskstore::salvage(&cfg.storage, &keys, input, output));
+    match salvaged {
+        Ok(report) if report.is_intact() => {
+            log::info!("The file isn't damaged: {}", report);
+            process::exit(0)
+        }
+        Ok(report) => {
+            log::warn!("The file is damaged: {}", report);
+            process::exit(0)
+        }
+        Err(e) => {
+            log::error!("Salvage failed with error: '{}'", e);
+            process::exit(1);
         }
     }
 }
@@ -0,0 +1,135 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # The metrics exporter
+//!
+//! A tiny HTTP/1.x listener that serves `GET /metrics` for Prometheus. It isn't meant to
+//! be a general purpose HTTP server: every request gets exactly one response and then
+//! the connection is closed
+
+use super::METRICS;
+use crate::coredb::CoreDB;
+use crate::dbnet::Terminator;
+use libtdb::TResult;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::time::{self, Duration};
+
+/// The largest request head that we're willing to read
+const MAX_REQUEST_HEAD: usize = 8 * 1024;
+
+/// How long a scraper gets to send its request and read the response. Scrapes are
+/// served one after the other, so a scraper that stalls mustn't hold up the others
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The metrics exporter
+pub struct Exporter {
+    /// An atomic reference to the coretable
+    db: CoreDB,
+    /// The HTTP listener
+    listener: TcpListener,
+    /// Tells us when the server is shutting down
+    terminator: Terminator,
+    /// Dropped when the exporter quits, so that the server knows that we're done
+    _term_sig_tx: mpsc::Sender<()>,
+}
+
+impl Exporter {
+    /// Create a new exporter
+    pub const fn new(
+        db: CoreDB,
+        listener: TcpListener,
+        terminator: Terminator,
+        term_sig_tx: mpsc::Sender<()>,
+    ) -> Self {
+        Exporter {
+            db,
+            listener,
+            terminator,
+            _term_sig_tx: term_sig_tx,
+        }
+    }
+    /// Serve scrapes until the server shuts down
+    pub async fn run(mut self) {
+        while !self.terminator.is_termination_signal() {
+            let stream = tokio::select! {
+                accepted = self.listener.accept() => accepted,
+                _ = self.terminator.receive_signal() => return,
+            };
+            match stream {
+                Ok((stream, _)) => {
+                    // Scrapes are cheap, so we'll serve them one after the other
+                    match time::timeout(SCRAPE_TIMEOUT, serve(stream, &self.db)).await {
+                        Ok(Ok(())) => {}
+                        Ok(Err(e)) => log::warn!("Failed to serve metrics scrape: '{}'", e),
+                        Err(_) => log::warn!("Dropped a metrics scrape that took too long"),
+                    }
+                }
+                Err(e) => log::error!("Metrics exporter failed to accept connection: '{}'", e),
+            }
+        }
+    }
+}
+
+/// Read a request from `stream` and write the response
+async fn serve(mut stream: TcpStream, db: &CoreDB) -> TResult<()> {
+    let mut head = Vec::with_capacity(512);
+    let mut buf = [0u8; 512];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            // The scraper went away before finishing the request
+            return Ok(());
+        }
+        head.extend_from_slice(&buf[..n]);
+        if head.len() > MAX_REQUEST_HEAD {
+            return respond(&mut stream, "431 Request Header Fields Too Large", "").await;
+        }
+    }
+    let request_line = String::from_utf8_lossy(&head);
+    let mut parts = request_line
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .split_whitespace();
+    match (parts.next(), parts.next()) {
+        (Some("GET"), Some(path)) if path == "/metrics" || path.starts_with("/metrics?") => {
+            let body = METRICS.render(db);
+            respond(&mut stream, "200 OK", &body).await
+        }
+        (Some("GET"), Some(_)) => respond(&mut stream, "404 Not Found", "").await,
+        _ => respond(&mut stream, "405 Method Not Allowed", "").await,
+    }
+}
+
+/// Write a complete HTTP response with the given status and body
+async fn respond(stream: &mut TcpStream, status: &str, body: &str) -> TResult<()> {
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body.as_bytes()).await?;
+    stream.flush().await?;
+    Ok(())
+}
@@ -0,0 +1,367 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # Server metrics
+//!
+//! This module keeps a process-wide tally of what the server has been up to: how many
+//! actions were run (and how long they took), how many clients are connected, how much
+//! data went through the sockets and how BGSAVE and the snapshot service have been doing.
+//! The numbers can be rendered in the Prometheus text exposition format with [`Metrics::render`],
+//! which is what the `exporter` serves over HTTP
+
+use crate::coredb::CoreDB;
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+pub mod exporter;
+
+/// Bucket bounds (in seconds) for action latencies
+const LATENCY_BUCKETS: [f64; 14] = [
+    0.00005, 0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
+];
+/// Bucket bounds (in seconds) for BGSAVE and snapshot durations
+const SAVE_BUCKETS: [f64; 10] = [0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 60.0];
+
+lazy_static! {
+    /// The process-wide metrics registry
+    pub static ref METRICS: Metrics = Metrics::new();
+}
+
+/// A cumulative histogram, as Prometheus likes it
+#[derive(Debug)]
+pub struct Histogram {
+    /// The upper bounds of the buckets (in seconds)
+    bounds: &'static [f64],
+    /// The number of observations that fell in each bucket (non-cumulative)
+    buckets: Vec<AtomicU64>,
+    /// The sum of all observations in microseconds
+    sum_micros: AtomicU64,
+    /// The number of observations
+    count: AtomicU64,
+}
+
+impl Histogram {
+    /// Create a new histogram with the given bucket bounds
+    fn new(bounds: &'static [f64]) -> Self {
+        Histogram {
+            bounds,
+            buckets: (0..bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum_micros: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+    /// Record an observation
+    pub fn observe(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        if let Some(idx) = self.bounds.iter().position(|bound| secs <= *bound) {
+            self.buckets[idx].fetch_add(1, Ordering::Relaxed);
+        }
+        self.sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+    /// Returns the number of observations
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+    /// Write the `_bucket`, `_sum` and `_count` series for this histogram
+    ///
+    /// `labels` is either empty or a comma separated list of `key="value"` pairs
+    fn render_into(&self, out: &mut String, name: &str, labels: &str) {
+        let sep = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+        for (bound, bucket) in self.bounds.iter().zip(self.buckets.iter()) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{}{}le=\"{}\"}} {}",
+                name, labels, sep, bound, cumulative
+            );
+        }
+        let count = self.count();
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{}{}le=\"+Inf\"}} {}",
+            name, labels, sep, count
+        );
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        if labels.is_empty() {
+            let _ = writeln!(out, "{}_sum {}", name, sum);
+            let _ = writeln!(out, "{}_count {}", name, count);
+        } else {
+            let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, sum);
+            let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, count);
+        }
+    }
+}
+
+/// Statistics for a background persistence task, like BGSAVE or the snapshot service
+#[derive(Debug)]
+pub struct SaveStats {
+    /// How long the runs took
+    durations: Histogram,
+    /// The number of runs that failed
+    failures: AtomicU64,
+}
+
+impl SaveStats {
+    fn new() -> Self {
+        SaveStats {
+            durations: Histogram::new(&SAVE_BUCKETS),
+            failures: AtomicU64::new(0),
+        }
+    }
+    /// Record a run that took `duration` and succeeded if `okay` is true
+    pub fn record(&self, duration: Duration, okay: bool) {
+        self.durations.observe(duration);
+        if !okay {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    fn render_into(&self, out: &mut String, name: &str, what: &str) {
+        let _ = writeln!(
+            out,
+            "# HELP tdb_{}_duration_seconds Time taken by {}",
+            name, what
+        );
+        let _ = writeln!(out, "# TYPE tdb_{}_duration_seconds histogram", name);
+        self.durations
+            .render_into(out, &format!("tdb_{}_duration_seconds", name), "");
+        let _ = writeln!(
+            out,
+            "# HELP tdb_{}_failures_total Number of failed runs of {}",
+            name, what
+        );
+        let _ = writeln!(out, "# TYPE tdb_{}_failures_total counter", name);
+        let _ = writeln!(
+            out,
+            "tdb_{}_failures_total {}",
+            name,
+            self.failures.load(Ordering::Relaxed)
+        );
+    }
+}
+
+/// The metrics registry
+#[derive(Debug)]
+pub struct Metrics {
+    /// Latency histograms for every action that was run, keyed by the action's name
+    actions: RwLock<HashMap<String, Arc<Histogram>>>,
+    /// The number of queries with an unknown action
+    unknown_actions: AtomicU64,
+    /// The number of connected clients
+    connections: AtomicUsize,
+    /// The total number of bytes read from client sockets
+    bytes_in: AtomicU64,
+    /// The total number of bytes written to client sockets
+    bytes_out: AtomicU64,
+    /// BGSAVE statistics
+    pub bgsave: SaveStats,
+    /// Snapshot statistics
+    pub snapshot: SaveStats,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            actions: RwLock::new(HashMap::new()),
+            unknown_actions: AtomicU64::new(0),
+            connections: AtomicUsize::new(0),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            bgsave: SaveStats::new(),
+            snapshot: SaveStats::new(),
+        }
+    }
+    /// Record that the action `action` was run, and that it took `duration`
+    pub fn record_action(&self, action: &str, duration: Duration) {
+        if let Some(hist) = self.actions.read().get(action) {
+            hist.observe(duration);
+            return;
+        }
+        self.actions
+            .write()
+            .entry(action.to_owned())
+            .or_insert_with(|| Arc::new(Histogram::new(&LATENCY_BUCKETS)))
+            .observe(duration);
+    }
+    /// Record a query with an unknown action
+    pub fn record_unknown_action(&self) {
+        self.unknown_actions.fetch_add(1, Ordering::Relaxed);
+    }
+    /// Record a newly accepted connection
+    pub fn connection_opened(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+    /// Record a closed connection
+    pub fn connection_closed(&self) {
+        self.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+    /// Record `n` bytes read from a client
+    pub fn add_bytes_in(&self, n: usize) {
+        self.bytes_in.fetch_add(n as u64, Ordering::Relaxed);
+    }
+    /// Record `n` bytes written to a client
+    pub fn add_bytes_out(&self, n: usize) {
+        self.bytes_out.fetch_add(n as u64, Ordering::Relaxed);
+    }
+    /// Render all the metrics in the Prometheus text exposition format
+    ///
+    /// The key count and the memory estimate are taken from `db`
+    pub fn render(&self, db: &CoreDB) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "# HELP tdb_actions_total Number of actions run, by action"
+        );
+        let _ = writeln!(out, "# TYPE tdb_actions_total counter");
+        // Sort the actions so that the output is stable across scrapes
+        let actions = self.actions.read();
+        let mut names: Vec<&String> = actions.keys().collect();
+        names.sort();
+        for name in names.iter() {
+            let _ = writeln!(
+                out,
+                "tdb_actions_total{{action=\"{}\"}} {}",
+                name,
+                actions[*name].count()
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP tdb_action_duration_seconds Time taken to run an action, by action"
+        );
+        let _ = writeln!(out, "# TYPE tdb_action_duration_seconds histogram");
+        for name in names {
+            actions[name].render_into(
+                &mut out,
+                "tdb_action_duration_seconds",
+                &format!("action=\"{}\"", name),
+            );
+        }
+        drop(actions);
+        let _ = writeln!(
+            out,
+            "# HELP tdb_unknown_actions_total Number of queries with an unknown action"
+        );
+        let _ = writeln!(out, "# TYPE tdb_unknown_actions_total counter");
+        let _ = writeln!(
+            out,
+            "tdb_unknown_actions_total {}",
+            self.unknown_actions.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "# HELP tdb_connected_clients Number of connected clients"
+        );
+        let _ = writeln!(out, "# TYPE tdb_connected_clients gauge");
+        let _ = writeln!(
+            out,
+            "tdb_connected_clients {}",
+            self.connections.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "# HELP tdb_network_received_bytes_total Bytes read from client connections"
+        );
+        let _ = writeln!(out, "# TYPE tdb_network_received_bytes_total counter");
+        let _ = writeln!(
+            out,
+            "tdb_network_received_bytes_total {}",
+            self.bytes_in.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "# HELP tdb_network_sent_bytes_total Bytes written to client connections"
+        );
+        let _ = writeln!(out, "# TYPE tdb_network_sent_bytes_total counter");
+        let _ = writeln!(
+            out,
+            "tdb_network_sent_bytes_total {}",
+            self.bytes_out.load(Ordering::Relaxed)
+        );
+        let (keys, memory) = estimate_table_size(db);
+        let _ = writeln!(out, "# HELP tdb_keys Number of keys in the database");
+        let _ = writeln!(out, "# TYPE tdb_keys gauge");
+        let _ = writeln!(out, "tdb_keys {}", keys);
+        let _ = writeln!(
+            out,
+            "# HELP tdb_memory_estimated_bytes Estimated memory used by the in-memory table"
+        );
+        let _ = writeln!(out, "# TYPE tdb_memory_estimated_bytes gauge");
+        let _ = writeln!(out, "tdb_memory_estimated_bytes {}", memory);
+        self.bgsave.render_into(&mut out, "bgsave", "BGSAVE");
+        self.snapshot
+            .render_into(&mut out, "snapshot", "the snapshot service");
+        out
+    }
+}
+
+/// Returns the number of keys in the table and an estimate of the memory used by it
+///
+/// The estimate is made by the storage engine. It doesn't account for allocator
+/// overhead, so take it with a grain of salt
+fn estimate_table_size(db: &CoreDB) -> (usize, usize) {
+    let rlock = db.acquire_read();
+    let table = rlock.get_ref();
+    (table.len(), table.memory_usage())
+}
+
+#[test]
+fn test_histogram_render() {
+    let hist = Histogram::new(&[0.001, 0.01]);
+    hist.observe(Duration::from_micros(500));
+    hist.observe(Duration::from_millis(5));
+    hist.observe(Duration::from_secs(1));
+    let mut out = String::new();
+    hist.render_into(&mut out, "tdb_test_seconds", "action=\"GET\"");
+    assert_eq!(
+        out,
+        "tdb_test_seconds_bucket{action=\"GET\",le=\"0.001\"} 1\n\
+         tdb_test_seconds_bucket{action=\"GET\",le=\"0.01\"} 2\n\
+         tdb_test_seconds_bucket{action=\"GET\",le=\"+Inf\"} 3\n\
+         tdb_test_seconds_sum{action=\"GET\"} 1.0055\n\
+         tdb_test_seconds_count{action=\"GET\"} 3\n"
+    );
+}
+
+#[test]
+fn test_render_includes_table_stats() {
+    let db = CoreDB::new_empty(0);
+    db.acquire_write()
+        .get_mut_ref()
+        .set(
+            String::from("sayan"),
+            crate::coredb::Data::from_string(String::from("is writing code")),
+        )
+        .unwrap();
+    let metrics = Metrics::new();
+    metrics.record_action("GET", Duration::from_micros(20));
+    metrics.record_action("GET", Duration::from_micros(40));
+    let rendered = metrics.render(&db);
+    assert!(rendered.contains("tdb_actions_total{action=\"GET\"} 2\n"));
+    assert!(rendered.contains("tdb_keys 1\n"));
+    assert!(rendered.contains("tdb_bgsave_failures_total 0\n"));
+}
@@ -43,6 +43,11 @@ use std::vec::IntoIter;
 pub struct ActionGroup(Vec<String>);
 
 impl ActionGroup {
+    #[cfg(test)]
+    /// Create a new `ActionGroup` from a vector of arguments
+    pub const fn new(args: Vec<String>) -> Self {
+        ActionGroup(args)
+    }
     /// Returns how many arguments are there excluding the name of the action
     pub fn howmany(&self) -> usize {
         self.0.len() - 1
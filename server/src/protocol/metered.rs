@@ -0,0 +1,96 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! A `Stream` wrapper which adds the bytes that go through it to the server's metrics
+//! and to the client's entry in the client registry
+
+use crate::admin::clients::ClientInfo;
+use crate::metrics::METRICS;
+use crate::protocol::stream::Stream;
+use std::io;
+use std::mem::MaybeUninit;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// A `Stream` that counts the bytes read from and written to it
+#[derive(Debug)]
+pub struct MeteredStream {
+    /// The underlying stream
+    inner: Stream,
+    /// The client on the remote end
+    client: Arc<ClientInfo>,
+}
+
+impl MeteredStream {
+    /// Create a new `MeteredStream` wrapping `inner`, which is connected to `client`
+    pub const fn new(inner: Stream, client: Arc<ClientInfo>) -> Self {
+        MeteredStream { inner, client }
+    }
+    /// Get a reference to the underlying `Stream`
+    pub const fn get_ref(&self) -> &Stream {
+        &self.inner
+    }
+    /// Get the client on the remote end
+    pub fn client(&self) -> &ClientInfo {
+        &self.client
+    }
+}
+
+impl AsyncRead for MeteredStream {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [MaybeUninit<u8>]) -> bool {
+        self.inner.prepare_uninitialized_buffer(buf)
+    }
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let polled = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(n)) = polled {
+            METRICS.add_bytes_in(n);
+            self.client.add_bytes_in(n);
+        }
+        polled
+    }
+}
+
+impl AsyncWrite for MeteredStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let polled = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = polled {
+            METRICS.add_bytes_out(n);
+            self.client.add_bytes_out(n);
+        }
+        polled
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
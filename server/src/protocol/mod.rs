@@ -19,8 +19,11 @@
  *
 */
 
-mod deserializer;
+pub mod deserializer;
+mod metered;
 pub mod responses;
+pub mod stream;
+use crate::admin::clients::ClientInfo;
 use crate::resp::Writable;
 use bytes::{Buf, BytesMut};
 pub use deserializer::ActionGroup;
@@ -28,18 +31,23 @@ pub use deserializer::ParseResult;
 pub use deserializer::Query;
 use libtdb::TResult;
 use libtdb::BUF_CAP;
+pub use metered::MeteredStream;
+use std::io::ErrorKind;
 use std::io::Result as IoResult;
 use std::net::SocketAddr;
+use std::sync::Arc;
+pub use stream::Stream;
 use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
-use tokio::net::TcpStream;
 
-/// A TCP connection wrapper
+/// A connection wrapper
 pub struct Connection {
     /// The connection to the remote socket, wrapped in a buffer to speed
     /// up writing
-    stream: BufWriter<TcpStream>,
+    stream: BufWriter<MeteredStream>,
     /// The in-memory read buffer. The size is given by `BUF_CAP`
     buffer: BytesMut,
+    /// The largest query that we're willing to buffer, in bytes
+    max_query_size: usize,
 }
 
 /// The outcome of running `Connection`'s `try_query` function
@@ -48,18 +56,31 @@ pub enum QueryResult {
     Q(Query),
     /// An error response
     E(Vec<u8>),
+    /// The query is larger than the maximum query size. Since the rest of the query
+    /// can't be skipped reliably, the connection should be closed
+    TooLarge,
     /// A closed connection
     Empty,
 }
 
 impl Connection {
-    /// Initiailize a new `Connection` instance
-    pub fn new(stream: TcpStream) -> Self {
+    /// Initiailize a new `Connection` instance for `client`, which will refuse queries
+    /// larger than `max_query_size` bytes
+    pub fn new(stream: Stream, client: Arc<ClientInfo>, max_query_size: usize) -> Self {
         Connection {
-            stream: BufWriter::new(stream),
+            stream: BufWriter::new(MeteredStream::new(stream, client)),
             buffer: BytesMut::with_capacity(BUF_CAP),
+            max_query_size,
         }
     }
+    /// Refuse queries larger than `max_query_size` bytes from now on
+    pub fn set_max_query_size(&mut self, max_query_size: usize) {
+        self.max_query_size = max_query_size;
+    }
+    /// The largest query that this connection accepts, in bytes
+    pub const fn max_query_size(&self) -> usize {
+        self.max_query_size
+    }
     /// Read a query from the remote end
     ///
     /// This function asynchronously waits until all the data required
@@ -68,6 +89,11 @@ impl Connection {
         self.read_again().await?;
         loop {
             match self.try_query() {
+                Ok(ParseResult::Query(_, forward)) if forward > self.max_query_size => {
+                    // The whole query might have arrived in one go, but it's still too large
+                    self.buffer.clear();
+                    return Ok(QueryResult::TooLarge);
+                }
                 Ok(ParseResult::Query(query, forward)) => {
                     self.buffer.advance(forward);
                     return Ok(QueryResult::Q(query));
@@ -81,6 +107,11 @@ impl Connection {
                 }
                 _ => (),
             }
+            if self.buffer.len() > self.max_query_size {
+                // Don't keep buffering a query that we'll never accept
+                self.buffer.clear();
+                return Ok(QueryResult::TooLarge);
+            }
             self.read_again().await?;
         }
     }
@@ -115,9 +146,25 @@ impl Connection {
             Err(e) => return Err(format!("{}", e)),
         }
     }
+    /// Read exactly `len` bytes from the remote end, without parsing them as a query
+    ///
+    /// This is used by connections that stop sending queries once they're set up, like
+    /// the ones between the nodes of a Raft group
+    pub async fn read_raw(&mut self, len: usize) -> IoResult<BytesMut> {
+        while self.buffer.len() < len {
+            if self.stream.read_buf(&mut self.buffer).await? == 0 {
+                return Err(ErrorKind::UnexpectedEof.into());
+            }
+        }
+        Ok(self.buffer.split_to(len))
+    }
     /// Get the peer address
-    fn get_peer(&self) -> IoResult<SocketAddr> {
-        self.stream.get_ref().peer_addr()
+    pub fn get_peer(&self) -> IoResult<SocketAddr> {
+        self.stream.get_ref().get_ref().peer_addr()
+    }
+    /// Get the client on the remote end
+    pub fn client(&self) -> &ClientInfo {
+        self.stream.get_ref().client()
     }
     /// Write a response to the stream
     pub async fn write_response(&mut self, streamer: impl Writable) -> TResult<()> {
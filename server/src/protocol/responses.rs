@@ -69,15 +69,61 @@ pub mod fresp {
         pub static ref R_SERVER_ERR: Vec<u8> = "#2\n*1\n#2\n&1\n!1\n5\n".as_bytes().to_owned();
         /// Response code: 6 (Other Error _without description_)
         pub static ref R_OTHER_ERR_EMPTY: Vec<u8> = "#2\n*1\n#2\n&1\n!1\n6\n".as_bytes().to_owned();
+        /// Response code: 8 (Read-only Error)
+        pub static ref R_READONLY: Vec<u8> = "#2\n*1\n#2\n&1\n!1\n8\n".as_bytes().to_owned();
         /// A heya response
         pub static ref R_HEYA: Vec<u8> = "#2\n*1\n#2\n&1\n+4\nHEY!\n".as_bytes().to_owned();
         /// An other response with description: "Unknown action"
         pub static ref R_UNKNOWN_ACTION: Vec<u8> = "#2\n*1\n#2\n&1\n!14\nUnknown action\n"
             .as_bytes()
             .to_owned();
+        /// An other response with description: "Too many clients"
+        pub static ref R_TOO_MANY_CLIENTS: Vec<u8> = "#2\n*1\n#2\n&1\n!16\nToo many clients\n"
+            .as_bytes()
+            .to_owned();
+        /// An other response with description: "Query too large"
+        pub static ref R_QUERY_TOO_LARGE: Vec<u8> = "#2\n*1\n#2\n&1\n!15\nQuery too large\n"
+            .as_bytes()
+            .to_owned();
+        /// An other response with description: "Save already in progress"
+        pub static ref R_SAVE_IN_PROGRESS: Vec<u8> = "#2\n*1\n#2\n&1\n!24\nSave already in progress\n"
+            .as_bytes()
+            .to_owned();
+        /// An other response with description: "Raft is disabled"
+        pub static ref R_RAFT_DISABLED: Vec<u8> = "#2\n*1\n#2\n&1\n!16\nRaft is disabled\n"
+            .as_bytes()
+            .to_owned();
+        /// An other response with description: "Cluster mode is disabled"
+        pub static ref R_CLUSTER_DISABLED: Vec<u8> = "#2\n*1\n#2\n&1\n!24\nCluster mode is disabled\n"
+            .as_bytes()
+            .to_owned();
+        /// An other response with description: "Authentication required"
+        pub static ref R_AUTH_REQUIRED: Vec<u8> = "#2\n*1\n#2\n&1\n!23\nAuthentication required\n"
+            .as_bytes()
+            .to_owned();
+        /// An other response with description: "Wrong password"
+        pub static ref R_WRONG_PASSWORD: Vec<u8> = "#2\n*1\n#2\n&1\n!14\nWrong password\n"
+            .as_bytes()
+            .to_owned();
+        /// An other response with description: "Keys span several slots"
+        pub static ref R_CROSS_SLOT: Vec<u8> = "#2\n*1\n#2\n&1\n!23\nKeys span several slots\n"
+            .as_bytes()
+            .to_owned();
         /// A 0 uint64 reply
         pub static ref R_ONE_INT_REPLY: Vec<u8> = "#2\n*1\n#2\n&1\n:1\n1\n".as_bytes().to_owned();
         /// A 1 uint64 reply
         pub static ref R_ZERO_INT_REPLY: Vec<u8> = "#2\n*1\n#2\n&1\n:1\n0\n".as_bytes().to_owned();
     }
 }
+
+/// Returns a complete _other error_ response with the description `desc`
+pub fn other_error(desc: &str) -> Vec<u8> {
+    format!("#2\n*1\n#2\n&1\n!{}\n{}\n", desc.len(), desc).into_bytes()
+}
+
+/// Returns a complete _redirect_ response (code `7`) to the node at `addr`, which serves
+/// the hash slot `slot`
+pub fn redirect(slot: u16, addr: &str) -> Vec<u8> {
+    let rcode = format!("7 {} {}", slot, addr);
+    format!("#2\n*1\n#2\n&1\n!{}\n{}\n", rcode.len(), rcode).into_bytes()
+}
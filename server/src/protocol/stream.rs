@@ -0,0 +1,129 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! The streams that clients connect over, which are either plain TCP streams or TLS
+//! streams on top of them
+
+use libtdb::TResult;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::mem::MaybeUninit;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::internal::pemfile;
+use tokio_rustls::rustls::{NoClientAuth, ServerConfig};
+use tokio_rustls::server::TlsStream;
+pub use tokio_rustls::TlsAcceptor;
+
+/// A client's stream
+#[derive(Debug)]
+pub enum Stream {
+    /// A plain TCP stream
+    Plain(TcpStream),
+    /// A TLS stream, once the handshake is done
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl Stream {
+    /// Get the address of the remote end
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Stream::Plain(stream) => stream.peer_addr(),
+            Stream::Tls(stream) => stream.get_ref().0.peer_addr(),
+        }
+    }
+}
+
+impl AsyncRead for Stream {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [MaybeUninit<u8>]) -> bool {
+        match self {
+            Stream::Plain(stream) => stream.prepare_uninitialized_buffer(buf),
+            Stream::Tls(stream) => stream.prepare_uninitialized_buffer(buf),
+        }
+    }
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            Stream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            Stream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            Stream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            Stream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Create a TLS acceptor which serves the certificate chain in `cert` with the private
+/// key in `key`, both in PEM format
+///
+/// The key can be a PKCS #8 or an RSA key
+pub fn tls_acceptor(cert: &Path, key: &Path) -> TResult<TlsAcceptor> {
+    let open = |path: &Path| {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|e| format!("Failed to open '{}' with error: '{}'", path.display(), e))
+    };
+    let certs = pemfile::certs(&mut open(cert)?)
+        .ok()
+        .filter(|certs| !certs.is_empty())
+        .ok_or_else(|| format!("'{}' has no PEM certificates", cert.display()))?;
+    let mut keys = pemfile::pkcs8_private_keys(&mut open(key)?).unwrap_or_default();
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut open(key)?).unwrap_or_default();
+    }
+    let key = keys
+        .into_iter()
+        .next()
+        .ok_or_else(|| format!("'{}' has no PEM private key", key.display()))?;
+    let mut cfg = ServerConfig::new(NoClientAuth::new());
+    cfg.set_single_cert(certs, key)?;
+    Ok(TlsAcceptor::from(Arc::new(cfg)))
+}
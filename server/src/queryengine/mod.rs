@@ -21,11 +21,18 @@
 
 //! # The Query Engine
 
+use crate::admin;
+use crate::admin::slowlog::QuerySummary;
+use crate::cluster;
 use crate::coredb::CoreDB;
 use crate::kvengine;
+use crate::metrics::METRICS;
 use crate::protocol::ActionGroup;
 use crate::protocol::{responses, Connection};
+use crate::raft;
+use crate::replication;
 use libtdb::TResult;
+use std::time::Instant;
 mod tags {
     //! This module is a collection of tags/strings used for evaluating queries
     //! and responses
@@ -61,6 +68,62 @@ mod tags {
     pub const TAG_USET: &'static str = "USET";
     /// `KEYLEN` action tag
     pub const TAG_KEYLEN: &'static str = "KEYLEN";
+    /// `SLOWLOG` action tag
+    pub const TAG_SLOWLOG: &'static str = "SLOWLOG";
+    /// `CLIENT` action tag
+    pub const TAG_CLIENT: &'static str = "CLIENT";
+    /// `SHUTDOWN` action tag
+    pub const TAG_SHUTDOWN: &'static str = "SHUTDOWN";
+    /// `SAVE` action tag
+    pub const TAG_SAVE: &'static str = "SAVE";
+    /// `BGSAVE` action tag
+    pub const TAG_BGSAVE: &'static str = "BGSAVE";
+    /// `LASTSAVE` action tag
+    pub const TAG_LASTSAVE: &'static str = "LASTSAVE";
+    /// `SNAPSHOT` action tag
+    pub const TAG_SNAPSHOT: &'static str = "SNAPSHOT";
+    /// `REPLICAOF` action tag
+    pub const TAG_REPLICAOF: &'static str = "REPLICAOF";
+    /// `REPLICATION` action tag
+    pub const TAG_REPLICATION: &'static str = "REPLICATION";
+    /// `RAFT` action tag
+    pub const TAG_RAFT: &'static str = "RAFT";
+    /// `CLUSTER` action tag
+    pub const TAG_CLUSTER: &'static str = "CLUSTER";
+    /// `READONLY` action tag
+    pub const TAG_READONLY: &'static str = "READONLY";
+    /// `CONFIG` action tag
+    pub const TAG_CONFIG: &'static str = "CONFIG";
+    /// The actions that change the data, which replicas and servers in read-only mode reject
+    pub const WRITE_TAGS: [&str; 10] = [
+        TAG_SET,
+        TAG_UPDATE,
+        TAG_DEL,
+        TAG_MSET,
+        TAG_MUPDATE,
+        TAG_SSET,
+        TAG_SDEL,
+        TAG_SUPDATE,
+        TAG_FLUSHDB,
+        TAG_USET,
+    ];
+    /// The actions that read the data, which only the leader serves in Raft mode
+    pub const READ_TAGS: [&str; 5] = [TAG_GET, TAG_EXISTS, TAG_MGET, TAG_DBSIZE, TAG_KEYLEN];
+}
+
+/// Returns `true` if the action `first` (with the arguments in `act`) changes the data
+///
+/// Besides the actions in `WRITE_TAGS`, `SNAPSHOT RESTORE` replaces the data and
+/// `CLUSTER IMPORT` adds keys to it
+fn changes_data(first: &str, act: &ActionGroup) -> bool {
+    let subaction = |name: &str| {
+        act.get_ref()
+            .get(1)
+            .is_some_and(|sub| sub.eq_ignore_ascii_case(name))
+    };
+    tags::WRITE_TAGS.contains(&first)
+        || (first == tags::TAG_SNAPSHOT && subaction("RESTORE"))
+        || (first == tags::TAG_CLUSTER && subaction("IMPORT"))
 }
 
 /// Execute a simple(*) query
@@ -73,27 +136,112 @@ pub async fn execute_simple(db: &CoreDB, con: &mut Connection, buf: ActionGroup)
         }
         Some(f) => f.to_uppercase(),
     };
-    match first.as_str() {
-        tags::TAG_DEL => kvengine::del::del(db, con, buf).await?,
-        tags::TAG_GET => kvengine::get::get(db, con, buf).await?,
-        tags::TAG_HEYA => kvengine::heya::heya(db, con, buf).await?,
-        tags::TAG_EXISTS => kvengine::exists::exists(db, con, buf).await?,
-        tags::TAG_SET => kvengine::set::set(db, con, buf).await?,
-        tags::TAG_MGET => kvengine::mget::mget(db, con, buf).await?,
-        tags::TAG_MSET => kvengine::mset::mset(db, con, buf).await?,
-        tags::TAG_UPDATE => kvengine::update::update(db, con, buf).await?,
-        tags::TAG_MUPDATE => kvengine::mupdate::mupdate(db, con, buf).await?,
-        tags::TAG_SSET => kvengine::strong::sset(db, con, buf).await?,
-        tags::TAG_SDEL => kvengine::strong::sdel(db, con, buf).await?,
-        tags::TAG_SUPDATE => kvengine::strong::supdate(db, con, buf).await?,
-        tags::TAG_DBSIZE => kvengine::dbsize::dbsize(db, con, buf).await?,
-        tags::TAG_FLUSHDB => kvengine::flushdb::flushdb(db, con, buf).await?,
-        tags::TAG_USET => kvengine::uset::uset(db, con, buf).await?,
-        tags::TAG_KEYLEN => kvengine::keylen::keylen(db, con, buf).await?,
+    con.client().touch(&first);
+    let write = changes_data(&first, &buf);
+    if write && db.shared.replication.is_replica() {
+        // All the changes on a replica come from its primary
+        return con
+            .write_response(responses::fresp::R_READONLY.to_owned())
+            .await;
+    }
+    // Writes can't start while the server is switching to read-only mode, and the
+    // switch waits for the running ones
+    let _write_permit = if write {
+        match db.shared.readonly.permit_write().await {
+            Some(permit) => Some(permit),
+            None => {
+                return con
+                    .write_response(responses::fresp::R_READONLY.to_owned())
+                    .await
+            }
+        }
+    } else {
+        None
+    };
+    // The keys might be served by another node, and a migration waits for the running
+    // writes before it copies the keys
+    let _cluster_permit = match &db.shared.cluster {
+        Some(cluster) => match cluster.route(buf.get_ref(), write).await {
+            Ok(permit) => permit,
+            Err(response) => return con.write_response(response).await,
+        },
+        None => None,
+    };
+    let raft_write = match &db.shared.raft {
+        Some(raft) => {
+            if tags::READ_TAGS.contains(&first.as_str()) {
+                // Reads have to see every write that was acknowledged
+                if let Err(response) = raft.read_barrier().await {
+                    return con.write_response(response).await;
+                }
+            }
+            let restore = first == tags::TAG_SNAPSHOT
+                && buf
+                    .get_ref()
+                    .get(1)
+                    .is_some_and(|sub| sub.eq_ignore_ascii_case("RESTORE"));
+            if first == tags::TAG_REPLICAOF || restore {
+                // The data can only change through the Raft log
+                return con
+                    .write_response(responses::other_error("Not possible in Raft mode"))
+                    .await;
+            }
+            tags::WRITE_TAGS.contains(&first.as_str())
+        }
+        None => false,
+    };
+    // Only keep a copy of the query around if it could end up in the slowlog
+    let summary = if db.shared.slowlog.is_enabled() {
+        Some(QuerySummary::new(&buf))
+    } else {
+        None
+    };
+    let start = Instant::now();
+    let result = match first.as_str() {
+        _ if raft_write => raft::write(db, con, buf, &first).await,
+        tags::TAG_DEL => kvengine::del::del(db, con, buf).await,
+        tags::TAG_GET => kvengine::get::get(db, con, buf).await,
+        tags::TAG_HEYA => kvengine::heya::heya(db, con, buf).await,
+        tags::TAG_EXISTS => kvengine::exists::exists(db, con, buf).await,
+        tags::TAG_SET => kvengine::set::set(db, con, buf).await,
+        tags::TAG_MGET => kvengine::mget::mget(db, con, buf).await,
+        tags::TAG_MSET => kvengine::mset::mset(db, con, buf).await,
+        tags::TAG_UPDATE => kvengine::update::update(db, con, buf).await,
+        tags::TAG_MUPDATE => kvengine::mupdate::mupdate(db, con, buf).await,
+        tags::TAG_SSET => kvengine::strong::sset(db, con, buf).await,
+        tags::TAG_SDEL => kvengine::strong::sdel(db, con, buf).await,
+        tags::TAG_SUPDATE => kvengine::strong::supdate(db, con, buf).await,
+        tags::TAG_DBSIZE => kvengine::dbsize::dbsize(db, con, buf).await,
+        tags::TAG_FLUSHDB => kvengine::flushdb::flushdb(db, con, buf).await,
+        tags::TAG_USET => kvengine::uset::uset(db, con, buf).await,
+        tags::TAG_KEYLEN => kvengine::keylen::keylen(db, con, buf).await,
+        tags::TAG_SLOWLOG => admin::slowlog::slowlog(db, con, buf).await,
+        tags::TAG_CLIENT => admin::clients::client(db, con, buf).await,
+        tags::TAG_SHUTDOWN => admin::shutdown::shutdown(db, con, buf).await,
+        tags::TAG_SAVE => admin::save::save(db, con, buf).await,
+        tags::TAG_BGSAVE => admin::save::bgsave(db, con, buf).await,
+        tags::TAG_LASTSAVE => admin::save::lastsave(db, con, buf).await,
+        tags::TAG_SNAPSHOT => admin::snapshot::snapshot(db, con, buf).await,
+        tags::TAG_REPLICAOF => replication::replicaof(db, con, buf).await,
+        tags::TAG_REPLICATION => replication::replication(db, con, buf).await,
+        tags::TAG_RAFT => raft::raft(db, con, buf).await,
+        tags::TAG_CLUSTER => cluster::cluster(db, con, buf).await,
+        tags::TAG_READONLY => admin::readonly::readonly(db, con, buf).await,
+        tags::TAG_CONFIG => admin::config::config(db, con, buf).await,
         _ => {
-            con.write_response(responses::fresp::R_UNKNOWN_ACTION.to_owned())
-                .await?
+            METRICS.record_unknown_action();
+            return con
+                .write_response(responses::fresp::R_UNKNOWN_ACTION.to_owned())
+                .await;
         }
+    };
+    // Stop the clock as soon as the action is done, and count the ones that failed too
+    let elapsed = start.elapsed();
+    METRICS.record_action(&first, elapsed);
+    if let Some(summary) = summary {
+        db.shared
+            .slowlog
+            .record(summary, elapsed, con.get_peer().ok());
     }
-    Ok(())
+    result
 }
@@ -0,0 +1,215 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # The state machine
+//!
+//! Every write that goes through the Raft log is applied to the table by every node, in
+//! the order of the log. The actions here have the same effect and the same responses
+//! as the ones in `kvengine`, but they only depend on the table, so every node ends up
+//! with the same data and the leader can hand the response back to the client once the
+//! write is applied
+
+use crate::coredb::storage::Storage;
+use crate::coredb::{Data, Shared};
+use crate::protocol::responses;
+use std::io;
+
+/// Check if `args` (the action followed by its arguments) is a valid write
+///
+/// Only valid writes are put in the log, so that every entry can be applied
+pub fn is_valid(args: &[String]) -> bool {
+    let howmany = args.len().saturating_sub(1);
+    match args.first().map(String::as_str) {
+        Some("SET") | Some("UPDATE") => howmany == 2,
+        Some("DEL") | Some("SDEL") => howmany != 0,
+        Some("MSET") | Some("MUPDATE") | Some("USET") | Some("SSET") | Some("SUPDATE") => {
+            howmany & 1 == 0 && howmany != 0
+        }
+        Some("FLUSHDB") => howmany == 0,
+        _ => false,
+    }
+}
+
+/// Apply the write in `args` (the action followed by its arguments) to `table`,
+/// returning the response for the client
+pub fn apply(shared: &Shared, table: &mut dyn Storage, args: &[String]) -> Vec<u8> {
+    let (action, args) = match args.split_first() {
+        Some((action, args)) => (action.as_str(), args),
+        None => return responses::fresp::R_ACTION_ERR.to_owned(),
+    };
+    let result = match action {
+        "SET" => set(shared, table, &args[0], &args[1], false).map(|done| {
+            if done {
+                responses::fresp::R_OKAY.to_owned()
+            } else {
+                responses::fresp::R_OVERWRITE_ERR.to_owned()
+            }
+        }),
+        "UPDATE" => set(shared, table, &args[0], &args[1], true).map(|done| {
+            if done {
+                responses::fresp::R_OKAY.to_owned()
+            } else {
+                responses::fresp::R_NIL.to_owned()
+            }
+        }),
+        "USET" => args
+            .chunks(2)
+            .try_for_each(|pair| {
+                table.set(pair[0].clone(), Data::from_string(pair[1].clone()))?;
+                shared.log_set(&pair[0], pair[1].as_bytes());
+                Ok(())
+            })
+            .map(|_| count(args.len() / 2)),
+        "MSET" | "MUPDATE" => {
+            let exists = action == "MUPDATE";
+            let mut done = 0;
+            args.chunks(2)
+                .try_for_each(|pair| {
+                    if set(shared, table, &pair[0], &pair[1], exists)? {
+                        done += 1;
+                    }
+                    Ok(())
+                })
+                .map(|_| count(done))
+        }
+        "SSET" | "SUPDATE" => {
+            let exists = action == "SUPDATE";
+            if args
+                .chunks(2)
+                .all(|pair| table.contains_key(&pair[0]) == exists)
+            {
+                // Either all of the keys are set or none are
+                let pairs = args
+                    .chunks(2)
+                    .map(|pair| (pair[0].clone(), Data::from_string(pair[1].clone())))
+                    .collect();
+                table.set_all(pairs).map(|_| {
+                    for pair in args.chunks(2) {
+                        shared.log_set(&pair[0], pair[1].as_bytes());
+                    }
+                    responses::fresp::R_OKAY.to_owned()
+                })
+            } else if exists {
+                Ok(responses::fresp::R_NIL.to_owned())
+            } else {
+                Ok(responses::fresp::R_OVERWRITE_ERR.to_owned())
+            }
+        }
+        "DEL" => {
+            let mut done = 0;
+            args.iter()
+                .try_for_each(|key| {
+                    if table.remove(key)? {
+                        shared.log_del(key);
+                        done += 1;
+                    }
+                    Ok(())
+                })
+                .map(|_| count(done))
+        }
+        "SDEL" => {
+            if args.iter().all(|key| table.contains_key(key)) {
+                // Either all of the keys are removed or none are
+                table.remove_all(args).map(|_| {
+                    for key in args {
+                        shared.log_del(key);
+                    }
+                    responses::fresp::R_OKAY.to_owned()
+                })
+            } else {
+                Ok(responses::fresp::R_NIL.to_owned())
+            }
+        }
+        "FLUSHDB" => table.clear().map(|_| {
+            shared.log_clear();
+            responses::fresp::R_OKAY.to_owned()
+        }),
+        _ => Ok(responses::fresp::R_ACTION_ERR.to_owned()),
+    };
+    result.unwrap_or_else(|e: io::Error| {
+        log::error!(
+            "Failed to apply a write from the Raft log with error: '{}'",
+            e
+        );
+        responses::fresp::R_SERVER_ERR.to_owned()
+    })
+}
+
+/// Set `key` to `value` if it exists (for `exists == true`) or if it doesn't (for
+/// `exists == false`), returning `true` if it was set
+fn set(
+    shared: &Shared,
+    table: &mut dyn Storage,
+    key: &str,
+    value: &str,
+    exists: bool,
+) -> io::Result<bool> {
+    if table.contains_key(key) != exists {
+        return Ok(false);
+    }
+    table.set(key.to_owned(), Data::from_string(value.to_owned()))?;
+    // Only log the change once it has been made
+    shared.log_set(key, value.as_bytes());
+    Ok(true)
+}
+
+/// Returns a complete response with the count `n`
+fn count(n: usize) -> Vec<u8> {
+    let n = n.to_string();
+    format!("#2\n*1\n#2\n&1\n:{}\n{}\n", n.len(), n).into_bytes()
+}
+
+#[test]
+fn test_raft_machine() {
+    use crate::coredb::CoreDB;
+    use std::collections::HashMap;
+    let db = CoreDB::new_empty(0);
+    let mut table: HashMap<String, Data> = HashMap::new();
+    let mut run = |query: &str| {
+        let args: Vec<String> = query.split_whitespace().map(str::to_owned).collect();
+        assert!(is_valid(&args));
+        apply(&db.shared, &mut table, &args)
+    };
+    assert_eq!(run("SET x 1"), *responses::fresp::R_OKAY);
+    assert_eq!(run("SET x 2"), *responses::fresp::R_OVERWRITE_ERR);
+    assert_eq!(run("UPDATE y 2"), *responses::fresp::R_NIL);
+    assert_eq!(run("MSET x 3 y 3 z 3"), count(2));
+    assert_eq!(run("SSET a 1 x 1"), *responses::fresp::R_OVERWRITE_ERR);
+    assert_eq!(run("SUPDATE x 4 y 4"), *responses::fresp::R_OKAY);
+    assert_eq!(run("DEL x nothere"), count(1));
+    assert_eq!(run("SDEL y x"), *responses::fresp::R_NIL);
+    assert_eq!(run("USET y 5 w 5"), count(2));
+    let value = |table: &HashMap<String, Data>, key: &str| {
+        table
+            .get(key)
+            .map(|value| String::from_utf8_lossy(value.get_blob()).into_owned())
+    };
+    assert_eq!(value(&table, "x"), None);
+    assert_eq!(value(&table, "y").as_deref(), Some("5"));
+    assert_eq!(value(&table, "z").as_deref(), Some("3"));
+    assert_eq!(
+        apply(&db.shared, &mut table, &["FLUSHDB".to_owned()]),
+        *responses::fresp::R_OKAY
+    );
+    assert!(table.is_empty());
+    assert!(!is_valid(&["SET".to_owned(), "x".to_owned()]));
+    assert!(!is_valid(&["GET".to_owned(), "x".to_owned()]));
+}
@@ -0,0 +1,1433 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # Raft
+//!
+//! In Raft mode, several servers (the _nodes_) form a group that keeps the same data with
+//! the [Raft consensus algorithm](https://raft.github.io/raft.pdf). One of the members is
+//! elected as the leader, and all the writes go through it: the leader appends every
+//! write to its log, sends it to the other members and only applies it (and replies to
+//! the client) once a majority of the members have it on disk. A write that was
+//! acknowledged survives as long as a majority of the members are up, and the members
+//! that were down catch up when they come back.
+//!
+//! Reads are served by the leader too, and they are linearizable: the leader only
+//! serves a read once it knows that it is still the leader. That is the case while its
+//! _lease_ holds, that is, shortly after a majority of the members replied to it (since
+//! members don't vote for anyone else for an election timeout after they hear from the
+//! leader). Otherwise the leader sends a round of heartbeats and waits for a majority to
+//! reply before it serves the read (the _read index_). Other nodes reply to reads and
+//! writes with the address of the leader.
+//!
+//! Once enough entries were applied, the log is compacted: the data is saved as a
+//! snapshot with the `SnapshotEngine` (named `raft-<term>-<index>`) and the entries in
+//! it are dropped. Members that are too far behind get the latest snapshot from the
+//! leader. Members are added and removed with `RAFT ADD` and `RAFT REMOVE`, one at a time
+
+use crate::config::{RaftPref, StorageConfig};
+use crate::coredb::{CoreDB, Data, Shared};
+use crate::diskstore;
+use crate::diskstore::encryption::Keyring;
+use crate::diskstore::format::{self, Codec};
+use crate::diskstore::snapshot::{self, SnapshotEngine};
+use crate::protocol::{responses, ActionGroup, Connection};
+use crate::resp::{BytesWrapper, GroupBegin};
+use bytes::Bytes;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+use libtdb::TResult;
+use parking_lot::Mutex;
+use peer::{Message, Outgoing};
+use raftlog::{RaftLog, SnapshotMeta};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{oneshot, watch, Notify};
+use tokio::time;
+mod machine;
+pub mod peer;
+mod raftlog;
+
+/// The most entries that the leader sends in one call
+const MAX_APPEND: usize = 256;
+/// The most entries that are applied while holding the write lock
+const MAX_APPLY: usize = 256;
+
+/// The members of a group, by ID, with the `host:port` on which they accept connections
+pub type Members = BTreeMap<u64, String>;
+
+/// An entry in the Raft log
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// The position of the entry in the log, starting from 1
+    pub index: u64,
+    /// The term in which the entry was created
+    pub term: u64,
+    /// What the entry does
+    pub command: Command,
+}
+
+/// What an entry in the Raft log does
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Nothing; every leader appends one of these when it is elected
+    Noop,
+    /// A write, which is the action (in upper case) followed by its arguments
+    Write(Vec<String>),
+    /// Change the members of the group to these
+    Members(Members),
+}
+
+/// The role of a node
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+impl Role {
+    fn as_str(self) -> &'static str {
+        match self {
+            Role::Follower => "follower",
+            Role::Candidate => "candidate",
+            Role::Leader => "leader",
+        }
+    }
+}
+
+/// What we know about another member
+#[derive(Debug)]
+struct Progress {
+    /// The index of the next entry to send to the member
+    next: u64,
+    /// The index of the last entry that the member has, as far as we know
+    matched: u64,
+    /// When we sent the last call that the member replied to in this term
+    contact: Option<Instant>,
+    /// Whether we've asked the member for its vote in this election
+    asked: bool,
+    /// Whether a call should be sent right away, even if there's nothing new
+    urgent: bool,
+}
+
+impl Progress {
+    const fn new(next: u64) -> Self {
+        Progress {
+            next,
+            matched: 0,
+            contact: None,
+            asked: false,
+            urgent: false,
+        }
+    }
+}
+
+/// The state of a node that can change
+#[derive(Debug)]
+struct Core {
+    /// The role of this node
+    role: Role,
+    /// The leader of the current term, if we know it
+    leader: Option<u64>,
+    /// The log, along with the term, the vote and the latest snapshot
+    log: RaftLog,
+    /// The index of the last entry that is known to be committed
+    commit: u64,
+    /// The index of the last entry that was applied to the table
+    applied: u64,
+    /// The members that voted for us in this election
+    votes: HashSet<u64>,
+    /// What we know about the other members, while we're a candidate or the leader
+    progress: HashMap<u64, Progress>,
+    /// The index of the first entry in our term, while we're the leader
+    term_start: u64,
+    /// When we last heard from the leader (or became the leader)
+    heard_at: Instant,
+    /// When we start an election if we don't hear from a leader
+    election_deadline: Instant,
+    /// The clients waiting for their entries to be applied, by index, with the term of
+    /// the entry
+    waiting: HashMap<u64, (u64, oneshot::Sender<Vec<u8>>)>,
+}
+
+/// A node in a Raft group
+///
+/// The locks are always taken in this order: `applying`, the snapshots, the table
+/// and `core`
+#[derive(Debug)]
+pub struct Raft {
+    /// The ID of this node
+    pub id: u64,
+    /// The election timeout
+    pub election_timeout: Duration,
+    /// How often the leader sends heartbeats
+    pub heartbeat_interval: Duration,
+    /// The number of applied entries after which the log is compacted
+    snapshot_after: u64,
+    /// The state of the node
+    core: Mutex<Core>,
+    /// Held while entries are applied or a snapshot is installed
+    applying: Mutex<()>,
+    /// Fires whenever entries are applied or the leader hears from a member
+    changed: watch::Sender<()>,
+    /// A receiver for `changed`, which is cloned by whoever waits for it
+    watcher: watch::Receiver<()>,
+    /// Wakes up the Raft service
+    service: Notify,
+    /// Wakes up the task for each of the other members
+    peers: Mutex<HashMap<u64, Arc<Notify>>>,
+    /// Whether the database is shutting down
+    stopped: AtomicBool,
+    /// The snapshot directory
+    snapdir: PathBuf,
+    /// The keys with which the snapshots are encrypted
+    keys: Keyring,
+    /// The codec with which snapshots are sent to the other members
+    codec: Codec,
+    /// The password that is sent with `AUTH` to the other members
+    password: Option<String>,
+}
+
+impl Raft {
+    /// Open the Raft state in the data directory, starting a new group with the members
+    /// in `pref` if there isn't any
+    pub fn new(pref: RaftPref, storage: &StorageConfig, keys: Keyring) -> TResult<Self> {
+        let members: Members = pref
+            .members
+            .into_iter()
+            .map(|member| (member.id, member.addr))
+            .collect();
+        let log = RaftLog::open(storage.raft_dir(), members)?;
+        let snapshot = log.snapshot().index;
+        let now = Instant::now();
+        let (changed, watcher) = watch::channel(());
+        let raft = Raft {
+            id: pref.id,
+            election_timeout: Duration::from_millis(pref.election_timeout),
+            heartbeat_interval: Duration::from_millis(pref.heartbeat_interval),
+            snapshot_after: pref.snapshot_after,
+            core: Mutex::new(Core {
+                role: Role::Follower,
+                leader: None,
+                log,
+                commit: snapshot,
+                applied: snapshot,
+                votes: HashSet::new(),
+                progress: HashMap::new(),
+                term_start: 0,
+                heard_at: now,
+                election_deadline: now,
+                waiting: HashMap::new(),
+            }),
+            applying: Mutex::new(()),
+            changed,
+            watcher,
+            service: Notify::new(),
+            peers: Mutex::new(HashMap::new()),
+            stopped: AtomicBool::new(false),
+            snapdir: storage.snapshot_dir(),
+            keys,
+            codec: storage.compression(),
+            password: pref.password,
+        };
+        raft.core.lock().election_deadline = now + raft.random_timeout();
+        Ok(raft)
+    }
+    /// Returns the data in the latest snapshot, which is what the table starts with
+    pub fn load_table(&self) -> TResult<HashMap<String, Data>> {
+        let name = self.core.lock().log.snapshot().name.clone();
+        match name {
+            Some(name) => {
+                diskstore::get_saved(Some(snapshot::path_in(&self.snapdir, &name)), &self.keys)?
+                    .ok_or_else(|| format!("The Raft snapshot '{}' is missing", name).into())
+            }
+            None => Ok(HashMap::new()),
+        }
+    }
+    /// Returns the members of the group
+    pub fn members(&self) -> Members {
+        self.core.lock().log.members().clone()
+    }
+    /// Check if the database is shutting down
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+    /// Stop the Raft service and the tasks for the other members, failing the writes
+    /// that are waiting
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        self.core.lock().waiting.clear();
+        self.service.notify();
+        self.wake_peers();
+    }
+    /// Returns an election timeout between one and two times `election_timeout`, so that
+    /// the nodes don't all start an election at once
+    fn random_timeout(&self) -> Duration {
+        let timeout = self.election_timeout.as_millis() as u64;
+        Duration::from_millis(timeout + OsRng.next_u64() % (timeout + 1))
+    }
+    /// Let the waiting reads know that something has changed
+    fn changed(&self) {
+        let _ = self.changed.broadcast(());
+    }
+    /// Wake up the tasks for the other members
+    fn wake_peers(&self) {
+        for notify in self.peers.lock().values() {
+            notify.notify();
+        }
+    }
+    /// Start a task for every member that doesn't have one yet
+    fn ensure_peers(self: &Arc<Self>) {
+        let members: Vec<u64> = {
+            let core = self.core.lock();
+            core.log
+                .members()
+                .keys()
+                .copied()
+                .filter(|&id| id != self.id)
+                .collect()
+        };
+        let mut peers = self.peers.lock();
+        for peer in members {
+            peers.entry(peer).or_insert_with(|| {
+                let notify = Arc::new(Notify::new());
+                tokio::spawn(peer::replicate(self.clone(), peer, notify.clone()));
+                notify
+            });
+        }
+    }
+    /// Forget the member `peer`, once its task has exited
+    fn forget_peer(&self, peer: u64) {
+        self.peers.lock().remove(&peer);
+        self.core.lock().progress.remove(&peer);
+    }
+    /// Returns the response for a client that sent a query to a node that isn't the
+    /// leader
+    fn not_leader(&self, core: &Core) -> Vec<u8> {
+        match core
+            .leader
+            .filter(|&leader| leader != self.id)
+            .and_then(|leader| core.log.members().get(&leader))
+        {
+            Some(addr) => responses::other_error(&format!("Not the leader, try {}", addr)),
+            None => responses::other_error("No leader elected"),
+        }
+    }
+    /// Returns the time at which a majority of the members (including us) were last in
+    /// touch with us, which is when the lease of the leader started
+    fn quorum_contact(&self, core: &Core, now: Instant) -> Option<Instant> {
+        let mut contacts: Vec<Option<Instant>> = core
+            .log
+            .members()
+            .keys()
+            .map(|&id| {
+                if id == self.id {
+                    Some(now)
+                } else {
+                    core.progress.get(&id).and_then(|progress| progress.contact)
+                }
+            })
+            .collect();
+        contacts.sort_unstable_by(|a, b| b.cmp(a));
+        contacts.get(contacts.len() / 2).copied().flatten()
+    }
+    /// Check if the members who voted for us are a majority
+    fn has_votes(&self, core: &Core) -> bool {
+        let members = core.log.members();
+        let votes = core
+            .votes
+            .iter()
+            .filter(|id| members.contains_key(id))
+            .count();
+        votes > members.len() / 2
+    }
+    /// Move to the term `term` (if it is newer) as a follower of `leader`
+    fn become_follower(&self, core: &mut Core, term: u64, leader: Option<u64>) -> io::Result<()> {
+        if term > core.log.term() {
+            core.log.set_hard_state(term, None)?;
+        }
+        if core.role == Role::Leader {
+            log::warn!(
+                "Stepped down as the Raft leader in term {}",
+                core.log.term()
+            );
+            // The writes that are committed are still applied; we don't know about the rest
+            let commit = core.commit;
+            let uncommitted: Vec<u64> = core
+                .waiting
+                .keys()
+                .copied()
+                .filter(|&index| index > commit)
+                .collect();
+            for index in uncommitted {
+                if let Some((_, sender)) = core.waiting.remove(&index) {
+                    let _ = sender.send(leadership_changed());
+                }
+            }
+        }
+        if core.role != Role::Follower {
+            core.election_deadline = Instant::now() + self.random_timeout();
+        }
+        if let Some(leader) = leader.filter(|&leader| core.leader != Some(leader)) {
+            log::info!("Following Raft leader {} in term {}", leader, term);
+        }
+        core.role = Role::Follower;
+        core.leader = leader;
+        core.votes.clear();
+        core.progress.clear();
+        Ok(())
+    }
+    /// Start an election for the next term
+    fn start_election(&self, core: &mut Core, now: Instant) -> io::Result<()> {
+        let term = core.log.term() + 1;
+        core.log.set_hard_state(term, Some(self.id))?;
+        log::info!("Starting an election for term {}", term);
+        core.role = Role::Candidate;
+        core.leader = None;
+        core.votes.clear();
+        core.votes.insert(self.id);
+        core.progress.clear();
+        core.election_deadline = now + self.random_timeout();
+        if self.has_votes(core) {
+            self.become_leader(core, now)?;
+        }
+        Ok(())
+    }
+    /// Take over as the leader of the current term
+    fn become_leader(&self, core: &mut Core, now: Instant) -> io::Result<()> {
+        let term = core.log.term();
+        let next = core.log.last_index() + 1;
+        core.role = Role::Leader;
+        core.leader = Some(self.id);
+        core.heard_at = now;
+        core.progress = core
+            .log
+            .members()
+            .keys()
+            .filter(|&&id| id != self.id)
+            .map(|&id| (id, Progress::new(next)))
+            .collect();
+        // Entries from earlier terms are only committed along with one from our term, so
+        // we need one right away
+        core.log.append(vec![Entry {
+            index: next,
+            term,
+            command: Command::Noop,
+        }])?;
+        core.term_start = next;
+        log::info!("Became the Raft leader in term {}", term);
+        self.advance_commit(core);
+        Ok(())
+    }
+    /// Commit the entries that a majority of the members have, returning `true` if the
+    /// commit index moved
+    fn advance_commit(&self, core: &mut Core) -> bool {
+        let last_index = core.log.last_index();
+        let mut matched: Vec<u64> = core
+            .log
+            .members()
+            .keys()
+            .map(|&id| {
+                if id == self.id {
+                    last_index
+                } else {
+                    core.progress
+                        .get(&id)
+                        .map_or(0, |progress| progress.matched)
+                }
+            })
+            .collect();
+        matched.sort_unstable_by(|a, b| b.cmp(a));
+        let majority = matched.get(matched.len() / 2).copied().unwrap_or(0);
+        // Entries from earlier terms can't be counted as committed just because a majority
+        // has them, see section 5.4.2 of the paper
+        if majority <= core.commit || core.log.term_at(majority) != Some(core.log.term()) {
+            return false;
+        }
+        core.commit = majority;
+        if !core.log.members().contains_key(&self.id) && core.log.members_index() <= core.commit {
+            log::warn!("This node was removed from the Raft group");
+            let term = core.log.term();
+            if let Err(e) = self.become_follower(core, term, None) {
+                log::error!("Failed to step down with error: '{}'", e);
+            }
+        }
+        self.service.notify();
+        true
+    }
+    /// Check on elections and the leader's lease, returning when this should be done
+    /// again
+    fn tick(&self) -> Instant {
+        let now = Instant::now();
+        let mut core = self.core.lock();
+        let result = match core.role {
+            Role::Leader => {
+                let contact = self
+                    .quorum_contact(&core, now)
+                    .map_or(core.heard_at, |contact| contact.max(core.heard_at));
+                if now >= contact + self.election_timeout * 2 {
+                    log::warn!("Lost touch with a majority of the Raft group");
+                    let term = core.log.term();
+                    self.become_follower(&mut core, term, None)
+                } else {
+                    return now + self.heartbeat_interval;
+                }
+            }
+            _ if now < core.election_deadline => Ok(()),
+            _ if core.log.members().contains_key(&self.id) => self.start_election(&mut core, now),
+            // We're waiting to be added to the group
+            _ => {
+                core.election_deadline = now + self.random_timeout();
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            log::error!("Failed to save the Raft state with error: '{}'", e);
+        }
+        let wake = match core.role {
+            Role::Leader => now + self.heartbeat_interval,
+            _ => core.election_deadline,
+        };
+        drop(core);
+        self.wake_peers();
+        self.changed();
+        wake
+    }
+    /// Returns what the task for the member `peer` should do next, given when it last
+    /// sent a call to the member
+    fn outgoing(&self, peer: u64, sent_at: Option<Instant>) -> Outgoing {
+        let mut core = self.core.lock();
+        if self.is_stopped() {
+            return Outgoing::Exit;
+        }
+        // A removed member should hear about its removal, so it stops asking for votes
+        let addr = match core.log.members().get(&peer) {
+            Some(addr) => addr.clone(),
+            None => match core.log.members_at(core.commit).get(&peer) {
+                Some(addr) if core.role == Role::Leader => addr.clone(),
+                _ => return Outgoing::Exit,
+            },
+        };
+        let term = core.log.term();
+        match core.role {
+            Role::Leader => {
+                let last_index = core.log.last_index();
+                let snapshot_index = core.log.snapshot().index;
+                let progress = core
+                    .progress
+                    .entry(peer)
+                    .or_insert_with(|| Progress::new(last_index + 1));
+                let since = sent_at.map(|sent_at| sent_at.elapsed());
+                let urgent = std::mem::replace(&mut progress.urgent, false);
+                let next = progress.next;
+                if next > last_index && !urgent {
+                    match since {
+                        Some(since) if since < self.heartbeat_interval => {
+                            return Outgoing::Idle(Some(self.heartbeat_interval - since))
+                        }
+                        _ => {}
+                    }
+                }
+                if next <= snapshot_index {
+                    let snapshot = core.log.snapshot().clone();
+                    drop(core);
+                    return match self.snapshot_message(term, snapshot) {
+                        Ok(message) => Outgoing::Send(addr, message),
+                        Err(e) => {
+                            log::error!(
+                                "Failed to read the Raft snapshot for node {}: '{}'",
+                                peer,
+                                e
+                            );
+                            Outgoing::Idle(Some(self.heartbeat_interval))
+                        }
+                    };
+                }
+                let prev_index = next - 1;
+                Outgoing::Send(
+                    addr,
+                    Message::Append {
+                        term,
+                        leader: self.id,
+                        prev_index,
+                        prev_term: core.log.term_at(prev_index).unwrap_or(0),
+                        entries: core.log.entries_from(next, MAX_APPEND),
+                        commit: core.commit,
+                    },
+                )
+            }
+            Role::Candidate => {
+                let last_index = core.log.last_index();
+                let last_term = core.log.last_term();
+                let progress = core
+                    .progress
+                    .entry(peer)
+                    .or_insert_with(|| Progress::new(last_index + 1));
+                if progress.asked {
+                    return Outgoing::Idle(None);
+                }
+                progress.asked = true;
+                Outgoing::Send(
+                    addr,
+                    Message::Vote {
+                        term,
+                        candidate: self.id,
+                        last_index,
+                        last_term,
+                    },
+                )
+            }
+            Role::Follower => Outgoing::Idle(None),
+        }
+    }
+    /// Returns a message with the snapshot described by `meta`
+    fn snapshot_message(&self, term: u64, meta: SnapshotMeta) -> Result<Message, String> {
+        let table = match &meta.name {
+            Some(name) => {
+                diskstore::get_saved(Some(snapshot::path_in(&self.snapdir, name)), &self.keys)
+                    .map_err(|e| e.to_string())?
+                    .ok_or_else(|| format!("The Raft snapshot '{}' is missing", name))?
+            }
+            None => HashMap::new(),
+        };
+        let data = format::write_table(Vec::new(), self.codec, &Keyring::none(), &table)
+            .map_err(|e| e.to_string())?;
+        Ok(Message::Snapshot {
+            term,
+            leader: self.id,
+            meta,
+            data,
+        })
+    }
+    /// Note that the call to the member `peer` failed
+    fn unreachable(&self, peer: u64) {
+        if let Some(progress) = self.core.lock().progress.get_mut(&peer) {
+            // Ask again, in case it comes back during the election
+            progress.asked = false;
+        }
+    }
+    /// Handle the reply of the member `peer` to `request`, which was sent at `sent_at`
+    fn handle_reply(&self, peer: u64, request: &Message, reply: Message, sent_at: Instant) {
+        let mut core = self.core.lock();
+        if reply.term() > core.log.term() {
+            if let Err(e) = self.become_follower(&mut core, reply.term(), None) {
+                log::error!("Failed to save the Raft state with error: '{}'", e);
+            }
+        } else if request.term() == core.log.term() {
+            match (core.role, request, reply) {
+                (
+                    Role::Candidate,
+                    Message::Vote { .. },
+                    Message::VoteReply { granted: true, .. },
+                ) => {
+                    core.votes.insert(peer);
+                    if self.has_votes(&core) {
+                        if let Err(e) = self.become_leader(&mut core, Instant::now()) {
+                            log::error!("Failed to take over as the leader: '{}'", e);
+                        }
+                    }
+                }
+                (
+                    Role::Leader,
+                    Message::Append { .. },
+                    Message::AppendReply { success, index, .. },
+                ) => {
+                    if let Some(progress) = core.progress.get_mut(&peer) {
+                        progress.contact = progress.contact.max(Some(sent_at));
+                        if success {
+                            progress.matched = progress.matched.max(index);
+                            progress.next = progress.matched + 1;
+                        } else {
+                            // Try again from where the member's log starts to differ
+                            progress.next = index
+                                .min(progress.next.saturating_sub(1))
+                                .max(progress.matched + 1);
+                        }
+                    }
+                    if success {
+                        self.advance_commit(&mut core);
+                    }
+                }
+                (
+                    Role::Leader,
+                    Message::Snapshot { meta, .. },
+                    Message::SnapshotReply { success, .. },
+                ) => {
+                    if let Some(progress) = core.progress.get_mut(&peer) {
+                        progress.contact = progress.contact.max(Some(sent_at));
+                        if success {
+                            progress.matched = progress.matched.max(meta.index);
+                            progress.next = progress.matched + 1;
+                        }
+                    }
+                    self.advance_commit(&mut core);
+                }
+                _ => {}
+            }
+        }
+        drop(core);
+        self.wake_peers();
+        self.changed();
+    }
+    /// Handle a call from another member, returning the reply
+    pub fn handle(&self, shared: &Shared, request: Message) -> Message {
+        match request {
+            Message::Vote {
+                term,
+                candidate,
+                last_index,
+                last_term,
+            } => {
+                let mut core = self.core.lock();
+                let granted = self
+                    .vote(&mut core, term, candidate, last_index, last_term)
+                    .unwrap_or_else(|e| {
+                        log::error!("Failed to save the Raft state with error: '{}'", e);
+                        false
+                    });
+                Message::VoteReply {
+                    term: core.log.term(),
+                    granted,
+                }
+            }
+            Message::Append {
+                term,
+                leader,
+                prev_index,
+                prev_term,
+                entries,
+                commit,
+            } => {
+                let mut core = self.core.lock();
+                let (success, index) = self
+                    .append(
+                        &mut core, term, leader, prev_index, prev_term, entries, commit,
+                    )
+                    .unwrap_or_else(|e| {
+                        log::error!("Failed to append to the Raft log with error: '{}'", e);
+                        (false, core.log.last_index() + 1)
+                    });
+                Message::AppendReply {
+                    term: core.log.term(),
+                    success,
+                    index,
+                }
+            }
+            Message::Snapshot {
+                term,
+                leader,
+                meta,
+                data,
+            } => self.install_snapshot(shared, term, leader, meta, data),
+            reply => {
+                log::warn!("Got an unexpected Raft message: {:?}", reply);
+                Message::VoteReply {
+                    term: self.core.lock().log.term(),
+                    granted: false,
+                }
+            }
+        }
+    }
+    /// Handle a request for a vote, returning `true` if we voted for the candidate
+    fn vote(
+        &self,
+        core: &mut Core,
+        term: u64,
+        candidate: u64,
+        last_index: u64,
+        last_term: u64,
+    ) -> io::Result<bool> {
+        let now = Instant::now();
+        if term < core.log.term() {
+            return Ok(false);
+        }
+        if term > core.log.term() {
+            // While we hear from a leader, nobody else can be elected. This is what makes
+            // the leader's lease safe, and it stops a node that was cut off from the rest
+            // of the group from disrupting it when it comes back
+            let has_leader = core.role == Role::Leader
+                || (core.leader.is_some() && now < core.heard_at + self.election_timeout);
+            if has_leader {
+                return Ok(false);
+            }
+            self.become_follower(core, term, None)?;
+        }
+        let up_to_date = (last_term, last_index) >= (core.log.last_term(), core.log.last_index());
+        let can_vote = core
+            .log
+            .voted_for()
+            .is_none_or(|voted_for| voted_for == candidate);
+        if !up_to_date || !can_vote || core.role != Role::Follower {
+            return Ok(false);
+        }
+        core.log.set_hard_state(term, Some(candidate))?;
+        core.election_deadline = now + self.random_timeout();
+        log::info!("Voted for node {} in term {}", candidate, term);
+        Ok(true)
+    }
+    /// Handle entries sent by the leader, returning whether they were appended and the
+    /// index for the reply
+    #[allow(clippy::too_many_arguments)]
+    fn append(
+        &self,
+        core: &mut Core,
+        term: u64,
+        leader: u64,
+        prev_index: u64,
+        prev_term: u64,
+        entries: Vec<Entry>,
+        commit: u64,
+    ) -> io::Result<(bool, u64)> {
+        if term < core.log.term() {
+            return Ok((false, 0));
+        }
+        self.become_follower(core, term, Some(leader))?;
+        let now = Instant::now();
+        core.heard_at = now;
+        core.election_deadline = now + self.random_timeout();
+        let snapshot = core.log.snapshot().index;
+        if prev_index > core.log.last_index() {
+            return Ok((false, core.log.last_index() + 1));
+        }
+        // The entries in the snapshot are committed, so they match the leader's
+        if prev_index >= snapshot && core.log.term_at(prev_index) != Some(prev_term) {
+            return Ok((false, core.log.first_index_of_term(prev_index)));
+        }
+        let last_new = prev_index + entries.len() as u64;
+        let mut new = Vec::new();
+        for entry in entries {
+            if entry.index <= snapshot {
+                continue;
+            }
+            if new.is_empty() {
+                match core.log.term_at(entry.index) {
+                    Some(term) if term == entry.term => continue,
+                    // Entries that conflict with the leader's were never committed
+                    Some(_) => core.log.truncate(entry.index)?,
+                    None => {}
+                }
+            }
+            new.push(entry);
+        }
+        if !new.is_empty() {
+            core.log.append(new)?;
+        }
+        let commit = commit.min(last_new);
+        if commit > core.commit {
+            core.commit = commit;
+            self.service.notify();
+        }
+        Ok((true, last_new.max(snapshot)))
+    }
+    /// Replace the data with a snapshot sent by the leader
+    fn install_snapshot(
+        &self,
+        shared: &Shared,
+        term: u64,
+        leader: u64,
+        meta: SnapshotMeta,
+        data: Vec<u8>,
+    ) -> Message {
+        let reply = |success| Message::SnapshotReply {
+            term: self.core.lock().log.term(),
+            success,
+        };
+        {
+            let mut core = self.core.lock();
+            if term < core.log.term() {
+                drop(core);
+                return reply(false);
+            }
+            if let Err(e) = self.become_follower(&mut core, term, Some(leader)) {
+                log::error!("Failed to save the Raft state with error: '{}'", e);
+                drop(core);
+                return reply(false);
+            }
+            let now = Instant::now();
+            core.heard_at = now;
+            core.election_deadline = now + self.random_timeout();
+            if meta.index <= core.commit {
+                // We already have everything in it
+                drop(core);
+                return reply(true);
+            }
+        }
+        let _applying = self.applying.lock();
+        let result = (|| -> TResult<()> {
+            let table = format::read_table(&data[..], &Keyring::none())?;
+            // The snapshot has to be saved before the log can continue from it
+            let mut snapshots = shared.snapshots.lock();
+            snapshots.scan()?;
+            let name = format!("raft-{}-{}", meta.term, meta.index);
+            diskstore::flush_data(
+                snapshots.path_of(&name),
+                &table,
+                shared.storage.compression(),
+                &shared.keys,
+            )?;
+            let saved = SnapshotMeta {
+                name: Some(name),
+                ..meta.clone()
+            };
+            self.switch_snapshot(&mut snapshots, saved)?;
+            drop(snapshots);
+            let mut whandle = shared.table.write();
+            whandle.get_mut_ref().replace(table.clone())?;
+            shared.log_reset_to(&table);
+            drop(whandle);
+            let mut core = self.core.lock();
+            core.commit = core.commit.max(meta.index);
+            core.applied = core.applied.max(meta.index);
+            Ok(())
+        })();
+        match result {
+            Ok(()) => {
+                log::info!(
+                    "Installed a snapshot from Raft leader {} up to index {}",
+                    leader,
+                    meta.index
+                );
+                self.changed();
+                reply(true)
+            }
+            Err(e) => {
+                log::error!("Failed to install a Raft snapshot with error: '{}'", e);
+                reply(false)
+            }
+        }
+    }
+    /// Continue the log from the snapshot described by `meta`, which has already been
+    /// saved, and delete the old snapshot
+    fn switch_snapshot(&self, snapshots: &mut SnapshotEngine, meta: SnapshotMeta) -> TResult<()> {
+        let old = {
+            let mut core = self.core.lock();
+            let old = core.log.snapshot().name.clone();
+            core.log.compact(meta.clone())?;
+            old
+        };
+        if let Some(old) = old.filter(|old| Some(old) != meta.name.as_ref()) {
+            if let Err(e) = snapshots.delete(&old) {
+                log::error!("Failed to delete Raft snapshot '{}': '{}'", old, e);
+            }
+        }
+        Ok(())
+    }
+    /// Apply the committed entries that weren't applied yet, and compact the log if
+    /// enough of them were applied since the last snapshot
+    fn apply_committed(&self, shared: &Shared) {
+        let _applying = self.applying.lock();
+        loop {
+            let entries = {
+                let core = self.core.lock();
+                let mut entries = core.log.entries_from(core.applied + 1, MAX_APPLY);
+                entries.retain(|entry| entry.index <= core.commit);
+                entries
+            };
+            if entries.is_empty() {
+                break;
+            }
+            let responses: Vec<(u64, u64, Vec<u8>)> = {
+                let mut whandle = shared.table.write();
+                entries
+                    .into_iter()
+                    .map(|entry| {
+                        let response = match &entry.command {
+                            Command::Write(args) => {
+                                machine::apply(shared, whandle.get_mut_ref(), args)
+                            }
+                            Command::Noop | Command::Members(_) => {
+                                responses::fresp::R_OKAY.to_owned()
+                            }
+                        };
+                        (entry.index, entry.term, response)
+                    })
+                    .collect()
+            };
+            let mut core = self.core.lock();
+            for (index, term, response) in responses {
+                core.applied = index;
+                if let Some((waiting_term, sender)) = core.waiting.remove(&index) {
+                    // Someone else's entry took the place of the one that the client is
+                    // waiting for
+                    let response = if waiting_term == term {
+                        response
+                    } else {
+                        leadership_changed()
+                    };
+                    let _ = sender.send(response);
+                }
+            }
+            drop(core);
+            self.changed();
+        }
+        self.maybe_compact(shared);
+    }
+    /// Compact the log if enough entries were applied since the last snapshot
+    ///
+    /// This is only called while `applying` is held, so the table has the data as of
+    /// the last applied entry
+    fn maybe_compact(&self, shared: &Shared) {
+        let meta = {
+            let core = self.core.lock();
+            let snapshot = core.log.snapshot();
+            if core.applied < snapshot.index + self.snapshot_after {
+                return;
+            }
+            let term = core.log.term_at(core.applied).unwrap_or(0);
+            SnapshotMeta {
+                index: core.applied,
+                term,
+                members: core.log.members_at(core.applied),
+                name: Some(format!("raft-{}-{}", term, core.applied)),
+            }
+        };
+        let index = meta.index;
+        let mut snapshots = shared.snapshots.lock();
+        let result = match snapshots.create(shared, meta.name.as_deref()) {
+            Some(Ok(_)) => self.switch_snapshot(&mut snapshots, meta),
+            Some(Err(e)) => Err(e),
+            // The database is shutting down
+            None => return,
+        };
+        match result {
+            Ok(()) => log::info!("Compacted the Raft log up to index {}", index),
+            Err(e) => log::error!("Failed to compact the Raft log with error: '{}'", e),
+        }
+    }
+    /// Append an entry with `command` if we're the leader, returning a receiver for the
+    /// response once it is applied
+    fn submit(
+        &self,
+        core: &mut Core,
+        command: Command,
+    ) -> Result<oneshot::Receiver<Vec<u8>>, Vec<u8>> {
+        if core.role != Role::Leader {
+            return Err(self.not_leader(core));
+        }
+        let term = core.log.term();
+        let index = core.log.last_index() + 1;
+        if let Err(e) = core.log.append(vec![Entry {
+            index,
+            term,
+            command,
+        }]) {
+            log::error!("Failed to append to the Raft log with error: '{}'", e);
+            return Err(responses::fresp::R_SERVER_ERR.to_owned());
+        }
+        let (sender, receiver) = oneshot::channel();
+        core.waiting.insert(index, (term, sender));
+        self.advance_commit(core);
+        Ok(receiver)
+    }
+    /// Wait for the response to a submitted entry
+    async fn wait_for(&self, submitted: Result<oneshot::Receiver<Vec<u8>>, Vec<u8>>) -> Vec<u8> {
+        match submitted {
+            Ok(receiver) => {
+                self.wake_peers();
+                receiver
+                    .await
+                    .unwrap_or_else(|_| responses::fresp::R_SERVER_ERR.to_owned())
+            }
+            Err(response) => response,
+        }
+    }
+    /// Make a write through the log, returning the response once it is applied
+    pub async fn propose(&self, command: Command) -> Vec<u8> {
+        let submitted = self.submit(&mut self.core.lock(), command);
+        self.wait_for(submitted).await
+    }
+    /// Add the member `id` at `addr`, or remove it if `addr` is `None`, returning the
+    /// response once the change is applied
+    pub async fn change_members(&self, id: u64, addr: Option<String>) -> Vec<u8> {
+        let submitted = {
+            let mut core = self.core.lock();
+            if core.role != Role::Leader {
+                return self.not_leader(&core);
+            }
+            // Two changes in flight could leave two majorities that don't overlap
+            if core.log.members_index() > core.commit {
+                return responses::other_error("A membership change is in progress");
+            }
+            let mut members = core.log.members().clone();
+            match addr {
+                Some(_) if members.contains_key(&id) => {
+                    return responses::fresp::R_OVERWRITE_ERR.to_owned()
+                }
+                Some(addr) => {
+                    log::info!("Adding node {} at {} to the Raft group", id, addr);
+                    members.insert(id, addr);
+                }
+                None if !members.contains_key(&id) => return responses::fresp::R_NIL.to_owned(),
+                None if members.len() == 1 => {
+                    return responses::other_error("Can't remove the last member")
+                }
+                None => {
+                    log::info!("Removing node {} from the Raft group", id);
+                    members.remove(&id);
+                }
+            }
+            self.submit(&mut core, Command::Members(members))
+        };
+        // The new member needs a task
+        self.service.notify();
+        self.wait_for(submitted).await
+    }
+    /// Wait until reads can be served, that is, until we know that we're still the
+    /// leader and we've applied every entry that was committed when the read came in
+    ///
+    /// This returns the response for the client if the read can't be served
+    pub async fn read_barrier(&self) -> Result<(), Vec<u8>> {
+        // Every change after this point wakes us up, so we can't miss one
+        let mut watcher = self.watcher.clone();
+        let start = Instant::now();
+        let deadline = start + self.election_timeout;
+        let mut read_index = None;
+        let mut asked = false;
+        loop {
+            {
+                let mut core = self.core.lock();
+                if core.role != Role::Leader {
+                    return Err(self.not_leader(&core));
+                }
+                if read_index.is_none() && core.commit >= core.term_start {
+                    let now = Instant::now();
+                    let confirmed = self.quorum_contact(&core, now).is_some_and(|contact| {
+                        contact >= start || now < contact + self.election_timeout * 9 / 10
+                    });
+                    if confirmed {
+                        read_index = Some(core.commit);
+                    }
+                }
+                match read_index {
+                    Some(read_index) if core.applied >= read_index => return Ok(()),
+                    Some(_) => {}
+                    None if !asked => {
+                        // Make sure that we hear from the members soon
+                        for progress in core.progress.values_mut() {
+                            progress.urgent = true;
+                        }
+                        drop(core);
+                        self.wake_peers();
+                        asked = true;
+                    }
+                    None => {}
+                }
+            }
+            let now = Instant::now();
+            if now >= deadline || self.is_stopped() {
+                return Err(responses::other_error("Couldn't confirm the leadership"));
+            }
+            let _ = time::timeout(deadline - now, watcher.recv()).await;
+        }
+    }
+    /// Describe the state of the node, one line for the node followed by a line for each
+    /// member
+    fn report(&self) -> Vec<String> {
+        let core = self.core.lock();
+        let now = Instant::now();
+        let mut lines = vec![format!(
+            "id={} role={} term={} leader={} commit={} applied={} last_index={} snapshot_index={}",
+            self.id,
+            core.role.as_str(),
+            core.log.term(),
+            core.leader
+                .map_or_else(|| "none".to_owned(), |leader| leader.to_string()),
+            core.commit,
+            core.applied,
+            core.log.last_index(),
+            core.log.snapshot().index
+        )];
+        for (id, addr) in core.log.members() {
+            match core.progress.get(id) {
+                Some(progress) if core.role == Role::Leader => lines.push(format!(
+                    "member id={} addr={} match={} next={} contact={}",
+                    id,
+                    addr,
+                    progress.matched,
+                    progress.next,
+                    progress.contact.map_or_else(
+                        || "never".to_owned(),
+                        |contact| format!("{}ms", now.duration_since(contact).as_millis())
+                    )
+                )),
+                _ => lines.push(format!("member id={} addr={}", id, addr)),
+            }
+        }
+        lines
+    }
+}
+
+/// The response for a client whose write was cut short by a change of leader
+fn leadership_changed() -> Vec<u8> {
+    responses::other_error("Leadership changed, the write may or may not have been made")
+}
+
+/// Run the Raft node of the database (if there is one) until the database is shutting
+/// down: this holds the elections, applies the committed entries and compacts the log
+pub async fn raft_service(handle: CoreDB) {
+    let raft = match &handle.shared.raft {
+        Some(raft) => raft.clone(),
+        None => return,
+    };
+    log::info!(
+        "Starting Raft node {} with {} member(s)",
+        raft.id,
+        raft.members().len()
+    );
+    while !handle.shared.is_termsig() && !raft.is_stopped() {
+        raft.ensure_peers();
+        let wake = raft.tick();
+        raft.apply_committed(&handle.shared);
+        tokio::select! {
+            _ = time::delay_until(time::Instant::from_std(wake)) => {}
+            _ = raft.service.notified() => {}
+        }
+    }
+    raft.stop();
+}
+
+/// Run a write in Raft mode, where `action` is the action in upper case
+///
+/// The write is validated here, then appended to the log by the leader and the response
+/// is sent once the write is applied
+pub async fn write(
+    handle: &CoreDB,
+    con: &mut Connection,
+    act: ActionGroup,
+    action: &str,
+) -> TResult<()> {
+    let raft = match &handle.shared.raft {
+        Some(raft) => raft.clone(),
+        None => {
+            return con
+                .write_response(responses::fresp::R_RAFT_DISABLED.to_owned())
+                .await
+        }
+    };
+    let mut args = act.get_ref().clone();
+    args[0] = action.to_owned();
+    if !machine::is_valid(&args) {
+        return con
+            .write_response(responses::fresp::R_ACTION_ERR.to_owned())
+            .await;
+    }
+    let response = raft.propose(Command::Write(args)).await;
+    con.write_response(response).await
+}
+
+/// Run a `RAFT` query
+///
+/// - `RAFT STATUS` returns a line with the state of this node, followed by a line for
+///   each member (with how far along it is, on the leader)
+/// - `RAFT ADD <id> <host> <port>` adds the node `id`, which accepts connections at
+///   `host:port`, to the group
+/// - `RAFT REMOVE <id>` removes the node `id` from the group
+///
+/// Members are added and removed through the leader, one at a time
+pub async fn raft(handle: &CoreDB, con: &mut Connection, act: ActionGroup) -> TResult<()> {
+    let raft = match &handle.shared.raft {
+        Some(raft) => raft.clone(),
+        None => {
+            return con
+                .write_response(responses::fresp::R_RAFT_DISABLED.to_owned())
+                .await
+        }
+    };
+    let args: Vec<String> = act.into_iter().collect();
+    let response = match args.as_slice() {
+        [subaction] if subaction.eq_ignore_ascii_case("STATUS") => {
+            let lines = raft.report();
+            con.write_response(GroupBegin(lines.len())).await?;
+            for line in lines {
+                con.write_response(BytesWrapper(Bytes::from(line))).await?;
+            }
+            return Ok(());
+        }
+        [subaction, id, host, port] if subaction.eq_ignore_ascii_case("ADD") => {
+            match (id.parse::<u64>(), port.parse::<u16>()) {
+                (Ok(id), Ok(port)) => {
+                    raft.change_members(id, Some(format!("{}:{}", host, port)))
+                        .await
+                }
+                _ => responses::fresp::R_ACTION_ERR.to_owned(),
+            }
+        }
+        [subaction, id] if subaction.eq_ignore_ascii_case("REMOVE") => match id.parse::<u64>() {
+            Ok(id) => raft.change_members(id, None).await,
+            Err(_) => responses::fresp::R_ACTION_ERR.to_owned(),
+        },
+        _ => responses::fresp::R_ACTION_ERR.to_owned(),
+    };
+    con.write_response(response).await
+}
+
+#[cfg(test)]
+/// Start node `id` of a test group with `members`, accepting connections on `listener`
+///
+/// This returns the node, a sender that stops the server and the data directory
+async fn start_test_node(
+    id: u64,
+    members: Vec<crate::config::RaftMember>,
+    listener: tokio::net::TcpListener,
+) -> (CoreDB, oneshot::Sender<()>, PathBuf) {
+    use crate::config::{BGSave, ParsedConfig, RaftConfig};
+    use crate::coredb::storage::Engine;
+    let dir = std::env::temp_dir().join(format!("tdb-test-raft-{}-{}", std::process::id(), id));
+    let _ = std::fs::remove_dir_all(&dir);
+    let mut cfg = ParsedConfig::default();
+    cfg.bgsave = BGSave::Disabled;
+    cfg.storage = StorageConfig::new(
+        &dir,
+        "data.bin",
+        "snapshots",
+        false,
+        Codec::None,
+        Engine::Memory,
+    );
+    cfg.raft = RaftConfig::Enabled(RaftPref::new(id, members, 150, 30, 5));
+    let db = CoreDB::new(cfg, None, Keyring::none(), None).unwrap();
+    let (stop, stopped) = oneshot::channel::<()>();
+    tokio::spawn(crate::dbnet::test_run(listener, db.clone(), stopped));
+    (db, stop, dir)
+}
+
+#[tokio::test]
+async fn test_raft_group() {
+    use crate::config::RaftMember;
+    use crate::replication::replica::run_query;
+    use tokio::net::TcpListener;
+    let mut listeners = Vec::new();
+    for _ in 0..4 {
+        listeners.push(TcpListener::bind("127.0.0.1:0").await.unwrap());
+    }
+    let addrs: Vec<String> = listeners
+        .iter()
+        .map(|listener| listener.local_addr().unwrap().to_string())
+        .collect();
+    let members: Vec<RaftMember> = (1..=3)
+        .map(|id| RaftMember {
+            id,
+            addr: addrs[id as usize - 1].clone(),
+        })
+        .collect();
+    let mut listeners = listeners.into_iter();
+    let mut nodes = Vec::new();
+    for id in 1..=3 {
+        nodes.push(start_test_node(id, members.clone(), listeners.next().unwrap()).await);
+    }
+    let raft_of = |node: &CoreDB| node.shared.raft.clone().unwrap();
+    let value_of = |node: &CoreDB, key: &str| {
+        node.acquire_read()
+            .get_ref()
+            .get(key)
+            .unwrap()
+            .map(|value| value.get_blob().clone())
+    };
+    // Wait until a leader is elected and its first entry is committed
+    let mut waited = 0;
+    let leader = loop {
+        let leader = nodes.iter().position(|(node, _, _)| {
+            let raft = raft_of(node);
+            let core = raft.core.lock();
+            core.role == Role::Leader && core.commit >= core.term_start
+        });
+        if let Some(leader) = leader {
+            break leader;
+        }
+        assert!(waited < 250, "No leader was elected");
+        time::delay_for(Duration::from_millis(20)).await;
+        waited += 1;
+    };
+    let follower = (leader + 1) % 3;
+    assert_eq!(
+        run_query(&addrs[leader], "SET x 100").await,
+        *responses::fresp::R_OKAY
+    );
+    assert_eq!(
+        run_query(&addrs[leader], "GET x").await,
+        b"#2\n*1\n#2\n&1\n+3\n100\n".to_vec()
+    );
+    assert_eq!(
+        run_query(&addrs[leader], "SET x 200").await,
+        *responses::fresp::R_OVERWRITE_ERR
+    );
+    // Followers send clients to the leader
+    assert_eq!(
+        run_query(&addrs[follower], "SET y 100").await,
+        responses::other_error(&format!("Not the leader, try {}", addrs[leader]))
+    );
+    assert_eq!(
+        run_query(&addrs[follower], "GET x").await,
+        responses::other_error(&format!("Not the leader, try {}", addrs[leader]))
+    );
+    // Enough writes for the log to be compacted
+    for n in 0..10 {
+        let query = format!("USET key{} {}", n, n);
+        run_query(&addrs[leader], &query).await;
+    }
+    assert!(raft_of(&nodes[leader].0).core.lock().log.snapshot().index > 0);
+    // A new node gets the latest snapshot and the entries after it
+    let (new_node, new_stop, new_dir) =
+        start_test_node(4, Vec::new(), listeners.next().unwrap()).await;
+    let port = addrs[3].rsplit(':').next().unwrap();
+    assert_eq!(
+        run_query(&addrs[leader], &format!("RAFT ADD 4 127.0.0.1 {}", port)).await,
+        *responses::fresp::R_OKAY
+    );
+    assert_eq!(
+        run_query(&addrs[leader], &format!("RAFT ADD 4 127.0.0.1 {}", port)).await,
+        *responses::fresp::R_OVERWRITE_ERR
+    );
+    nodes.push((new_node, new_stop, new_dir));
+    let mut waited = 0;
+    while nodes
+        .iter()
+        .any(|(node, _, _)| value_of(node, "key9").is_none())
+    {
+        assert!(waited < 250, "The data didn't reach every node");
+        time::delay_for(Duration::from_millis(20)).await;
+        waited += 1;
+    }
+    for (node, _, _) in &nodes {
+        assert_eq!(value_of(node, "x"), Some(Bytes::from("100")));
+        assert_eq!(raft_of(node).members().len(), 4);
+    }
+    assert!(raft_of(&nodes[3].0).core.lock().log.snapshot().index > 0);
+    assert_eq!(
+        run_query(&addrs[leader], "RAFT REMOVE 4").await,
+        *responses::fresp::R_OKAY
+    );
+    assert_eq!(
+        run_query(&addrs[leader], "RAFT REMOVE 4").await,
+        *responses::fresp::R_NIL
+    );
+    assert_eq!(raft_of(&nodes[leader].0).members().len(), 3);
+    for (node, stop, dir) in nodes {
+        raft_of(&node).stop();
+        let _ = stop.send(());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,421 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # Talking to the other nodes
+//!
+//! Every node connects to the other members of the group like any other client and
+//! sends `RAFT PEER <id>` with its own ID. Once the other node replies with an okay
+//! response, the connection carries a [`Message`] in each direction for every call,
+//! each of them framed as:
+//! ```text
+//! | message length (u64, little-endian) | message (bincode) |
+//! ```
+//! A node runs a task for each of the other members, which sends that member whatever
+//! it needs: votes while the node is a candidate, and entries, heartbeats or the latest
+//! snapshot while it is the leader. Lost links are reestablished on the next call
+
+use super::raftlog::SnapshotMeta;
+use super::{Entry, Raft};
+use crate::admin::auth;
+use crate::coredb::CoreDB;
+use crate::dbnet::Terminator;
+use crate::protocol::{responses, Connection, Query};
+use libtdb::terrapipe;
+use libtdb::TResult;
+use serde::{Deserialize, Serialize};
+use std::io::{self, ErrorKind};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::Notify;
+use tokio::time::{self, Duration};
+
+/// How long we wait for a member to install a snapshot
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The longest reply that we accept from another member, in bytes. Replies only carry a
+/// few numbers, so anything longer than this is garbage
+const MAX_REPLY_LEN: u64 = 1024;
+
+/// A call from one node to another, or the reply to it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Message {
+    /// A candidate asks for a vote
+    Vote {
+        term: u64,
+        candidate: u64,
+        last_index: u64,
+        last_term: u64,
+    },
+    /// The reply to [`Message::Vote`]
+    VoteReply { term: u64, granted: bool },
+    /// The leader sends the entries after `prev_index`, or a heartbeat if there are none
+    Append {
+        term: u64,
+        leader: u64,
+        prev_index: u64,
+        prev_term: u64,
+        entries: Vec<Entry>,
+        commit: u64,
+    },
+    /// The reply to [`Message::Append`], where `index` is the index of the last entry
+    /// that matches the leader's if it succeeded, or the index that the leader should
+    /// try next if it didn't
+    AppendReply {
+        term: u64,
+        success: bool,
+        index: u64,
+    },
+    /// The leader sends its latest snapshot to a member that is too far behind, with the
+    /// data in the format of the dump file
+    Snapshot {
+        term: u64,
+        leader: u64,
+        meta: SnapshotMeta,
+        data: Vec<u8>,
+    },
+    /// The reply to [`Message::Snapshot`]
+    SnapshotReply { term: u64, success: bool },
+}
+
+impl Message {
+    /// Returns the term of the sender
+    pub fn term(&self) -> u64 {
+        match self {
+            Message::Vote { term, .. }
+            | Message::VoteReply { term, .. }
+            | Message::Append { term, .. }
+            | Message::AppendReply { term, .. }
+            | Message::Snapshot { term, .. }
+            | Message::SnapshotReply { term, .. } => *term,
+        }
+    }
+}
+
+/// What a peer task should do next
+pub enum Outgoing {
+    /// Send this message to the member at this address
+    Send(String, Message),
+    /// Wait until there's something to do, or for at most this long
+    Idle(Option<Duration>),
+    /// The peer isn't a member any more
+    Exit,
+}
+
+/// Encode `message` as a frame
+fn encode_message(message: &Message) -> io::Result<Vec<u8>> {
+    let encoded =
+        bincode::serialize(message).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+    let mut frame = Vec::with_capacity(8 + encoded.len());
+    frame.extend_from_slice(&(encoded.len() as u64).to_le_bytes());
+    frame.extend_from_slice(&encoded);
+    Ok(frame)
+}
+
+/// Decode the message in a frame's payload
+fn decode_message(payload: &[u8]) -> io::Result<Message> {
+    bincode::deserialize(payload).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+}
+
+/// Make sure that a frame which is `len` bytes long isn't longer than `max` bytes
+///
+/// The length comes from the network, so this has to be checked before anything is
+/// allocated or buffered for the frame
+fn check_len(len: u64, max: u64) -> io::Result<usize> {
+    if len > max {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("The message is too long ({} bytes)", len),
+        ));
+    }
+    Ok(len as usize)
+}
+
+/// Read a frame from `stream`, returning the message in it. Frames longer than `max`
+/// bytes are refused
+async fn read_message<R: AsyncRead + Unpin>(stream: &mut R, max: u64) -> io::Result<Message> {
+    let mut len = [0u8; 8];
+    stream.read_exact(&mut len).await?;
+    let mut payload = vec![0u8; check_len(u64::from_le_bytes(len), max)?];
+    stream.read_exact(&mut payload).await?;
+    decode_message(&payload)
+}
+
+/// A connection to another member
+struct Link {
+    /// The address of the member
+    addr: String,
+    /// The connection
+    stream: BufReader<TcpStream>,
+}
+
+impl Link {
+    /// Connect to the member at `addr`, sending it `password` if it is set and then
+    /// introducing ourselves as the node `id`
+    async fn connect(
+        addr: &str,
+        id: u64,
+        password: Option<&str>,
+        timeout: Duration,
+    ) -> Result<Self, String> {
+        let stream = match time::timeout(timeout, TcpStream::connect(addr)).await {
+            Ok(Ok(stream)) => stream,
+            Ok(Err(e)) => return Err(format!("Failed to connect: {}", e)),
+            Err(_) => return Err("Timed out while connecting".to_owned()),
+        };
+        stream.set_nodelay(true).map_err(|e| e.to_string())?;
+        let mut stream = BufReader::new(stream);
+        if let Some(password) = password {
+            auth::send_auth(&mut stream, password, timeout).await?;
+        }
+        stream
+            .write_all(&terrapipe::proc_query(format!("RAFT PEER {}", id)))
+            .await
+            .map_err(|e| e.to_string())?;
+        let mut reply = vec![0u8; responses::fresp::R_OKAY.len()];
+        match time::timeout(timeout, stream.read_exact(&mut reply)).await {
+            Ok(Ok(_)) if reply == *responses::fresp::R_OKAY => Ok(Link {
+                addr: addr.to_owned(),
+                stream,
+            }),
+            Ok(Ok(_)) => Err("The node refused the connection".to_owned()),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err("The node stopped responding".to_owned()),
+        }
+    }
+    /// Send `request` and wait for the reply
+    async fn call(&mut self, request: &Message, timeout: Duration) -> Result<Message, String> {
+        let frame = encode_message(request).map_err(|e| e.to_string())?;
+        let exchange = async {
+            self.stream.write_all(&frame).await?;
+            read_message(&mut self.stream, MAX_REPLY_LEN).await
+        };
+        match time::timeout(timeout, exchange).await {
+            Ok(Ok(reply)) => Ok(reply),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err("The node stopped responding".to_owned()),
+        }
+    }
+}
+
+/// Send the member `peer` whatever it needs for as long as it is a member, until the
+/// database is shutting down
+///
+/// `notify` wakes the task up when there's something new to send
+pub async fn replicate(raft: Arc<Raft>, peer: u64, notify: Arc<Notify>) {
+    let mut link: Option<Link> = None;
+    let mut down = false;
+    let mut sent_at: Option<Instant> = None;
+    while !raft.is_stopped() {
+        let (addr, request) = match raft.outgoing(peer, sent_at) {
+            Outgoing::Send(addr, request) => (addr, request),
+            Outgoing::Idle(wait) => {
+                // Don't keep an idle link around, the member might have moved
+                if wait.is_none() {
+                    link = None;
+                }
+                match wait {
+                    Some(wait) => {
+                        tokio::select! {
+                            _ = notify.notified() => {}
+                            _ = time::delay_for(wait) => {}
+                        }
+                    }
+                    None => notify.notified().await,
+                }
+                continue;
+            }
+            Outgoing::Exit => break,
+        };
+        let timeout = match request {
+            Message::Snapshot { .. } => SNAPSHOT_TIMEOUT,
+            _ => raft.election_timeout,
+        };
+        let now = Instant::now();
+        sent_at = Some(now);
+        let result = async {
+            if link.as_ref().is_none_or(|link| link.addr != addr) {
+                link = Some(
+                    Link::connect(
+                        &addr,
+                        raft.id,
+                        raft.password.as_deref(),
+                        raft.election_timeout,
+                    )
+                    .await?,
+                );
+            }
+            match link.as_mut() {
+                Some(link) => link.call(&request, timeout).await,
+                None => unreachable!("The link was just set up"),
+            }
+        }
+        .await;
+        match result {
+            Ok(reply) => {
+                if down {
+                    log::info!("Reconnected to Raft node {} at {}", peer, addr);
+                    down = false;
+                }
+                raft.handle_reply(peer, &request, reply, now);
+            }
+            Err(e) => {
+                link = None;
+                if !down {
+                    log::warn!("Can't reach Raft node {} at {}: '{}'", peer, addr, e);
+                    down = true;
+                }
+                raft.unreachable(peer);
+                // Don't hammer a node that is down
+                time::delay_for(raft.heartbeat_interval).await;
+            }
+        }
+    }
+    raft.forget_peer(peer);
+}
+
+/// Returns `true` if `query` is a `RAFT PEER` query
+///
+/// `RAFT PEER` takes over the connection, so the connection handler has to check for it
+/// before handing the query off to the query engine
+pub fn is_peer(query: &Query) -> bool {
+    match query {
+        Query::Simple(act) => match act.get_ref().as_slice() {
+            [action, subaction, ..] => {
+                action.eq_ignore_ascii_case("RAFT") && subaction.eq_ignore_ascii_case("PEER")
+            }
+            _ => false,
+        },
+        Query::Pipelined(_) => false,
+    }
+}
+
+/// Run a `RAFT PEER <id>` query, which is sent by the other members of the group
+///
+/// This replies to every message that the member sends, until it disconnects or the
+/// server shuts down
+pub async fn serve(
+    handle: &CoreDB,
+    con: &mut Connection,
+    act: Query,
+    terminator: &mut Terminator,
+) -> TResult<()> {
+    let raft = match &handle.shared.raft {
+        Some(raft) => raft.clone(),
+        None => {
+            con.write_response(responses::fresp::R_RAFT_DISABLED.to_owned())
+                .await?;
+            return con.flush_stream().await;
+        }
+    };
+    let peer = match &act {
+        Query::Simple(act) => match act.get_ref().as_slice() {
+            [_, _, id] => id.parse::<u64>().ok(),
+            _ => None,
+        },
+        Query::Pipelined(_) => None,
+    };
+    let peer = match peer {
+        Some(peer) => peer,
+        None => {
+            con.write_response(responses::fresp::R_ACTION_ERR.to_owned())
+                .await?;
+            return con.flush_stream().await;
+        }
+    };
+    con.write_response(responses::fresp::R_OKAY.to_owned())
+        .await?;
+    con.flush_stream().await?;
+    log::debug!("Raft node {} connected", peer);
+    loop {
+        let request = tokio::select! {
+            request = read_request(con) => match request {
+                Ok(request) => request,
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                    log::debug!("Raft node {} disconnected", peer);
+                    return Ok(());
+                }
+                Err(e) => return Err(e.into()),
+            },
+            _ = terminator.receive_signal() => return Ok(()),
+        };
+        let reply = raft.handle(&handle.shared, request);
+        con.write_response(encode_message(&reply)?).await?;
+        con.flush_stream().await?;
+    }
+}
+
+/// Read a message sent by another member
+///
+/// Messages are bound by the maximum query size, like every other query, so a snapshot
+/// can only be sent to a member if it fits in `limits.max_query_size`
+async fn read_request(con: &mut Connection) -> io::Result<Message> {
+    let mut len = [0u8; 8];
+    len.copy_from_slice(&con.read_raw(8).await?);
+    let len = check_len(u64::from_le_bytes(len), con.max_query_size() as u64)?;
+    let payload = con.read_raw(len).await?;
+    decode_message(&payload)
+}
+
+#[test]
+fn test_raft_messages() {
+    use super::Command;
+    let append = Message::Append {
+        term: 3,
+        leader: 1,
+        prev_index: 7,
+        prev_term: 2,
+        entries: vec![Entry {
+            index: 8,
+            term: 3,
+            command: Command::Write(vec!["SET".to_owned(), "x".to_owned(), "1".to_owned()]),
+        }],
+        commit: 7,
+    };
+    let reply = Message::AppendReply {
+        term: 3,
+        success: true,
+        index: 8,
+    };
+    let mut stream = encode_message(&append).unwrap();
+    stream.extend(encode_message(&reply).unwrap());
+    let mut reader = &stream[..];
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        assert_eq!(read_message(&mut reader, 1024).await.unwrap(), append);
+        assert_eq!(
+            read_message(&mut reader, MAX_REPLY_LEN).await.unwrap(),
+            reply
+        );
+        assert!(read_message(&mut reader, 1024).await.is_err());
+        // A length that is larger than the limit is refused before it's allocated
+        let mut oversized = &u64::MAX.to_le_bytes()[..];
+        let e = read_message(&mut oversized, 1024).await.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        let frame = encode_message(&append).unwrap();
+        let mut reader = &frame[..];
+        let max = frame.len() as u64 - 9;
+        let e = read_message(&mut reader, max).await.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    });
+    assert_eq!(append.term(), 3);
+}
@@ -0,0 +1,416 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # The Raft log
+//!
+//! Everything that a node has to remember across restarts is kept in the `raft` directory
+//! of the data directory:
+//! - `state` has the current term and the node that we voted for in it
+//! - `snapshot` has the index, term and membership of the last entry in the latest
+//!   snapshot, along with the name of the snapshot (in the snapshot directory) with the data
+//! - `log` has the entries after the snapshot, one record for each entry:
+//!   ```text
+//!   | entry length (u32) | entry CRC (u32) | entry |
+//!   ```
+//!
+//! All integers are little-endian and everything else is encoded with bincode. `state`
+//! and `snapshot` are replaced atomically, and appends to `log` are synced to disk before
+//! they return, since a node must not forget an entry that it has acknowledged. A record
+//! that was only partly written when the server crashed is cut off when the log is opened
+
+use super::{Entry, Members};
+use libtdb::TResult;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// The name of the file with the term and vote
+const STATE_FILENAME: &str = "state";
+/// The name of the file that describes the latest snapshot
+const SNAPSHOT_FILENAME: &str = "snapshot";
+/// The name of the file with the entries
+const LOG_FILENAME: &str = "log";
+/// The size of everything in a record before the entry
+const RECORD_HEADER_LEN: usize = 4 + 4;
+
+/// The state that has to survive restarts, apart from the log
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+struct HardState {
+    /// The current term
+    term: u64,
+    /// The node that we voted for in the current term
+    voted_for: Option<u64>,
+}
+
+/// Describes the latest snapshot, which the log continues from
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SnapshotMeta {
+    /// The index of the last entry in the snapshot
+    pub index: u64,
+    /// The term of the last entry in the snapshot
+    pub term: u64,
+    /// The membership as of the last entry in the snapshot
+    pub members: Members,
+    /// The name of the snapshot with the data, or `None` if the log starts from an
+    /// empty table
+    pub name: Option<String>,
+}
+
+/// The Raft log, along with the term, the vote and the latest snapshot
+#[derive(Debug)]
+pub struct RaftLog {
+    /// The directory with the files
+    dir: PathBuf,
+    /// The log file, opened for appending
+    file: File,
+    /// The term and vote
+    hard: HardState,
+    /// The latest snapshot
+    snapshot: SnapshotMeta,
+    /// The entries after the snapshot, in order
+    entries: Vec<Entry>,
+    /// The index of the latest membership entry (or of the snapshot) and the membership
+    /// in it, which is the membership that the node uses
+    members: (u64, Members),
+}
+
+impl RaftLog {
+    /// Open the log in `dir`, creating the directory if it doesn't exist
+    ///
+    /// `members` is only used if the node has never been started before, in which case
+    /// it is the membership that the group starts with
+    pub fn open(dir: impl Into<PathBuf>, members: Members) -> TResult<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        let hard = match read_file(&dir.join(STATE_FILENAME))? {
+            Some(state) => bincode::deserialize(&state)?,
+            None => HardState::default(),
+        };
+        let snapshot = match read_file(&dir.join(SNAPSHOT_FILENAME))? {
+            Some(snapshot) => bincode::deserialize(&snapshot)?,
+            None => {
+                let snapshot = SnapshotMeta {
+                    index: 0,
+                    term: 0,
+                    members,
+                    name: None,
+                };
+                write_atomically(
+                    &dir.join(SNAPSHOT_FILENAME),
+                    &bincode::serialize(&snapshot)?,
+                )?;
+                snapshot
+            }
+        };
+        let path = dir.join(LOG_FILENAME);
+        let records = read_file(&path)?.unwrap_or_default();
+        let (entries, intact) = decode_records(&records, snapshot.index);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        if intact < records.len() {
+            log::warn!(
+                "Cut off {} damaged byte(s) at the end of the Raft log",
+                records.len() - intact
+            );
+            file.set_len(intact as u64)?;
+        }
+        let mut log = RaftLog {
+            dir,
+            file,
+            hard,
+            snapshot,
+            entries,
+            members: (0, Members::new()),
+        };
+        log.members = log.latest_members(u64::MAX);
+        Ok(log)
+    }
+    /// Returns the current term
+    pub fn term(&self) -> u64 {
+        self.hard.term
+    }
+    /// Returns the node that we voted for in the current term
+    pub fn voted_for(&self) -> Option<u64> {
+        self.hard.voted_for
+    }
+    /// Set the current term and the vote, syncing them to disk
+    pub fn set_hard_state(&mut self, term: u64, voted_for: Option<u64>) -> io::Result<()> {
+        let hard = HardState { term, voted_for };
+        if hard == self.hard {
+            return Ok(());
+        }
+        let encoded = bincode::serialize(&hard).map_err(to_io_error)?;
+        write_atomically(&self.dir.join(STATE_FILENAME), &encoded)?;
+        self.hard = hard;
+        Ok(())
+    }
+    /// Returns the latest snapshot
+    pub fn snapshot(&self) -> &SnapshotMeta {
+        &self.snapshot
+    }
+    /// Returns the index of the last entry
+    pub fn last_index(&self) -> u64 {
+        self.snapshot.index + self.entries.len() as u64
+    }
+    /// Returns the term of the last entry
+    pub fn last_term(&self) -> u64 {
+        self.entries
+            .last()
+            .map(|entry| entry.term)
+            .unwrap_or(self.snapshot.term)
+    }
+    /// Returns the entry at `index`, unless it is in the snapshot or doesn't exist yet
+    pub fn entry(&self, index: u64) -> Option<&Entry> {
+        if index <= self.snapshot.index {
+            return None;
+        }
+        self.entries.get((index - self.snapshot.index - 1) as usize)
+    }
+    /// Returns the term of the entry at `index`, which can be the last entry in the
+    /// snapshot. This is `None` for entries that were compacted away or don't exist yet
+    pub fn term_at(&self, index: u64) -> Option<u64> {
+        if index == self.snapshot.index {
+            Some(self.snapshot.term)
+        } else {
+            self.entry(index).map(|entry| entry.term)
+        }
+    }
+    /// Returns the index of the first entry with the same term as the entry at `index`
+    pub fn first_index_of_term(&self, index: u64) -> u64 {
+        let term = self.term_at(index);
+        let mut first = index;
+        while first > self.snapshot.index + 1 && self.term_at(first - 1) == term {
+            first -= 1;
+        }
+        first
+    }
+    /// Returns up to `max` entries, starting from the one at `from`
+    pub fn entries_from(&self, from: u64, max: usize) -> Vec<Entry> {
+        let start = from.saturating_sub(self.snapshot.index + 1) as usize;
+        self.entries.iter().skip(start).take(max).cloned().collect()
+    }
+    /// Append `entries`, which have to follow the last entry, syncing them to disk
+    pub fn append(&mut self, entries: Vec<Entry>) -> io::Result<()> {
+        let mut records = Vec::new();
+        for entry in &entries {
+            encode_record(&mut records, entry)?;
+        }
+        self.file.write_all(&records)?;
+        self.file.sync_data()?;
+        self.entries.extend(entries);
+        self.members = self.latest_members(u64::MAX);
+        Ok(())
+    }
+    /// Remove the entry at `from` and every entry after it
+    ///
+    /// This is done when a follower finds out that its last entries don't match the
+    /// leader's, so they can't have been committed
+    pub fn truncate(&mut self, from: u64) -> io::Result<()> {
+        let keep = from.saturating_sub(self.snapshot.index + 1) as usize;
+        self.entries.truncate(keep);
+        self.rewrite()?;
+        self.members = self.latest_members(u64::MAX);
+        Ok(())
+    }
+    /// Continue the log from the snapshot in `snapshot`, dropping the entries in it
+    ///
+    /// If the log doesn't have the snapshot's last entry (which happens when a follower
+    /// gets the leader's snapshot), all the entries are dropped
+    pub fn compact(&mut self, snapshot: SnapshotMeta) -> io::Result<()> {
+        if self.term_at(snapshot.index) == Some(snapshot.term) {
+            let drop = snapshot.index.saturating_sub(self.snapshot.index) as usize;
+            self.entries.drain(..drop.min(self.entries.len()));
+        } else {
+            self.entries.clear();
+        }
+        let encoded = bincode::serialize(&snapshot).map_err(to_io_error)?;
+        // The new snapshot has to be on disk before the entries in it are gone
+        write_atomically(&self.dir.join(SNAPSHOT_FILENAME), &encoded)?;
+        self.snapshot = snapshot;
+        self.rewrite()?;
+        self.members = self.latest_members(u64::MAX);
+        Ok(())
+    }
+    /// Returns the index of the entry with the membership that is being used
+    pub fn members_index(&self) -> u64 {
+        self.members.0
+    }
+    /// Returns the membership that is being used, which is the one in the latest
+    /// membership entry, even if it isn't committed yet
+    pub fn members(&self) -> &Members {
+        &self.members.1
+    }
+    /// Returns the membership as of the entry at `index`
+    pub fn members_at(&self, index: u64) -> Members {
+        self.latest_members(index).1
+    }
+    /// Find the latest membership entry at or before `index`, falling back to the
+    /// membership in the snapshot
+    fn latest_members(&self, index: u64) -> (u64, Members) {
+        self.entries
+            .iter()
+            .rev()
+            .filter(|entry| entry.index <= index)
+            .find_map(|entry| match &entry.command {
+                super::Command::Members(members) => Some((entry.index, members.clone())),
+                _ => None,
+            })
+            .unwrap_or_else(|| (self.snapshot.index, self.snapshot.members.clone()))
+    }
+    /// Replace the log file with one that has the entries that we have now
+    fn rewrite(&mut self) -> io::Result<()> {
+        let mut records = Vec::new();
+        for entry in &self.entries {
+            encode_record(&mut records, entry)?;
+        }
+        let path = self.dir.join(LOG_FILENAME);
+        write_atomically(&path, &records)?;
+        self.file = OpenOptions::new().append(true).open(&path)?;
+        Ok(())
+    }
+}
+
+fn to_io_error(e: bincode::Error) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, e)
+}
+
+/// Read the file at `path`, returning `None` if it doesn't exist
+fn read_file(path: &Path) -> io::Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(data) => Ok(Some(data)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Replace the file at `path` with `data`, so that it either has the old data or the new
+/// data even if the server crashes
+fn write_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+/// Append the record for `entry` to `records`
+fn encode_record(records: &mut Vec<u8>, entry: &Entry) -> io::Result<()> {
+    let encoded = bincode::serialize(entry).map_err(to_io_error)?;
+    records.extend_from_slice(&(encoded.len() as u32).to_le_bytes());
+    records.extend_from_slice(&crc32fast::hash(&encoded).to_le_bytes());
+    records.extend_from_slice(&encoded);
+    Ok(())
+}
+
+/// Decode the records in `records`, keeping the entries after `after`
+///
+/// This stops at the first record that is damaged or that doesn't follow the previous
+/// one, and returns the entries along with the number of bytes that were intact
+fn decode_records(records: &[u8], after: u64) -> (Vec<Entry>, usize) {
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut pos = 0;
+    while records.len() - pos >= RECORD_HEADER_LEN {
+        let mut len = [0u8; 4];
+        len.copy_from_slice(&records[pos..pos + 4]);
+        let mut crc = [0u8; 4];
+        crc.copy_from_slice(&records[pos + 4..pos + 8]);
+        let start = pos + RECORD_HEADER_LEN;
+        let end = start + u32::from_le_bytes(len) as usize;
+        if end > records.len() || crc32fast::hash(&records[start..end]) != u32::from_le_bytes(crc) {
+            break;
+        }
+        let entry: Entry = match bincode::deserialize(&records[start..end]) {
+            Ok(entry) => entry,
+            Err(_) => break,
+        };
+        let expected = after + 1 + entries.len() as u64;
+        if entry.index > expected {
+            break;
+        }
+        // Entries that are in the snapshot are left behind if we crash while compacting
+        if entry.index == expected {
+            entries.push(entry);
+        }
+        pos = end;
+    }
+    (entries, pos)
+}
+
+#[test]
+fn test_raft_log() {
+    use super::Command;
+    let dir = std::env::temp_dir().join(format!("tdb-test-raftlog-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let mut members = Members::new();
+    members.insert(1, "127.0.0.1:2003".to_owned());
+    let entry = |index, term, command| Entry {
+        index,
+        term,
+        command,
+    };
+    let write = |key: &str| Command::Write(vec!["SET".to_owned(), key.to_owned(), "1".to_owned()]);
+    let mut log = RaftLog::open(&dir, members.clone()).unwrap();
+    assert_eq!((log.term(), log.last_index(), log.last_term()), (0, 0, 0));
+    log.set_hard_state(2, Some(1)).unwrap();
+    log.append(vec![
+        entry(1, 1, Command::Noop),
+        entry(2, 1, write("x")),
+        entry(3, 2, write("y")),
+    ])
+    .unwrap();
+    assert_eq!(log.first_index_of_term(2), 1);
+    assert_eq!(log.entries_from(2, 1), vec![entry(2, 1, write("x"))]);
+    // A follower that finds out that its last entry doesn't match the leader's
+    log.truncate(3).unwrap();
+    let mut grown = members.clone();
+    grown.insert(2, "127.0.0.1:2004".to_owned());
+    log.append(vec![entry(3, 2, Command::Members(grown.clone()))])
+        .unwrap();
+    assert_eq!((log.members_index(), log.members()), (3, &grown));
+    assert_eq!(log.members_at(2), members);
+    // The membership and the entries survive a restart, even with a torn record at the end
+    drop(log);
+    let mut file = OpenOptions::new()
+        .append(true)
+        .open(dir.join(LOG_FILENAME))
+        .unwrap();
+    file.write_all(&[7, 0, 0]).unwrap();
+    drop(file);
+    let mut log = RaftLog::open(&dir, Members::new()).unwrap();
+    assert_eq!((log.term(), log.voted_for()), (2, Some(1)));
+    assert_eq!((log.last_index(), log.last_term()), (3, 2));
+    assert_eq!(log.members(), &grown);
+    // Compacting keeps the entries after the snapshot
+    log.compact(SnapshotMeta {
+        index: 2,
+        term: 1,
+        members: members.clone(),
+        name: Some("raft-1-2".to_owned()),
+    })
+    .unwrap();
+    assert_eq!((log.entry(2), log.term_at(2)), (None, Some(1)));
+    assert_eq!(log.entries_from(1, 10).len(), 1);
+    drop(log);
+    let log = RaftLog::open(&dir, Members::new()).unwrap();
+    assert_eq!((log.snapshot().index, log.last_index()), (2, 3));
+    assert_eq!(log.members(), &grown);
+    fs::remove_dir_all(&dir).unwrap();
+}
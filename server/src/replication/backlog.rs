@@ -0,0 +1,178 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # The replication backlog
+//!
+//! Every change that a server makes (or applies, on a replica) gets the next _offset_ in
+//! its replication history, which is named by a random _replication ID_. The backlog
+//! keeps the most recent changes, up to a configurable number of bytes, so that a replica
+//! that lost its link can resume from the last offset that it applied instead of copying
+//! all the data again. The backlog starts keeping changes once the first replica attaches
+
+use bytes::Bytes;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+use std::collections::VecDeque;
+
+/// The length of a replication ID, in bytes
+pub const ID_LEN: usize = 40;
+
+/// Create a new random replication ID
+pub fn new_id() -> String {
+    let mut id = [0u8; ID_LEN / 2];
+    OsRng.fill_bytes(&mut id);
+    id.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The replication history of a server and its most recent changes
+#[derive(Debug)]
+pub struct Backlog {
+    /// The ID of the replication history
+    id: String,
+    /// The offset of the last change
+    offset: u64,
+    /// The most recent changes and their offsets, oldest first
+    changes: VecDeque<(u64, Bytes)>,
+    /// The total size of the changes in the backlog, in bytes
+    size: usize,
+    /// The largest that `size` can get
+    capacity: usize,
+    /// Whether changes are being kept
+    active: bool,
+}
+
+impl Backlog {
+    /// Create a new history that keeps up to `capacity` bytes of changes
+    pub fn new(capacity: usize) -> Self {
+        Backlog {
+            id: new_id(),
+            offset: 0,
+            changes: VecDeque::new(),
+            size: 0,
+            capacity,
+            active: false,
+        }
+    }
+    /// Returns the ID of the history and the offset of the last change
+    pub fn position(&self) -> (&str, u64) {
+        (&self.id, self.offset)
+    }
+    /// Returns `true` if changes are being kept
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+    /// Start keeping changes
+    pub fn activate(&mut self) {
+        self.active = true;
+    }
+    /// Returns the number of changes in the backlog and their total size in bytes
+    pub fn usage(&self) -> (usize, usize) {
+        (self.changes.len(), self.size)
+    }
+    /// Count a change without keeping it
+    pub fn skip(&mut self) {
+        self.offset += 1;
+        // The backlog can't have any gaps
+        self.changes.clear();
+        self.size = 0;
+    }
+    /// Keep a change, returning its offset
+    pub fn push(&mut self, change: Bytes) -> u64 {
+        self.offset += 1;
+        self.size += change.len();
+        self.changes.push_back((self.offset, change));
+        while self.size > self.capacity {
+            match self.changes.pop_front() {
+                Some((_, old)) => self.size -= old.len(),
+                None => break,
+            }
+        }
+        self.offset
+    }
+    /// Returns the changes made after `offset` in the history called `id`, or `None` if
+    /// some of them are no longer in the backlog (or were never in it)
+    pub fn since(&self, id: &str, offset: u64) -> Option<Vec<(u64, Bytes)>> {
+        if id != self.id || offset > self.offset {
+            return None;
+        }
+        let first = self
+            .changes
+            .front()
+            .map(|(first, _)| *first)
+            .unwrap_or(self.offset + 1);
+        if offset + 1 < first {
+            return None;
+        }
+        Some(
+            self.changes
+                .iter()
+                .filter(|(change, _)| *change > offset)
+                .cloned()
+                .collect(),
+        )
+    }
+    /// Continue the history called `id` from `offset`, dropping all the changes
+    ///
+    /// This is used by replicas after they copy all the data from their primary
+    pub fn reset(&mut self, id: String, offset: u64) {
+        self.id = id;
+        self.offset = offset;
+        self.changes.clear();
+        self.size = 0;
+    }
+    /// Start a new history from the current offset
+    ///
+    /// This is used when a replica becomes a primary, since its changes no longer follow
+    /// the history of its old primary
+    pub fn fork(&mut self) {
+        let offset = self.offset;
+        self.reset(new_id(), offset);
+    }
+}
+
+#[test]
+fn test_backlog_since() {
+    let mut backlog = Backlog::new(8);
+    backlog.activate();
+    let id = backlog.position().0.to_owned();
+    assert_eq!(backlog.push(Bytes::from("abc")), 1);
+    assert_eq!(backlog.push(Bytes::from("def")), 2);
+    assert_eq!(
+        backlog.since(&id, 0).unwrap(),
+        vec![(1, Bytes::from("abc")), (2, Bytes::from("def"))]
+    );
+    assert_eq!(backlog.since(&id, 2).unwrap(), Vec::new());
+    // This pushes the first change out of the backlog
+    assert_eq!(backlog.push(Bytes::from("ghi")), 3);
+    assert_eq!(backlog.usage(), (2, 6));
+    assert!(backlog.since(&id, 0).is_none());
+    assert_eq!(backlog.since(&id, 1).unwrap().len(), 2);
+    // The replica can't be ahead of us, or in another history
+    assert!(backlog.since(&id, 4).is_none());
+    assert!(backlog.since(&new_id(), 3).is_none());
+    // Skipped changes can't be resumed from
+    backlog.skip();
+    assert!(backlog.since(&id, 3).is_none());
+    assert_eq!(backlog.since(&id, 4).unwrap(), Vec::new());
+    backlog.fork();
+    assert_ne!(backlog.position().0, id);
+    assert_eq!(backlog.position().1, 4);
+}
@@ -0,0 +1,764 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # Replication
+//!
+//! A server can be a replica of another server (its primary), in which case it keeps a
+//! copy of all the primary's data. Replicas serve reads just like any other server, but
+//! they reject writes: all the changes come from the primary.
+//!
+//! Every change gets the next offset in the server's replication history (see
+//! [`backlog`]). A replica connects to its primary like any other client and sends
+//! `SYNC <id> <offset>` with the ID of its history and the offset of the last change that
+//! it applied. The primary replies with an okay response, after which the connection
+//! carries a stream of frames:
+//! ```text
+//! kind: u8 | payload length: u64 (little-endian) | payload
+//! ```
+//! If the replica's history is the primary's and the changes after its offset are still
+//! in the primary's backlog, the first frame says that the replica can continue from
+//! where it was and the missed changes follow. Otherwise, the first frame is a full dump
+//! of the primary's data in the same format as the dump file (without encryption),
+//! prefixed with the offset and the ID of the primary's history, which the replica adopts.
+//! The dump goes through a file in the data directory on both ends, so that neither of
+//! them has to hold it in memory. That file isn't encrypted, so servers with an
+//! encryption key can't replicate or be replicated.
+//!
+//! After that, the primary sends a frame for every change (with its offset) in the order
+//! in which the changes were made, and a heartbeat frame whenever it has been idle for a
+//! while. Changes are sent in the format of the write log, so a replica applies the
+//! _effect_ of each change. Replicas report the offset that they've reached with
+//! `ACK <offset>`, which is how the primary knows how far behind they are.
+//!
+//! A replica that falls too far behind is disconnected by the primary, and a replica
+//! whose link is lost reconnects. A replica can itself have replicas, since it passes on
+//! every change that it applies
+
+use crate::config::{ReplicationConfig, StorageConfig};
+use crate::coredb::{CoreDB, Data};
+use crate::dbnet::Terminator;
+use crate::diskstore;
+use crate::diskstore::encryption::Keyring;
+use crate::diskstore::writelog::Change;
+use crate::protocol::{responses, ActionGroup, Connection, Query, QueryResult};
+use crate::resp::{BytesWrapper, GroupBegin};
+use backlog::{Backlog, ID_LEN};
+use bytes::Bytes;
+use libtdb::TResult;
+use parking_lot::Mutex;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::sync::broadcast::{self, RecvError};
+use tokio::sync::Notify;
+use tokio::task;
+use tokio::time::{self, Duration};
+pub mod backlog;
+pub mod replica;
+
+/// The number of changes that a replica can fall behind by before it is disconnected
+const FEED_BACKLOG: usize = 16384;
+/// The primary sends a heartbeat if it hasn't sent anything for this long
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+/// A frame with a full dump of the data
+const FRAME_DUMP: u8 = 0;
+/// A frame with a change to the data
+const FRAME_CHANGE: u8 = 1;
+/// A heartbeat frame, which has no payload
+const FRAME_HEARTBEAT: u8 = 2;
+/// A frame that tells the replica to continue from its offset, which has no payload
+const FRAME_CONTINUE: u8 = 3;
+/// The size of the chunks in which a dump is sent and received
+const DUMP_CHUNK_LEN: usize = 64 * 1024;
+/// The error returned by `SYNC` and `REPLICAOF` on a server with an encryption key
+const ERR_ENCRYPTED: &str = "Replication can't be used with encryption, since the copy of the data isn't encrypted";
+
+/// The number of dump files made so far, which keeps their names apart
+static DUMP_FILES: AtomicU64 = AtomicU64::new(0);
+
+/// An attached replica, as seen by its primary
+#[derive(Debug)]
+struct ReplicaState {
+    /// The address of the replica
+    addr: String,
+    /// The offset that the replica last reported
+    offset: u64,
+    /// When the replica last reported its offset
+    acked_at: Instant,
+}
+
+/// The replication state of a server
+#[derive(Debug)]
+pub struct Replication {
+    /// The replication history and its most recent changes
+    backlog: Mutex<Backlog>,
+    /// The changes that are sent to the attached replicas, with their offsets
+    feed: Mutex<broadcast::Sender<(u64, Bytes)>>,
+    /// The attached replicas, by client ID
+    replicas: Mutex<HashMap<u64, ReplicaState>>,
+    /// The `host:port` of the primary, if this server is a replica
+    primary: Mutex<Option<String>>,
+    /// Whether the link to the primary is up
+    linked: AtomicBool,
+    /// Wakes up the replica service when the primary changes or the server shuts down
+    service: Notify,
+}
+
+impl Replication {
+    /// Create a new `Replication` instance, which is a replica if `cfg` has a primary
+    pub fn new(cfg: ReplicationConfig) -> Self {
+        let (feed, _) = broadcast::channel(FEED_BACKLOG);
+        Replication {
+            backlog: Mutex::new(Backlog::new(cfg.backlog_size)),
+            feed: Mutex::new(feed),
+            replicas: Mutex::new(HashMap::new()),
+            primary: Mutex::new(cfg.primary),
+            linked: AtomicBool::new(false),
+            service: Notify::new(),
+        }
+    }
+    /// Returns `true` if this server is a replica
+    pub fn is_replica(&self) -> bool {
+        self.primary.lock().is_some()
+    }
+    /// Returns the `host:port` of the primary, if this server is a replica
+    pub fn primary(&self) -> Option<String> {
+        self.primary.lock().clone()
+    }
+    /// Replicate `primary`, or stop replicating if it is `None`
+    pub fn set_primary(&self, primary: Option<String>) {
+        let mut current = self.primary.lock();
+        if current.is_some() && primary.is_none() {
+            // Our changes won't follow the old primary's history any more
+            self.backlog.lock().fork();
+        }
+        *current = primary;
+        drop(current);
+        self.wake_service();
+    }
+    /// Wake up the replica service, so that it can check if anything has changed
+    pub fn wake_service(&self) {
+        self.service.notify();
+    }
+    /// Returns the ID of the replication history and the offset of the last change
+    pub fn position(&self) -> (String, u64) {
+        let backlog = self.backlog.lock();
+        let (id, offset) = backlog.position();
+        (id.to_owned(), offset)
+    }
+    /// Send a change to the replicas
+    ///
+    /// Changes have to be sent while the write lock on the table is held, so that
+    /// the replicas receive them in the same order in which they were made
+    pub fn publish(&self, change: &Change) {
+        let mut backlog = self.backlog.lock();
+        if !backlog.is_active() {
+            // Nobody has ever replicated us, so there's no need to keep the change
+            backlog.skip();
+            return;
+        }
+        match bincode::serialize(change) {
+            Ok(change) => {
+                let change = Bytes::from(change);
+                let offset = backlog.push(change.clone());
+                // A replica might have gone away in the meantime, which is fine
+                let _ = self.feed.lock().send((offset, change));
+            }
+            Err(e) => {
+                log::error!("Failed to encode a change for the replicas: '{}'", e);
+                backlog.skip();
+            }
+        }
+    }
+    /// Send the replicas the changes that turn their data into `table`
+    ///
+    /// This is used when the whole table is replaced, like when a snapshot is restored
+    pub fn publish_reset(&self, table: &HashMap<String, Data>) {
+        self.publish(&Change::Clear);
+        for (key, value) in table {
+            self.publish(&Change::Set(
+                Cow::Borrowed(key),
+                Cow::Borrowed(value.get_blob()),
+            ));
+        }
+    }
+    /// Continue the history called `id` from `offset`
+    ///
+    /// This is used by replicas after they copy all the data from their primary. Our own
+    /// replicas are disconnected, since their data no longer matches ours
+    fn adopt(&self, id: String, offset: u64) {
+        self.backlog.lock().reset(id, offset);
+        let (feed, _) = broadcast::channel(FEED_BACKLOG);
+        *self.feed.lock() = feed;
+    }
+    /// Attach a new replica, which has applied the changes up to `offset` in the history
+    /// called `id` (if it has anything)
+    ///
+    /// This returns the feed of the changes made from now on, along with either the
+    /// changes that the replica missed (if it can continue from its offset) or the ID of
+    /// our history and the offset of the last change (if it has to copy all the data)
+    fn attach(&self, from: Option<(String, u64)>) -> (broadcast::Receiver<(u64, Bytes)>, Attach) {
+        let mut backlog = self.backlog.lock();
+        backlog.activate();
+        let feed = self.feed.lock().subscribe();
+        let missed = from.and_then(|(id, offset)| backlog.since(&id, offset));
+        let attach = match missed {
+            Some(missed) => Attach::Continue(missed),
+            None => {
+                let (id, offset) = backlog.position();
+                Attach::Copy(id.to_owned(), offset)
+            }
+        };
+        (feed, attach)
+    }
+    /// Note that the replica with the client ID `client` has applied the changes up
+    /// to `offset`
+    fn ack(&self, client: u64, offset: u64) {
+        if let Some(replica) = self.replicas.lock().get_mut(&client) {
+            replica.offset = offset;
+            replica.acked_at = Instant::now();
+        }
+    }
+    /// Note whether the link to the primary is up
+    fn set_linked(&self, linked: bool) {
+        self.linked.store(linked, Ordering::Relaxed);
+    }
+    /// Describe the replication state, one line for this server followed by a line for
+    /// each attached replica
+    fn report(&self) -> Vec<String> {
+        let (id, offset) = self.position();
+        let (changes, size) = self.backlog.lock().usage();
+        let mut lines = vec![match self.primary() {
+            Some(primary) => format!(
+                "role=replica primary={} link={} id={} offset={} backlog_changes={} backlog_size={}",
+                primary,
+                if self.linked.load(Ordering::Relaxed) {
+                    "up"
+                } else {
+                    "down"
+                },
+                id,
+                offset,
+                changes,
+                size
+            ),
+            None => format!(
+                "role=primary id={} offset={} backlog_changes={} backlog_size={}",
+                id, offset, changes, size
+            ),
+        }];
+        let now = Instant::now();
+        let replicas = self.replicas.lock();
+        let mut clients: Vec<&u64> = replicas.keys().collect();
+        clients.sort();
+        for client in clients {
+            let replica = &replicas[client];
+            lines.push(format!(
+                "replica id={} addr={} offset={} lag={} last_ack={}",
+                client,
+                replica.addr,
+                replica.offset,
+                offset.saturating_sub(replica.offset),
+                now.duration_since(replica.acked_at).as_secs()
+            ));
+        }
+        lines
+    }
+}
+
+/// How a replica attaches to its primary
+#[derive(Debug, PartialEq)]
+enum Attach {
+    /// The replica continues from its offset, after applying these changes
+    Continue(Vec<(u64, Bytes)>),
+    /// The replica copies all the data, which is at this offset in the history with
+    /// this ID
+    Copy(String, u64),
+}
+
+/// Keeps an attached replica in the list of replicas until it is dropped
+struct Attached<'a> {
+    replication: &'a Replication,
+    client: u64,
+}
+
+impl<'a> Attached<'a> {
+    fn new(replication: &'a Replication, client: u64, addr: String, offset: u64) -> Self {
+        replication.replicas.lock().insert(
+            client,
+            ReplicaState {
+                addr,
+                offset,
+                acked_at: Instant::now(),
+            },
+        );
+        Attached {
+            replication,
+            client,
+        }
+    }
+}
+
+impl Drop for Attached<'_> {
+    fn drop(&mut self) {
+        self.replication.replicas.lock().remove(&self.client);
+    }
+}
+
+/// A file in the data directory with a dump that is being sent or received, which is
+/// deleted when it is dropped
+struct DumpFile(PathBuf);
+
+impl DumpFile {
+    fn new(storage: &StorageConfig) -> Self {
+        let n = DUMP_FILES.fetch_add(1, Ordering::Relaxed);
+        DumpFile(storage.data_dir().join(format!("replication-{}.dump", n)))
+    }
+    fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for DumpFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Encode a frame of the replication stream
+fn encode_frame(kind: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(9 + payload.len());
+    frame.push(kind);
+    frame.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Encode a frame with the change `change`, which has the offset `offset`
+fn encode_change(offset: u64, change: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(17 + change.len());
+    frame.push(FRAME_CHANGE);
+    frame.extend_from_slice(&(8 + change.len() as u64).to_le_bytes());
+    frame.extend_from_slice(&offset.to_le_bytes());
+    frame.extend_from_slice(change);
+    frame
+}
+
+/// Encode the start of a frame with a full dump of `len` bytes, which is at `offset` in
+/// the history called `id`
+///
+/// The dump itself follows
+fn encode_dump_head(id: &str, offset: u64, len: u64) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(17 + ID_LEN);
+    frame.push(FRAME_DUMP);
+    frame.extend_from_slice(&(8 + ID_LEN as u64 + len).to_le_bytes());
+    frame.extend_from_slice(&offset.to_le_bytes());
+    frame.extend_from_slice(id.as_bytes());
+    frame
+}
+
+/// Send a frame with the dump in `dump`, which is at `offset` in the history called `id`
+async fn send_dump(con: &mut Connection, id: &str, offset: u64, dump: &Path) -> TResult<()> {
+    let file = tokio::fs::File::open(dump).await?;
+    let len = file.metadata().await?.len();
+    con.write_response(encode_dump_head(id, offset, len))
+        .await?;
+    let mut file = file.take(len);
+    let mut buf = vec![0u8; DUMP_CHUNK_LEN];
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        con.write_response(buf[..read].to_vec()).await?;
+    }
+}
+
+/// Split the payload of a change frame into the offset and the change
+fn decode_change(payload: &[u8]) -> Option<(u64, &[u8])> {
+    if payload.len() < 8 {
+        return None;
+    }
+    let mut offset = [0u8; 8];
+    offset.copy_from_slice(&payload[..8]);
+    Some((u64::from_le_bytes(offset), &payload[8..]))
+}
+
+/// Split the start of the payload of a dump frame into the ID of the history and the
+/// offset
+fn decode_dump_head(head: &[u8; 8 + ID_LEN]) -> Option<(String, u64)> {
+    let mut offset = [0u8; 8];
+    offset.copy_from_slice(&head[..8]);
+    let id = String::from_utf8(head[8..].to_vec()).ok()?;
+    Some((id, u64::from_le_bytes(offset)))
+}
+
+/// Read the head of a frame of the replication stream, returning its kind and the length
+/// of its payload
+async fn read_head<R: AsyncRead + Unpin>(stream: &mut R) -> io::Result<(u8, u64)> {
+    let mut head = [0u8; 9];
+    stream.read_exact(&mut head).await?;
+    let mut len = [0u8; 8];
+    len.copy_from_slice(&head[1..]);
+    Ok((head[0], u64::from_le_bytes(len)))
+}
+
+/// Read the payload of a frame, which is `len` bytes long
+///
+/// The length comes from the network, so frames longer than `max` bytes are refused
+/// before anything is allocated for them
+async fn read_payload<R: AsyncRead + Unpin>(
+    stream: &mut R,
+    len: u64,
+    max: u64,
+) -> io::Result<Vec<u8>> {
+    if len > max {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("The frame is too long ({} bytes)", len),
+        ));
+    }
+    let mut payload = vec![0u8; len as usize];
+    stream.read_exact(&mut payload).await?;
+    Ok(payload)
+}
+
+/// Returns `true` if `query` is a `SYNC` query
+///
+/// `SYNC` takes over the connection, so the connection handler has to check for it
+/// before handing the query off to the query engine
+pub fn is_sync(query: &Query) -> bool {
+    match query {
+        Query::Simple(act) => act
+            .get_first()
+            .map(|first| first.eq_ignore_ascii_case("SYNC"))
+            .unwrap_or(false),
+        Query::Pipelined(_) => false,
+    }
+}
+
+/// Returns the offset in `query` if it is an `ACK <offset>` query
+fn parse_ack(query: &Query) -> Option<u64> {
+    match query {
+        Query::Simple(act) => match act.get_ref().as_slice() {
+            [action, offset] if action.eq_ignore_ascii_case("ACK") => offset.parse().ok(),
+            _ => None,
+        },
+        Query::Pipelined(_) => None,
+    }
+}
+
+/// Tell the replica that the data couldn't be dumped for it
+async fn sync_failed(con: &mut Connection, replica: &str, e: &str) -> TResult<()> {
+    log::error!("Failed to dump the data for replica {}: '{}'", replica, e);
+    con.write_response(responses::fresp::R_SERVER_ERR.to_owned())
+        .await?;
+    con.flush_stream().await
+}
+
+/// Run a `SYNC` query, which is sent by a replica
+///
+/// `SYNC` copies all the data and `SYNC <id> <offset>` tries to continue from `offset`
+/// first. Either way, this then sends the replica every change made from then on, until
+/// the replica disconnects, falls too far behind or the server shuts down
+pub async fn sync(
+    handle: &CoreDB,
+    con: &mut Connection,
+    act: Query,
+    terminator: &mut Terminator,
+) -> TResult<()> {
+    let from = match &act {
+        Query::Simple(act) => match act.get_ref().as_slice() {
+            [_] => None,
+            [_, id, offset] => match offset.parse::<u64>() {
+                Ok(offset) => Some((id.to_owned(), offset)),
+                Err(_) => None,
+            },
+            _ => {
+                con.write_response(responses::fresp::R_ACTION_ERR.to_owned())
+                    .await?;
+                return con.flush_stream().await;
+            }
+        },
+        Query::Pipelined(_) => None,
+    };
+    if handle.shared.keys.current().is_some() {
+        // The dump for the replica would be written to the data directory in plaintext
+        con.write_response(responses::other_error(ERR_ENCRYPTED))
+            .await?;
+        return con.flush_stream().await;
+    }
+    let replica = con
+        .get_peer()
+        .map(|peer| peer.to_string())
+        .unwrap_or_else(|_| "unknown".to_owned());
+    let replication = &handle.shared.replication;
+    // We attach to the feed while holding the lock, so that every change is either in
+    // what we send first or in the feed, but not in both. Only a copy of the data is
+    // taken under the lock, and it is written to a file once the lock is released
+    let attached = {
+        let table = handle.acquire_read();
+        let (feed, attach) = replication.attach(from);
+        match attach {
+            Attach::Copy(..) => table
+                .get_ref()
+                .freeze()
+                .map(|frozen| (feed, attach, Some(frozen))),
+            attach => Ok((feed, attach, None)),
+        }
+    };
+    let (mut feed, attach, frozen) = match attached {
+        Ok(attached) => attached,
+        Err(e) => return sync_failed(con, &replica, &e.to_string()).await,
+    };
+    let dump = match frozen {
+        Some(frozen) => {
+            let dump = DumpFile::new(&handle.shared.storage);
+            let path = dump.path().to_owned();
+            let codec = handle.shared.storage.compression();
+            // Writing the dump is slow, so it is kept off the executor's threads
+            let written = task::spawn_blocking(move || {
+                diskstore::flush_data(path, &*frozen, codec, &Keyring::none())
+                    .map_err(|e| e.to_string())
+            })
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|written| written);
+            if let Err(e) = written {
+                return sync_failed(con, &replica, &e).await;
+            }
+            Some(dump)
+        }
+        None => None,
+    };
+    let (dump, frames, offset) = match (attach, dump) {
+        (Attach::Copy(id, offset), Some(dump)) => (Some((id, dump)), Vec::new(), offset),
+        (Attach::Continue(missed), _) => {
+            let mut frames = vec![encode_frame(FRAME_CONTINUE, &[])];
+            frames.extend(
+                missed
+                    .iter()
+                    .map(|(offset, change)| encode_change(*offset, change)),
+            );
+            (None, frames, replication.position().1)
+        }
+        (Attach::Copy(..), None) => unreachable!("The data is always dumped for a full sync"),
+    };
+    let full = dump.is_some();
+    let _attached = Attached::new(replication, con.client().id(), replica.clone(), offset);
+    con.write_response(responses::fresp::R_OKAY.to_owned())
+        .await?;
+    if let Some((id, dump)) = dump {
+        send_dump(con, &id, offset, dump.path()).await?;
+    }
+    for frame in frames {
+        con.write_response(frame).await?;
+    }
+    con.flush_stream().await?;
+    if full {
+        log::info!("Replica {} has copied the data", replica);
+    } else {
+        log::info!("Replica {} has continued from where it was", replica);
+    }
+    loop {
+        let frame = tokio::select! {
+            change = feed.recv() => match change {
+                Ok((offset, change)) => encode_change(offset, &change),
+                Err(RecvError::Lagged(_)) => {
+                    log::warn!(
+                        "Disconnecting replica {} since it fell too far behind",
+                        replica
+                    );
+                    return Ok(());
+                }
+                Err(RecvError::Closed) => {
+                    log::info!("Disconnecting replica {} since our data was replaced", replica);
+                    return Ok(());
+                }
+            },
+            _ = time::delay_for(HEARTBEAT_INTERVAL) => encode_frame(FRAME_HEARTBEAT, &[]),
+            query = con.read_query() => match query {
+                Ok(QueryResult::Q(query)) => {
+                    if let Some(offset) = parse_ack(&query) {
+                        replication.ack(con.client().id(), offset);
+                    }
+                    // Replicas don't send anything else, so we ignore it
+                    continue;
+                }
+                Ok(QueryResult::E(_)) => continue,
+                Ok(QueryResult::Empty) | Ok(QueryResult::TooLarge) => {
+                    log::info!("Replica {} disconnected", replica);
+                    return Ok(());
+                }
+                Err(e) => return Err(e.into()),
+            },
+            _ = terminator.receive_signal() => return Ok(()),
+        };
+        con.write_response(frame).await?;
+        con.flush_stream().await?;
+    }
+}
+
+/// Run a `REPLICAOF` query
+///
+/// `REPLICAOF <host> <port>` makes this server a replica of the server at `host:port`,
+/// replacing all its data with the primary's data. `REPLICAOF NO ONE` turns a replica
+/// into a primary, keeping the data that it has
+pub async fn replicaof(handle: &CoreDB, con: &mut Connection, act: ActionGroup) -> TResult<()> {
+    if act.howmany() != 2 {
+        return con
+            .write_response(responses::fresp::R_ACTION_ERR.to_owned())
+            .await;
+    }
+    let mut args = act.into_iter();
+    let (host, port) = match (args.next(), args.next()) {
+        (Some(host), Some(port)) => (host, port),
+        _ => {
+            return con
+                .write_response(responses::fresp::R_ACTION_ERR.to_owned())
+                .await
+        }
+    };
+    let replication = &handle.shared.replication;
+    if host.eq_ignore_ascii_case("NO") && port.eq_ignore_ascii_case("ONE") {
+        if replication.is_replica() {
+            replication.set_primary(None);
+            log::warn!("Stopped replicating, so this server is now a primary");
+        }
+    } else {
+        if port.parse::<u16>().is_err() {
+            return con
+                .write_response(responses::fresp::R_ACTION_ERR.to_owned())
+                .await;
+        }
+        if handle.shared.keys.current().is_some() {
+            return con
+                .write_response(responses::other_error(ERR_ENCRYPTED))
+                .await;
+        }
+        let primary = format!("{}:{}", host, port);
+        log::warn!("Replicating primary {} from now on", primary);
+        replication.set_primary(Some(primary));
+    }
+    con.write_response(responses::fresp::R_OKAY.to_owned())
+        .await
+}
+
+/// Run a `REPLICATION` query
+///
+/// This returns a line with the role of this server, the ID of its replication history,
+/// the offset of the last change and the size of the backlog, followed by a line for each
+/// attached replica with the offset that it last reported, how many changes it is behind
+/// by and how many seconds ago it reported its offset
+pub async fn replication(handle: &CoreDB, con: &mut Connection, act: ActionGroup) -> TResult<()> {
+    if act.howmany() != 0 {
+        return con
+            .write_response(responses::fresp::R_ACTION_ERR.to_owned())
+            .await;
+    }
+    let lines = handle.shared.replication.report();
+    con.write_response(GroupBegin(lines.len())).await?;
+    for line in lines {
+        con.write_response(BytesWrapper(Bytes::from(line))).await?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_replication_frames() {
+    use crate::diskstore::format::{self, Codec};
+    let mut data = HashMap::new();
+    data.insert("x".to_owned(), Data::from_string("100".to_owned()));
+    let id = backlog::new_id();
+    let dump = format::write_table(Vec::new(), Codec::None, &Keyring::none(), &data).unwrap();
+    let mut stream = encode_change(7, b"hello");
+    stream.extend(encode_frame(FRAME_HEARTBEAT, &[]));
+    stream.extend(encode_dump_head(&id, 8, dump.len() as u64));
+    stream.extend(&dump);
+    let mut reader = &stream[..];
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let (kind, len) = read_head(&mut reader).await.unwrap();
+        assert_eq!(kind, FRAME_CHANGE);
+        // Frames that are too long are refused
+        let mut peek = reader;
+        assert!(read_payload(&mut peek, len, len - 1).await.is_err());
+        let payload = read_payload(&mut reader, len, len).await.unwrap();
+        assert_eq!(decode_change(&payload).unwrap(), (7, &b"hello"[..]));
+        assert_eq!(read_head(&mut reader).await.unwrap(), (FRAME_HEARTBEAT, 0));
+        let (kind, len) = read_head(&mut reader).await.unwrap();
+        assert_eq!(kind, FRAME_DUMP);
+        assert_eq!(len, (8 + ID_LEN + dump.len()) as u64);
+        let mut head = [0u8; 8 + ID_LEN];
+        reader.read_exact(&mut head).await.unwrap();
+        assert_eq!(decode_dump_head(&head).unwrap(), (id, 8));
+        assert_eq!(format::read_table(reader, &Keyring::none()).unwrap(), data);
+    });
+}
+
+#[test]
+fn test_replication_attach() {
+    let replication = Replication::new(ReplicationConfig::default());
+    assert!(!replication.is_replica());
+    // Nobody has attached yet, so this is only counted
+    replication.publish(&Change::Clear);
+    let (id, offset) = replication.position();
+    assert_eq!(offset, 1);
+    // A new replica has to copy all the data
+    let (mut feed, attach) = replication.attach(None);
+    assert_eq!(attach, Attach::Copy(id.clone(), 1));
+    let mut table = HashMap::new();
+    table.insert("x".to_owned(), Data::from_string("100".to_owned()));
+    replication.publish_reset(&table);
+    let (offset, change) = feed.try_recv().unwrap();
+    assert_eq!(offset, 2);
+    assert_eq!(
+        bincode::deserialize::<Change>(&change).unwrap(),
+        Change::Clear
+    );
+    let (offset, change) = feed.try_recv().unwrap();
+    assert_eq!(offset, 3);
+    assert_eq!(
+        bincode::deserialize::<Change>(&change).unwrap(),
+        Change::Set(Cow::Borrowed("x"), Cow::Borrowed(b"100"))
+    );
+    assert!(feed.try_recv().is_err());
+    // A replica that has the first change can continue with the rest
+    match replication.attach(Some((id.clone(), 1))).1 {
+        Attach::Continue(missed) => {
+            assert_eq!(
+                missed.iter().map(|(offset, _)| *offset).collect::<Vec<_>>(),
+                vec![2, 3]
+            )
+        }
+        attach => panic!("Expected to continue, got {:?}", attach),
+    }
+    // But a replica with another history can't
+    assert_eq!(
+        replication.attach(Some((backlog::new_id(), 1))).1,
+        Attach::Copy(id, 3)
+    );
+}
@@ -0,0 +1,401 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # The replica service
+//!
+//! The replica service runs on every server, but it only does something while the server
+//! is a replica: it connects to the primary, catches up with it (by continuing from the
+//! last change that it applied or by copying all the data) and then applies the changes
+//! that the primary sends, until the link is lost or the primary changes. Lost links are
+//! reestablished with an exponential backoff
+
+use super::backlog::ID_LEN;
+use super::{
+    decode_change, decode_dump_head, read_head, read_payload, DumpFile, DUMP_CHUNK_LEN,
+    FRAME_CHANGE, FRAME_CONTINUE, FRAME_DUMP, FRAME_HEARTBEAT,
+};
+use crate::admin::auth;
+use crate::coredb::{CoreDB, Data, Shared};
+use crate::diskstore::encryption::Keyring;
+use crate::diskstore::format;
+use crate::diskstore::writelog::Change;
+use crate::protocol::responses;
+use bytes::Bytes;
+use libtdb::terrapipe;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::time::{self, Duration};
+
+/// How long we wait for the primary to accept the connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// The link is considered lost if the primary hasn't sent anything for this long
+///
+/// The primary sends heartbeats well within this time
+const LINK_TIMEOUT: Duration = Duration::from_secs(30);
+/// The longest that we wait (in seconds) before reconnecting to the primary
+const MAX_BACKOFF: u64 = 64;
+/// We report our offset to the primary at least this often while changes are coming in
+const ACK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Follow the primary for as long as this server is a replica, until the database is
+/// shutting down
+pub async fn replica_service(handle: CoreDB) {
+    let shared = &handle.shared;
+    let mut backoff = 1;
+    loop {
+        if shared.is_termsig() {
+            return;
+        }
+        let primary = match shared.replication.primary() {
+            Some(primary) => primary,
+            None => {
+                shared.replication.service.notified().await;
+                continue;
+            }
+        };
+        log::info!("Connecting to primary {}", primary);
+        tokio::select! {
+            result = follow(&handle, &primary) => {
+                shared.replication.set_linked(false);
+                let synced = match result {
+                    Ok(synced) => synced,
+                    Err((synced, e)) => {
+                        log::error!("Lost the link to primary {}: '{}'", primary, e);
+                        synced
+                    }
+                };
+                if synced {
+                    // The link was working, so this is probably just a blip
+                    backoff = 1;
+                }
+                log::warn!("Reconnecting to primary {} in {} second(s)", primary, backoff);
+                tokio::select! {
+                    _ = time::delay_for(Duration::from_secs(backoff)) => {}
+                    // Don't keep the new primary (or a shutdown) waiting
+                    _ = shared.replication.service.notified() => {}
+                }
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+            // The primary changed or the database is shutting down
+            _ = shared.replication.service.notified() => {
+                shared.replication.set_linked(false);
+                backoff = 1;
+            }
+        }
+    }
+}
+
+/// Catch up with `primary` and apply the changes that it sends
+///
+/// This only returns when the link is lost. Either way, the returned flag is `true` if
+/// we caught up with the primary before that
+async fn follow(handle: &CoreDB, primary: &str) -> Result<bool, (bool, String)> {
+    let connect = time::timeout(CONNECT_TIMEOUT, TcpStream::connect(primary)).await;
+    let stream = match connect {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(e)) => return Err((false, format!("Failed to connect: {}", e))),
+        Err(_) => return Err((false, "Timed out while connecting".to_owned())),
+    };
+    let mut stream = BufReader::new(stream);
+    let shared = &handle.shared;
+    let mut synced = false;
+    let password = shared.config.read().replication.password.clone();
+    let result = async {
+        if let Some(password) = &password {
+            auth::send_auth(&mut stream, password, LINK_TIMEOUT).await?;
+        }
+        // If we've never replicated this primary, the ID won't match and we'll get a copy
+        // of all the data
+        let (id, offset) = shared.replication.position();
+        stream
+            .write_all(&terrapipe::proc_query(format!("SYNC {} {}", id, offset)))
+            .await
+            .map_err(|e| e.to_string())?;
+        let mut reply = vec![0u8; responses::fresp::R_OKAY.len()];
+        match time::timeout(LINK_TIMEOUT, stream.read_exact(&mut reply)).await {
+            Ok(Ok(_)) if reply == *responses::fresp::R_OKAY => {}
+            Ok(Ok(_)) => return Err("The primary refused to sync".to_owned()),
+            Ok(Err(e)) => return Err(e.to_string()),
+            Err(_) => return Err("The primary stopped responding".to_owned()),
+        }
+        // A change is never larger than the query that made it
+        let (_, _, max_query_size) = shared.config.read().limits.decompose();
+        let mut acked_at = Instant::now();
+        loop {
+            let (kind, len) = match time::timeout(LINK_TIMEOUT, read_head(&mut stream)).await {
+                Ok(Ok(head)) => head,
+                Ok(Err(e)) => return Err(e.to_string()),
+                Err(_) => return Err("The primary stopped responding".to_owned()),
+            };
+            let payload = if kind == FRAME_DUMP {
+                // The dump is received in chunks
+                Vec::new()
+            } else {
+                let read = read_payload(&mut stream, len, max_query_size as u64);
+                match time::timeout(LINK_TIMEOUT, read).await {
+                    Ok(Ok(payload)) => payload,
+                    Ok(Err(e)) => return Err(e.to_string()),
+                    Err(_) => return Err("The primary stopped responding".to_owned()),
+                }
+            };
+            match kind {
+                FRAME_DUMP => {
+                    let keys = receive_dump(shared, &mut stream, len).await?;
+                    log::info!("Copied {} keys from primary {}", keys, primary);
+                }
+                FRAME_CONTINUE => {
+                    log::info!(
+                        "Continuing from offset {} with primary {}",
+                        shared.replication.position().1,
+                        primary
+                    );
+                }
+                FRAME_CHANGE if synced => {
+                    let (offset, change) = decode_change(&payload)
+                        .ok_or_else(|| "The primary sent a damaged change".to_owned())?;
+                    apply(shared, offset, change).map_err(|e| e.to_string())?;
+                }
+                FRAME_HEARTBEAT if synced => {}
+                _ => return Err(format!("The primary sent an unexpected frame ({})", kind)),
+            }
+            if !synced {
+                synced = true;
+                shared.replication.set_linked(true);
+            }
+            // Let the primary know how far we've got when it's idle, and every now and then
+            // while changes are coming in
+            if kind == FRAME_HEARTBEAT || acked_at.elapsed() >= ACK_INTERVAL {
+                let ack = format!("ACK {}", shared.replication.position().1);
+                stream
+                    .write_all(&terrapipe::proc_query(ack))
+                    .await
+                    .map_err(|e| e.to_string())?;
+                acked_at = Instant::now();
+            }
+        }
+    }
+    .await;
+    result.map_err(|e| (synced, e))
+}
+
+/// Receive the payload of a dump frame, which is `len` bytes long, and replace all the
+/// data with the dump, returning the number of keys
+///
+/// The dump is written to a file as it comes in, so only the data read from it has to fit
+/// in memory
+async fn receive_dump<R: AsyncRead + Unpin>(
+    shared: &Shared,
+    stream: &mut R,
+    len: u64,
+) -> Result<usize, String> {
+    let mut head = [0u8; 8 + ID_LEN];
+    if len < head.len() as u64 {
+        return Err("The primary sent a damaged dump".to_owned());
+    }
+    match time::timeout(LINK_TIMEOUT, stream.read_exact(&mut head)).await {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => return Err(e.to_string()),
+        Err(_) => return Err("The primary stopped responding".to_owned()),
+    }
+    let (id, offset) =
+        decode_dump_head(&head).ok_or_else(|| "The primary sent a damaged dump".to_owned())?;
+    let dump = DumpFile::new(&shared.storage);
+    let mut file = tokio::fs::File::create(dump.path())
+        .await
+        .map_err(|e| format!("Failed to create '{}': {}", dump.path().display(), e))?;
+    let mut left = len - head.len() as u64;
+    let mut buf = vec![0u8; DUMP_CHUNK_LEN];
+    while left > 0 {
+        let want = left.min(buf.len() as u64) as usize;
+        let read = match time::timeout(LINK_TIMEOUT, stream.read(&mut buf[..want])).await {
+            Ok(Ok(0)) => return Err("The primary closed the link during the dump".to_owned()),
+            Ok(Ok(read)) => read,
+            Ok(Err(e)) => return Err(e.to_string()),
+            Err(_) => return Err("The primary stopped responding".to_owned()),
+        };
+        file.write_all(&buf[..read])
+            .await
+            .map_err(|e| format!("Failed to write '{}': {}", dump.path().display(), e))?;
+        left -= read as u64;
+    }
+    file.flush()
+        .await
+        .map_err(|e| format!("Failed to write '{}': {}", dump.path().display(), e))?;
+    drop(file);
+    load_dump(shared, id, offset, dump.path())
+}
+
+/// Replace all the data with the dump in the file `dump`, which is at `offset` in the
+/// primary's history called `id`, returning the number of keys
+fn load_dump(shared: &Shared, id: String, offset: u64, dump: &Path) -> Result<usize, String> {
+    let file =
+        fs::File::open(dump).map_err(|e| format!("Failed to open '{}': {}", dump.display(), e))?;
+    let data: HashMap<String, Data> =
+        format::read_table(io::BufReader::new(file), &Keyring::none())
+            .map_err(|e| format!("Failed to read the primary's data: {}", e))?;
+    let keys = data.len();
+    let mut table = shared.table.write();
+    table
+        .get_mut_ref()
+        .replace(data.clone())
+        .map_err(|e| format!("Failed to store the primary's data: {}", e))?;
+    // Our own replicas are disconnected and catch up with us again, so only the write
+    // log has to hear about this
+    shared.writelog.reset_to(&data);
+    shared.replication.adopt(id, offset);
+    Ok(keys)
+}
+
+/// Apply the encoded change in `change`, which has the offset `offset`
+fn apply(shared: &Shared, offset: u64, change: &[u8]) -> io::Result<()> {
+    let change: Change =
+        bincode::deserialize(change).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let mut table = shared.table.write();
+    let expected = shared.replication.position().1 + 1;
+    if offset != expected {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Expected the change at offset {}, got {}", expected, offset),
+        ));
+    }
+    let table = table.get_mut_ref();
+    // Only log the change once it has been made
+    match change {
+        Change::Set(key, value) => {
+            table.set(
+                key.clone().into_owned(),
+                Data::from_blob(Bytes::from(value.clone().into_owned())),
+            )?;
+            shared.log_set(&key, &value);
+        }
+        Change::Del(key) => {
+            table.remove(&key)?;
+            shared.log_del(&key);
+        }
+        Change::Clear => {
+            table.clear()?;
+            shared.log_clear();
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+/// Run `query` on the server at `addr`, returning the response
+pub async fn run_query(addr: &str, query: &str) -> Vec<u8> {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(&terrapipe::proc_query(query))
+        .await
+        .unwrap();
+    let mut response = vec![0u8; 1024];
+    let len = stream.read(&mut response).await.unwrap();
+    response.truncate(len);
+    response
+}
+
+#[cfg(test)]
+/// Follow the primary at `addr` with `replica` until `unlinked` fires
+fn spawn_follower(replica: &CoreDB, addr: &str) -> tokio::sync::oneshot::Sender<()> {
+    let (unlink, unlinked) = tokio::sync::oneshot::channel::<()>();
+    let (replica, addr) = (replica.clone(), addr.to_owned());
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = follow(&replica, &addr) => {}
+            _ = unlinked => {}
+        }
+    });
+    unlink
+}
+
+#[tokio::test]
+async fn test_replica_follows_primary() {
+    use crate::dbnet;
+    use tokio::net::TcpListener;
+    use tokio::sync::oneshot;
+    let primary = CoreDB::new_empty(0);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let (stop, stopped) = oneshot::channel::<()>();
+    tokio::spawn(dbnet::test_run(listener, primary.clone(), stopped));
+    assert_eq!(
+        run_query(&addr, "SET x 100").await,
+        *responses::fresp::R_OKAY
+    );
+    let replica = CoreDB::new_empty(0);
+    let unlink = spawn_follower(&replica, &addr);
+    let value_of = |key: &str| {
+        replica
+            .acquire_read()
+            .get_ref()
+            .get(key)
+            .unwrap()
+            .map(|value| value.get_blob().clone())
+    };
+    let mut waited = 0;
+    while value_of("x").is_none() {
+        assert!(waited < 100, "The replica didn't copy the data");
+        time::delay_for(Duration::from_millis(20)).await;
+        waited += 1;
+    }
+    assert_eq!(value_of("x"), Some(Bytes::from("100")));
+    assert_eq!(
+        replica.shared.replication.position(),
+        primary.shared.replication.position()
+    );
+    // Now the changes should follow
+    run_query(&addr, "SET y 200").await;
+    run_query(&addr, "DEL x").await;
+    let mut waited = 0;
+    while value_of("x").is_some() {
+        assert!(waited < 100, "The replica didn't apply the changes");
+        time::delay_for(Duration::from_millis(20)).await;
+        waited += 1;
+    }
+    assert_eq!(value_of("y"), Some(Bytes::from("200")));
+    // Drop the link and make a change that the replica misses
+    let _ = unlink.send(());
+    run_query(&addr, "SET z 300").await;
+    // This key would be gone if the replica copied all the data again
+    replica
+        .acquire_write()
+        .get_mut_ref()
+        .set("local".to_owned(), Data::from_string("1".to_owned()))
+        .unwrap();
+    let _unlink = spawn_follower(&replica, &addr);
+    let mut waited = 0;
+    while value_of("z").is_none() {
+        assert!(waited < 100, "The replica didn't catch up");
+        time::delay_for(Duration::from_millis(20)).await;
+        waited += 1;
+    }
+    assert_eq!(value_of("local"), Some(Bytes::from("1")));
+    assert_eq!(
+        replica.shared.replication.position(),
+        primary.shared.replication.position()
+    );
+    let _ = stop.send(());
+}
@@ -22,6 +22,7 @@
 //! Utilities for generating responses, which are only used by the `server`
 //!
 
+use crate::protocol::MeteredStream;
 use bytes::Bytes;
 use libtdb::terrapipe::RespCodes;
 use std::error::Error;
@@ -29,7 +30,6 @@ use std::future::Future;
 use std::pin::Pin;
 use tokio::io::AsyncWriteExt;
 use tokio::io::BufWriter;
-use tokio::net::TcpStream;
 
 /// # The `Writable` trait
 /// All trait implementors are given access to an asynchronous stream to which
@@ -42,7 +42,7 @@ pub trait Writable {
     */
     fn write<'s>(
         self,
-        con: &'s mut BufWriter<TcpStream>,
+        con: &'s mut BufWriter<MeteredStream>,
     ) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn Error>>> + Send + Sync + 's>>;
 }
 
@@ -73,11 +73,11 @@ impl BytesWrapper {
 impl Writable for Vec<u8> {
     fn write<'s>(
         self,
-        con: &'s mut BufWriter<TcpStream>,
+        con: &'s mut BufWriter<MeteredStream>,
     ) -> Pin<Box<(dyn Future<Output = Result<(), Box<(dyn Error + 'static)>>> + Send + Sync + 's)>>
     {
         async fn write_bytes(
-            con: &mut BufWriter<TcpStream>,
+            con: &mut BufWriter<MeteredStream>,
             resp: Vec<u8>,
         ) -> Result<(), Box<dyn Error>> {
             con.write(&resp).await?;
@@ -90,11 +90,11 @@ impl Writable for Vec<u8> {
 impl Writable for BytesWrapper {
     fn write<'s>(
         self,
-        con: &'s mut BufWriter<TcpStream>,
+        con: &'s mut BufWriter<MeteredStream>,
     ) -> Pin<Box<(dyn Future<Output = Result<(), Box<(dyn Error + 'static)>>> + Send + Sync + 's)>>
     {
         async fn write_bytes(
-            con: &mut BufWriter<TcpStream>,
+            con: &mut BufWriter<MeteredStream>,
             bytes: Bytes,
         ) -> Result<(), Box<dyn Error>> {
             // First write a `+` character to the stream since this is a
@@ -121,11 +121,11 @@ impl Writable for BytesWrapper {
 impl Writable for RespCodes {
     fn write<'s>(
         self,
-        con: &'s mut BufWriter<TcpStream>,
+        con: &'s mut BufWriter<MeteredStream>,
     ) -> Pin<Box<(dyn Future<Output = Result<(), Box<(dyn Error + 'static)>>> + Send + Sync + 's)>>
     {
         async fn write_bytes(
-            con: &mut BufWriter<TcpStream>,
+            con: &mut BufWriter<MeteredStream>,
             code: RespCodes,
         ) -> Result<(), Box<dyn Error>> {
             // Self's tsymbol is !
@@ -148,11 +148,11 @@ impl Writable for RespCodes {
 impl Writable for GroupBegin {
     fn write<'s>(
         self,
-        con: &'s mut BufWriter<TcpStream>,
+        con: &'s mut BufWriter<MeteredStream>,
     ) -> Pin<Box<(dyn Future<Output = Result<(), Box<(dyn Error + 'static)>>> + Send + Sync + 's)>>
     {
         async fn write_bytes(
-            con: &mut BufWriter<TcpStream>,
+            con: &mut BufWriter<MeteredStream>,
             size: usize,
         ) -> Result<(), Box<dyn Error>> {
             con.write(b"#2\n*1\n").await?;
@@ -178,11 +178,11 @@ impl Writable for GroupBegin {
 impl Writable for usize {
     fn write<'s>(
         self,
-        con: &'s mut BufWriter<TcpStream>,
+        con: &'s mut BufWriter<MeteredStream>,
     ) -> Pin<Box<(dyn Future<Output = Result<(), Box<(dyn Error + 'static)>>> + Send + Sync + 's)>>
     {
         async fn write_bytes(
-            con: &mut BufWriter<TcpStream>,
+            con: &mut BufWriter<MeteredStream>,
             val: usize,
         ) -> Result<(), Box<dyn Error>> {
             con.write(b":").await?;
@@ -201,11 +201,11 @@ impl Writable for usize {
 impl Writable for u64 {
     fn write<'s>(
         self,
-        con: &'s mut BufWriter<TcpStream>,
+        con: &'s mut BufWriter<MeteredStream>,
     ) -> Pin<Box<(dyn Future<Output = Result<(), Box<(dyn Error + 'static)>>> + Send + Sync + 's)>>
     {
         async fn write_bytes(
-            con: &mut BufWriter<TcpStream>,
+            con: &mut BufWriter<MeteredStream>,
             val: u64,
         ) -> Result<(), Box<dyn Error>> {
             con.write(b":").await?;
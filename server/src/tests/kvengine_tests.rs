@@ -21,6 +21,7 @@
 
 use super::{fresp, start_server, terrapipe, QueryVec, TcpStream};
 use crate::__func__;
+use crate::protocol::responses;
 use tokio::prelude::*;
 
 #[tokio::test]
@@ -78,6 +79,9 @@ async fn test_queries() {
     queries.add(test_uset_syntax_error).await;
     queries.add(test_keylen).await;
     queries.add(test_keylen_syntax_error).await;
+    queries.add(test_readonly).await;
+    queries.add(test_config).await;
+    queries.add(test_slowlog_maxlen).await;
     queries.run_queries_and_close_sockets();
 
     // Clean up everything else
@@ -895,3 +899,119 @@ async fn test_keylen_syntax_error(mut stream: TcpStream) -> TcpStream {
     );
     stream
 }
+
+/// Test `READONLY`: writes are refused with a read-only error until it's switched off,
+/// while reads keep working
+async fn test_readonly(stream: TcpStream) -> TcpStream {
+    let mut stream = set_values("x 100", 1, stream).await;
+    let queries = [
+        ("READONLY ON", fresp::R_OKAY.to_owned()),
+        (
+            "READONLY",
+            "#2\n*1\n#2\n&1\n+2\nON\n".to_owned().into_bytes(),
+        ),
+        ("SET y 200", fresp::R_READONLY.to_owned()),
+        ("DEL x", fresp::R_READONLY.to_owned()),
+        ("FLUSHDB", fresp::R_READONLY.to_owned()),
+        ("SNAPSHOT RESTORE nothing", fresp::R_READONLY.to_owned()),
+        ("CLUSTER IMPORT 10", fresp::R_READONLY.to_owned()),
+        ("GET x", "#2\n*1\n#2\n&1\n+3\n100\n".to_owned().into_bytes()),
+        (
+            "EXISTS x y",
+            "#2\n*1\n#2\n&1\n:1\n1\n".to_owned().into_bytes(),
+        ),
+        ("READONLY MAYBE", fresp::R_ACTION_ERR.to_owned()),
+        ("READONLY OFF", fresp::R_OKAY.to_owned()),
+        ("SET y 200", fresp::R_OKAY.to_owned()),
+    ];
+    for (query, res_should_be) in queries.iter() {
+        stream
+            .write_all(&terrapipe::proc_query(query))
+            .await
+            .unwrap();
+        let mut response = vec![0; res_should_be.len()];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(&response, res_should_be, "{}:{}", __func__!(), query);
+    }
+    stream
+}
+
+/// Test `CONFIG GET`, `CONFIG SET` and `CONFIG REWRITE`
+async fn test_config(mut stream: TcpStream) -> TcpStream {
+    let queries = [
+        (
+            "CONFIG GET limits.idle_timeout",
+            "#2\n*1\n#2\n&1\n+1\n0\n".to_owned().into_bytes(),
+        ),
+        (
+            "CONFIG SET limits.idle_timeout 300",
+            fresp::R_OKAY.to_owned(),
+        ),
+        (
+            "CONFIG GET limits.idle_timeout",
+            "#2\n*1\n#2\n&1\n+3\n300\n".to_owned().into_bytes(),
+        ),
+        ("READONLY ON", fresp::R_OKAY.to_owned()),
+        (
+            "CONFIG GET server.readonly",
+            "#2\n*1\n#2\n&1\n+4\ntrue\n".to_owned().into_bytes(),
+        ),
+        ("CONFIG SET server.readonly false", fresp::R_OKAY.to_owned()),
+        ("SET x 100", fresp::R_OKAY.to_owned()),
+        (
+            "CONFIG SET server.port 2004",
+            responses::other_error("'server.port' can't be changed while the server is running"),
+        ),
+        ("CONFIG GET nothing.here", fresp::R_NIL.to_owned()),
+        (
+            "CONFIG SET limits.idle_timeout",
+            fresp::R_ACTION_ERR.to_owned(),
+        ),
+        ("CONFIG SET limits.idle_timeout 0", fresp::R_OKAY.to_owned()),
+        (
+            "CONFIG REWRITE",
+            responses::other_error("The server wasn't started with a configuration file"),
+        ),
+    ];
+    for (query, res_should_be) in queries.iter() {
+        stream
+            .write_all(&terrapipe::proc_query(query))
+            .await
+            .unwrap();
+        let mut response = vec![0; res_should_be.len()];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(&response, res_should_be, "{}:{}", __func__!(), query);
+    }
+    stream
+}
+
+/// Test `SLOWLOG MAXLEN`, which resizes the slowlog like `CONFIG SET slowlog.maxlen`
+async fn test_slowlog_maxlen(mut stream: TcpStream) -> TcpStream {
+    let queries = [
+        (
+            "SLOWLOG MAXLEN",
+            "#2\n*1\n#2\n&1\n:3\n128\n".to_owned().into_bytes(),
+        ),
+        ("SLOWLOG MAXLEN 16", fresp::R_OKAY.to_owned()),
+        (
+            "SLOWLOG MAXLEN",
+            "#2\n*1\n#2\n&1\n:2\n16\n".to_owned().into_bytes(),
+        ),
+        (
+            "CONFIG GET slowlog.maxlen",
+            "#2\n*1\n#2\n&1\n+2\n16\n".to_owned().into_bytes(),
+        ),
+        ("SLOWLOG MAXLEN many", fresp::R_ACTION_ERR.to_owned()),
+        ("SLOWLOG MAXLEN 128", fresp::R_OKAY.to_owned()),
+    ];
+    for (query, res_should_be) in queries.iter() {
+        stream
+            .write_all(&terrapipe::proc_query(query))
+            .await
+            .unwrap();
+        let mut response = vec![0; res_should_be.len()];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(&response, res_should_be, "{}:{}", __func__!(), query);
+    }
+    stream
+}
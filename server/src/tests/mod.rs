@@ -21,11 +21,11 @@
 
 //! This module contains automated tests for queries
 
-use crate::config::SnapshotConfig;
+use crate::config::ParsedConfig;
 use crate::coredb::CoreDB;
+use crate::diskstore::encryption::Keyring;
 use crate::dbnet;
 use crate::protocol::responses::fresp;
-use crate::BGSave;
 use libtdb::terrapipe;
 use std::future::Future;
 use std::net::{Shutdown, SocketAddr};
@@ -53,7 +53,12 @@ async fn start_server() -> (Option<SocketAddr>, CoreDB) {
     // running, or use it if it is already running, we just return none if we failed
     // to bind to the port, since this will _almost_ never happen on our CI
     let listener = TcpListener::bind(ADDR).await.unwrap();
-    let db = CoreDB::new(BGSave::default(), SnapshotConfig::default()).unwrap();
+    // Keep the dump file out of the working directory
+    let dir = std::env::temp_dir().join(format!("tdb-test-server-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut cfg = ParsedConfig::default();
+    cfg.storage.set_data_dir(&dir);
+    let db = CoreDB::new(cfg, None, Keyring::none(), None).unwrap();
     let asyncdb = db.clone();
     let addr = if let Ok(addr) = listener.local_addr() {
         Some(addr)
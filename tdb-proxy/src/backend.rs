@@ -0,0 +1,294 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # Backends
+//!
+//! This module has the connections to the backend servers, the reader for their
+//! responses, and the health checks that tell the proxy which backends are up
+
+use crate::proxy::Proxy;
+use bytes::{Buf, BytesMut};
+use libtdb::terrapipe;
+use libtdb::BUF_CAP;
+use std::io::{self, ErrorKind};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tdb::protocol::responses;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time;
+
+/// How long we wait for a backend to accept a connection or to reply
+const BACKEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A backend server
+pub struct Backend {
+    /// The `host:port` of the server
+    addr: String,
+    /// Whether the server passed its last health check
+    up: AtomicBool,
+}
+
+impl Backend {
+    /// A backend at `addr`, which is taken to be up until it fails a health check
+    pub fn new(addr: String) -> Self {
+        Backend {
+            addr,
+            up: AtomicBool::new(true),
+        }
+    }
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+    /// Returns true if the server passed its last health check
+    pub fn is_up(&self) -> bool {
+        self.up.load(Ordering::Acquire)
+    }
+    /// Record the outcome of a health check
+    pub fn mark(&self, up: bool) {
+        if self.up.swap(up, Ordering::AcqRel) != up {
+            if up {
+                log::info!("Backend {} is up again", self.addr);
+            } else {
+                log::warn!("Backend {} is down", self.addr);
+            }
+        }
+    }
+    /// Open a new connection to the server
+    pub async fn connect(&self) -> io::Result<BackendConn> {
+        let stream = time::timeout(BACKEND_TIMEOUT, TcpStream::connect(&self.addr))
+            .await
+            .map_err(|_| io::Error::from(ErrorKind::TimedOut))??;
+        Ok(BackendConn {
+            stream,
+            buffer: BytesMut::with_capacity(BUF_CAP),
+        })
+    }
+    /// Returns true if the server replies to a `HEYA` on a new connection
+    async fn check(&self) -> bool {
+        let reply = async {
+            let mut con = self.connect().await?;
+            con.send(&terrapipe::proc_query("HEYA")).await?;
+            con.recv().await
+        };
+        match reply.await {
+            Ok(reply) => reply.raw == *responses::fresp::R_HEYA,
+            Err(_) => false,
+        }
+    }
+}
+
+/// Check on the backend at position `backend` in the proxy every `interval`, forever
+pub async fn health_check(proxy: Arc<Proxy>, backend: usize, interval: Duration) {
+    let backend = &proxy.backends()[backend];
+    loop {
+        backend.mark(backend.check().await);
+        time::delay_for(interval).await;
+    }
+}
+
+/// A connection to a backend server
+pub struct BackendConn {
+    stream: TcpStream,
+    /// The responses that have been read but not parsed yet
+    buffer: BytesMut,
+}
+
+impl BackendConn {
+    /// Send a query packet
+    pub async fn send(&mut self, packet: &[u8]) -> io::Result<()> {
+        self.stream.write_all(packet).await
+    }
+    /// Read the next response
+    pub async fn recv(&mut self) -> io::Result<Reply> {
+        loop {
+            match Reply::parse(&self.buffer) {
+                Ok(Some((reply, len))) => {
+                    self.buffer.advance(len);
+                    return Ok(reply);
+                }
+                Ok(None) => (),
+                Err(()) => return Err(ErrorKind::InvalidData.into()),
+            }
+            let read = time::timeout(BACKEND_TIMEOUT, self.stream.read_buf(&mut self.buffer))
+                .await
+                .map_err(|_| io::Error::from(ErrorKind::TimedOut))??;
+            if read == 0 {
+                return Err(ErrorKind::UnexpectedEof.into());
+            }
+        }
+    }
+}
+
+/// An element of a response
+#[derive(Debug, PartialEq, Clone)]
+pub enum Element {
+    /// A string (`+`)
+    Str(Vec<u8>),
+    /// A response code (`!`), with its body
+    Code(Vec<u8>),
+    /// An unsigned integer (`:`)
+    Int(u64),
+}
+
+impl Element {
+    /// Append the element to a response
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let int;
+        let (tsymbol, data) = match self {
+            Element::Str(data) => (b'+', data.as_slice()),
+            Element::Code(data) => (b'!', data.as_slice()),
+            Element::Int(val) => {
+                int = val.to_string();
+                (b':', int.as_bytes())
+            }
+        };
+        buf.push(tsymbol);
+        buf.extend(data.len().to_string().as_bytes());
+        buf.push(b'\n');
+        buf.extend(data);
+        buf.push(b'\n');
+    }
+}
+
+/// Returns a complete response with the `elements` in one data group
+pub fn group(elements: &[Element]) -> Vec<u8> {
+    let count = elements.len().to_string();
+    let mut buf = format!("#2\n*1\n#{}\n&{}\n", count.len() + 1, count).into_bytes();
+    elements.iter().for_each(|element| element.encode(&mut buf));
+    buf
+}
+
+/// A response from a backend
+#[derive(Debug, PartialEq)]
+pub struct Reply {
+    /// The response as it was sent by the backend
+    pub raw: Vec<u8>,
+    /// The elements in the data group of the response
+    pub elements: Vec<Element>,
+}
+
+impl Reply {
+    /// Parse a response from the beginning of `buf`, which looks like:
+    /// ```text
+    /// #2\n*1\n#<len>\n&<count>\n<tsymbol><len>\n<data>\n...
+    /// ```
+    /// Returns the response and its length, or `None` if more data is needed to
+    /// parse it
+    pub fn parse(buf: &[u8]) -> Result<Option<(Self, usize)>, ()> {
+        let mut pos = 0;
+        let mut header = Vec::with_capacity(4);
+        for tsymbol in b"#*#&" {
+            let line = match next_line(buf, &mut pos) {
+                Some(line) => line,
+                None => return Ok(None),
+            };
+            match line.split_first() {
+                Some((symbol, value)) if symbol == tsymbol => header.push(parse_int(value)?),
+                _ => return Err(()),
+            }
+        }
+        if header[1] != 1 {
+            // The server only sends back one data group
+            return Err(());
+        }
+        let count = header[3] as usize;
+        let mut elements = Vec::with_capacity(count);
+        for _ in 0..count {
+            let (tsymbol, len) = match next_line(buf, &mut pos) {
+                Some(line) => match line.split_first() {
+                    Some((tsymbol, len)) => (*tsymbol, parse_int(len)? as usize),
+                    None => return Err(()),
+                },
+                None => return Ok(None),
+            };
+            if buf.len() < pos + len + 1 {
+                return Ok(None);
+            }
+            let data = &buf[pos..pos + len];
+            if buf[pos + len] != b'\n' {
+                return Err(());
+            }
+            pos += len + 1;
+            elements.push(match tsymbol {
+                b'+' => Element::Str(data.to_owned()),
+                b'!' => Element::Code(data.to_owned()),
+                b':' => Element::Int(parse_int(data)?),
+                _ => return Err(()),
+            });
+        }
+        let reply = Reply {
+            raw: buf[..pos].to_owned(),
+            elements,
+        };
+        Ok(Some((reply, pos)))
+    }
+}
+
+/// Returns the line that starts at `pos` without its LF, and moves `pos` past it
+fn next_line<'a>(buf: &'a [u8], pos: &mut usize) -> Option<&'a [u8]> {
+    let len = buf[*pos..].iter().position(|byte| *byte == b'\n')?;
+    let line = &buf[*pos..*pos + len];
+    *pos += len + 1;
+    Some(line)
+}
+
+fn parse_int(value: &[u8]) -> Result<u64, ()> {
+    std::str::from_utf8(value)
+        .map_err(|_| ())?
+        .parse()
+        .map_err(|_| ())
+}
+
+#[test]
+fn test_reply_parse() {
+    let resp = b"#2\n*1\n#2\n&3\n+4\nHEY!\n!1\n1\n:2\n42\n";
+    let (reply, len) = Reply::parse(resp).unwrap().unwrap();
+    assert_eq!(len, resp.len());
+    assert_eq!(reply.raw, resp.to_vec());
+    assert_eq!(
+        reply.elements,
+        vec![
+            Element::Str(b"HEY!".to_vec()),
+            Element::Code(b"1".to_vec()),
+            Element::Int(42)
+        ]
+    );
+    assert_eq!(group(&reply.elements), resp.to_vec());
+    // Values can have LFs in them
+    let resp = b"#2\n*1\n#2\n&1\n+3\na\nb\n";
+    let (reply, _) = Reply::parse(resp).unwrap().unwrap();
+    assert_eq!(reply.elements, vec![Element::Str(b"a\nb".to_vec())]);
+    // Any prefix of a response is incomplete
+    for len in 0..resp.len() {
+        assert_eq!(Reply::parse(&resp[..len]), Ok(None));
+    }
+    assert_eq!(Reply::parse(b"#2\n*1\n#2\n&1\n?1\n0\n"), Err(()));
+    assert_eq!(
+        Reply::parse(&responses::fresp::R_OKAY)
+            .unwrap()
+            .unwrap()
+            .0
+            .elements,
+        vec![Element::Code(b"0".to_vec())]
+    );
+}
@@ -0,0 +1,146 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! The configuration file of the proxy
+//!
+//! It looks like this:
+//! ```toml
+//! [proxy]
+//! host = "127.0.0.1"
+//! port = 2010
+//! backends = ["127.0.0.1:2003", "127.0.0.1:2004"]
+//! health_check = 5
+//! eject = false
+//! ```
+
+use serde::Deserialize;
+use std::fs;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// The port that the proxy listens on if none is given
+const DEFAULT_PORT: u16 = 2010;
+/// How often the backends are checked on if no interval is given, in seconds
+const DEFAULT_HEALTH_CHECK: u64 = 5;
+
+/// The configuration file
+#[derive(Deserialize, Debug, PartialEq)]
+struct Config {
+    proxy: ConfigKeyProxy,
+}
+
+/// The `[proxy]` section of the configuration file
+#[derive(Deserialize, Debug, PartialEq)]
+struct ConfigKeyProxy {
+    /// The IP address that the proxy listens on
+    host: Option<IpAddr>,
+    /// The port that the proxy listens on
+    port: Option<u16>,
+    /// The `host:port` of every backend server
+    backends: Vec<String>,
+    /// How often the backends are checked on, in seconds
+    health_check: Option<u64>,
+    /// Whether the keys of a backend that is down are served by the next backend on
+    /// the ring until it's back
+    eject: Option<bool>,
+}
+
+/// The parsed configuration of the proxy
+#[derive(Debug, PartialEq)]
+pub struct ProxyConfig {
+    /// The IP address that the proxy listens on
+    pub host: IpAddr,
+    /// The port that the proxy listens on
+    pub port: u16,
+    /// The `host:port` of every backend server, in the order that they were given in
+    pub backends: Vec<String>,
+    /// How often the backends are checked on
+    pub health_check: Duration,
+    /// Whether the keys of a backend that is down are served by the next backend on
+    /// the ring until it's back. Otherwise, queries for them fail until it's back
+    pub eject: bool,
+}
+
+impl ProxyConfig {
+    /// Read the configuration from the file at `path`
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let file = fs::read_to_string(path)
+            .map_err(|e| format!("Couldn't read the configuration file '{}': {}", path, e))?;
+        Self::parse(&file)
+    }
+    /// Parse the configuration from the contents of a configuration file
+    fn parse(file: &str) -> Result<Self, String> {
+        let cfg: Config = toml::from_str(file)
+            .map_err(|e| format!("Couldn't parse the configuration file: {}", e))?;
+        let ConfigKeyProxy {
+            host,
+            port,
+            backends,
+            health_check,
+            eject,
+        } = cfg.proxy;
+        if backends.is_empty() {
+            return Err("No backends were given".to_owned());
+        }
+        for (idx, backend) in backends.iter().enumerate() {
+            if backends[..idx].contains(backend) {
+                return Err(format!("The backend '{}' was given twice", backend));
+            }
+        }
+        let health_check = health_check.unwrap_or(DEFAULT_HEALTH_CHECK);
+        if health_check == 0 {
+            return Err("The health check interval must be at least one second".to_owned());
+        }
+        Ok(ProxyConfig {
+            host: host.unwrap_or_else(|| IpAddr::from([127, 0, 0, 1])),
+            port: port.unwrap_or(DEFAULT_PORT),
+            backends,
+            health_check: Duration::from_secs(health_check),
+            eject: eject.unwrap_or(false),
+        })
+    }
+}
+
+#[test]
+fn test_config() {
+    let cfg = ProxyConfig::parse(
+        r#"
+        [proxy]
+        port = 2011
+        backends = ["127.0.0.1:2003", "127.0.0.1:2004"]
+        eject = true
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        cfg,
+        ProxyConfig {
+            host: IpAddr::from([127, 0, 0, 1]),
+            port: 2011,
+            backends: vec!["127.0.0.1:2003".to_owned(), "127.0.0.1:2004".to_owned()],
+            health_check: Duration::from_secs(5),
+            eject: true,
+        }
+    );
+    assert!(ProxyConfig::parse("[proxy]\nbackends = []").is_err());
+    assert!(ProxyConfig::parse("[proxy]\nbackends = [\"a:1\", \"a:1\"]").is_err());
+    assert!(ProxyConfig::parse("[proxy]\nbackends = [\"a:1\"]\nhealth_check = 0").is_err());
+}
@@ -0,0 +1,84 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # tdb-proxy
+//!
+//! A proxy for clients that can't follow cluster redirects. It speaks Terrapipe to
+//! clients like a `tdb` server would, and spreads the keys over several independent
+//! `tdb` servers (the _backends_) with consistent hashing. Queries with keys on several
+//! backends are split up, and the replies are put back together. Every backend is
+//! health-checked in the background, so that queries for keys on a backend that is down
+//! fail right away (or, with `eject = true`, go to the next backend on the ring)
+
+use clap::{load_yaml, App};
+use env_logger::Builder;
+use std::env;
+use std::process;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::signal;
+mod backend;
+mod config;
+mod proxy;
+mod ring;
+use config::ProxyConfig;
+use proxy::Proxy;
+
+#[tokio::main]
+async fn main() {
+    Builder::new()
+        .parse_filters(&env::var("TDB_LOG").unwrap_or("info".to_owned()))
+        .init();
+    let cli = load_yaml!("cli.yml");
+    let matches = App::from_yaml(cli).get_matches();
+    let cfg = match ProxyConfig::from_file(matches.value_of("config").unwrap()) {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            log::error!("{}", e);
+            process::exit(1);
+        }
+    };
+    let listener = match TcpListener::bind((cfg.host, cfg.port)).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::error!("Failed to bind to socket with error: '{}'", e);
+            process::exit(1);
+        }
+    };
+    let proxy = Arc::new(Proxy::new(cfg.backends, cfg.eject));
+    for backend in 0..proxy.backends().len() {
+        tokio::spawn(backend::health_check(
+            proxy.clone(),
+            backend,
+            cfg.health_check,
+        ));
+    }
+    log::info!(
+        "Proxying {}:{} to {} backends",
+        cfg.host,
+        cfg.port,
+        proxy.backends().len()
+    );
+    tokio::select! {
+        _ = proxy::run(listener, proxy) => {}
+        _ = signal::ctrl_c() => log::info!("Stopping the proxy"),
+    }
+}
@@ -0,0 +1,485 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # Routing queries
+//!
+//! Queries for one key are sent as they are to the backend that the key belongs to.
+//! `MGET`, `MSET`, `MUPDATE`, `USET`, `DEL` and `EXISTS` are split into one query
+//! per backend with the keys that belong to it, and the replies are merged into the
+//! reply that one server would have sent. `DBSIZE` and `FLUSHDB` are sent to every
+//! backend.
+//!
+//! Split queries aren't atomic: if a backend fails halfway through an `MSET`, the
+//! other backends will still have set their keys. That's why the strong actions
+//! (`SSET`, `SDEL` and `SUPDATE`) are only run if all of their keys belong to the
+//! same backend, which can be made sure of with hash tags
+
+use crate::backend::{self, Backend, BackendConn, Element, Reply};
+use crate::ring::Ring;
+use bytes::{Buf, BytesMut};
+use libtdb::terrapipe;
+use libtdb::BUF_CAP;
+use std::sync::Arc;
+use tdb::protocol::deserializer::{self, ParseResult, Query};
+use tdb::protocol::responses;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// The largest query that we're willing to buffer, which is the server's default
+const MAX_QUERY_SIZE: usize = 64 * 1024 * 1024;
+/// The actions whose arguments are keys
+const KEY_ACTIONS: [&str; 6] = ["GET", "DEL", "EXISTS", "MGET", "SDEL", "KEYLEN"];
+/// The actions whose arguments are pairs of keys and values
+const PAIR_ACTIONS: [&str; 7] = [
+    "SET", "UPDATE", "MSET", "MUPDATE", "SSET", "SUPDATE", "USET",
+];
+
+/// The proxy's view of the backends
+pub struct Proxy {
+    backends: Vec<Backend>,
+    ring: Ring,
+    /// Whether the keys of a backend that is down are served by the next backend on
+    /// the ring
+    eject: bool,
+}
+
+/// How a query is run
+#[derive(Debug, PartialEq)]
+enum Plan {
+    /// Send back this response without asking any backend
+    Reply(Vec<u8>),
+    /// Send the query to this backend and send back its reply
+    Forward(usize),
+    /// Send a part of the query to each backend and merge the replies
+    Split(Vec<Part>, Merge),
+    /// Send the query to each of these backends and merge the replies
+    Broadcast(Vec<usize>, Merge),
+}
+
+/// The part of a split query that is sent to one backend
+#[derive(Debug, PartialEq)]
+struct Part {
+    backend: usize,
+    /// The positions of the keys (or pairs) of this part among the keys of the query
+    keys: Vec<usize>,
+    /// The query for this part
+    args: Vec<String>,
+}
+
+/// How the replies from several backends are merged
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Merge {
+    /// Every reply is a count and we send back the sum
+    Sum,
+    /// Every reply has a value for each key of its part, and we send back the values
+    /// in the order of the keys in the query
+    Values,
+    /// Every reply is an _Okay_ and so is ours
+    Okay,
+}
+
+impl Proxy {
+    /// Create a proxy for the backends at `addrs`
+    pub fn new(addrs: Vec<String>, eject: bool) -> Self {
+        Proxy {
+            ring: Ring::new(&addrs),
+            backends: addrs.into_iter().map(Backend::new).collect(),
+            eject,
+        }
+    }
+    pub fn backends(&self) -> &[Backend] {
+        &self.backends
+    }
+    /// Returns the backend that should serve `key`, or the response to send if the
+    /// backend is down
+    fn route(&self, key: &str) -> Result<usize, Vec<u8>> {
+        let backend = self.ring.route(key);
+        if self.backends[backend].is_up() {
+            return Ok(backend);
+        }
+        if self.eject {
+            if let Some(backend) = self
+                .ring
+                .walk(key)
+                .find(|backend| self.backends[*backend].is_up())
+            {
+                return Ok(backend);
+            }
+        }
+        Err(self.down(backend))
+    }
+    /// The response to send when `backend` is down
+    fn down(&self, backend: usize) -> Vec<u8> {
+        responses::other_error(&format!(
+            "Backend {} is down",
+            self.backends[backend].addr()
+        ))
+    }
+    /// Work out how the query with the arguments `args` should be run
+    fn plan(&self, args: &[String]) -> Plan {
+        let action = match args.first() {
+            Some(action) => action.to_uppercase(),
+            None => return Plan::Reply(responses::fresp::R_PACKET_ERR.to_owned()),
+        };
+        let merge = match action.as_str() {
+            "HEYA" => return Plan::Reply(responses::fresp::R_HEYA.to_owned()),
+            "DBSIZE" => return self.broadcast(Merge::Sum),
+            "FLUSHDB" => return self.broadcast(Merge::Okay),
+            "MGET" => Some(Merge::Values),
+            "DEL" | "EXISTS" | "MSET" | "MUPDATE" | "USET" => Some(Merge::Sum),
+            _ => None,
+        };
+        let step = if KEY_ACTIONS.contains(&action.as_str()) {
+            1
+        } else if PAIR_ACTIONS.contains(&action.as_str()) {
+            2
+        } else {
+            return Plan::Reply(responses::other_error("Action not supported by the proxy"));
+        };
+        let howmany = args.len() - 1;
+        if howmany == 0 || howmany % step != 0 {
+            return Plan::Reply(responses::fresp::R_ACTION_ERR.to_owned());
+        }
+        let mut parts: Vec<Part> = Vec::new();
+        for (idx, arg) in args[1..].chunks(step).enumerate() {
+            let backend = match self.route(&arg[0]) {
+                Ok(backend) => backend,
+                Err(resp) => return Plan::Reply(resp),
+            };
+            let part = match parts.iter_mut().find(|part| part.backend == backend) {
+                Some(part) => part,
+                None => {
+                    parts.push(Part {
+                        backend,
+                        keys: Vec::new(),
+                        args: vec![args[0].clone()],
+                    });
+                    parts.last_mut().unwrap()
+                }
+            };
+            part.keys.push(idx);
+            part.args.extend_from_slice(arg);
+        }
+        match (parts.len(), merge) {
+            (1, _) => Plan::Forward(parts[0].backend),
+            (_, Some(merge)) => Plan::Split(parts, merge),
+            (_, None) => Plan::Reply(responses::other_error("Keys span several backends")),
+        }
+    }
+    /// Plan to send a query to every backend
+    fn broadcast(&self, merge: Merge) -> Plan {
+        let mut backends = Vec::with_capacity(self.backends.len());
+        for (idx, backend) in self.backends.iter().enumerate() {
+            if backend.is_up() {
+                backends.push(idx);
+            } else if !self.eject {
+                return Plan::Reply(self.down(idx));
+            }
+        }
+        if backends.is_empty() {
+            return Plan::Reply(self.down(0));
+        }
+        Plan::Broadcast(backends, merge)
+    }
+}
+
+/// Merge the `replies` to the `parts` of a query (or to a broadcast query, with no
+/// parts) into one response
+fn merge(merge: Merge, parts: &[Part], replies: Vec<Reply>) -> Vec<u8> {
+    match merge {
+        Merge::Sum => {
+            let mut sum = 0;
+            for reply in replies {
+                match reply.elements.as_slice() {
+                    [Element::Int(count)] => sum += count,
+                    // Something went wrong, so send back what the backend said
+                    _ => return reply.raw,
+                }
+            }
+            backend::group(&[Element::Int(sum)])
+        }
+        Merge::Values => {
+            let howmany = parts.iter().map(|part| part.keys.len()).sum();
+            let mut values = vec![Element::Code(Vec::new()); howmany];
+            for (part, reply) in parts.iter().zip(replies) {
+                if reply.elements.len() != part.keys.len() {
+                    return reply.raw;
+                }
+                for (key, value) in part.keys.iter().zip(reply.elements) {
+                    values[*key] = value;
+                }
+            }
+            backend::group(&values)
+        }
+        Merge::Okay => {
+            for reply in replies {
+                if reply.raw != *responses::fresp::R_OKAY {
+                    return reply.raw;
+                }
+            }
+            responses::fresp::R_OKAY.to_owned()
+        }
+    }
+}
+
+/// Accept clients forever
+pub async fn run(mut listener: TcpListener, proxy: Arc<Proxy>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let proxy = proxy.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = Session::new(proxy).serve(stream).await {
+                        log::error!("Error: {}", e);
+                    }
+                });
+            }
+            Err(e) => log::error!("Failed to accept connection with error: '{}'", e),
+        }
+    }
+}
+
+/// A client's connection to the proxy, with its own connections to the backends
+struct Session {
+    proxy: Arc<Proxy>,
+    /// The connection to each backend, which is opened when it's first needed
+    conns: Vec<Option<BackendConn>>,
+}
+
+impl Session {
+    fn new(proxy: Arc<Proxy>) -> Self {
+        let conns = proxy.backends.iter().map(|_| None).collect();
+        Session { proxy, conns }
+    }
+    /// Run the client's queries until it disconnects
+    async fn serve(&mut self, mut stream: TcpStream) -> Result<(), String> {
+        let mut buffer = BytesMut::with_capacity(BUF_CAP);
+        loop {
+            let resp = match deserializer::parse(&buffer) {
+                ParseResult::Query(Query::Simple(act), forward) => {
+                    buffer.advance(forward);
+                    self.run(act.get_ref()).await
+                }
+                ParseResult::Query(Query::Pipelined(_), forward) => {
+                    buffer.advance(forward);
+                    responses::other_error("Pipelined queries aren't supported by the proxy")
+                }
+                ParseResult::BadPacket(len) => {
+                    buffer.advance(len);
+                    responses::fresp::R_PACKET_ERR.to_owned()
+                }
+                ParseResult::Incomplete => {
+                    if buffer.len() > MAX_QUERY_SIZE {
+                        return Err("Query too large".to_owned());
+                    }
+                    match stream.read_buf(&mut buffer).await {
+                        Ok(0) if buffer.is_empty() => return Ok(()),
+                        Ok(0) => return Err("Connection reset while reading".to_owned()),
+                        Ok(_) => continue,
+                        Err(e) => return Err(e.to_string()),
+                    }
+                }
+            };
+            stream.write_all(&resp).await.map_err(|e| e.to_string())?;
+        }
+    }
+    /// Run a query and return the response for the client
+    async fn run(&mut self, args: &[String]) -> Vec<u8> {
+        let plan = self.proxy.plan(args);
+        let (queries, merger, parts) = match plan {
+            Plan::Reply(resp) => return resp,
+            Plan::Forward(backend) => {
+                return match self.exchange(vec![(backend, args.to_vec())]).await {
+                    Ok(mut replies) => replies.remove(0).raw,
+                    Err(resp) => resp,
+                };
+            }
+            Plan::Split(parts, merger) => {
+                let queries = parts
+                    .iter()
+                    .map(|part| (part.backend, part.args.clone()))
+                    .collect();
+                (queries, merger, parts)
+            }
+            Plan::Broadcast(backends, merger) => {
+                let queries = backends
+                    .into_iter()
+                    .map(|backend| (backend, args.to_vec()))
+                    .collect();
+                (queries, merger, Vec::new())
+            }
+        };
+        match self.exchange(queries).await {
+            Ok(replies) => merge(merger, &parts, replies),
+            Err(resp) => resp,
+        }
+    }
+    /// Send each query to its backend and then read the replies in the same order, so
+    /// that the backends run their queries at the same time
+    async fn exchange(
+        &mut self,
+        queries: Vec<(usize, Vec<String>)>,
+    ) -> Result<Vec<Reply>, Vec<u8>> {
+        for (backend, args) in queries.iter() {
+            let backend = *backend;
+            if self.conns[backend].is_none() {
+                match self.proxy.backends[backend].connect().await {
+                    Ok(con) => self.conns[backend] = Some(con),
+                    Err(e) => return Err(self.failed(backend, e)),
+                }
+            }
+            let con = self.conns[backend].as_mut().unwrap();
+            if let Err(e) = con.send(&terrapipe::proc_args(args)).await {
+                return Err(self.failed(backend, e));
+            }
+        }
+        let mut replies = Vec::with_capacity(queries.len());
+        for (backend, _) in queries {
+            match self.conns[backend].as_mut().unwrap().recv().await {
+                Ok(reply) => replies.push(reply),
+                Err(e) => return Err(self.failed(backend, e)),
+            }
+        }
+        Ok(replies)
+    }
+    /// Drop the connection to `backend` after an error and return the response to send
+    fn failed(&mut self, backend: usize, e: std::io::Error) -> Vec<u8> {
+        let addr = self.proxy.backends[backend].addr();
+        log::warn!(
+            "Lost the connection to backend {} with error: '{}'",
+            addr,
+            e
+        );
+        // The other backends that were sent a query still have to reply to it
+        self.conns.iter_mut().for_each(|con| *con = None);
+        responses::other_error(&format!("Backend {} is unreachable", addr))
+    }
+}
+
+#[cfg(test)]
+fn reply(resp: &[u8]) -> Reply {
+    Reply::parse(resp).unwrap().unwrap().0
+}
+
+#[test]
+fn test_plan_and_merge() {
+    let proxy = Proxy::new(
+        vec!["127.0.0.1:2003".to_owned(), "127.0.0.1:2004".to_owned()],
+        false,
+    );
+    let args = |query: &str| -> Vec<String> { query.split(' ').map(String::from).collect() };
+    // Find two keys that belong to different backends
+    let (x, y) = (
+        "x",
+        (0..)
+            .map(|key| key.to_string())
+            .find(|key| proxy.ring.route(key) != proxy.ring.route("x"))
+            .unwrap(),
+    );
+    let (bx, by) = (proxy.ring.route(x), proxy.ring.route(&y));
+    assert_eq!(
+        proxy.plan(&args("HEYA")),
+        Plan::Reply(responses::fresp::R_HEYA.to_owned())
+    );
+    assert_eq!(proxy.plan(&args("get x")), Plan::Forward(bx));
+    assert_eq!(proxy.plan(&args("MSET x 1")), Plan::Forward(bx));
+    assert_eq!(
+        proxy.plan(&args("MSET x 1 y")),
+        Plan::Reply(responses::fresp::R_ACTION_ERR.to_owned())
+    );
+    assert_eq!(
+        proxy.plan(&args(&format!("SSET x 1 {} 2", y))),
+        Plan::Reply(responses::other_error("Keys span several backends"))
+    );
+    assert_eq!(
+        proxy.plan(&args("CLIENT LIST")),
+        Plan::Reply(responses::other_error("Action not supported by the proxy"))
+    );
+    assert_eq!(
+        proxy.plan(&args("DBSIZE")),
+        Plan::Broadcast(vec![0, 1], Merge::Sum)
+    );
+    // An MGET that is split and put back together
+    let plan = proxy.plan(&args(&format!("MGET {} x {}", y, y)));
+    let parts = match plan {
+        Plan::Split(parts, Merge::Values) => parts,
+        plan => panic!("Unexpected plan {:?}", plan),
+    };
+    assert_eq!(
+        parts,
+        vec![
+            Part {
+                backend: by,
+                keys: vec![0, 2],
+                args: args(&format!("MGET {} {}", y, y)),
+            },
+            Part {
+                backend: bx,
+                keys: vec![1],
+                args: args("MGET x"),
+            },
+        ]
+    );
+    let replies = vec![
+        reply(b"#2\n*1\n#2\n&2\n+1\ny\n+1\ny\n"),
+        reply(b"#2\n*1\n#2\n&1\n!1\n1\n"),
+    ];
+    assert_eq!(
+        merge(Merge::Values, &parts, replies),
+        b"#2\n*1\n#2\n&3\n+1\ny\n!1\n1\n+1\ny\n".to_vec()
+    );
+    // Counts are added up, and errors are passed on
+    let replies = vec![
+        reply(b"#2\n*1\n#2\n&1\n:1\n2\n"),
+        reply(b"#2\n*1\n#2\n&1\n:1\n1\n"),
+    ];
+    assert_eq!(
+        merge(Merge::Sum, &[], replies),
+        b"#2\n*1\n#2\n&1\n:1\n3\n".to_vec()
+    );
+    let replies = vec![
+        reply(b"#2\n*1\n#2\n&1\n:1\n2\n"),
+        reply(&responses::fresp::R_SERVER_ERR),
+    ];
+    assert_eq!(
+        merge(Merge::Sum, &[], replies),
+        responses::fresp::R_SERVER_ERR.to_owned()
+    );
+    // Queries for keys on a backend that is down fail, unless they're ejected
+    proxy.backends[by].mark(false);
+    assert_eq!(
+        proxy.plan(&args(&format!("GET {}", y))),
+        Plan::Reply(proxy.down(by))
+    );
+    assert_eq!(proxy.plan(&args("DBSIZE")), Plan::Reply(proxy.down(by)));
+    let ejecting = Proxy {
+        eject: true,
+        ..proxy
+    };
+    assert_eq!(
+        ejecting.plan(&args(&format!("GET {}", y))),
+        Plan::Forward(bx)
+    );
+    assert_eq!(
+        ejecting.plan(&args("DBSIZE")),
+        Plan::Broadcast(vec![bx], Merge::Sum)
+    );
+}
@@ -0,0 +1,127 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # Consistent hashing
+//!
+//! Every backend is placed at [`VNODES`] points on a ring of 64-bit hashes, and a key
+//! belongs to the backend at the first point at or after the hash of the key. Adding
+//! or removing a backend only moves the keys between it and its neighbours on the
+//! ring, instead of reshuffling all of them.
+//!
+//! Like in cluster mode, only the hash tag of a key (the part between the first `{` and
+//! the next `}`) is hashed if it has one, so that related keys can be kept on the
+//! same backend
+
+use libtdb::cluster;
+
+/// The number of points on the ring for every backend
+const VNODES: usize = 160;
+
+/// A consistent hash ring
+pub struct Ring {
+    /// The points on the ring in ascending order, each with the position of its backend
+    points: Vec<(u64, usize)>,
+}
+
+impl Ring {
+    /// Create a ring with the `backends`, which are known by their positions in the slice
+    pub fn new<T: AsRef<str>>(backends: &[T]) -> Self {
+        let mut points = Vec::with_capacity(backends.len() * VNODES);
+        for (node, backend) in backends.iter().enumerate() {
+            for vnode in 0..VNODES {
+                let point = hash(format!("{}-{}", backend.as_ref(), vnode).as_bytes());
+                points.push((point, node));
+            }
+        }
+        points.sort_unstable();
+        Ring { points }
+    }
+    /// Returns the backends on the ring starting at the one that `key` belongs to, in
+    /// the order that they should take over the key if the ones before them are down
+    ///
+    /// A backend can be returned more than once
+    pub fn walk<'a>(&'a self, key: &str) -> impl Iterator<Item = usize> + 'a {
+        let point = hash(cluster::hash_tag(key).as_bytes());
+        let start = match self.points.binary_search_by(|(p, _)| p.cmp(&point)) {
+            Ok(idx) | Err(idx) => idx,
+        };
+        self.points[start..]
+            .iter()
+            .chain(self.points[..start].iter())
+            .map(|(_, node)| *node)
+    }
+    /// Returns the backend that `key` belongs to
+    pub fn route(&self, key: &str) -> usize {
+        // The ring is never empty since we need at least one backend
+        self.walk(key).next().unwrap_or(0)
+    }
+}
+
+/// The 64-bit FNV-1a hash of `data`, with the bits mixed some more (like the finalizer
+/// of MurmurHash3) to spread out the points of similar backend names
+fn hash(data: &[u8]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for byte in data {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
+
+#[test]
+fn test_ring() {
+    let backends = ["127.0.0.1:2003", "127.0.0.1:2004", "127.0.0.1:2005"];
+    let ring = Ring::new(&backends);
+    let keys: Vec<String> = (0..30000).map(|key| format!("key{}", key)).collect();
+    let mut counts = [0usize; 3];
+    keys.iter().for_each(|key| counts[ring.route(key)] += 1);
+    // Every backend should get its fair share of the keys, give or take
+    assert!(
+        counts.iter().all(|count| *count > 7000 && *count < 13000),
+        "{:?}",
+        counts
+    );
+    // Adding a backend should only move keys to the new backend
+    let grown = Ring::new(&[
+        "127.0.0.1:2003",
+        "127.0.0.1:2004",
+        "127.0.0.1:2005",
+        "127.0.0.1:2006",
+    ]);
+    let mut moved = 0;
+    for key in keys.iter() {
+        let (before, after) = (ring.route(key), grown.route(key));
+        if before != after {
+            assert_eq!(after, 3);
+            moved += 1;
+        }
+    }
+    assert!(moved > 4000 && moved < 11000, "{}", moved);
+    // Keys with the same hash tag stay together
+    assert_eq!(ring.route("{user1}.name"), ring.route("{user1}.mail"));
+    // The walk visits every point, starting at the backend of the key
+    assert_eq!(ring.walk("foo").count(), 3 * VNODES);
+    assert_eq!(ring.walk("foo").next(), Some(ring.route("foo")));
+}
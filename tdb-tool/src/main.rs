@@ -0,0 +1,187 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! # `tdb-tool`
+//!
+//! A tool for working with dump files and snapshots while the server isn't running:
+//! it shows their statistics, exports them to and imports them from JSON lines and
+//! CSV, merges them, and converts them between the legacy `bincode` format and the
+//! current format (with any codec and key)
+
+use clap::{load_yaml, App, ArgMatches};
+use libtdb::TResult;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use tdb::config::EncryptionConfig;
+use tdb::coredb::Data;
+use tdb::diskstore::{self, encryption::Keyring, format};
+mod pattern;
+mod text;
+use pattern::Pattern;
+use text::Format;
+
+fn main() {
+    let cli = load_yaml!("cli.yml");
+    let matches = App::from_yaml(cli).get_matches();
+    if let Err(e) = run(&matches) {
+        eprintln!("ERROR: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(matches: &ArgMatches) -> TResult<()> {
+    let (name, args) = matches.subcommand();
+    let args = match args {
+        Some(args) => args,
+        None => return Err("No command was given. Run `tdb-tool --help` for help".into()),
+    };
+    let keys = Keyring::load(&EncryptionConfig::new(
+        args.value_of("key-file").map(PathBuf::from),
+        args.value_of("key-env").map(String::from),
+        args.values_of("old-key-file")
+            .map(|files| files.map(PathBuf::from).collect())
+            .unwrap_or_default(),
+    ))?;
+    let pattern = args.value_of("pattern").map(Pattern::new);
+    match name {
+        "stats" => stats(args.value_of("file").unwrap(), &keys, pattern.as_ref()),
+        "export" => {
+            let table = load(args.value_of("file").unwrap(), &keys, pattern.as_ref())?;
+            let format = Format::from_name(args.value_of("format").unwrap()).unwrap();
+            export(&table, format, args.value_of("output"))
+        }
+        "import" => {
+            let input = args.value_of("file").unwrap();
+            let format = Format::from_name(args.value_of("format").unwrap()).unwrap();
+            let file =
+                fs::File::open(input).map_err(|e| format!("Couldn't open '{}': {}", input, e))?;
+            let mut table = text::import(BufReader::new(file), format)
+                .map_err(|e| format!("Failed to import '{}': {}", input, e))?;
+            if let Some(pattern) = &pattern {
+                table.retain(|key, _| pattern.matches(key));
+            }
+            write(args, &table, &keys)
+        }
+        "merge" => {
+            let mut table = HashMap::new();
+            for file in args.values_of("files").unwrap() {
+                table.extend(load(file, &keys, pattern.as_ref())?);
+            }
+            write(args, &table, &keys)
+        }
+        "convert" => {
+            let table = load(args.value_of("file").unwrap(), &keys, pattern.as_ref())?;
+            write(args, &table, &keys)
+        }
+        _ => unreachable!("clap only accepts the subcommands in cli.yml"),
+    }
+}
+
+/// Read the table in `file`, keeping only the keys that match `pattern`
+fn load(file: &str, keys: &Keyring, pattern: Option<&Pattern>) -> TResult<HashMap<String, Data>> {
+    let mut table = diskstore::get_saved(Some(file), keys)?
+        .ok_or_else(|| format!("'{}' doesn't exist", file))?;
+    if let Some(pattern) = pattern {
+        table.retain(|key, _| pattern.matches(key));
+    }
+    Ok(table)
+}
+
+/// Write `table` into the output file in `args`, in the format that `args` ask for
+fn write(args: &ArgMatches, table: &HashMap<String, Data>, keys: &Keyring) -> TResult<()> {
+    let output = args.value_of("output").unwrap();
+    if args.is_present("legacy") {
+        if keys.current().is_some() {
+            return Err("The legacy format can't be encrypted. Pass the key with --old-key-file to only read with it".into());
+        }
+        let file = BufWriter::new(fs::File::create(output)?);
+        format::write_legacy_table(file, table)?.flush()?;
+    } else {
+        let codec = match args.value_of("compression").unwrap_or("none") {
+            "lz4" => format::Codec::Lz4,
+            "zstd" => format::Codec::Zstd,
+            _ => format::Codec::None,
+        };
+        diskstore::flush_data(output, table, codec, keys)?;
+    }
+    eprintln!("Wrote {} keys to '{}'", table.len(), output);
+    Ok(())
+}
+
+fn export(table: &HashMap<String, Data>, format: Format, output: Option<&str>) -> TResult<()> {
+    let mut pairs: Vec<_> = table.iter().collect();
+    pairs.sort_by(|a, b| a.0.cmp(b.0));
+    let count = match output {
+        Some(output) => {
+            let file = BufWriter::new(fs::File::create(output)?);
+            text::export(file, format, pairs.into_iter())?
+        }
+        None => text::export(io::stdout().lock(), format, pairs.into_iter())?,
+    };
+    eprintln!("Exported {} keys", count);
+    Ok(())
+}
+
+fn stats(file: &str, keys: &Keyring, pattern: Option<&Pattern>) -> TResult<()> {
+    let size = fs::metadata(file)
+        .map_err(|e| format!("Couldn't open '{}': {}", file, e))?
+        .len();
+    let header = format::inspect(BufReader::new(fs::File::open(file)?))
+        .map_err(|e| format!("Failed to read '{}': {}", file, e))?;
+    let table = load(file, keys, pattern)?;
+    println!("File: {}", Path::new(file).display());
+    println!("Size: {} bytes", size);
+    match header {
+        format::FileFormat::Legacy => println!("Format: legacy (bincode, without a header)"),
+        format::FileFormat::Header {
+            version,
+            codec,
+            encrypted,
+        } => {
+            let layout = if version == 1 { "bincode" } else { "records" };
+            println!("Format: version {} ({})", version, layout);
+            let codec = match codec {
+                format::Codec::None => "none",
+                format::Codec::Lz4 => "lz4",
+                format::Codec::Zstd => "zstd",
+            };
+            println!("Compression: {}", codec);
+            println!("Encrypted: {}", if encrypted { "yes" } else { "no" });
+        }
+    }
+    println!("Keys: {}", table.len());
+    let key_bytes: usize = table.keys().map(|key| key.len()).sum();
+    let value_bytes: usize = table.values().map(|value| value.get_blob().len()).sum();
+    println!("Key bytes: {}", key_bytes);
+    println!("Value bytes: {}", value_bytes);
+    if let Some((key, value)) = table.iter().max_by_key(|(_, value)| value.get_blob().len()) {
+        println!("Average value: {} bytes", value_bytes / table.len());
+        println!(
+            "Largest value: {} bytes (key '{}')",
+            value.get_blob().len(),
+            key
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,107 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! Glob patterns for picking keys
+
+/// A glob pattern: `*` matches any number of characters, `?` matches exactly one, and
+/// `\` makes the character after it match only itself
+pub struct Pattern {
+    tokens: Vec<Token>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Any,
+    One,
+    Char(char),
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Self {
+        let mut tokens = Vec::new();
+        let mut chars = pattern.chars();
+        while let Some(c) = chars.next() {
+            tokens.push(match c {
+                '*' => Token::Any,
+                '?' => Token::One,
+                '\\' => Token::Char(chars.next().unwrap_or('\\')),
+                c => Token::Char(c),
+            });
+        }
+        Pattern { tokens }
+    }
+    /// Check if `key` matches the pattern
+    pub fn matches(&self, key: &str) -> bool {
+        let key: Vec<char> = key.chars().collect();
+        let (mut t, mut k) = (0, 0);
+        // Where to go back to if what comes after the last `*` doesn't match: the token
+        // after the `*` and the character that the `*` should stop at instead
+        let mut backtrack = None;
+        while k < key.len() {
+            match self.tokens.get(t) {
+                Some(Token::Any) => {
+                    backtrack = Some((t + 1, k));
+                    t += 1;
+                    continue;
+                }
+                Some(Token::One) => {
+                    t += 1;
+                    k += 1;
+                    continue;
+                }
+                Some(Token::Char(c)) if *c == key[k] => {
+                    t += 1;
+                    k += 1;
+                    continue;
+                }
+                _ => {}
+            }
+            match backtrack {
+                Some((after_any, stopped_at)) => {
+                    t = after_any;
+                    k = stopped_at + 1;
+                    backtrack = Some((after_any, k));
+                }
+                None => return false,
+            }
+        }
+        self.tokens[t..].iter().all(|token| *token == Token::Any)
+    }
+}
+
+#[test]
+fn test_pattern() {
+    let matches = |pattern: &str, key: &str| Pattern::new(pattern).matches(key);
+    assert!(matches("*", ""));
+    assert!(matches("*", "anything"));
+    assert!(matches("user:*", "user:42"));
+    assert!(!matches("user:*", "session:42"));
+    assert!(matches("user:?", "user:4"));
+    assert!(!matches("user:?", "user:42"));
+    assert!(matches("*:*:name", "user:42:name"));
+    assert!(matches("a*b*c", "aXbYbZc"));
+    assert!(!matches("a*b*c", "aXbYbZ"));
+    assert!(matches("exact", "exact"));
+    assert!(!matches("exact", "exactly"));
+    assert!(matches("what\\?", "what?"));
+    assert!(!matches("what\\?", "whats"));
+    assert!(matches("*\\*", "5*"));
+}
@@ -0,0 +1,197 @@
+/*
+ * Created on Sun Oct 18 2026
+ *
+ * This file is a part of TerrabaseDB
+ * Copyright (c) 2020, Sayan Nandan <ohsayan at outlook dot com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <https://www.gnu.org/licenses/>.
+ *
+*/
+
+//! Exporting and importing tables as JSON lines and CSV
+//!
+//! Every JSON line is an object with a `key` and either a `value`, if the value is
+//! valid UTF-8, or a `value_base64` with the value encoded as base64. CSV files have a
+//! `key,value` header and the values are written as they are
+
+use bytes::Bytes;
+use libtdb::TResult;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{BufRead, Read, Write};
+use tdb::coredb::Data;
+
+/// The text formats that tables can be exported to and imported from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    JsonLines,
+    Csv,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "jsonl" => Some(Format::JsonLines),
+            "csv" => Some(Format::Csv),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Line {
+    key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value_base64: Option<String>,
+}
+
+/// Write `pairs` into `writer` in `format`, returning the number of pairs written
+pub fn export<'a, W: Write>(
+    mut writer: W,
+    format: Format,
+    pairs: impl Iterator<Item = (&'a String, &'a Data)>,
+) -> TResult<usize> {
+    let mut count = 0;
+    match format {
+        Format::JsonLines => {
+            for (key, value) in pairs {
+                let blob = value.get_blob();
+                let line = match std::str::from_utf8(blob) {
+                    Ok(value) => Line {
+                        key: key.clone(),
+                        value: Some(value.to_owned()),
+                        value_base64: None,
+                    },
+                    Err(_) => Line {
+                        key: key.clone(),
+                        value: None,
+                        value_base64: Some(base64::encode(blob)),
+                    },
+                };
+                serde_json::to_writer(&mut writer, &line)?;
+                writer.write_all(b"\n")?;
+                count += 1;
+            }
+            writer.flush()?;
+        }
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            writer.write_record(["key", "value"])?;
+            for (key, value) in pairs {
+                writer.write_record([key.as_bytes(), value.get_blob()])?;
+                count += 1;
+            }
+            writer.flush()?;
+        }
+    }
+    Ok(count)
+}
+
+/// Read pairs written by [`export`] from `reader`. If a key appears more than once,
+/// the last value wins
+pub fn import<R: BufRead>(reader: R, format: Format) -> TResult<HashMap<String, Data>> {
+    let mut table = HashMap::new();
+    match format {
+        Format::JsonLines => {
+            for (number, line) in reader.lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let (key, value) = parse_line(&line)
+                    .map_err(|e| format!("line {} is invalid: {}", number + 1, e))?;
+                table.insert(key, Data::from_blob(value));
+            }
+        }
+        Format::Csv => {
+            for (number, record) in csv_reader(reader).byte_records().enumerate() {
+                // The header is line 1
+                let line = number + 2;
+                let record = record.map_err(|e| format!("line {} is invalid: {}", line, e))?;
+                if record.len() != 2 {
+                    return Err(format!(
+                        "line {} has {} fields instead of a key and a value",
+                        line,
+                        record.len()
+                    )
+                    .into());
+                }
+                let key = String::from_utf8(record[0].to_vec())
+                    .map_err(|_| format!("the key on line {} isn't valid UTF-8", line))?;
+                table.insert(key, Data::from_blob(Bytes::from(record[1].to_vec())));
+            }
+        }
+    }
+    Ok(table)
+}
+
+fn parse_line(line: &str) -> TResult<(String, Bytes)> {
+    let line: Line = serde_json::from_str(line)?;
+    let value = match (line.value, line.value_base64) {
+        (Some(value), None) => Bytes::from(value),
+        (None, Some(value)) => Bytes::from(base64::decode(value)?),
+        _ => return Err("it needs either a `value` or a `value_base64`".into()),
+    };
+    Ok((line.key, value))
+}
+
+fn csv_reader<R: Read>(reader: R) -> csv::Reader<R> {
+    csv::ReaderBuilder::new()
+        .has_headers(true)
+        .flexible(true)
+        .from_reader(reader)
+}
+
+#[test]
+fn test_export_import() {
+    let mut table = HashMap::new();
+    table.insert("plain".to_owned(), Data::from_string("value".to_owned()));
+    table.insert(
+        "quoted".to_owned(),
+        Data::from_string("\"a, b\"\nand a new line".to_owned()),
+    );
+    table.insert(
+        "binary".to_owned(),
+        Data::from_blob(Bytes::from(vec![0xFF, 0x00, 0xFE])),
+    );
+    table.insert("empty".to_owned(), Data::from_string(String::new()));
+    for &format in &[Format::JsonLines, Format::Csv] {
+        let mut exported = Vec::new();
+        let count = export(&mut exported, format, table.iter()).unwrap();
+        assert_eq!(count, table.len());
+        assert_eq!(import(&exported[..], format).unwrap(), table);
+    }
+    let mut exported = Vec::new();
+    export(
+        &mut exported,
+        Format::JsonLines,
+        table.iter().filter(|(k, _)| *k == "binary"),
+    )
+    .unwrap();
+    assert_eq!(
+        exported,
+        b"{\"key\":\"binary\",\"value_base64\":\"/wD+\"}\n"
+    );
+    // Errors say where they are
+    let err = import(
+        &b"{\"key\":\"a\",\"value\":\"b\"}\n{\"key\":\"c\"}\n"[..],
+        Format::JsonLines,
+    )
+    .unwrap_err();
+    assert!(err.to_string().starts_with("line 2 is invalid"));
+    let err = import(&b"key,value\na,b\nc\n"[..], Format::Csv).unwrap_err();
+    assert!(err.to_string().starts_with("line 3 has 1 fields"));
+}